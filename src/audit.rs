@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use percent_encoding::percent_decode_str;
+use regex::{Captures, Regex};
+use scraper::{Html, Selector};
+use serde::Serialize;
+use tokio::fs;
+use tokio::io::AsyncReadExt as _;
+
+use crate::config;
+
+/// Images larger than this many bytes load slowly on GitHub.
+const MAX_BYTES: u64 = 1024 * 1024;
+
+/// Images wider or taller than this are almost certainly being scaled down a lot.
+const MAX_DIMENSION: u32 = 4000;
+
+/// How much of an image is read to find its dimensions, which are almost always near the start.
+const HEADER_BYTES: u64 = 64 * 1024;
+
+/// Information about a local image referenced by the document.
+#[derive(Serialize)]
+pub(crate) struct ImageReport {
+    pub(crate) src: String,
+    /// The URL GitHub will fetch the image from, if the repository is known and `src` isn't
+    /// already one.
+    pub(crate) github_url: Option<String>,
+    pub(crate) bytes: Option<u64>,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    pub(crate) warnings: Vec<String>,
+}
+
+/// A GitHub repository and its local checkout, for resolving images the way GitHub does.
+pub(crate) struct Repository<'a> {
+    /// The repository's name, as `owner/name`.
+    pub(crate) name: &'a str,
+    /// The branch the document will be viewed on, if not the default branch.
+    pub(crate) branch: Option<&'a str>,
+    /// The root of the local checkout.
+    pub(crate) root: &'a Path,
+}
+
+/// Inspect every local image in rendered HTML, resolving relative paths against `base_dir`.
+///
+/// If the document is in a checkout of a known repository, paths starting with `/` and raw
+/// GitHub URLs of files in the repository are resolved to local files too, the way GitHub will.
+pub(crate) async fn images(
+    html: &str,
+    base_dir: &Path,
+    repository: Option<&Repository<'_>>,
+) -> Vec<ImageReport> {
+    let mut reports = Vec::new();
+    for src in image_srcs(html) {
+        let resolved = match resolve(&src, base_dir, repository) {
+            Some(resolved) => resolved,
+            None => continue,
+        };
+        let mut report = ImageReport {
+            src,
+            github_url: None,
+            bytes: None,
+            width: None,
+            height: None,
+            warnings: Vec::new(),
+        };
+        let path = match resolved {
+            Ok(resolved) => {
+                report.github_url = resolved.github_url;
+                report.warnings = resolved.warnings;
+                resolved.path
+            }
+            Err(warning) => {
+                report.warnings.push(warning);
+                reports.push(report);
+                continue;
+            }
+        };
+        match fs::read(&path).await {
+            Ok(data) => {
+                let bytes = data.len() as u64;
+                report.bytes = Some(bytes);
+                if bytes > MAX_BYTES {
+                    report.warnings.push(format!(
+                        "image is {:.1} MiB, which will load slowly",
+                        bytes as f64 / (1024.0 * 1024.0)
+                    ));
+                }
+                if let Some((width, height)) = dimensions(&data) {
+                    report.width = Some(width);
+                    report.height = Some(height);
+                    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+                        report.warnings.push(format!(
+                            "image is {}×{}, which is far larger than it will be displayed",
+                            width, height
+                        ));
+                    }
+                }
+            }
+            Err(e) => {
+                report
+                    .warnings
+                    .push(format!("`{}` could not be read: {}", path.display(), e))
+            }
+        }
+        reports.push(report);
+    }
+    reports
+}
+
+/// Keep images from holding up the rest of the page: every image is loaded lazily and decoded off
+/// the main thread. With `placeholders`, local images are also given their dimensions, so that the
+/// page is laid out with room for them from the start, and shown as placeholders until they load.
+pub(crate) async fn defer_images(
+    html: &str,
+    base_dir: &Path,
+    repository: Option<&Repository<'_>>,
+    placeholders: bool,
+) -> String {
+    static IMG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<img\b([^>]*?)(\s*/?>)").unwrap());
+    static SRC: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\ssrc="([^"]*)""#).unwrap());
+    let has = |attributes: &str, name: &str| attributes.contains(&format!(" {}=", name));
+
+    let mut sizes = HashMap::new();
+    if placeholders {
+        for src in image_srcs(html) {
+            if sizes.contains_key(&src) {
+                continue;
+            }
+            let size = match resolve(&src, base_dir, repository) {
+                Some(Ok(resolved)) => read_dimensions(&resolved.path).await,
+                _ => None,
+            };
+            sizes.insert(src, size);
+        }
+    }
+
+    IMG.replace_all(html, |captures: &Captures<'_>| {
+        let attributes = &captures[1];
+        let mut added = String::new();
+        if !has(attributes, "loading") {
+            added.push_str(" loading=\"lazy\"");
+        }
+        if !has(attributes, "decoding") {
+            added.push_str(" decoding=\"async\"");
+        }
+        let size = SRC
+            .captures(attributes)
+            .map(|src| src[1].split(['?', '#']).next().unwrap().to_owned())
+            .and_then(|src| *sizes.get(&src)?);
+        if let Some((width, height)) = size {
+            if !has(attributes, "width") && !has(attributes, "height") {
+                added += &format!(
+                    " width=\"{}\" height=\"{}\" data-ghmd-placeholder",
+                    width, height
+                );
+            }
+        }
+        format!("<img{}{}{}", attributes, added, &captures[2])
+    })
+    .into_owned()
+}
+
+/// Read the dimensions of an image from the start of its file.
+async fn read_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let mut header = Vec::new();
+    fs::File::open(path)
+        .await
+        .ok()?
+        .take(HEADER_BYTES)
+        .read_to_end(&mut header)
+        .await
+        .ok()?;
+    dimensions(&header)
+}
+
+fn image_srcs(html: &str) -> Vec<String> {
+    static SELECTOR: Lazy<Selector> = Lazy::new(|| Selector::parse("img[src]").unwrap());
+    Html::parse_fragment(html)
+        .select(&SELECTOR)
+        .filter_map(|img| img.value().attr("src"))
+        .map(|src| src.split(['?', '#']).next().unwrap().to_owned())
+        .collect()
+}
+
+/// The local file an image will be fetched from.
+struct Resolved {
+    path: PathBuf,
+    github_url: Option<String>,
+    warnings: Vec<String>,
+}
+
+/// Work out which local file an image's `src` refers to, or why it can't be worked out. Images
+/// hosted anywhere other than the repository are skipped.
+fn resolve(
+    src: &str,
+    base_dir: &Path,
+    repository: Option<&Repository<'_>>,
+) -> Option<Result<Resolved, String>> {
+    let decode = |s: &str| PathBuf::from(&*percent_decode_str(s).decode_utf8_lossy());
+
+    if let Some((name, branch, path)) = raw_url(src) {
+        let repository =
+            repository.filter(|repository| repository.name.eq_ignore_ascii_case(name))?;
+        let mut warnings = Vec::new();
+        match repository.branch {
+            Some(expected) if branch != expected && branch != "HEAD" => warnings.push(format!(
+                "GitHub will fetch this from the `{}` branch rather than `{}`",
+                branch, expected
+            )),
+            None if branch != "HEAD" => warnings.push(format!(
+                "GitHub will fetch this from the `{}` branch, which may not be the one the document is viewed on",
+                branch
+            )),
+            _ => {}
+        }
+        return Some(Ok(Resolved {
+            path: repository.root.join(decode(path)),
+            github_url: None,
+            warnings,
+        }));
+    }
+    if src.contains(':') || src.starts_with("//") {
+        return None;
+    }
+
+    let path =
+        match (src.strip_prefix('/'), repository) {
+            (Some(path), Some(repository)) => repository.root.join(decode(path)),
+            (Some(_), None) => return Some(Err(
+                "paths starting with `/` are resolved against the root of the repository, which \
+                    is only known when the repository is configured and the document is in a \
+                    checkout of it"
+                    .to_owned(),
+            )),
+            (None, _) => base_dir.join(decode(src)),
+        };
+    let repository = match repository {
+        Some(repository) => repository,
+        None => {
+            return Some(Ok(Resolved {
+                path,
+                github_url: None,
+                warnings: Vec::new(),
+            }))
+        }
+    };
+
+    let mut resolved = Resolved {
+        path: normalize(&path),
+        github_url: None,
+        warnings: Vec::new(),
+    };
+    match resolved.path.strip_prefix(repository.root) {
+        Ok(relative) => {
+            resolved.github_url = Some(format!(
+                "https://github.com/{}/raw/{}/{}",
+                repository.name,
+                repository.branch.unwrap_or("HEAD"),
+                config::url_path(relative)
+            ));
+        }
+        Err(_) => resolved
+            .warnings
+            .push("the path leads outside the repository, so GitHub can't show it".to_owned()),
+    }
+    Some(Ok(resolved))
+}
+
+/// Split a URL of a file served raw from GitHub into its repository, branch and path.
+fn raw_url(url: &str) -> Option<(&str, &str, &str)> {
+    let (rest, on_github) = match url.strip_prefix("https://raw.githubusercontent.com/") {
+        Some(rest) => (rest, false),
+        None => (url.strip_prefix("https://github.com/")?, true),
+    };
+    let mut parts = rest.splitn(if on_github { 5 } else { 4 }, '/');
+    let owner = parts.next()?;
+    let name = parts.next()?;
+    if on_github && parts.next()? != "raw" {
+        return None;
+    }
+    let branch = parts.next()?;
+    let path = parts.next()?;
+    Some((&rest[..owner.len() + 1 + name.len()], branch, path))
+}
+
+/// Resolve `.` and `..` in a path without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Read the natural dimensions of a PNG, GIF, JPEG or WebP image from its header.
+fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |i: usize| Some(u16::from_be_bytes(data.get(i..i + 2)?.try_into().ok()?) as u32);
+    let le16 = |i: usize| Some(u16::from_le_bytes(data.get(i..i + 2)?.try_into().ok()?) as u32);
+    let be32 = |i: usize| Some(u32::from_be_bytes(data.get(i..i + 4)?.try_into().ok()?));
+    let le24 = |i: usize| {
+        let bytes = data.get(i..i + 3)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+    };
+
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some((be32(16)?, be32(20)?));
+    }
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Some((le16(6)?, le16(8)?));
+    }
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        return match data.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3FFF, le16(28)? & 0x3FFF)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+            }
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            _ => None,
+        };
+    }
+    if data.starts_with(b"\xFF\xD8") {
+        let mut i = 2;
+        while i + 9 < data.len() {
+            if data[i] != 0xFF {
+                return None;
+            }
+            let marker = data[i + 1];
+            // Start-of-frame markers, excluding DHT, JPG and DAC which share the range.
+            if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+                return Some((be16(i + 7)?, be16(i + 5)?));
+            }
+            i += 2 + be16(i + 2)? as usize;
+        }
+    }
+    None
+}
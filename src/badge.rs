@@ -0,0 +1,204 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, ensure, Context as _};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tokio::{fs, task};
+
+use crate::config::ProjectConfig;
+use crate::queue::Priority;
+use crate::renderer::Renderer;
+use crate::review;
+use crate::templater::{Liveness, Templater, Theme};
+
+/// What is known about the repository in the working directory.
+pub(crate) struct Repository {
+    /// The root of the checkout, or the working directory if it isn't in one.
+    pub(crate) root: PathBuf,
+    /// The repository's `owner/repo` on GitHub, if it is there.
+    pub(crate) github: Option<String>,
+    /// The file name and name of each GitHub Actions workflow.
+    pub(crate) workflows: Vec<(String, String)>,
+    /// The name of the crate at the root, if it is published.
+    pub(crate) published_crate: Option<String>,
+    /// The name of the license file at the root, if there is one.
+    pub(crate) license: Option<String>,
+}
+
+impl Repository {
+    /// Look at the repository in the working directory, finding it on GitHub from `remote` unless
+    /// the project configures it.
+    pub(crate) async fn detect(project: &ProjectConfig, remote: &str) -> anyhow::Result<Self> {
+        let (root, url) = task::spawn_blocking({
+            let remote = remote.to_owned();
+            move || match review::git_root() {
+                Ok(root) => {
+                    let url = review::git(&root, &["remote", "get-url", &remote]).ok();
+                    (root, url)
+                }
+                Err(_) => (PathBuf::from("."), None),
+            }
+        })
+        .await
+        .unwrap();
+        Ok(Self {
+            github: project
+                .repository
+                .clone()
+                .or_else(|| github_repository(url?.trim())),
+            workflows: workflows(&root).await?,
+            published_crate: published_crate(&root).await?,
+            license: license(&root).await?,
+            root,
+        })
+    }
+
+    /// Markdown for each of the badges that suit the repository: one for each GitHub Actions
+    /// workflow, crates.io and docs.rs ones if it is a published crate, and one for its license.
+    pub(crate) fn badges(&self) -> Vec<String> {
+        let mut badges = Vec::new();
+        if let Some(github) = &self.github {
+            for (file, name) in &self.workflows {
+                let url = format!("https://github.com/{}/actions/workflows/{}", github, file);
+                badges.push(format!("[![{}]({}/badge.svg)]({})", name, url, url));
+            }
+        }
+        if let Some(name) = &self.published_crate {
+            badges.push(format!(
+                "[![crates.io](https://img.shields.io/crates/v/{0}.svg)](https://crates.io/crates/{0})",
+                name
+            ));
+            badges.push(format!(
+                "[![docs.rs](https://docs.rs/{0}/badge.svg)](https://docs.rs/{0})",
+                name
+            ));
+        }
+        if let (Some(github), Some(license)) = (&self.github, &self.license) {
+            badges.push(format!(
+                "[![License](https://img.shields.io/github/license/{})]({})",
+                github, license
+            ));
+        }
+        badges
+    }
+}
+
+/// Print markdown for the badges that suit the repository in the working directory. With
+/// `output`, a page showing them rendered is written there too, so that broken badges can be
+/// spotted before they are committed.
+pub(crate) async fn run(
+    renderer: &Renderer,
+    project: &ProjectConfig,
+    theme: Theme,
+    remote: &str,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let repository = Repository::detect(project, remote).await?;
+    let github = repository.github.as_deref().ok_or_else(|| {
+        anyhow!(
+            "the `{}` remote is not on GitHub; give the repository with `--repo owner/repo`",
+            remote
+        )
+    })?;
+    let badges = repository.badges();
+    ensure!(
+        !badges.is_empty(),
+        "found nothing in the repository to make badges for"
+    );
+    let markdown = badges.join("\n") + "\n";
+    print!("{}", markdown);
+
+    if let Some(output) = output {
+        let rendered = renderer.render(&markdown, Priority::Background).await??;
+        let page = Templater::new(format!("Badges for {}", github).into(), theme)
+            .generate(&rendered, Liveness::Static, None)
+            .await?;
+        fs::write(output, page)
+            .await
+            .context("could not write to output file")?;
+    }
+    Ok(())
+}
+
+/// The `owner/repo` a remote URL points to, if it is on GitHub, in any of the forms Git accepts.
+fn github_repository(url: &str) -> Option<String> {
+    static GITHUB: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"github\.com[:/]([\w.-]+)/([\w.-]+?)(?:\.git)?/?$").unwrap());
+    let captures = GITHUB.captures(url)?;
+    Some(format!("{}/{}", &captures[1], &captures[2]))
+}
+
+/// The file name and name of each GitHub Actions workflow, in order of file name.
+async fn workflows(root: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    static NAME: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"(?m)^name:\s*["']?(.*?)["']?\s*$"#).unwrap());
+
+    let dir = root.join(".github/workflows");
+    let mut entries = match fs::read_dir(&dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read `{}`", dir.display())),
+    };
+    let mut workflows = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let file = entry.file_name().to_string_lossy().into_owned();
+        if !file.ends_with(".yml") && !file.ends_with(".yaml") {
+            continue;
+        }
+        let source = fs::read_to_string(entry.path()).await?;
+        let name = match NAME.captures(&source) {
+            Some(captures) => captures[1].to_owned(),
+            None => file.clone(),
+        };
+        workflows.push((file, name));
+    }
+    workflows.sort();
+    Ok(workflows)
+}
+
+/// The name of the crate at the root of the repository, unless there is none or it isn't
+/// published to crates.io.
+async fn published_crate(root: &Path) -> anyhow::Result<Option<String>> {
+    static ENTRY: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"^([\w-]+)\s*=\s*(?:"([^"]*)"|(\w+))"#).unwrap());
+
+    let manifest = match fs::read_to_string(root.join("Cargo.toml")).await {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context("failed to read `Cargo.toml`"),
+    };
+    let mut in_package = false;
+    let mut name = None;
+    for line in manifest.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+            continue;
+        }
+        let captures = match ENTRY.captures(line) {
+            Some(captures) if in_package => captures,
+            _ => continue,
+        };
+        match (&captures[1], captures.get(2), captures.get(3)) {
+            ("name", Some(value), _) => name = Some(value.as_str().to_owned()),
+            ("publish", _, Some(value)) if value.as_str() == "false" => return Ok(None),
+            _ => {}
+        }
+    }
+    Ok(name)
+}
+
+/// The license file at the root of the repository, if there is one.
+async fn license(root: &Path) -> anyhow::Result<Option<String>> {
+    let mut entries = fs::read_dir(root).await?;
+    let mut licenses = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_ascii_uppercase();
+        if name.starts_with("LICENSE") || name.starts_with("LICENCE") || name.starts_with("COPYING")
+        {
+            licenses.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    licenses.sort();
+    Ok(licenses.into_iter().next())
+}
@@ -0,0 +1,127 @@
+use std::io::Write as _;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{ensure, Context as _};
+use fn_error_context::context;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use tokio::fs;
+
+use crate::queue::Priority;
+use crate::renderer::Renderer;
+use crate::terminal;
+
+/// Styles approximating GitHub's light theme, applied inline so they survive being pasted into
+/// email clients and word processors that ignore stylesheets.
+const STYLES: &[(&str, &str)] = &[
+    ("h1", "font-size: 2em; font-weight: 600; padding-bottom: .3em; border-bottom: 1px solid #d0d7de; margin: 24px 0 16px"),
+    ("h2", "font-size: 1.5em; font-weight: 600; padding-bottom: .3em; border-bottom: 1px solid #d0d7de; margin: 24px 0 16px"),
+    ("h3", "font-size: 1.25em; font-weight: 600; margin: 24px 0 16px"),
+    ("h4", "font-size: 1em; font-weight: 600; margin: 24px 0 16px"),
+    ("h5", "font-size: .875em; font-weight: 600; margin: 24px 0 16px"),
+    ("h6", "font-size: .85em; font-weight: 600; color: #656d76; margin: 24px 0 16px"),
+    ("p", "margin: 0 0 16px"),
+    ("a", "color: #0969da; text-decoration: none"),
+    ("code", "font-family: ui-monospace, SFMono-Regular, Menlo, Consolas, monospace; font-size: 85%; background-color: rgba(175, 184, 193, .2); padding: .2em .4em; border-radius: 6px"),
+    ("pre", "font-family: ui-monospace, SFMono-Regular, Menlo, Consolas, monospace; font-size: 85%; background-color: #f6f8fa; padding: 16px; border-radius: 6px; overflow: auto; line-height: 1.45"),
+    ("blockquote", "margin: 0 0 16px; padding: 0 1em; color: #656d76; border-left: .25em solid #d0d7de"),
+    ("table", "border-collapse: collapse; margin: 0 0 16px"),
+    ("th", "font-weight: 600; padding: 6px 13px; border: 1px solid #d0d7de"),
+    ("td", "padding: 6px 13px; border: 1px solid #d0d7de"),
+    ("hr", "height: .25em; padding: 0; margin: 24px 0; background-color: #d0d7de; border: 0"),
+    ("img", "max-width: 100%"),
+    ("ul", "padding-left: 2em; margin: 0 0 16px"),
+    ("ol", "padding-left: 2em; margin: 0 0 16px"),
+];
+
+const BODY_STYLE: &str = "font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Helvetica, Arial, sans-serif; font-size: 16px; line-height: 1.5; color: #1f2328";
+
+/// Render `input` and place it on the system clipboard as HTML, along with a plain-text version
+/// where the platform allows both.
+#[context("failed to copy `{}` to the clipboard", input.display())]
+pub(crate) async fn run(renderer: &Renderer, input: &Path) -> anyhow::Result<()> {
+    let markdown = fs::read_to_string(input).await?;
+    let rendered = renderer.render(&markdown, Priority::Interactive).await??;
+
+    let html = format!(
+        "<div style=\"{}\">{}</div>",
+        BODY_STYLE,
+        inline_styles(&rendered)
+    );
+    let text = terminal::to_ansi(&rendered, false, None);
+    copy(&html, &text)?;
+
+    println!("Copied the rendered document to the clipboard");
+    Ok(())
+}
+
+fn inline_styles(html: &str) -> String {
+    static TAGS: Lazy<Regex> = Lazy::new(|| {
+        let names: Vec<&str> = STYLES.iter().map(|(name, _)| *name).collect();
+        Regex::new(&format!(r"<({})([\s>/])", names.join("|"))).unwrap()
+    });
+    TAGS.replace_all(html, |captures: &Captures<'_>| {
+        let style = STYLES
+            .iter()
+            .find(|(name, _)| *name == &captures[1])
+            .unwrap()
+            .1;
+        format!("<{} style=\"{}\"{}", &captures[1], style, &captures[2])
+    })
+    .into_owned()
+}
+
+#[cfg(target_os = "macos")]
+fn copy(html: &str, text: &str) -> anyhow::Result<()> {
+    use std::fmt::Write as _;
+
+    let hex = |s: &str| {
+        s.bytes().fold(String::new(), |mut hex, byte| {
+            write!(hex, "{:02X}", byte).unwrap();
+            hex
+        })
+    };
+    let script = format!(
+        "set the clipboard to {{«class HTML»:«data HTML{}», «class utf8»:«data utf8{}»}}",
+        hex(html),
+        hex(text),
+    );
+    pipe_to(Command::new("osascript").args(["-e", &script]), "")
+}
+
+#[cfg(windows)]
+fn copy(_html: &str, text: &str) -> anyhow::Result<()> {
+    // `clip` only supports plain text.
+    pipe_to(&mut Command::new("clip"), text)
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn copy(html: &str, _text: &str) -> anyhow::Result<()> {
+    // Neither tool can offer several formats at once, so only HTML is copied; pasting into a
+    // plain-text context will show the markup.
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        pipe_to(Command::new("wl-copy").args(["--type", "text/html"]), html)
+    } else if std::env::var_os("DISPLAY").is_some() {
+        pipe_to(
+            Command::new("xclip").args(["-selection", "clipboard", "-t", "text/html"]),
+            html,
+        )
+    } else {
+        anyhow::bail!("no graphical session was found to copy to")
+    }
+}
+
+/// Run a command with the given standard input, failing if it does.
+fn pipe_to(command: &mut Command, input: &str) -> anyhow::Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .with_context(|| format!("failed to run `{}`; is it installed?", program))?;
+    child.stdin.take().unwrap().write_all(input.as_bytes())?;
+    let status = child.wait()?;
+    ensure!(status.success(), "`{}` exited with {}", program, status);
+    Ok(())
+}
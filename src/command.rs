@@ -0,0 +1,116 @@
+use std::future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, ensure, Context as _};
+use fn_error_context::context;
+use tokio::process::Command;
+use tokio::sync::watch;
+use tokio::time;
+
+use crate::watcher::{DocumentState, File, ReadError};
+
+/// Run a command and publish its output as the document, running it again every `interval` and
+/// whenever `trigger` is notified.
+pub(crate) async fn spawn(
+    command: Vec<String>,
+    interval: Option<Duration>,
+    mut trigger: watch::Receiver<()>,
+) -> watch::Receiver<DocumentState> {
+    let initial = state(run(&command).await.map(|output| file(output, 0)));
+    let mut previous_contents = initial.file().map(|file| file.contents.clone());
+    let mut revision = 0;
+    let (sender, receiver) = watch::channel(initial);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                () = async {
+                    match interval {
+                        Some(interval) => time::sleep(interval).await,
+                        None => future::pending().await,
+                    }
+                } => {}
+                res = trigger.changed() => if res.is_err() { break },
+                () = sender.closed() => break,
+            }
+
+            let res = run(&command).await;
+
+            let same = matches!(
+                (&res, &previous_contents),
+                (Ok(output), Some(previous_contents)) if **output == **previous_contents
+            );
+            if same {
+                continue;
+            }
+
+            revision += 1;
+            let state = state(res.map(|output| file(output, revision)));
+            previous_contents = state.file().map(|file| file.contents.clone());
+            if let Some(file) = state.file() {
+                log::info!("Read revision {} ({} bytes)", file.revision, file.size);
+            }
+            if sender.send(state).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Split a command line into the program and its arguments the way a POSIX shell would, so that
+/// arguments containing spaces can be quoted.
+pub(crate) fn split(command: &str) -> anyhow::Result<Vec<String>> {
+    shell_words::split(command).with_context(|| format!("`{}` is not a valid command", command))
+}
+
+/// Run a command once, returning its standard output.
+#[context("failed to run `{}`", shell_words::join(command))]
+pub(crate) async fn run(command: &[String]) -> anyhow::Result<String> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("the command is empty"))?;
+    let output = Command::new(program).args(args).output().await?;
+    ensure!(
+        output.status.success(),
+        "command exited with {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim(),
+    );
+    String::from_utf8(output.stdout).context("output is not UTF-8")
+}
+
+fn file(output: String, revision: u64) -> File {
+    File {
+        size: output.len() as u64,
+        contents: Arc::from(output),
+        modified: None,
+        revision,
+    }
+}
+
+fn state(res: anyhow::Result<File>) -> DocumentState {
+    match res {
+        Ok(file) => DocumentState::Content(file),
+        Err(e) => DocumentState::Error(ReadError::new(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(split("cargo readme").unwrap(), ["cargo", "readme"]);
+        assert_eq!(
+            split("sh -c 'cat \"my notes.md\"'").unwrap(),
+            ["sh", "-c", "cat \"my notes.md\""]
+        );
+        assert_eq!(split(r"cat my\ notes.md").unwrap(), ["cat", "my notes.md"]);
+        assert_eq!(split("  ").unwrap(), Vec::<String>::new());
+        assert!(split("echo 'unterminated").is_err());
+    }
+}
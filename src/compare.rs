@@ -0,0 +1,341 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, ensure, Context as _};
+use fn_error_context::context;
+use scraper::{ElementRef, Html};
+use tokio::{fs, task};
+
+use crate::diff::{self, Line};
+use crate::queue::Priority;
+use crate::renderer::Renderer;
+use crate::templater::{Liveness, Templater, Theme};
+
+const STYLE: &str = "\
+<style>
+.ghmd-removed, .ghmd-added { border-left: 4px solid; padding-left: 12px; margin-left: -16px; }
+.ghmd-removed { border-color: var(--color-danger-emphasis, #da3633); \
+    background: var(--color-danger-subtle, rgba(248, 81, 73, 0.1)); }
+.ghmd-added { border-color: var(--color-success-emphasis, #238636); \
+    background: var(--color-success-subtle, rgba(46, 160, 67, 0.15)); }
+</style>
+";
+
+/// One side of a comparison.
+pub(crate) struct Source {
+    path: PathBuf,
+    /// The git revision to read the file at, or the working tree if `None`.
+    rev: Option<String>,
+}
+
+impl Source {
+    fn name(&self) -> String {
+        match &self.rev {
+            Some(rev) => format!("{}@{}", self.path.display(), rev),
+            None => self.path.display().to_string(),
+        }
+    }
+
+    #[context("failed to read `{}`", self.name())]
+    async fn read(&self) -> anyhow::Result<String> {
+        let rev = match &self.rev {
+            Some(rev) => rev,
+            None => return Ok(fs::read_to_string(&self.path).await?),
+        };
+        let dir = match self.path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        let file_name = self.path.file_name().context("path has no file name")?;
+        let output = task::spawn_blocking({
+            let dir = dir.to_owned();
+            let spec = format!("{}:./{}", rev, file_name.to_string_lossy());
+            move || {
+                Command::new("git")
+                    .current_dir(dir)
+                    .arg("show")
+                    .arg(spec)
+                    .output()
+            }
+        })
+        .await
+        .unwrap()
+        .context("failed to run git")?;
+        ensure!(
+            output.status.success(),
+            "git show failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        Ok(String::from_utf8(output.stdout)?)
+    }
+}
+
+/// Work out what to compare from the files and revisions given on the command line.
+pub(crate) fn sources(files: Vec<PathBuf>, mut revs: Vec<String>) -> anyhow::Result<[Source; 2]> {
+    let mut files = files.into_iter();
+    let (first, second) = (files.next(), files.next());
+    Ok(match (first, second, revs.len()) {
+        (Some(old), Some(new), 0) => [
+            Source {
+                path: old,
+                rev: None,
+            },
+            Source {
+                path: new,
+                rev: None,
+            },
+        ],
+        (Some(path), None, 1) => [
+            Source {
+                path: path.clone(),
+                rev: revs.pop(),
+            },
+            Source { path, rev: None },
+        ],
+        (Some(path), None, 2) => {
+            let new = revs.pop();
+            let old = revs.pop();
+            [
+                Source {
+                    path: path.clone(),
+                    rev: old,
+                },
+                Source { path, rev: new },
+            ]
+        }
+        _ => bail!("expected either two files, or one file and one or two revisions"),
+    })
+}
+
+/// Render both sources and produce a page showing the blocks that were removed and added.
+#[context("failed to compare documents")]
+pub(crate) async fn run(
+    renderer: &Renderer,
+    theme: Theme,
+    [old, new]: [Source; 2],
+    output: &Path,
+) -> anyhow::Result<()> {
+    let content = render_diff(renderer, &old.read().await?, &new.read().await?).await?;
+
+    let templater = Templater::new(
+        format!("{} → {}", old.name(), new.name()).into_boxed_str(),
+        theme,
+    );
+    let page = templater.generate(&content, Liveness::Static, None).await?;
+    write(output, &page).await
+}
+
+/// Render `input` with both GitHub's API and comrak, and produce a page showing the top-level
+/// blocks that GitHub renders differently. Blocks are compared by their structure, so differences
+/// in the order of attributes or amount of whitespace don't count.
+#[context("failed to compare renders of `{}`", input.display())]
+pub(crate) async fn run_renderers(
+    renderer: &Renderer,
+    theme: Theme,
+    input: &Path,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let markdown = fs::read_to_string(input).await?;
+    let api = renderer.render(&markdown, Priority::Background).await??;
+    let local = renderer.render_locally(&markdown).await;
+
+    let api_blocks = blocks(&api);
+    let (marked_up, differing) = mark_up(&api_blocks, &blocks(&local), structure);
+    log::info!(
+        "{} of GitHub's {} blocks are rendered differently offline",
+        differing.0,
+        api_blocks.len(),
+    );
+    let mut content = format!(
+        "<p>Blocks only GitHub renders this way are marked as removed ({}), and blocks only the \
+            offline renderer does are marked as added ({}).</p>\n",
+        differing.0, differing.1,
+    );
+    content.push_str(&marked_up);
+
+    let templater = Templater::new(
+        format!("GitHub API → offline: {}", input.display()).into_boxed_str(),
+        theme,
+    );
+    let page = templater.generate(&content, Liveness::Static, None).await?;
+    write(output, &page).await
+}
+
+async fn write(output: &Path, page: &str) -> anyhow::Result<()> {
+    if output.to_str() == Some("-") {
+        print!("{}", page);
+    } else {
+        fs::write(output, page)
+            .await
+            .context("could not write to output file")?;
+    }
+    Ok(())
+}
+
+/// Render two versions of a document and mark up the blocks that were removed and added.
+pub(crate) async fn render_diff(
+    renderer: &Renderer,
+    old: &str,
+    new: &str,
+) -> anyhow::Result<String> {
+    let old_html = renderer.render(old, Priority::Background).await??;
+    let new_html = renderer.render(new, Priority::Background).await??;
+
+    let (content, _) = mark_up(&blocks(&old_html), &blocks(&new_html), str::to_owned);
+    Ok(content)
+}
+
+/// Mark up the blocks that were removed and added between two lists of blocks, which are the same
+/// if their `key`s are. Also returns how many were removed and added.
+fn mark_up(
+    old: &[String],
+    new: &[String],
+    key: impl Fn(&str) -> String,
+) -> (String, (usize, usize)) {
+    let old_keys: Vec<String> = old.iter().map(|block| key(block)).collect();
+    let new_keys: Vec<String> = new.iter().map(|block| key(block)).collect();
+    let old_keys: Vec<&str> = old_keys.iter().map(String::as_str).collect();
+    let new_keys: Vec<&str> = new_keys.iter().map(String::as_str).collect();
+
+    let mut content = STYLE.to_owned();
+    let mut counts = (0, 0);
+    let (mut old, mut new) = (old.iter(), new.iter());
+    for block in diff::sequences(&old_keys, &new_keys) {
+        let (class, html) = match block {
+            Line::Same(_) => {
+                new.next();
+                (None, old.next().unwrap())
+            }
+            Line::Removed(_) => {
+                counts.0 += 1;
+                (Some("ghmd-removed"), old.next().unwrap())
+            }
+            Line::Added(_) => {
+                counts.1 += 1;
+                (Some("ghmd-added"), new.next().unwrap())
+            }
+        };
+        match class {
+            Some(class) => {
+                content.push_str("<div class=\"");
+                content.push_str(class);
+                content.push_str("\">");
+                content.push_str(html);
+                content.push_str("</div>");
+            }
+            None => content.push_str(html),
+        }
+        content.push('\n');
+    }
+    (content, counts)
+}
+
+/// Split rendered HTML into its top-level blocks. Text between the elements is escaped again, as
+/// the parser decoded it.
+fn blocks(html: &str) -> Vec<String> {
+    let fragment = Html::parse_fragment(html);
+    fragment
+        .root_element()
+        .children()
+        .filter_map(|node| match ElementRef::wrap(node) {
+            Some(element) => Some(element.html()),
+            None => node
+                .value()
+                .as_text()
+                .filter(|text| !text.trim().is_empty())
+                .map(|text| tera::escape_html(text)),
+        })
+        .collect()
+}
+
+/// A form of an HTML block that is the same for blocks that only differ in the order of their
+/// elements' attributes or the amount of whitespace in their text.
+fn structure(html: &str) -> String {
+    fn element(element: ElementRef<'_>, out: &mut String) {
+        let value = element.value();
+        out.push('<');
+        out.push_str(value.name());
+        let mut attrs: Vec<(&str, &str)> = value.attrs().collect();
+        attrs.sort_unstable();
+        for (name, value) in attrs {
+            out.push_str(&format!(" {}={:?}", name, value));
+        }
+        out.push('>');
+        children(element, out);
+        out.push_str("</");
+        out.push_str(value.name());
+        out.push('>');
+    }
+    fn children(parent: ElementRef<'_>, out: &mut String) {
+        for node in parent.children() {
+            if let Some(child) = ElementRef::wrap(node) {
+                element(child, out);
+            } else if let Some(text) = node.value().as_text() {
+                let mut words = text.split_whitespace();
+                if text.starts_with(char::is_whitespace) {
+                    out.push(' ');
+                }
+                if let Some(word) = words.next() {
+                    out.push_str(&tera::escape_html(word));
+                    for word in words {
+                        out.push(' ');
+                        out.push_str(&tera::escape_html(word));
+                    }
+                    if text.ends_with(char::is_whitespace) {
+                        out.push(' ');
+                    }
+                }
+            }
+        }
+    }
+
+    let fragment = Html::parse_fragment(html);
+    let mut out = String::new();
+    children(fragment.root_element(), &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blocks, mark_up, structure};
+
+    #[test]
+    fn top_level_text_is_escaped() {
+        assert_eq!(
+            blocks("<p>a &lt; b</p>\n1 &lt; 2 &amp; <b>x</b> &lt;script&gt;"),
+            [
+                "<p>a &lt; b</p>",
+                "\n1 &lt; 2 &amp; ",
+                "<b>x</b>",
+                " &lt;script&gt;"
+            ]
+        );
+    }
+
+    #[test]
+    fn structure_ignores_attribute_order_and_whitespace() {
+        assert_eq!(
+            structure("<p a=\"1\" b=\"2\">x  \n y <i>z</i></p>"),
+            structure("<p b=\"2\" a=\"1\">x y\n<i>z</i></p>"),
+        );
+        assert_ne!(structure("<p>x y</p>"), structure("<p>xy</p>"));
+        assert_ne!(structure("<p a=\"1\">x</p>"), structure("<p a=\"2\">x</p>"));
+        assert_ne!(structure("<p>x</p>"), structure("<div>x</div>"));
+    }
+
+    #[test]
+    fn mark_up_counts_changes() {
+        let blocks = |blocks: &[&str]| -> Vec<String> {
+            blocks.iter().map(|block| block.to_string()).collect()
+        };
+        let (content, counts) = mark_up(
+            &blocks(&["<p>a</p>", "<p>b</p>", "<p>c</p>"]),
+            &blocks(&["<p>a</p>", "<p>B</p>", "<p>c</p>", "<p>d</p>"]),
+            str::to_owned,
+        );
+        assert_eq!(counts, (1, 2));
+        assert!(content.contains("<div class=\"ghmd-removed\"><p>b</p></div>"));
+        assert!(content.contains("<div class=\"ghmd-added\"><p>B</p></div>"));
+        assert!(content.contains("<div class=\"ghmd-added\"><p>d</p></div>"));
+    }
+}
@@ -0,0 +1,449 @@
+use std::borrow::Cow;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context as _};
+use clap::ArgEnum as _;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use once_cell::sync::Lazy;
+use percent_encoding::{utf8_percent_encode, AsciiSet};
+use regex::{Captures, Regex};
+use tokio::fs;
+
+use crate::audit;
+use crate::normalize;
+use crate::outline;
+use crate::paths;
+use crate::templater::Theme;
+
+/// Settings a document chooses for itself with a `ghmd` key in its frontmatter, which take
+/// precedence over the command line. Either form of YAML mapping is accepted:
+///
+/// ```yaml
+/// ghmd: {theme: light, max-width: 900}
+/// ```
+///
+/// ```yaml
+/// ghmd:
+///   title: Design notes
+///   theme: light
+///   max-width: 900
+///   scroll-margin: 64
+///   toc: false
+///   ignore-start: "<!-- begin generated -->"
+///   ignore-end: "<!-- end generated -->"
+/// ```
+#[derive(Default, PartialEq)]
+pub(crate) struct DocumentOptions {
+    /// The title shown for the document instead of its file name.
+    pub(crate) title: Option<String>,
+    pub(crate) theme: Option<Theme>,
+    /// The maximum width of the page in pixels.
+    pub(crate) max_width: Option<u32>,
+    /// How far below the top of the window headings are scrolled to, in pixels.
+    pub(crate) scroll_margin: Option<u32>,
+    /// Whether the outline of the document's headings is shown.
+    pub(crate) toc: Option<bool>,
+}
+
+impl DocumentOptions {
+    /// Read the options from a document, logging a warning for each one that is invalid.
+    pub(crate) fn parse(markdown: &str) -> Self {
+        let mut options = Self::default();
+        let frontmatter = match normalize::frontmatter(markdown) {
+            Some(frontmatter) => frontmatter,
+            None => return options,
+        };
+        for (key, value) in entries(frontmatter) {
+            match key {
+                "title" => options.title = Some(value.to_owned()),
+                "theme" => match Theme::from_str(value, true) {
+                    Ok(theme) => options.theme = Some(theme),
+                    Err(_) => log::warn!("Unknown theme `{}` in frontmatter", value),
+                },
+                "max-width" => match value.trim_end_matches("px").parse() {
+                    Ok(max_width) => options.max_width = Some(max_width),
+                    Err(_) => log::warn!("Invalid max-width `{}` in frontmatter", value),
+                },
+                "scroll-margin" => match value.trim_end_matches("px").parse() {
+                    Ok(scroll_margin) => options.scroll_margin = Some(scroll_margin),
+                    Err(_) => log::warn!("Invalid scroll-margin `{}` in frontmatter", value),
+                },
+                "toc" => match value {
+                    "true" => options.toc = Some(true),
+                    "false" => options.toc = Some(false),
+                    _ => log::warn!("Invalid toc `{}` in frontmatter", value),
+                },
+                // These are read by the renderer, with `ignore_markers`.
+                "ignore-start" | "ignore-end" => {}
+                _ => log::warn!("Unknown option `{}` in frontmatter", key),
+            }
+        }
+        options
+    }
+}
+
+/// The pair of lines delimiting regions of a document that are removed before it is rendered,
+/// which a document sets with `ignore-start` and `ignore-end` in its frontmatter. These are in
+/// addition to ghmd's own and any given with `--ignore-region`.
+pub(crate) fn ignore_markers(markdown: &str) -> Option<(String, String)> {
+    let entries = entries(normalize::frontmatter(markdown)?);
+    let get = |name| {
+        entries
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+    };
+    Some((get("ignore-start")?, get("ignore-end")?))
+}
+
+/// The key-value pairs under the `ghmd` key of YAML frontmatter. Only the simple mappings that
+/// settings need are understood, not YAML in general.
+fn entries(frontmatter: &str) -> Vec<(&str, &str)> {
+    let mut lines = frontmatter.lines();
+    let rest = match lines.find_map(|line| line.strip_prefix("ghmd:")) {
+        Some(rest) => rest.trim(),
+        None => return Vec::new(),
+    };
+
+    let pairs: Vec<&str> = if let Some(flow) = rest.strip_prefix('{') {
+        flow.trim_end_matches('}').split(',').collect()
+    } else {
+        lines
+            .take_while(|line| line.starts_with([' ', '\t']) || line.trim().is_empty())
+            .collect()
+    };
+
+    pairs
+        .into_iter()
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once(':')?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(value);
+            Some((key.trim(), value))
+        })
+        .collect()
+}
+
+/// The name of the project configuration file, which is searched for in the input file's
+/// directory and each of its ancestors.
+const PROJECT_FILE: &str = ".ghmd.toml";
+
+/// Settings shared by every document in a project, read from a `.ghmd.toml` such as:
+///
+/// ```toml
+/// # Repository used to resolve autolinks like `#123` and `@user`.
+/// repository = "owner/repo"
+/// # Branch that links to files on GitHub point to, instead of the default branch.
+/// branch = "main"
+/// # A stylesheet added to every page, relative to this file.
+/// css = "docs/preview.css"
+/// # Paths left out of `ghmd review`, relative to this file.
+/// ignore = ["vendor/**", "CHANGELOG.md"]
+/// # Number headings like `1.2.`, as in a specification.
+/// number_headings = true
+/// # Lay out local images at their size before they load, with a placeholder in their place.
+/// image_placeholders = true
+///
+/// # Link rewrites, from a regular expression to its replacement.
+/// [links]
+/// "^https://example\\.com/docs/" = "./"
+/// ```
+#[derive(Default)]
+pub(crate) struct ProjectConfig {
+    /// The directory containing the configuration file.
+    pub(crate) dir: PathBuf,
+    pub(crate) repository: Option<String>,
+    pub(crate) branch: Option<String>,
+    pub(crate) css: Option<String>,
+    pub(crate) number_headings: bool,
+    pub(crate) image_placeholders: bool,
+    ignore: GlobSet,
+    links: Vec<(Regex, String)>,
+}
+
+impl ProjectConfig {
+    /// Find and load the configuration governing `start`, or an empty one if there is none.
+    pub(crate) async fn discover(start: &Path) -> anyhow::Result<Self> {
+        let start = fs::canonicalize(start)
+            .await
+            .map(paths::simplify)
+            .with_context(|| format!("failed to resolve `{}`", start.display()))?;
+        for dir in start.ancestors() {
+            let path = dir.join(PROJECT_FILE);
+            match fs::read_to_string(&path).await {
+                Ok(source) => {
+                    log::info!("Using configuration from {}", path.display());
+                    return Self::load(dir, &source)
+                        .await
+                        .with_context(|| format!("invalid configuration in `{}`", path.display()));
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) if e.kind() == io::ErrorKind::NotADirectory => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to read `{}`", path.display()))
+                }
+            }
+        }
+        Ok(Self::default())
+    }
+
+    async fn load(dir: &Path, source: &str) -> anyhow::Result<Self> {
+        let mut config = Self {
+            dir: dir.to_owned(),
+            ..Self::default()
+        };
+        let mut ignore = GlobSetBuilder::new();
+        for (table, key, value) in toml::parse(source)? {
+            match (&*table, &*key, value) {
+                ("", "repository", toml::Value::String(repository)) => {
+                    ensure!(
+                        repository.split('/').count() == 2,
+                        "`repository` must be of the form `owner/repo`"
+                    );
+                    config.repository = Some(repository);
+                }
+                ("", "branch", toml::Value::String(branch)) => {
+                    config.branch = Some(branch);
+                }
+                ("", "css", toml::Value::String(css)) => {
+                    let path = dir.join(css);
+                    config.css = Some(
+                        fs::read_to_string(&path)
+                            .await
+                            .with_context(|| format!("failed to read `{}`", path.display()))?,
+                    );
+                }
+                ("", "number_headings", toml::Value::Bool(number_headings)) => {
+                    config.number_headings = number_headings;
+                }
+                ("", "image_placeholders", toml::Value::Bool(image_placeholders)) => {
+                    config.image_placeholders = image_placeholders;
+                }
+                ("", "ignore", toml::Value::Array(patterns)) => {
+                    for pattern in patterns {
+                        ignore.add(Glob::new(&pattern)?);
+                    }
+                }
+                ("links", _, toml::Value::String(replacement)) => {
+                    let pattern = Regex::new(&key)?;
+                    config.links.push((pattern, replacement));
+                }
+                (table, key, _) => {
+                    let key = match table {
+                        "" => key.to_owned(),
+                        _ => format!("{}.{}", table, key),
+                    };
+                    bail!("unknown option or wrong type for `{}`", key);
+                }
+            }
+        }
+        config.ignore = ignore.build()?;
+        Ok(config)
+    }
+
+    /// Whether a path is excluded by the `ignore` option.
+    pub(crate) fn is_ignored(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.dir)
+            .is_ok_and(|relative| self.ignore.is_match(relative))
+    }
+
+    /// The URL of a file on GitHub, if the repository is configured and the file is in a Git
+    /// checkout.
+    pub(crate) fn github_url(&self, path: &Path) -> Option<String> {
+        let repository = self.repository.as_ref()?;
+        let relative = path.strip_prefix(checkout_root(path)?).ok()?;
+        // `HEAD` stands for the default branch.
+        let branch = self.branch.as_deref().unwrap_or("HEAD");
+        Some(format!(
+            "https://github.com/{}/blob/{}/{}",
+            repository,
+            branch,
+            url_path(relative)
+        ))
+    }
+
+    /// Number the headings of rendered HTML, if configured to.
+    pub(crate) fn number_headings<'a>(&self, html: &'a str) -> Cow<'a, str> {
+        match self.number_headings {
+            true => Cow::Owned(outline::number_headings(html)),
+            false => Cow::Borrowed(html),
+        }
+    }
+
+    /// Make the images of rendered HTML load lazily, giving local ones placeholders if configured
+    /// to. Images are resolved against `base_dir`, and `checkout` if the document is in one.
+    pub(crate) async fn defer_images(
+        &self,
+        html: &str,
+        base_dir: &Path,
+        checkout: Option<&Path>,
+    ) -> String {
+        let repository = match (&self.repository, checkout) {
+            (Some(name), Some(root)) => Some(audit::Repository {
+                name,
+                branch: self.branch.as_deref(),
+                root,
+            }),
+            _ => None,
+        };
+        audit::defer_images(html, base_dir, repository.as_ref(), self.image_placeholders).await
+    }
+
+    /// Apply the link rewrite rules to every `href` and `src` in rendered HTML.
+    pub(crate) fn rewrite_links<'a>(&self, html: &'a str) -> Cow<'a, str> {
+        static ATTRIBUTE: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r#"\b(href|src)="([^"]*)""#).unwrap());
+        if self.links.is_empty() {
+            return Cow::Borrowed(html);
+        }
+        ATTRIBUTE.replace_all(html, |captures: &Captures<'_>| {
+            let url = &captures[2];
+            let url = self
+                .links
+                .iter()
+                .find(|(pattern, _)| pattern.is_match(url))
+                .map_or(Cow::Borrowed(url), |(pattern, replacement)| {
+                    pattern.replace(url, &**replacement)
+                });
+            format!("{}=\"{}\"", &captures[1], url)
+        })
+    }
+}
+
+/// The root of the Git checkout containing a file, if it is in one.
+pub(crate) fn checkout_root(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .skip(1)
+        .find(|dir| dir.join(".git").exists())
+}
+
+/// A relative path escaped for use in a URL, with `/` between its components.
+pub(crate) fn url_path(path: &Path) -> String {
+    /// Characters that must be escaped in the path of a URL.
+    const URL_PATH: &AsciiSet = &crate::PATH.add(b'#').add(b'?');
+
+    path.iter()
+        .map(|component| utf8_percent_encode(&component.to_string_lossy(), URL_PATH).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Just enough of TOML for ghmd's configuration: tables, and strings, booleans and single-line
+/// arrays of strings as values.
+mod toml {
+    use anyhow::{bail, Context as _};
+
+    pub(crate) enum Value {
+        String(String),
+        Bool(bool),
+        Array(Vec<String>),
+    }
+
+    /// Parse a document into `(table, key, value)` triples, with `""` as the top-level table.
+    pub(crate) fn parse(source: &str) -> anyhow::Result<Vec<(String, String, Value)>> {
+        let mut entries = Vec::new();
+        let mut table = String::new();
+        for (i, line) in source.lines().enumerate() {
+            let res = (|| -> anyhow::Result<()> {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    return Ok(());
+                }
+                if let Some(header) = line.strip_prefix('[') {
+                    let (name, rest) = header.split_once(']').context("unclosed table header")?;
+                    end(rest)?;
+                    table = name.trim().to_owned();
+                    return Ok(());
+                }
+                let (key, rest) = key(line)?;
+                let rest = rest
+                    .trim_start()
+                    .strip_prefix('=')
+                    .context("expected `=` after key")?
+                    .trim_start();
+                let (value, rest) = value(rest)?;
+                end(rest)?;
+                entries.push((table.clone(), key, value));
+                Ok(())
+            })();
+            res.with_context(|| format!("on line {}", i + 1))?;
+        }
+        Ok(entries)
+    }
+
+    /// Make sure nothing but a comment follows a value.
+    fn end(rest: &str) -> anyhow::Result<()> {
+        let rest = rest.trim();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            bail!("unexpected `{}`", rest);
+        }
+        Ok(())
+    }
+
+    fn key(s: &str) -> anyhow::Result<(String, &str)> {
+        if s.starts_with(['"', '\'']) {
+            return string(s);
+        }
+        let len = s
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+            .unwrap_or(s.len());
+        if len == 0 {
+            bail!("expected a key");
+        }
+        Ok((s[..len].to_owned(), &s[len..]))
+    }
+
+    fn value(s: &str) -> anyhow::Result<(Value, &str)> {
+        if s.starts_with(['"', '\'']) {
+            let (string, rest) = string(s)?;
+            return Ok((Value::String(string), rest));
+        }
+        for (word, bool) in [("true", true), ("false", false)] {
+            if let Some(rest) = s.strip_prefix(word) {
+                return Ok((Value::Bool(bool), rest));
+            }
+        }
+        if let Some(mut rest) = s.strip_prefix('[') {
+            let mut items = Vec::new();
+            loop {
+                rest = rest.trim_start();
+                if let Some(after) = rest.strip_prefix(']') {
+                    return Ok((Value::Array(items), after));
+                }
+                let (item, after) = string(rest).context("arrays may only contain strings")?;
+                items.push(item);
+                rest = after.trim_start();
+                rest = rest.strip_prefix(',').unwrap_or(rest);
+            }
+        }
+        bail!("expected a string, a boolean or an array of strings")
+    }
+
+    /// Parse a basic (`"…"`) or literal (`'…'`) string.
+    fn string(s: &str) -> anyhow::Result<(String, &str)> {
+        let mut chars = s.char_indices();
+        let quote = match chars.next() {
+            Some((_, quote @ ('"' | '\''))) => quote,
+            _ => bail!("expected a string"),
+        };
+        let mut res = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                _ if c == quote => return Ok((res, &s[i + 1..])),
+                '\\' if quote == '"' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => res.push('\n'),
+                    Some('t') => res.push('\t'),
+                    Some(c @ ('"' | '\\')) => res.push(c),
+                    _ => bail!("unsupported escape sequence"),
+                },
+                _ => res.push(c),
+            }
+        }
+        bail!("unterminated string")
+    }
+}
@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use fn_error_context::context;
+use tokio::fs;
+
+use crate::diff::{self, Line};
+use crate::renderer;
+
+/// The largest document that can be saved through `POST /content`, in bytes.
+pub(crate) const MAX_BYTES: u64 = 5 * 1024 * 1024;
+
+/// The number of unchanged lines to show around each change in a conflict.
+const CONTEXT: usize = 2;
+
+/// An edit that couldn't be saved because the file changed on disk after the edit was started.
+pub(crate) struct Conflict {
+    /// Where the edit was saved instead.
+    pub(crate) path: PathBuf,
+    pub(crate) edit: String,
+}
+
+/// What happened to an edit sent to [`save`].
+pub(crate) enum Saved {
+    /// The edit was written to the file.
+    Written,
+    /// The file had changed, so it was left as it was and the edit was saved next to it.
+    Conflict(Conflict),
+}
+
+/// Save an edit to a document, given the hash of the version the edit was made to.
+///
+/// If the file no longer has that version, it was changed on disk in the meantime, and
+/// overwriting it would lose that change. Instead the edit is kept beside it, as
+/// `README.conflict.md` for `README.md`, so that the two can be merged by hand.
+#[context("failed to save `{}`", path.display())]
+pub(crate) async fn save(path: &Path, base: &str, edit: String) -> anyhow::Result<Saved> {
+    let current = match fs::read_to_string(path).await {
+        Ok(current) => Some(current),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let unchanged = current
+        .as_deref()
+        .is_none_or(|current| renderer::content_hash(current) == base || current == edit);
+    if unchanged {
+        fs::write(path, &edit).await?;
+        return Ok(Saved::Written);
+    }
+
+    let conflict_path = conflict_path(path);
+    fs::write(&conflict_path, &edit)
+        .await
+        .with_context(|| format!("could not write to `{}`", conflict_path.display()))?;
+    Ok(Saved::Conflict(Conflict {
+        path: conflict_path,
+        edit,
+    }))
+}
+
+/// Where the edit is kept when saving to `path` conflicts.
+fn conflict_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.conflict.{}", stem, extension.to_string_lossy()),
+        None => format!("{}.conflict", stem),
+    };
+    path.with_file_name(name)
+}
+
+/// A banner explaining a conflict, showing how the edit differs from what is now on disk.
+pub(crate) fn banner(conflict: &Conflict, current: &str) -> String {
+    let lines = diff::lines(current, &conflict.edit);
+    let changed: Vec<bool> = lines
+        .iter()
+        .map(|line| !matches!(line, Line::Same(_)))
+        .collect();
+    let mut diff = String::new();
+    let mut skipped = false;
+    for (i, line) in lines.iter().enumerate() {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(lines.len());
+        if !changed[start..end].contains(&true) {
+            skipped = true;
+            continue;
+        }
+        if skipped {
+            diff.push_str("<span class=\"color-text-tertiary\">…</span>\n");
+            skipped = false;
+        }
+        let (class, prefix, text) = match line {
+            Line::Same(text) => ("", ' ', text),
+            Line::Removed(text) => ("color-text-danger", '-', text),
+            Line::Added(text) => ("color-text-success", '+', text),
+        };
+        diff += &format!(
+            "<span class=\"{}\">{}{}</span>\n",
+            class,
+            prefix,
+            tera::escape_html(text),
+        );
+    }
+    format!(
+        "<div class=\"flash flash-error mb-3\">\
+            <strong>Conflicting edit</strong>\
+            <p>The file changed on disk while an edit to it was being saved, so the edit was kept in \
+            <code>{}</code> instead. Merge the two and save again to dismiss this.</p>\
+            <pre class=\"color-bg-primary p-2\">{}</pre>\
+        </div>",
+        tera::escape_html(&conflict.path.to_string_lossy()),
+        diff,
+    )
+}
@@ -0,0 +1,117 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use hyper::http;
+use hyper::http::header::{self, HeaderValue};
+
+use crate::router::{self, Request, Response};
+
+/// How long browsers may cache the result of a preflight request, in seconds.
+const MAX_AGE: &str = "600";
+
+/// Lets pages from other origins call the `/api/` routes, such as editor plugins running in a
+/// browser. Other routes are left alone.
+#[derive(Clone)]
+pub(crate) struct Cors {
+    /// The origins that are allowed, which may include `*` to allow any origin.
+    origins: Arc<[String]>,
+}
+
+impl Cors {
+    pub(crate) fn new(origins: Vec<String>) -> Self {
+        Self {
+            origins: origins.into(),
+        }
+    }
+
+    /// The value of `Access-Control-Allow-Origin` to send in response to `origin`, if it is
+    /// allowed.
+    fn allow(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        if self.origins.iter().any(|allowed| allowed == "*") {
+            Some(HeaderValue::from_static("*"))
+        } else {
+            let allowed = self
+                .origins
+                .iter()
+                .any(|allowed| allowed.as_bytes() == origin);
+            allowed.then(|| origin.clone())
+        }
+    }
+}
+
+impl<S> tower::Layer<S> for Cors {
+    type Service = CorsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CorsService {
+            cors: self.clone(),
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct CorsService<S> {
+    cors: Cors,
+    inner: S,
+}
+
+impl<S> tower::Service<Request> for CorsService<S>
+where
+    S: tower::Service<Request, Response = Response, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let origin = match req.headers().get(header::ORIGIN) {
+            Some(origin) if req.uri().path().starts_with("/api/") => origin.clone(),
+            _ => return Box::pin(self.inner.call(req)),
+        };
+        let allow_origin = self.cors.allow(&origin);
+
+        let preflight = req.method() == http::Method::OPTIONS
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if preflight {
+            let response = match allow_origin {
+                Some(allow_origin) => {
+                    let mut response = http::Response::builder()
+                        .status(http::StatusCode::NO_CONTENT)
+                        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)
+                        .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, POST")
+                        .header(header::ACCESS_CONTROL_MAX_AGE, MAX_AGE)
+                        .header(header::VARY, "Origin");
+                    if let Some(headers) = req.headers().get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+                    {
+                        response = response.header(header::ACCESS_CONTROL_ALLOW_HEADERS, headers);
+                    }
+                    response.body(router::full("")).unwrap()
+                }
+                None => router::text(http::StatusCode::FORBIDDEN, "origin not allowed"),
+            };
+            return Box::pin(async move { Ok(response) });
+        }
+
+        let response = self.inner.call(req);
+        Box::pin(async move {
+            let mut response = response.await?;
+            let headers = response.headers_mut();
+            if let Some(allow_origin) = allow_origin {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+            }
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::hash_map::RandomState;
+use std::ffi::OsStr;
+use std::fs;
+use std::hash::{BuildHasher as _, Hasher as _};
+use std::io::{BufRead as _, BufReader, Write as _};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context as _};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use tokio::io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::sync::Notify;
+
+use crate::dirs;
+use crate::paths;
+
+/// How long to wait for a background instance to answer on its control socket.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The longest request accepted on the control socket, in bytes.
+const MAX_CONTROL_REQUEST: u64 = 1024;
+
+/// Start without a console window.
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+/// Start in a new process group, so Ctrl+C in the console ghmd was started from doesn't reach it.
+#[cfg(windows)]
+const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
+/// The directory PID files and daemon logs are kept in.
+fn run_dir() -> anyhow::Result<PathBuf> {
+    Ok(dirs::cache_dir()?.join("run"))
+}
+
+/// The path of the PID file for an instance serving the given input, without its extension.
+fn instance_path(input: &Path) -> anyhow::Result<PathBuf> {
+    let input = fs::canonicalize(input)?;
+    let hash = Sha512::digest(input.to_string_lossy().as_bytes());
+    Ok(run_dir()?.join(&format!("{:x}", hash)[..16]))
+}
+
+/// Re-run the current command in the background, without `--daemon`. If `startup` is set, it is
+/// also registered to be run whenever the user logs in.
+#[context("failed to start daemon")]
+pub(crate) fn spawn(input: &Path, startup: bool) -> anyhow::Result<()> {
+    let instance = instance_path(input)?;
+    let pid_file = instance.with_extension("pid");
+    if let Some(record) = read_pid_file(&pid_file)?.filter(Record::is_running) {
+        bail!("ghmd is already running for this file (PID {})", record.pid);
+    }
+    if startup {
+        register_startup(&instance)?;
+    }
+
+    fs::create_dir_all(run_dir()?)?;
+    let log_path = instance.with_extension("log");
+    let log = fs::File::create(&log_path)?;
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(
+            std::env::args_os()
+                .skip(1)
+                .filter(|arg| arg != "--daemon" && arg != "--startup"),
+        )
+        .arg("--pid-file")
+        .arg(&pid_file)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt as _;
+        // Detach from the terminal's process group so that Ctrl+C there doesn't reach us.
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt as _;
+        command.creation_flags(CREATE_NO_WINDOW | CREATE_NEW_PROCESS_GROUP);
+    }
+    let child = command.spawn()?;
+
+    println!(
+        "Started ghmd in the background (PID {}), logging to {}",
+        child.id(),
+        log_path.display()
+    );
+    Ok(())
+}
+
+/// A PID file that is removed when dropped.
+pub(crate) struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the PID file, along with the address of a control socket that `ghmd status` and
+    /// `ghmd stop` can reach the instance through.
+    #[context("failed to write PID file `{}`", path.display())]
+    pub(crate) fn create(path: &Path, input: &Path) -> anyhow::Result<(Self, Control)> {
+        let input = paths::simplify(fs::canonicalize(input)?);
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        // Only the user who started the instance can read the PID file, so requiring its key
+        // keeps other users from stopping it.
+        let key = format!(
+            "{:016x}{:016x}",
+            RandomState::new().build_hasher().finish(),
+            RandomState::new().build_hasher().finish(),
+        );
+        // A PID file left by an instance that didn't exit cleanly is replaced, rather than
+        // written through, so that its permissions and any symlink there don't carry over.
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt as _;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        write!(
+            file,
+            "{}\n{}\n{}\n{}\n",
+            std::process::id(),
+            input.display(),
+            listener.local_addr()?,
+            key,
+        )?;
+        let pid_file = Self {
+            path: path.to_owned(),
+        };
+        Ok((pid_file, Control { listener, key }))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// What a background instance reports about itself through its control socket.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Status {
+    pub(crate) documents: Vec<String>,
+    pub(crate) url: String,
+    /// When the instance started, in seconds since the Unix epoch.
+    pub(crate) started: u64,
+}
+
+/// The control socket of a background instance, which accepts one request per connection: its
+/// key followed by `status` or `stop` on a single line.
+pub(crate) struct Control {
+    listener: std::net::TcpListener,
+    key: String,
+}
+
+impl Control {
+    /// Answer requests in the background. The returned `Notify` is notified once a stop is
+    /// requested.
+    pub(crate) fn spawn(self, status: Status) -> anyhow::Result<Arc<Notify>> {
+        self.listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(self.listener)?;
+        let stop = Arc::new(Notify::new());
+        let status = Arc::new(serde_json::to_string(&status)?);
+        let key = Arc::<str>::from(self.key);
+        tokio::spawn({
+            let stop = stop.clone();
+            async move {
+                loop {
+                    let (stream, _) = match listener.accept().await {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            log::error!("failed to accept control connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let (stop, status, key) = (stop.clone(), status.clone(), key.clone());
+                    tokio::spawn(async move {
+                        let res = tokio::time::timeout(
+                            CONTROL_TIMEOUT,
+                            answer(stream, &key, &status, &stop),
+                        )
+                        .await;
+                        if let Ok(Err(e)) = res {
+                            log::debug!("failed to answer control request: {}", e);
+                        }
+                    });
+                }
+            }
+        });
+        Ok(stop)
+    }
+}
+
+async fn answer(
+    mut stream: tokio::net::TcpStream,
+    key: &str,
+    status: &str,
+    stop: &Notify,
+) -> std::io::Result<()> {
+    let (read, mut write) = stream.split();
+    let mut line = String::new();
+    tokio::io::BufReader::new(read)
+        .take(MAX_CONTROL_REQUEST)
+        .read_line(&mut line)
+        .await?;
+    let response = match line.trim_end().split_once(' ') {
+        Some((given, command)) if given == key => match command {
+            "status" => status,
+            "stop" => {
+                log::info!("Stopping, as requested through the control socket");
+                stop.notify_one();
+                "ok"
+            }
+            _ => "unknown command",
+        },
+        _ => "wrong key",
+    };
+    write.write_all(format!("{}\n", response).as_bytes()).await
+}
+
+/// What a PID file records about a background instance.
+struct Record {
+    pid: u32,
+    input: String,
+    /// The address and key of its control socket, which versions of ghmd before it was added
+    /// don't record.
+    control: Option<(SocketAddr, String)>,
+}
+
+impl Record {
+    /// Send a request to the instance's control socket, returning its response.
+    fn request(&self, command: &str) -> anyhow::Result<String> {
+        let (address, key) = self
+            .control
+            .as_ref()
+            .context("this instance has no control socket")?;
+        let mut stream = TcpStream::connect_timeout(address, CONTROL_TIMEOUT)?;
+        stream.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+        stream.write_all(format!("{} {}\n", key, command).as_bytes())?;
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response)?;
+        match response.trim_end() {
+            "wrong key" => bail!("the instance rejected its key"),
+            "unknown command" => bail!("the instance doesn't understand `{}`", command),
+            response => Ok(response.to_owned()),
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        match &self.control {
+            Some((address, _)) => TcpStream::connect_timeout(address, CONTROL_TIMEOUT).is_ok(),
+            None => is_running(self.pid),
+        }
+    }
+
+    fn stop(&self) -> anyhow::Result<()> {
+        match &self.control {
+            Some(_) => {
+                self.request("stop")?;
+                Ok(())
+            }
+            None => interrupt(self.pid),
+        }
+    }
+}
+
+fn read_pid_file(path: &Path) -> anyhow::Result<Option<Record>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let malformed = || format!("PID file `{}` is malformed", path.display());
+    let mut lines = contents.lines();
+    let pid = lines.next().unwrap_or("").parse().with_context(malformed)?;
+    let input = lines.next().unwrap_or("").to_owned();
+    let control = match (lines.next(), lines.next()) {
+        (Some(address), Some(key)) => {
+            Some((address.parse().with_context(malformed)?, key.to_owned()))
+        }
+        _ => None,
+    };
+    Ok(Some(Record {
+        pid,
+        input,
+        control,
+    }))
+}
+
+/// The PID files of the background instance serving `input`, or of every background instance if
+/// it is `None`.
+fn pid_files(input: Option<&Path>) -> anyhow::Result<Vec<PathBuf>> {
+    Ok(match input {
+        Some(input) => vec![instance_path(input)?.with_extension("pid")],
+        None => match fs::read_dir(run_dir()?) {
+            Ok(dir) => dir
+                .map(|entry| Ok(entry?.path()))
+                .filter(|path| {
+                    path.as_ref()
+                        .map_or(true, |path| path.extension() == Some(OsStr::new("pid")))
+                })
+                .collect::<anyhow::Result<_>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        },
+    })
+}
+
+/// The background instances that are still running, forgetting those that aren't.
+fn running(input: Option<&Path>) -> anyhow::Result<Vec<(PathBuf, Record)>> {
+    let mut running = Vec::new();
+    for pid_file in pid_files(input)? {
+        let record = match read_pid_file(&pid_file)? {
+            Some(record) => record,
+            None => continue,
+        };
+        if !record.is_running() {
+            let _ = fs::remove_file(&pid_file);
+            continue;
+        }
+        running.push((pid_file, record));
+    }
+    Ok(running)
+}
+
+/// Print what the background instance serving `input`, or every background instance if it is
+/// `None`, is serving and for how long.
+#[context("failed to get the status of ghmd")]
+pub(crate) fn status(input: Option<&Path>) -> anyhow::Result<()> {
+    let running = running(input)?;
+    if running.is_empty() {
+        bail!("no running instance found");
+    }
+    for (pid_file, record) in running {
+        let startup = startup_path(&pid_file.with_extension("")).filter(|path| path.exists());
+        let startup = if startup.is_some() {
+            ", starts at login"
+        } else {
+            ""
+        };
+        let status = match record.request("status") {
+            Ok(response) => serde_json::from_str::<Status>(&response)
+                .context("the instance sent an invalid status")?,
+            Err(e) => {
+                println!("PID {}: {}{}", record.pid, record.input, startup);
+                println!("    status unavailable: {:#}", e);
+                continue;
+            }
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        let uptime = Duration::from_secs(now.saturating_sub(status.started));
+        println!(
+            "PID {}: {}{}",
+            record.pid,
+            status.documents.join(", "),
+            startup
+        );
+        println!(
+            "    {}, up for {}",
+            status.url,
+            humantime::format_duration(uptime)
+        );
+    }
+    Ok(())
+}
+
+/// Stop the background instance serving `input`, or every background instance if it is `None`.
+/// If `remove_startup` is set, they are also kept from starting at login.
+#[context("failed to stop ghmd")]
+pub(crate) fn stop(input: Option<&Path>, remove_startup: bool) -> anyhow::Result<()> {
+    let mut stopped = 0;
+    for (_, record) in running(input)? {
+        record.stop()?;
+        println!("Stopped ghmd for {} (PID {})", record.input, record.pid);
+        stopped += 1;
+    }
+    if remove_startup {
+        stopped += unregister_startup(input)?;
+    }
+
+    if stopped == 0 {
+        bail!("no running instance found");
+    }
+    Ok(())
+}
+
+/// The folder of scripts Windows runs when the user logs in.
+#[cfg(windows)]
+fn startup_dir() -> Option<PathBuf> {
+    let app_data = PathBuf::from(std::env::var_os("APPDATA")?);
+    Some(app_data.join(r"Microsoft\Windows\Start Menu\Programs\Startup"))
+}
+
+#[cfg(not(windows))]
+fn startup_dir() -> Option<PathBuf> {
+    None
+}
+
+/// The script that starts an instance at login.
+fn startup_path(instance: &Path) -> Option<PathBuf> {
+    let name = instance.file_name()?.to_string_lossy();
+    Some(startup_dir()?.join(format!("ghmd-{}.cmd", name)))
+}
+
+/// Add a script to the startup folder that runs the current command again, in the current
+/// directory so that relative paths still work.
+fn register_startup(instance: &Path) -> anyhow::Result<()> {
+    let path = startup_path(instance).context(
+        "`--startup` is only supported on Windows; \
+            elsewhere, use a service manager such as a systemd user unit",
+    )?;
+    let mut command = quote(std::env::current_exe()?.as_os_str());
+    for arg in std::env::args_os().skip(1).filter(|arg| arg != "--startup") {
+        command.push(' ');
+        command.push_str(&quote(&arg));
+    }
+    let script = format!(
+        "@echo off\r\n\
+            rem Started by `ghmd --daemon --startup`; remove with `ghmd stop --remove-startup`.\r\n\
+            cd /d {}\r\n\
+            {}\r\n",
+        quote(std::env::current_dir()?.as_os_str()),
+        command,
+    );
+    fs::write(&path, script).with_context(|| format!("failed to write `{}`", path.display()))?;
+    println!("ghmd will start at login, using {}", path.display());
+    Ok(())
+}
+
+/// Remove the startup scripts of the instance serving `input`, or of every instance if it is
+/// `None`, returning how many there were.
+fn unregister_startup(input: Option<&Path>) -> anyhow::Result<usize> {
+    let dir = match startup_dir() {
+        Some(dir) => dir,
+        None => return Ok(0),
+    };
+    let scripts = match input {
+        Some(input) => startup_path(&instance_path(input)?).into_iter().collect(),
+        None => match fs::read_dir(&dir) {
+            Ok(dir) => dir
+                .map(|entry| Ok(entry?.path()))
+                .filter(|path| {
+                    path.as_ref().map_or(true, |path| {
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        name.starts_with("ghmd-") && name.ends_with(".cmd")
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        },
+    };
+    let mut removed = 0;
+    for script in scripts {
+        match fs::remove_file(&script) {
+            Ok(()) => {
+                println!("Removed {} from startup", script.display());
+                removed += 1;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to remove `{}`", script.display()))
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Quote an argument for a batch file, following the rules programs use to split their command
+/// line and doubling `%` so that it isn't taken as a variable.
+fn quote(arg: &OsStr) -> String {
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.to_string_lossy().chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+            }
+        }
+        match c {
+            '\\' => {}
+            '%' => quoted.push_str("%%"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // SAFETY: signal 0 performs error checking only.
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+#[cfg(unix)]
+fn interrupt(pid: u32) -> anyhow::Result<()> {
+    // SAFETY: sending a signal has no memory safety implications.
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGINT) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("failed to signal PID {}", pid));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(not(unix))]
+fn interrupt(pid: u32) -> anyhow::Result<()> {
+    bail!("stopping PID {} is not supported on this platform", pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PidFile;
+
+    #[cfg(unix)]
+    #[test]
+    fn pid_file_is_private() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("README.md");
+        std::fs::write(&input, "").unwrap();
+        let path = dir.path().join("instance.pid");
+        // A stale PID file readable by everyone is replaced.
+        std::fs::write(&path, "stale").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let (_pid_file, control) = PidFile::create(&path, &input).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains(&control.key));
+    }
+}
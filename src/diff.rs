@@ -0,0 +1,245 @@
+use std::ops::{Index, IndexMut};
+
+/// A line in the difference between two texts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Compute a line-based diff of two texts using the longest common subsequence.
+pub(crate) fn lines<'a>(old: &'a str, new: &'a str) -> Vec<Line<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    sequences(&old, &new)
+}
+
+/// Compute the diff of two sequences of arbitrary chunks of text, such as lines or HTML blocks.
+///
+/// This uses the linear space variant of Myers' algorithm, so it takes time proportional to the
+/// length of the sequences times the number of differences, and memory proportional to their
+/// length only. Large documents with small changes, the usual case, are therefore cheap to diff.
+pub(crate) fn sequences<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    let mut res = Vec::with_capacity(old.len().max(new.len()));
+    let max_d = (old.len() + new.len()).div_ceil(2) + 1;
+    let mut forward = V::new(max_d);
+    let mut backward = V::new(max_d);
+    conquer(old, new, &mut forward, &mut backward, &mut res);
+    res
+}
+
+/// Diff `old` and `new` onto the end of `res`, by splitting them at the middle of a shortest edit
+/// script and diffing each half.
+fn conquer<'a>(
+    old: &[&'a str],
+    new: &[&'a str],
+    forward: &mut V,
+    backward: &mut V,
+    res: &mut Vec<Line<'a>>,
+) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    res.extend(old[..prefix].iter().map(|line| Line::Same(line)));
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[..old.len() - suffix];
+    let new_mid = &new[..new.len() - suffix];
+
+    if old_mid.is_empty() {
+        res.extend(new_mid.iter().map(|line| Line::Added(line)));
+    } else if new_mid.is_empty() {
+        res.extend(old_mid.iter().map(|line| Line::Removed(line)));
+    } else {
+        let (x, y) = middle_snake(old_mid, new_mid, forward, backward);
+        conquer(&old_mid[..x], &new_mid[..y], forward, backward, res);
+        conquer(&old_mid[x..], &new_mid[y..], forward, backward, res);
+    }
+
+    res.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| Line::Same(line)),
+    );
+}
+
+/// Find a point that a shortest edit script turning `old` into `new` passes through roughly half
+/// way, by searching from both ends at once until the searches meet. Neither sequence may be
+/// empty, and they must differ at both ends.
+///
+/// `forward[k]` is the furthest `x` reached from the start on diagonal `k = x - y`, and
+/// `backward[k]` the furthest reached from the end, counting back from it.
+fn middle_snake(old: &[&str], new: &[&str], forward: &mut V, backward: &mut V) -> (usize, usize) {
+    let n = old.len();
+    let m = new.len();
+    let delta = n as isize - m as isize;
+    let odd = delta & 1 == 1;
+    forward[1] = 0;
+    backward[1] = 0;
+
+    let max_d = ((n + m).div_ceil(2) + 1) as isize;
+    for d in 0..max_d {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && forward[k - 1] < forward[k + 1]) {
+                forward[k + 1]
+            } else {
+                forward[k - 1] + 1
+            };
+            let start = (x, (x as isize - k) as usize);
+            let mut y = start.1;
+            while x < n && y < m && old[x] == new[y] {
+                x += 1;
+                y += 1;
+            }
+            forward[k] = x;
+            if odd && (k - delta).abs() < d && forward[k] + backward[delta - k] >= n {
+                return start;
+            }
+        }
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && backward[k - 1] < backward[k + 1]) {
+                backward[k + 1]
+            } else {
+                backward[k - 1] + 1
+            };
+            let mut y = (x as isize - k) as usize;
+            while x < n && y < m && old[n - x - 1] == new[m - y - 1] {
+                x += 1;
+                y += 1;
+            }
+            backward[k] = x;
+            if !odd && (k - delta).abs() <= d && backward[k] + forward[delta - k] >= n {
+                return (n - x, m - y);
+            }
+        }
+    }
+    unreachable!("the searches always meet")
+}
+
+/// The furthest points reached on each diagonal, indexed by diagonals from `-max_d` to `max_d`.
+struct V {
+    offset: isize,
+    v: Vec<usize>,
+}
+
+impl V {
+    fn new(max_d: usize) -> Self {
+        Self {
+            offset: max_d as isize + 1,
+            v: vec![0; 2 * max_d + 3],
+        }
+    }
+}
+
+impl Index<isize> for V {
+    type Output = usize;
+    fn index(&self, k: isize) -> &usize {
+        &self.v[(k + self.offset) as usize]
+    }
+}
+
+impl IndexMut<isize> for V {
+    fn index_mut(&mut self, k: isize) -> &mut usize {
+        &mut self.v[(k + self.offset) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sequences, Line};
+
+    /// The length of the longest common subsequence, by the textbook quadratic method.
+    fn lcs_len(old: &[&str], new: &[&str]) -> usize {
+        let mut table = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                table[i][j] = if old[i] == new[j] {
+                    table[i + 1][j + 1] + 1
+                } else {
+                    table[i + 1][j].max(table[i][j + 1])
+                };
+            }
+        }
+        table[0][0]
+    }
+
+    /// Check that the diff of `old` and `new` turns one into the other with as few changes as
+    /// possible.
+    fn check(old: &[&str], new: &[&str]) {
+        let diff = sequences(old, new);
+        let before: Vec<&str> = diff
+            .iter()
+            .filter_map(|line| match *line {
+                Line::Same(line) | Line::Removed(line) => Some(line),
+                Line::Added(_) => None,
+            })
+            .collect();
+        let after: Vec<&str> = diff
+            .iter()
+            .filter_map(|line| match *line {
+                Line::Same(line) | Line::Added(line) => Some(line),
+                Line::Removed(_) => None,
+            })
+            .collect();
+        assert_eq!(before, old);
+        assert_eq!(after, new);
+        let same = diff
+            .iter()
+            .filter(|line| matches!(line, Line::Same(_)))
+            .count();
+        assert_eq!(same, lcs_len(old, new), "{:?} → {:?}", old, new);
+    }
+
+    #[test]
+    fn small() {
+        check(&[], &[]);
+        check(&["a"], &[]);
+        check(&[], &["a"]);
+        check(&["a", "b", "c"], &["a", "b", "c"]);
+        check(&["a", "b", "c"], &["a", "c"]);
+        check(&["a", "c"], &["a", "b", "c"]);
+        check(&["a", "b", "c"], &["d", "e", "f"]);
+        check(
+            &["a", "b", "c", "a", "b", "b", "a"],
+            &["c", "b", "a", "b", "a", "c"],
+        );
+    }
+
+    #[test]
+    fn random() {
+        // A small linear congruential generator, so that failures can be reproduced.
+        let mut state = 1_u64;
+        let mut next = |bound: u64| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 33) % bound
+        };
+        let alphabet = ["a", "b", "c", "d"];
+        for _ in 0..500 {
+            let old: Vec<&str> = (0..next(12)).map(|_| alphabet[next(4) as usize]).collect();
+            let new: Vec<&str> = (0..next(12)).map(|_| alphabet[next(4) as usize]).collect();
+            check(&old, &new);
+        }
+    }
+
+    #[test]
+    fn large() {
+        let lines: Vec<String> = (0..20_000).map(|i| format!("<p>{}</p>", i)).collect();
+        let old: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut new = old.clone();
+        new[5_000] = "changed";
+        new.insert(15_000, "added");
+        new.remove(100);
+        let diff = sequences(&old, &new);
+        let changes = diff
+            .iter()
+            .filter(|line| !matches!(line, Line::Same(_)))
+            .count();
+        assert_eq!(changes, 4);
+    }
+}
@@ -0,0 +1,350 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context as _;
+use async_stream::stream;
+use hyper::http;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use tokio::sync::watch;
+use tokio::{fs, task, time};
+
+use crate::config::{self, DocumentOptions, ProjectConfig};
+use crate::files;
+use crate::queue::Priority;
+use crate::redact;
+use crate::renderer::{self, RenderError, Renderer};
+use crate::review;
+use crate::router::{self, Request, Response, Router};
+use crate::templater::{Liveness, Templater};
+use crate::Event;
+
+/// How often to look for changes to the markdown files.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Characters that must be escaped in a path in a URL, which may keep its `/`s.
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?');
+
+/// Serve an index of every markdown file in `root` and its subdirectories, rendering each one
+/// when it is opened. Pages reload whenever a markdown file is added, removed or changed.
+pub(crate) async fn run(
+    renderer: Renderer,
+    project: ProjectConfig,
+    templater: Templater,
+    root: &Path,
+    bind: &[IpAddr],
+    port: u16,
+) -> anyhow::Result<()> {
+    let root = fs::canonicalize(root).await?;
+    let project = Arc::new(project);
+    let (sender, listing) = watch::channel(Listing::read(&root, &project).await?);
+    tokio::spawn({
+        let root = root.clone();
+        let project = project.clone();
+        async move {
+            loop {
+                time::sleep(POLL_INTERVAL).await;
+                let new_listing = match Listing::read(&root, &project).await {
+                    Ok(listing) => listing,
+                    Err(e) => {
+                        log::error!("{:?}", e);
+                        continue;
+                    }
+                };
+                if *sender.borrow() != new_listing && sender.send(new_listing).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    log::info!(
+        "Serving {} markdown files from {}",
+        listing.borrow().files.len(),
+        root.display()
+    );
+
+    let directory = Arc::new(Directory {
+        renderer,
+        project,
+        templater,
+        root,
+        listing,
+        prewarming: AtomicBool::new(true),
+        prewarmed: AtomicUsize::new(0),
+    });
+    tokio::spawn({
+        let directory = directory.clone();
+        async move { directory.prewarm().await }
+    });
+    let listeners = crate::bind(bind, port).await?;
+    let router = Router::new(|directory: Arc<Directory>, req: Request| async move {
+        directory.handle_request(req).await
+    })
+    .events(|directory: Arc<Directory>| async move { directory.event_stream() });
+    crate::serve(listeners, router.into_service(directory), None).await
+}
+
+/// The markdown files in the directory, relative to it and with `/` between components, along
+/// with their modification times so that edits are noticed.
+#[derive(PartialEq)]
+struct Listing {
+    files: Vec<(String, Option<SystemTime>)>,
+}
+
+impl Listing {
+    async fn read(root: &Path, project: &ProjectConfig) -> anyhow::Result<Self> {
+        let mut files = task::spawn_blocking({
+            let root = root.to_owned();
+            move || {
+                let mut files = Vec::new();
+                walk(&root, &root, &mut files)?;
+                anyhow::Ok(files)
+            }
+        })
+        .await
+        .unwrap()?;
+        files.retain(|(path, _)| !project.is_ignored(&root.join(path)));
+        files.sort();
+        Ok(Self { files })
+    }
+}
+
+/// Add every markdown file in `dir` to `files`, skipping hidden files and directories.
+fn walk(
+    root: &Path,
+    dir: &Path,
+    files: &mut Vec<(String, Option<SystemTime>)>,
+) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            walk(root, &path, files)?;
+        } else if review::is_markdown(&name.to_string_lossy()) {
+            let relative = path.strip_prefix(root).unwrap();
+            let relative: Vec<_> = relative
+                .iter()
+                .map(|component| component.to_string_lossy())
+                .collect();
+            files.push((relative.join("/"), metadata.modified().ok()));
+        }
+    }
+    Ok(())
+}
+
+struct Directory {
+    renderer: Renderer,
+    project: Arc<ProjectConfig>,
+    templater: Templater,
+    root: PathBuf,
+    listing: watch::Receiver<Listing>,
+    /// Whether files are still being rendered in the background, and how many have been so far.
+    prewarming: AtomicBool,
+    prewarmed: AtomicUsize,
+}
+
+impl Directory {
+    async fn handle_request(&self, req: Request) -> Response {
+        let path = percent_decode_str(req.uri().path()).decode_utf8_lossy();
+        let res = match path.strip_prefix('/') {
+            Some("") => self.index().await.map(Some),
+            Some(file) if self.listing.borrow().files.iter().any(|(p, _)| p == file) => {
+                self.file(file).await.map(Some)
+            }
+            _ => {
+                if let Some(path) = files::find(&self.root, req.uri().path()).await {
+                    return files::serve(&path, req.headers()).await;
+                }
+                Ok(None)
+            }
+        };
+
+        match res {
+            Ok(Some(page)) => http::Response::builder()
+                .status(http::StatusCode::OK)
+                .header("Content-Type", "text/html")
+                .body(router::full(page))
+                .unwrap(),
+            Ok(None) => http::Response::builder()
+                .status(http::StatusCode::NOT_FOUND)
+                .header("Content-Type", "text/plain")
+                .body(router::full("Not Found"))
+                .unwrap(),
+            Err(e) => http::Response::builder()
+                .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+                .header("Content-Type", "text/plain")
+                .body(router::full(format!(
+                    "\
+                        Internal Server Error\n\
+                        =====================\n\
+                        \
+                        {}\
+                    ",
+                    redact::redact(&format!("{:?}", e)),
+                )))
+                .unwrap(),
+        }
+    }
+
+    /// Render the files in the background so that they open straight away. This stops early if
+    /// the API quota runs low, leaving what is left of it for the files that are actually opened,
+    /// and once the cache is full.
+    async fn prewarm(&self) {
+        let files: Vec<String> = self
+            .listing
+            .borrow()
+            .files
+            .iter()
+            .map(|(path, _)| path.clone())
+            .take(renderer::CACHE_SIZE)
+            .collect();
+        for path in &files {
+            if self.renderer.quota_is_low() {
+                log::info!("Stopped pre-rendering documents to save the API quota");
+                break;
+            }
+            let markdown = match fs::read_to_string(self.root.join(path)).await {
+                Ok(markdown) => markdown,
+                Err(_) => continue,
+            };
+            match self.renderer.render(&markdown, Priority::Background).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(RenderError::RateLimited(_))) => {
+                    log::info!("Stopped pre-rendering documents because of the rate limit");
+                    break;
+                }
+                Ok(Err(e)) => log::debug!("failed to pre-render `{}`: {}", path, e),
+                Err(e) => log::debug!("failed to pre-render `{}`: {:?}", path, e),
+            }
+            let done = self.prewarmed.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+            log::debug!("Pre-rendered {} of {} documents", done, files.len());
+        }
+        self.prewarming.store(false, atomic::Ordering::Relaxed);
+        let done = self.prewarmed.load(atomic::Ordering::Relaxed);
+        if done > 0 {
+            log::info!("Pre-rendered {} of {} documents", done, files.len());
+        }
+    }
+
+    /// A page listing the files as a tree of nested lists, one per directory.
+    async fn index(&self) -> anyhow::Result<String> {
+        let name = self.root.file_name().map_or_else(
+            || self.root.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let mut content = format!("<h1>{}</h1>\n", tera::escape_html(&name));
+        if self.prewarming.load(atomic::Ordering::Relaxed) {
+            let total = self.listing.borrow().files.len().min(renderer::CACHE_SIZE);
+            content.push_str(&format!(
+                "<p><small>Pre-rendered {} of {} documents…</small></p>\n",
+                self.prewarmed.load(atomic::Ordering::Relaxed),
+                total,
+            ));
+        }
+        let files: Vec<String> = self
+            .listing
+            .borrow()
+            .files
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect();
+        if files.is_empty() {
+            content.push_str("<p>There are no markdown files in this directory.</p>\n");
+        } else {
+            content.push_str("<ul>\n");
+        }
+        // The directories whose lists are open, which the previous file was in.
+        let mut open: Vec<&str> = Vec::new();
+        for path in &files {
+            let mut dirs: Vec<&str> = path.split('/').collect();
+            let file_name = dirs.pop().unwrap();
+            let common = open.iter().zip(&dirs).take_while(|(a, b)| a == b).count();
+            for _ in common..open.len() {
+                content.push_str("</ul></li>\n");
+            }
+            open.truncate(common);
+            for &dir in &dirs[common..] {
+                content.push_str(&format!(
+                    "<li><strong>{}/</strong><ul>\n",
+                    tera::escape_html(dir)
+                ));
+                open.push(dir);
+            }
+            content.push_str(&format!(
+                "<li><a href=\"/{}\">{}</a></li>\n",
+                utf8_percent_encode(path, PATH),
+                tera::escape_html(file_name),
+            ));
+        }
+        for _ in &open {
+            content.push_str("</ul></li>\n");
+        }
+        if !files.is_empty() {
+            content.push_str("</ul>\n");
+        }
+
+        self.templater
+            .with_title(name.into())
+            .generate(&content, Liveness::Live, None)
+            .await
+    }
+
+    /// Render one of the files.
+    async fn file(&self, path: &str) -> anyhow::Result<String> {
+        let full_path = self.root.join(path);
+        let markdown = fs::read_to_string(&full_path)
+            .await
+            .with_context(|| format!("failed to read `{}`", path))?;
+        let rendered = self
+            .renderer
+            .render(&markdown, Priority::Interactive)
+            .await??;
+        let rendered = self.project.rewrite_links(&rendered);
+        let rendered = self.project.number_headings(&rendered);
+        let base_dir = full_path.parent().unwrap();
+        let rendered = self
+            .project
+            .defer_images(&rendered, base_dir, config::checkout_root(&full_path))
+            .await;
+        let provenance = self.renderer.provenance(&markdown).await;
+
+        let mut content = String::from("<p><a href=\"/\">← All documents</a></p>\n");
+        content.push_str(&rendered);
+        self.templater
+            .with_title(path.into())
+            .for_document(&DocumentOptions::parse(&markdown))
+            .generate(&content, Liveness::Live, provenance.as_ref())
+            .await
+    }
+
+    fn event_stream(&self) -> Response {
+        let mut listing = self.listing.clone();
+        let stream = router::stream(stream! {
+            let mut seq = 0;
+            while listing.changed().await.is_ok() {
+                yield Event::page("reload").into_sse(seq);
+                seq += 1;
+            }
+        });
+
+        http::Response::builder()
+            .status(http::StatusCode::OK)
+            .header("Content-Type", "text/event-stream")
+            .body(stream)
+            .unwrap()
+    }
+}
@@ -0,0 +1,13 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+
+/// The directory ghmd stores persistent caches and state in.
+pub(crate) fn cache_dir() -> anyhow::Result<PathBuf> {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .context("could not determine cache directory")?;
+    Ok(cache.join("ghmd"))
+}
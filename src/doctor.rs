@@ -0,0 +1,284 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use tokio::fs;
+use tokio::net::TcpListener;
+
+use crate::dirs;
+use crate::vendor;
+
+/// Fewer inotify watches than this are likely to run out when previewing files in a large
+/// repository, since directories are watched recursively.
+const MIN_INOTIFY_WATCHES: u64 = 8192;
+
+/// What `ghmd doctor` checks with.
+pub(crate) struct Options<'a> {
+    pub(crate) client: &'a reqwest::Client,
+    /// The client octicons are fetched with, if they aren't built in.
+    pub(crate) octicon_client: Option<&'a reqwest::Client>,
+    pub(crate) api_url: &'a str,
+    pub(crate) api_version: &'a str,
+    pub(crate) token: Option<&'a str>,
+    pub(crate) bind: &'a [IpAddr],
+    pub(crate) port: u16,
+    /// Files whose permissions are checked.
+    pub(crate) inputs: &'a [PathBuf],
+}
+
+/// The outcome of one check.
+enum Outcome {
+    Pass(String),
+    /// Not a problem yet, but likely to become one.
+    Warn {
+        message: String,
+        hint: String,
+    },
+    Fail {
+        message: String,
+        hint: String,
+    },
+}
+
+impl Outcome {
+    fn warn(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self::Warn {
+            message: message.into(),
+            hint: hint.into(),
+        }
+    }
+
+    fn fail(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self::Fail {
+            message: message.into(),
+            hint: hint.into(),
+        }
+    }
+}
+
+/// Check everything ghmd depends on, printing the outcome of every check and how to fix any
+/// problems to standard output. Fails if any check does.
+pub(crate) async fn run(options: Options<'_>) -> anyhow::Result<()> {
+    let mut outcomes = vec![
+        ("GitHub API", api(&options).await),
+        (
+            "CDN",
+            cdn(options.octicon_client.unwrap_or(options.client)).await,
+        ),
+        ("File watching", inotify().await),
+        ("Port", port(options.bind, options.port).await),
+        ("Cache directory", cache_dir().await),
+    ];
+    for input in options.inputs {
+        outcomes.push(("File", file(input).await));
+    }
+
+    let mut failed = 0;
+    for (name, outcome) in outcomes {
+        match outcome {
+            Outcome::Pass(message) => println!("ok      {}: {}", name, message),
+            Outcome::Warn { message, hint } => {
+                println!("warning {}: {}", name, message);
+                println!("        {}", hint);
+            }
+            Outcome::Fail { message, hint } => {
+                failed += 1;
+                println!("FAILED  {}: {}", name, message);
+                println!("        {}", hint);
+            }
+        }
+    }
+    if failed > 0 {
+        println!();
+        bail!("{} check(s) failed", failed);
+    }
+    Ok(())
+}
+
+/// Check that the API can be reached and accepts the token, without using up any quota.
+async fn api(options: &Options<'_>) -> Outcome {
+    let mut request = options
+        .client
+        .get(format!("{}/rate_limit", options.api_url))
+        .header("Accept", "application/vnd.github+json")
+        .header("X-GitHub-Api-Version", options.api_version);
+    if let Some(token) = options.token {
+        request = request.bearer_auth(token);
+    }
+    let res = match request.send().await {
+        Ok(res) => res,
+        Err(e) => {
+            return Outcome::fail(
+                format!("could not reach {}: {}", options.api_url, e.without_url()),
+                "Check your network connection, and set HTTPS_PROXY if you need a proxy.",
+            )
+        }
+    };
+
+    let status = res.status();
+    let remaining = res
+        .headers()
+        .get("X-RateLimit-Remaining")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("an unknown number of");
+    if status == reqwest::StatusCode::UNAUTHORIZED {
+        return Outcome::fail(
+            "the token was rejected",
+            "It may have expired or been revoked; generate a new one at \
+                https://github.com/settings/tokens.",
+        );
+    }
+    if !status.is_success() {
+        return Outcome::fail(
+            format!("GitHub responded with {}", status),
+            "See https://www.githubstatus.com for whether GitHub is having problems.",
+        );
+    }
+    if options.token.is_none() {
+        return Outcome::fail(
+            "reachable, but no token was given",
+            "Pass one with `--token` or set GITHUB_TOKEN or GH_TOKEN; generate one at \
+                https://github.com/settings/tokens.",
+        );
+    }
+
+    // Only classic tokens have scopes, and ghmd needs none of them.
+    let scopes = res
+        .headers()
+        .get("X-OAuth-Scopes")
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|scopes| !scopes.is_empty());
+    match scopes {
+        Some(scopes) => Outcome::warn(
+            format!(
+                "the token works, with {} requests remaining, but has the scopes {}",
+                remaining, scopes
+            ),
+            "ghmd needs no scopes, so a token without any would be safer.",
+        ),
+        None => Outcome::Pass(format!(
+            "the token works, with {} requests remaining",
+            remaining
+        )),
+    }
+}
+
+/// Check the CDN pages load their styles from, since pages look wrong without it.
+async fn cdn(client: &reqwest::Client) -> Outcome {
+    match client
+        .head(vendor::PRIMER.url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+    {
+        Ok(_) => Outcome::Pass("cdn.jsdelivr.net is reachable".to_owned()),
+        Err(e) => Outcome::fail(
+            format!("could not reach cdn.jsdelivr.net: {}", e.without_url()),
+            "Pages will be unstyled and octicons built in. Check for a firewall or ad blocker \
+                blocking the CDN, or use `--octicon-proxy`.",
+        ),
+    }
+}
+
+/// Check the limits on inotify watches, which only exist on Linux.
+async fn inotify() -> Outcome {
+    if !cfg!(target_os = "linux") {
+        return Outcome::Pass(crate::watcher::backend());
+    }
+    let watches = fs::read_to_string("/proc/sys/fs/inotify/max_user_watches").await;
+    match watches
+        .ok()
+        .and_then(|watches| watches.trim().parse::<u64>().ok())
+    {
+        Some(watches) if watches < MIN_INOTIFY_WATCHES => Outcome::warn(
+            format!("only {} inotify watches are allowed", watches),
+            "Previewing files in large directories may fail; raise the limit with \
+                `sudo sysctl fs.inotify.max_user_watches=524288`.",
+        ),
+        Some(watches) => Outcome::Pass(format!(
+            "{}, with {} watches allowed",
+            crate::watcher::backend(),
+            watches
+        )),
+        None => Outcome::warn(
+            "could not read the inotify watch limit",
+            "Changes to files may not be noticed if inotify is unavailable.",
+        ),
+    }
+}
+
+async fn port(addresses: &[IpAddr], port: u16) -> Outcome {
+    let loopback = [Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()];
+    let defaulted = addresses.is_empty();
+    let addresses = if defaulted { &loopback } else { addresses };
+    for &address in addresses {
+        match TcpListener::bind((address, port)).await {
+            Ok(_) => {}
+            // As when serving, IPv6 being unavailable is fine by default.
+            Err(_) if defaulted && address.is_ipv6() => {}
+            Err(e) => {
+                return Outcome::fail(
+                    format!("could not listen on port {} of {}: {}", port, address, e),
+                    "Another server may be using the port; pass `--port` with another one, or \
+                        `--port 0` to pick any free port.",
+                )
+            }
+        }
+    }
+    Outcome::Pass(format!("port {} is free", port))
+}
+
+/// Check that the cache directory, which holds snapshots and the state of background servers,
+/// can be written to.
+async fn cache_dir() -> Outcome {
+    let dir = match dirs::cache_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            return Outcome::fail(
+                e.to_string(),
+                "Set XDG_CACHE_HOME to the directory caches should be kept in.",
+            )
+        }
+    };
+    let probe = dir.join(format!(".doctor-{}", std::process::id()));
+    let res = async {
+        fs::create_dir_all(&dir).await?;
+        fs::write(&probe, "").await?;
+        fs::remove_file(&probe).await
+    }
+    .await;
+    match res {
+        Ok(()) => Outcome::Pass(format!("`{}` is writable", dir.display())),
+        Err(e) => Outcome::fail(
+            format!("could not write to `{}`: {}", dir.display(), e),
+            "Fix the directory's permissions, or set XDG_CACHE_HOME to somewhere writable.",
+        ),
+    }
+}
+
+/// Check that a file can be read, and whether it can be written to as edits and restores need.
+async fn file(path: &Path) -> Outcome {
+    let metadata = match fs::metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return Outcome::fail(
+                format!("could not find `{}`: {}", path.display(), e),
+                "Check the path, and that every directory leading to it can be entered.",
+            )
+        }
+    };
+    if let Err(e) = fs::read_to_string(path).await {
+        return Outcome::fail(
+            format!("could not read `{}`: {}", path.display(), e),
+            "Make sure the file is readable and is UTF-8.",
+        );
+    }
+    if metadata.permissions().readonly() {
+        return Outcome::warn(
+            format!("`{}` is read-only", path.display()),
+            "It can be previewed, but not edited through ghmd or restored from its journal.",
+        );
+    }
+    Outcome::Pass(format!("`{}` is readable and writable", path.display()))
+}
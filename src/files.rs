@@ -0,0 +1,605 @@
+use std::borrow::Cow;
+use std::io::SeekFrom;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_stream::stream;
+use bytes::Bytes;
+use httpdate::HttpDate;
+use hyper::http;
+use once_cell::sync::Lazy;
+use percent_encoding::percent_decode_str;
+use regex::{Captures, Regex};
+use tokio::fs;
+use tokio::io::{AsyncReadExt as _, AsyncSeekExt as _};
+
+use crate::review;
+use crate::router::{self, Query, Response};
+use crate::templater;
+
+/// How much of a file is read at a time while sending it.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// The MIME type of a local file that pages may load, from its extension. Any other file, such as
+/// the documents themselves, is never served.
+pub(crate) fn content_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    Some(match &*extension {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "avif" => "image/avif",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "ogv" => "video/ogg",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "ogg" | "oga" => "audio/ogg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        _ => return None,
+    })
+}
+
+/// The file in `dir` that a request for the URL path `url_path` is for, if it exists and may be
+/// served. Paths that would leave `dir` are refused.
+pub(crate) async fn find(dir: &Path, url_path: &str) -> Option<PathBuf> {
+    resolve(dir, url_path, |path| content_type(path).is_some()).await
+}
+
+/// The markdown file in `dir` that a request for the URL path `url_path` is for, if it exists.
+/// Paths that would leave `dir` are refused.
+pub(crate) async fn find_markdown(dir: &Path, url_path: &str) -> Option<PathBuf> {
+    resolve(dir, url_path, |path| {
+        review::is_markdown(&path.to_string_lossy())
+    })
+    .await
+}
+
+async fn resolve(dir: &Path, url_path: &str, allowed: fn(&Path) -> bool) -> Option<PathBuf> {
+    let decoded = percent_decode_str(url_path).decode_utf8().ok()?;
+    let relative = Path::new(decoded.trim_start_matches('/'));
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        || !allowed(relative)
+    {
+        return None;
+    }
+    let path = dir.join(relative);
+    // Symlinks inside `dir` may point anywhere, so check where the path really ends up.
+    let (root, target) = tokio::join!(fs::canonicalize(dir), fs::canonicalize(&path));
+    if !target.ok()?.starts_with(root.ok()?) {
+        return None;
+    }
+    fs::metadata(&path)
+        .await
+        .is_ok_and(|metadata| metadata.is_file())
+        .then_some(path)
+}
+
+/// Rewrite the relative `src` attributes in a document's rendered HTML to URL paths resolved
+/// against its route, so that its images load from its own directory whichever page it is shown
+/// on. Sources that would leave the working directory are left alone.
+pub(crate) fn absolute_srcs<'a>(html: &'a str, route: &str) -> Cow<'a, str> {
+    static SRC: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(\ssrc=")([^"]*)""#).unwrap());
+    static SCHEME: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Za-z][A-Za-z0-9+.-]*:").unwrap());
+    let dir = &route[..route.rfind('/').unwrap()];
+    SRC.replace_all(html, |captures: &Captures<'_>| {
+        let src = &captures[2];
+        if src.is_empty() || src.starts_with(['/', '#', '?']) || SCHEME.is_match(src) {
+            return captures[0].to_owned();
+        }
+        let end = src.find(['?', '#']).unwrap_or(src.len());
+        let mut segments: Vec<&str> = dir.split('/').skip(1).collect();
+        for segment in src[..end].split('/') {
+            match segment {
+                "." => {}
+                ".." => {
+                    if segments.pop().is_none() {
+                        return captures[0].to_owned();
+                    }
+                }
+                _ => segments.push(segment),
+            }
+        }
+        format!("{}/{}{}\"", &captures[1], segments.join("/"), &src[end..])
+    })
+}
+
+/// Respond with a local file, or with the part of it asked for by the request's `Range` header,
+/// so that browsers can seek in videos and audio without downloading all of them first.
+pub(crate) async fn serve(path: &Path, headers: &http::HeaderMap) -> Response {
+    let content_type = content_type(path).unwrap_or("application/octet-stream");
+    let file = match fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) => {
+            log::warn!("failed to open `{}`: {}", path.display(), e);
+            return router::text(http::StatusCode::NOT_FOUND, "file not found");
+        }
+    };
+    let metadata = match file.metadata().await {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            log::warn!("failed to read `{}`: {}", path.display(), e);
+            return router::text(
+                http::StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to read file",
+            );
+        }
+    };
+    let modified = metadata.modified().ok();
+    let nanos = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos());
+    let version = Version {
+        etag: format!("\"{:x}-{:x}\"", metadata.len(), nanos),
+        last_modified: modified,
+    };
+
+    let response = http::Response::builder()
+        .header("Content-Type", content_type)
+        .header("Cache-Control", "no-cache")
+        // Local files are the user's own, but an SVG opened directly still shouldn't be able to
+        // run scripts with the preview's origin.
+        .header("Content-Security-Policy", "sandbox")
+        .header("X-Content-Type-Options", "nosniff");
+    respond(
+        response,
+        headers,
+        &version,
+        Content::File(file, path.to_owned()),
+        metadata.len(),
+    )
+    .await
+}
+
+/// Respond with one of ghmd's own assets, letting the browser cache it indefinitely if the
+/// version it asked for is the current one.
+pub(crate) async fn serve_asset(
+    asset: &'static templater::Asset,
+    query: &Query,
+    headers: &http::HeaderMap,
+) -> Response {
+    let current = asset.version();
+    let cache_control = if query.get("v") == Some(&*current) {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+    let version = Version {
+        etag: format!("\"{}\"", current),
+        last_modified: None,
+    };
+    let response = http::Response::builder()
+        .header("Content-Type", asset.content_type)
+        .header("Cache-Control", cache_control);
+    let content = asset.content.as_bytes();
+    respond(
+        response,
+        headers,
+        &version,
+        Content::Static(content),
+        content.len() as u64,
+    )
+    .await
+}
+
+/// A version of something served, which conditional requests are checked against.
+struct Version {
+    /// A strong entity tag, including its quotes.
+    etag: String,
+    last_modified: Option<SystemTime>,
+}
+
+impl Version {
+    fn last_modified(&self) -> Option<String> {
+        self.last_modified.map(httpdate::fmt_http_date)
+    }
+
+    /// Whether the client already has this version, according to `If-None-Match` or, without it,
+    /// `If-Modified-Since`.
+    fn not_modified(&self, headers: &http::HeaderMap) -> bool {
+        if let Some(tags) = header(headers, "if-none-match") {
+            // Weak comparison, since the response would be the same either way.
+            return tags.trim() == "*"
+                || tags
+                    .split(',')
+                    .any(|tag| tag.trim().trim_start_matches("W/") == self.etag);
+        }
+        match (header(headers, "if-modified-since"), self.last_modified) {
+            (Some(since), Some(modified)) => httpdate::parse_http_date(since)
+                .is_ok_and(|since| HttpDate::from(modified) <= HttpDate::from(since)),
+            _ => false,
+        }
+    }
+
+    /// Whether an `If-Range` header names this version, so that the range it accompanies is of
+    /// the copy the client has. Only strong validators may be used, so weak tags never match.
+    fn matches_if_range(&self, if_range: &str) -> bool {
+        let if_range = if_range.trim();
+        if if_range.starts_with('"') {
+            return if_range == self.etag;
+        }
+        match (httpdate::parse_http_date(if_range), self.last_modified) {
+            (Ok(date), Some(modified)) => HttpDate::from(date) == HttpDate::from(modified),
+            _ => false,
+        }
+    }
+}
+
+/// What is being served.
+enum Content {
+    File(fs::File, PathBuf),
+    Static(&'static [u8]),
+}
+
+/// Respond with `content`, which is `len` bytes long, following the request's conditional and
+/// `Range` headers: a client with the current version gets `304 Not Modified`, and one asking for
+/// a range of it gets just that range. A range with an `If-Range` for an older version is ignored,
+/// since the client's partial copy is out of date and needs replacing entirely.
+async fn respond(
+    response: http::response::Builder,
+    headers: &http::HeaderMap,
+    version: &Version,
+    content: Content,
+    len: u64,
+) -> Response {
+    let mut response = response
+        .header("Accept-Ranges", "bytes")
+        .header("ETag", &version.etag);
+    if let Some(last_modified) = version.last_modified() {
+        response = response.header("Last-Modified", last_modified);
+    }
+    if version.not_modified(headers) {
+        return response
+            .status(http::StatusCode::NOT_MODIFIED)
+            .body(router::full(""))
+            .unwrap();
+    }
+
+    let range = header(headers, "range")
+        .filter(|_| header(headers, "if-range").is_none_or(|tag| version.matches_if_range(tag)))
+        .map(|range| parse_range(range, len));
+    let (response, start, end) = match range {
+        None | Some(Range::Ignored) => (response.status(http::StatusCode::OK), 0, len),
+        Some(Range::Satisfiable(start, end)) if start < end => (
+            response.status(http::StatusCode::PARTIAL_CONTENT).header(
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, end - 1, len),
+            ),
+            start,
+            end,
+        ),
+        Some(Range::Satisfiable(..) | Range::Unsatisfiable) => {
+            return response
+                .status(http::StatusCode::RANGE_NOT_SATISFIABLE)
+                .header("Content-Range", format!("bytes */{}", len))
+                .body(router::full(""))
+                .unwrap();
+        }
+    };
+
+    let body = match content {
+        Content::Static(content) => {
+            router::full(Bytes::from_static(&content[start as usize..end as usize]))
+        }
+        Content::File(mut file, path) => {
+            if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+                log::warn!("failed to read `{}`: {}", path.display(), e);
+                return router::text(
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "failed to read file",
+                );
+            }
+            let mut file = file.take(end - start);
+            router::stream(stream! {
+                loop {
+                    let mut chunk = vec![0; CHUNK_BYTES];
+                    match file.read(&mut chunk).await {
+                        Ok(0) => break,
+                        Ok(read) => {
+                            chunk.truncate(read);
+                            yield chunk;
+                        }
+                        Err(e) => {
+                            log::warn!("failed to read `{}`: {}", path.display(), e);
+                            break;
+                        }
+                    }
+                }
+            })
+        }
+    };
+    response
+        .header("Content-Length", end - start)
+        .body(body)
+        .unwrap()
+}
+
+fn header<'a>(headers: &'a http::HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok()
+}
+
+/// What a `Range` header asks for.
+#[derive(Debug, PartialEq)]
+enum Range {
+    /// The bytes from the first offset up to but not including the second.
+    Satisfiable(u64, u64),
+    /// None of the file, since it is shorter than the range's start or the range is backwards.
+    Unsatisfiable,
+    /// A header that can't be parsed, or asks for several ranges, which is answered with the
+    /// whole file as the header may be ignored.
+    Ignored,
+}
+
+fn parse_range(header: &str, len: u64) -> Range {
+    let spec = match header.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Range::Ignored,
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return Range::Ignored,
+    };
+    let parse = |bound: &str| bound.trim().parse::<u64>().ok();
+    match (start.trim(), end.trim()) {
+        // The last `suffix` bytes.
+        ("", suffix) => match parse(suffix) {
+            Some(0) => Range::Unsatisfiable,
+            Some(_) if len == 0 => Range::Unsatisfiable,
+            Some(suffix) => Range::Satisfiable(len.saturating_sub(suffix), len),
+            None => Range::Ignored,
+        },
+        (start, "") => match parse(start) {
+            Some(start) if start >= len => Range::Unsatisfiable,
+            Some(start) => Range::Satisfiable(start, len),
+            None => Range::Ignored,
+        },
+        (start, end) => match (parse(start), parse(end)) {
+            (Some(start), Some(end)) if start > end || start >= len => Range::Unsatisfiable,
+            (Some(start), Some(end)) => Range::Satisfiable(start, end.saturating_add(1).min(len)),
+            _ => Range::Ignored,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use hyper::http;
+
+    use super::{absolute_srcs, find, parse_range, respond, Content, Range, Version};
+
+    const CONTENT: &[u8] = b"0123456789";
+
+    /// Respond with [`CONTENT`] to a request with the given headers, returning the status and
+    /// the `Content-Range` header.
+    async fn request(headers: &[(&'static str, &str)]) -> (u16, Option<String>) {
+        let version = Version {
+            etag: "\"v1\"".to_owned(),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(1_000_000_000)),
+        };
+        let mut map = http::HeaderMap::new();
+        for &(name, value) in headers {
+            map.insert(name, value.parse().unwrap());
+        }
+        let response = respond(
+            http::Response::builder(),
+            &map,
+            &version,
+            Content::Static(CONTENT),
+            CONTENT.len() as u64,
+        )
+        .await;
+        let content_range = response
+            .headers()
+            .get("Content-Range")
+            .map(|value| value.to_str().unwrap().to_owned());
+        (response.status().as_u16(), content_range)
+    }
+
+    #[tokio::test]
+    async fn conditional_requests() {
+        // The date of the version, and one a second later.
+        const MODIFIED: &str = "Sun, 09 Sep 2001 01:46:40 GMT";
+        const LATER: &str = "Sun, 09 Sep 2001 01:46:41 GMT";
+        let partial = (206, Some("bytes 2-4/10".to_owned()));
+        let whole = (200, None);
+        let not_modified = (304, None);
+
+        let cases: &[(&[(&str, &str)], _)] = &[
+            (&[], &whole),
+            (&[("range", "bytes=2-4")], &partial),
+            // `If-Range` with an entity tag must match it exactly and strongly.
+            (&[("range", "bytes=2-4"), ("if-range", "\"v1\"")], &partial),
+            (&[("range", "bytes=2-4"), ("if-range", "\"v0\"")], &whole),
+            (&[("range", "bytes=2-4"), ("if-range", "W/\"v1\"")], &whole),
+            // `If-Range` with a date must be the exact modification time.
+            (&[("range", "bytes=2-4"), ("if-range", MODIFIED)], &partial),
+            (&[("range", "bytes=2-4"), ("if-range", LATER)], &whole),
+            (&[("range", "bytes=2-4"), ("if-range", "yesterday")], &whole),
+            // A client that already has the version gets nothing, even if it asked for a range.
+            (
+                &[("range", "bytes=2-4"), ("if-none-match", "\"v1\"")],
+                &not_modified,
+            ),
+            (
+                &[("range", "bytes=2-4"), ("if-none-match", "W/\"v1\"")],
+                &not_modified,
+            ),
+            (
+                &[("range", "bytes=2-4"), ("if-none-match", "\"v0\", \"v1\"")],
+                &not_modified,
+            ),
+            (
+                &[("range", "bytes=2-4"), ("if-none-match", "*")],
+                &not_modified,
+            ),
+            (
+                &[("range", "bytes=2-4"), ("if-none-match", "\"v0\"")],
+                &partial,
+            ),
+            (
+                &[
+                    ("range", "bytes=2-4"),
+                    ("if-none-match", "\"v1\""),
+                    ("if-range", "\"v0\""),
+                ],
+                &not_modified,
+            ),
+            (
+                &[
+                    ("range", "bytes=2-4"),
+                    ("if-none-match", "\"v0\""),
+                    ("if-range", "\"v1\""),
+                ],
+                &partial,
+            ),
+            // `If-Modified-Since` is only looked at without `If-None-Match`.
+            (&[("if-modified-since", MODIFIED)], &not_modified),
+            (&[("if-modified-since", LATER)], &not_modified),
+            (
+                &[("if-modified-since", "Sat, 08 Sep 2001 00:00:00 GMT")],
+                &whole,
+            ),
+            (
+                &[("if-modified-since", MODIFIED), ("if-none-match", "\"v0\"")],
+                &whole,
+            ),
+        ];
+        for (headers, expected) in cases {
+            assert_eq!(request(headers).await, **expected, "{:?}", headers);
+        }
+    }
+
+    #[test]
+    fn rewrites_relative_srcs() {
+        let cases = [
+            ("img.png", "/docs/img.png"),
+            ("./img/a%20b.png?v=1#x", "/docs/img/a%20b.png?v=1#x"),
+            ("../img.png", "/img.png"),
+            ("../../img.png", "../../img.png"),
+            ("/img.png", "/img.png"),
+            ("https://example.com/img.png", "https://example.com/img.png"),
+            ("data:image/png;base64,AA", "data:image/png;base64,AA"),
+            ("#top", "#top"),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(
+                absolute_srcs(&format!("<img src=\"{}\">", src), "/docs/README.md"),
+                format!("<img src=\"{}\">", expected),
+            );
+        }
+        assert_eq!(
+            absolute_srcs("<img src=\"a.png\">", "/README.md"),
+            "<img src=\"/a.png\">"
+        );
+        assert_eq!(
+            absolute_srcs("<img data-src=\"a.png\">", "/docs/README.md"),
+            "<img data-src=\"a.png\">",
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_stay_inside() {
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.png"), "secret").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.png"), "a").unwrap();
+        let link = |target: &std::path::Path, name| {
+            std::os::unix::fs::symlink(target, dir.path().join(name)).unwrap();
+        };
+        link(&dir.path().join("a.png"), "inside.png");
+        link(&outside.path().join("secret.png"), "outside.png");
+        link(outside.path(), "out");
+
+        assert!(find(dir.path(), "/a.png").await.is_some());
+        assert!(find(dir.path(), "/inside.png").await.is_some());
+        assert!(find(dir.path(), "/outside.png").await.is_none());
+        assert!(find(dir.path(), "/out/secret.png").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn unsatisfiable_ranges() {
+        let unsatisfiable = (416, Some("bytes */10".to_owned()));
+        assert_eq!(request(&[("range", "bytes=10-")]).await, unsatisfiable);
+        assert_eq!(request(&[("range", "bytes=5-4")]).await, unsatisfiable);
+        assert_eq!(
+            request(&[("range", "bytes=0-18446744073709551615")]).await,
+            (206, Some("bytes 0-9/10".to_owned()))
+        );
+    }
+
+    #[test]
+    fn open_ended() {
+        assert_eq!(parse_range("bytes=10-", 100), Range::Satisfiable(10, 100));
+        assert_eq!(parse_range("bytes=0-", 100), Range::Satisfiable(0, 100));
+        assert_eq!(parse_range("bytes=99-", 100), Range::Satisfiable(99, 100));
+    }
+
+    #[test]
+    fn bounded() {
+        assert_eq!(parse_range("bytes=0-0", 100), Range::Satisfiable(0, 1));
+        assert_eq!(parse_range("bytes=10-19", 100), Range::Satisfiable(10, 20));
+        assert_eq!(
+            parse_range(" bytes= 10 - 19 ", 100),
+            Range::Satisfiable(10, 20)
+        );
+        assert_eq!(
+            parse_range("bytes=90-200", 100),
+            Range::Satisfiable(90, 100)
+        );
+    }
+
+    #[test]
+    fn suffix() {
+        assert_eq!(parse_range("bytes=-10", 100), Range::Satisfiable(90, 100));
+        assert_eq!(parse_range("bytes=-200", 100), Range::Satisfiable(0, 100));
+        assert_eq!(parse_range("bytes=-0", 100), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-10", 0), Range::Unsatisfiable);
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        assert_eq!(parse_range("bytes=100-", 100), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=100-200", 100), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=20-10", 100), Range::Unsatisfiable);
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(
+            parse_range("bytes=0-18446744073709551615", 100),
+            Range::Satisfiable(0, 100)
+        );
+        assert_eq!(
+            parse_range("bytes=18446744073709551615-18446744073709551615", 100),
+            Range::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range("bytes=-18446744073709551615", 100),
+            Range::Satisfiable(0, 100)
+        );
+        assert_eq!(
+            parse_range("bytes=0-18446744073709551616", 100),
+            Range::Ignored
+        );
+    }
+
+    #[test]
+    fn ignored() {
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), Range::Ignored);
+        assert_eq!(parse_range("bytes=0-1, 5-6", 100), Range::Ignored);
+        assert_eq!(parse_range("items=0-1", 100), Range::Ignored);
+        assert_eq!(parse_range("bytes=5", 100), Range::Ignored);
+        assert_eq!(parse_range("bytes=a-b", 100), Range::Ignored);
+        assert_eq!(parse_range("bytes=-", 100), Range::Ignored);
+    }
+}
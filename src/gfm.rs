@@ -0,0 +1,112 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::highlight::Highlighter;
+
+/// Render GitHub Flavored Markdown on this machine with comrak, for when GitHub can't be reached.
+///
+/// The extensions GitHub enables are turned on, and heading anchors are given GitHub's markup so
+/// that links, the outline and octicons work as they do with the API. Code blocks are highlighted
+/// with syntect, which knows fewer languages than GitHub. Unlike GitHub, references like `#123`
+/// and `@user` aren't linked and emoji shortcodes are left as they are. Raw HTML is sanitized
+/// with an allowlist like GitHub's, so scripts and event handlers in a document never run in the
+/// preview.
+pub(crate) fn render(markdown: &str, hard_breaks: bool) -> String {
+    let mut options = comrak::Options::default();
+    options.extension.strikethrough = true;
+    options.extension.tagfilter = true;
+    options.extension.table = true;
+    options.extension.autolink = true;
+    options.extension.tasklist = true;
+    options.extension.footnotes = true;
+    options.extension.alerts = true;
+    options.extension.header_ids = Some("user-content-".to_owned());
+    options.render.hardbreaks = hard_breaks;
+    options.render.github_pre_lang = true;
+    options.render.gfm_quirks = true;
+    options.render.tasklist_classes = true;
+    // Raw HTML is kept here and sanitized afterwards.
+    options.render.unsafe_ = true;
+    let mut plugins = comrak::Plugins::default();
+    plugins.render.codefence_syntax_highlighter = Some(&Highlighter);
+    let html = comrak::markdown_to_html_with_plugins(markdown, &options, &plugins);
+
+    static ANCHOR: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"<a inert href="([^"]*)" aria-hidden="true" class="anchor" id="([^"]*)"></a>"#)
+            .unwrap()
+    });
+    let html = ANCHOR.replace_all(
+        &html,
+        r#"<a id="$2" class="anchor" aria-hidden="true" href="$1"><span aria-hidden="true" class="octicon octicon-link"></span></a>"#,
+    );
+    sanitize(&html)
+}
+
+/// Remove everything from rendered HTML that GitHub wouldn't keep and that could run code, such
+/// as `<script>`, event handler attributes and `javascript:` links, leaving comrak's own markup.
+fn sanitize(html: &str) -> String {
+    static SANITIZER: Lazy<ammonia::Builder<'static>> = Lazy::new(|| {
+        let mut builder = ammonia::Builder::default();
+        builder
+            .add_tags(["input"])
+            .add_tag_attributes("input", ["type", "checked", "disabled"])
+            .add_tag_attributes("pre", ["lang"])
+            .add_generic_attributes([
+                "id",
+                "class",
+                "aria-hidden",
+                "aria-label",
+                "data-footnotes",
+                "data-footnote-ref",
+                "data-footnote-backref",
+                "data-footnote-backref-idx",
+            ])
+            .link_rel(None)
+            .url_relative(ammonia::UrlRelative::PassThrough);
+        builder
+    });
+    SANITIZER.clean(html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+
+    #[test]
+    fn sanitizes_raw_html() {
+        let html = render(
+            "<img src=\"x.png\" onerror=\"alert(1)\">\n\n\
+            <a href=\"javascript:alert(1)\" onclick=\"alert(1)\">link</a>\n\n\
+            <script>alert(1)</script>\n\n\
+            <details><summary>More</summary>Hidden</details>\n",
+            false,
+        );
+        for unsafe_ in ["<script", "onerror", "onclick", "javascript:"] {
+            assert!(!html.contains(unsafe_), "{}", html);
+        }
+        assert!(html.contains("<img src=\"x.png\">"), "{}", html);
+        assert!(html.contains("<a>link</a>"), "{}", html);
+        assert!(html.contains("<summary>More</summary>"), "{}", html);
+    }
+
+    #[test]
+    fn keeps_markup() {
+        let html = render(
+            "# Heading\n\n- [x] Done\n\nText[^1]\n\n[^1]: Note\n\n\
+            > [!NOTE]\n> Alert\n\n```rust\nfn f() {}\n```\n\n| a |\n|:-:|\n| b |\n",
+            false,
+        );
+        for markup in [
+            "<a id=\"user-content-heading\" class=\"anchor\" aria-hidden=\"true\" href=\"#heading\">",
+            "<span aria-hidden=\"true\" class=\"octicon octicon-link\"></span>",
+            "<input type=\"checkbox\" class=\"task-list-item-checkbox\" checked=\"\" disabled=\"\">",
+            "data-footnote-ref",
+            "data-footnote-backref",
+            "class=\"markdown-alert markdown-alert-note\"",
+            "<pre lang=\"rust\"><code><span class=\"pl-k\">fn</span>",
+            "<th align=\"center\">a</th>",
+        ] {
+            assert!(html.contains(markup), "{} not in {}", markup, html);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! The server's routes other than the page itself and its event stream, one module each.
+
+pub(crate) mod anchors;
+pub(crate) mod audit;
+pub(crate) mod fallback;
+pub(crate) mod history;
+pub(crate) mod lint;
+pub(crate) mod restore;
+pub(crate) mod save;
+pub(crate) mod upload;
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, RwLock};
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use http_body_util::{BodyExt as _, Full};
+    use hyper::http;
+    use tempfile::TempDir;
+    use tokio::sync::watch;
+
+    use crate::config::ProjectConfig;
+    use crate::normalize::IgnoreRegions;
+    use crate::renderer::{self, Octicons, Renderer};
+    use crate::revisions::Revisions;
+    use crate::router::Request;
+    use crate::templater::{Templater, Theme};
+    use crate::watcher::Watcher;
+    use crate::{Opener, Server, Source, MAX_LINKED};
+
+    /// A server previewing `README.md` in a temporary directory, rendering offline.
+    pub(crate) struct TestServer {
+        pub(crate) dir: TempDir,
+        pub(crate) server: Arc<Server>,
+    }
+
+    impl TestServer {
+        /// Write `markdown` to `README.md` and serve it, recording its versions if `journal` is
+        /// set.
+        pub(crate) async fn new(markdown: &str, journal: bool) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let dir_path = dir.path().canonicalize().unwrap();
+            std::fs::write(dir_path.join("README.md"), markdown).unwrap();
+
+            let renderer = Renderer::new(
+                reqwest::Client::new(),
+                Octicons::new(None),
+                renderer::DEFAULT_API_VERSION,
+                "",
+                1,
+                Duration::ZERO,
+                0,
+                None,
+                IgnoreRegions::new([]),
+                false,
+                None,
+            )
+            .with_offline(true);
+            let watcher = Watcher::new().unwrap();
+            let extra = watcher.add_globs(&[]).unwrap();
+            let opener = Opener {
+                watcher,
+                extra: extra.clone(),
+                // Linked documents are opened relative to the process's working directory.
+                current_dir: std::env::current_dir().unwrap().canonicalize().unwrap(),
+                prose_lint: None,
+                journal: journal.then(|| dir_path.join(".journal")),
+                hooks: None,
+            };
+            let project = ProjectConfig::default();
+            let source = Source::File(dir_path.join("README.md"));
+            let document = opener.open(&source, &project).await.unwrap();
+            let server = Arc::new(Server {
+                audit_banner: false,
+                assets_dir: PathBuf::from("images"),
+                renderer,
+                templater: Templater::new("README.md".into(), Theme::Dark),
+                project,
+                max_documents: 1 + MAX_LINKED,
+                opened: watch::channel(1).0,
+                documents: RwLock::new(vec![Arc::new(document)]),
+                opener,
+                revisions: Revisions::new(),
+                extra_watcher: extra,
+            });
+            Self { dir, server }
+        }
+
+        pub(crate) fn path(&self, name: &str) -> PathBuf {
+            self.dir.path().join(name)
+        }
+
+        /// Record `markdown` as the next version in the document's journal.
+        pub(crate) async fn record(&self, markdown: &str) {
+            let document = self.server.document(0);
+            let journal = document.journal.as_ref().unwrap();
+            journal.lock().await.record(markdown).await.unwrap();
+        }
+
+        /// Send a request through the server's router, returning the status and body. The `Host`
+        /// header is `localhost` unless it is given.
+        pub(crate) async fn send(
+            &self,
+            method: http::Method,
+            uri: &str,
+            headers: &[(&'static str, &str)],
+            body: impl Into<Bytes>,
+        ) -> (http::StatusCode, String) {
+            let body = Full::new(body.into())
+                .map_err(|never| match never {})
+                .boxed_unsync();
+            let mut req = Request::new(body);
+            *req.method_mut() = method;
+            *req.uri_mut() = uri.parse().unwrap();
+            req.headers_mut()
+                .insert("host", http::HeaderValue::from_static("localhost"));
+            for &(name, value) in headers {
+                req.headers_mut().insert(name, value.parse().unwrap());
+            }
+            let response = Server::router().handle(&self.server, req).await;
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, String::from_utf8_lossy(&body).into_owned())
+        }
+
+        pub(crate) async fn get(&self, uri: &str) -> (http::StatusCode, String) {
+            self.send(http::Method::GET, uri, &[], Bytes::new()).await
+        }
+    }
+
+    /// The header ghmd's pages and editor plugins send with requests that change files.
+    pub(crate) const FROM_PAGE: (&str, &str) = ("x-requested-by", "ghmd");
+
+    /// Whether `path` exists and holds `contents`.
+    pub(crate) fn holds(path: &Path, contents: &str) -> bool {
+        std::fs::read_to_string(path).is_ok_and(|read| read == contents)
+    }
+}
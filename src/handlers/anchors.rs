@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use crate::outline;
+use crate::router::Response;
+use crate::{json_response, DocumentIndex, Server};
+
+/// List the document's headings with their anchors, for editor plugins.
+pub(crate) async fn get(server: Arc<Server>, DocumentIndex(index): DocumentIndex) -> Response {
+    let document = server.document(index);
+    let res = async {
+        let markdown = document.markdown()?;
+        let rendered = server.render_current(&document).await?;
+        Ok(outline::headings(&rendered, &markdown))
+    }
+    .await;
+    json_response(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::http;
+
+    use crate::handlers::tests::TestServer;
+
+    #[tokio::test]
+    async fn lists_headings() {
+        let server = TestServer::new("# First heading\n\n## Second heading\n", false).await;
+        let (status, body) = server.get("/api/anchors").await;
+        assert_eq!(status, http::StatusCode::OK);
+        let headings: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let slugs: Vec<&str> = headings
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|heading| heading["slug"].as_str().unwrap())
+            .collect();
+        assert_eq!(slugs, ["first-heading", "second-heading"]);
+        assert_eq!(headings[1]["level"], 2);
+        assert_eq!(headings[1]["source_line"], 3);
+    }
+}
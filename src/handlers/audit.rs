@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use crate::audit;
+use crate::router::Response;
+use crate::{json_response, DocumentIndex, Server};
+
+/// Report on the images in a document: how large they are, and whether they will show on GitHub.
+pub(crate) async fn get(server: Arc<Server>, DocumentIndex(index): DocumentIndex) -> Response {
+    let document = server.document(index);
+    let res = async {
+        let rendered = server.render_current(&document).await?;
+        Ok(audit::images(
+            &rendered,
+            &document.base_dir,
+            server.repository(&document).as_ref(),
+        )
+        .await)
+    }
+    .await;
+    json_response(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::http;
+
+    use crate::handlers::tests::TestServer;
+
+    #[tokio::test]
+    async fn reports_images() {
+        let server = TestServer::new("![A missing image](missing.png)\n", false).await;
+        let (status, body) = server.get("/api/audit").await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(body.contains("\"src\":\"missing.png\""), "{}", body);
+    }
+}
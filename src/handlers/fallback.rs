@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use hyper::http;
+use percent_encoding::percent_decode_str;
+
+use crate::files;
+use crate::handlers::history;
+use crate::router::{self, Request, Response};
+use crate::{redact, Server, MAX_LINKED};
+
+/// Serve the local file a request is for, such as an image or video in a document, and the page
+/// otherwise. Relative paths are resolved against each document's directory in turn, then against
+/// the working directory, which documents' relative sources are rewritten to be from. Markdown
+/// files are added as documents the first time they are visited, so that links between
+/// documents open in the preview.
+pub(crate) async fn handle(server: Arc<Server>, req: Request) -> Response {
+    if let Some(hash) = req.uri().path().strip_prefix("/rev/") {
+        return history::revision(&server, hash).await;
+    }
+    let path = percent_decode_str(req.uri().path()).decode_utf8_lossy();
+    let documents = server.documents();
+    let routed = documents.iter().position(|document| {
+        document
+            .route
+            .as_deref()
+            .is_some_and(|route| percent_decode_str(route).decode_utf8_lossy() == path)
+    });
+    if let Some(index) = routed {
+        return server.get(index).await;
+    }
+    if matches!(*req.method(), http::Method::GET | http::Method::HEAD) {
+        for document in &documents {
+            // On a document's own page, its relative links are resolved against its route.
+            let from_route = document.route.as_deref().and_then(|route| {
+                let dir = &route[..route.rfind('/').unwrap()];
+                req.uri().path().strip_prefix(dir)
+            });
+            for url_path in [Some(req.uri().path()), from_route].into_iter().flatten() {
+                if let Some(path) = files::find(&document.base_dir, url_path).await {
+                    return files::serve(&path, req.headers()).await;
+                }
+                if let Some(path) = files::find_markdown(&document.base_dir, url_path).await {
+                    return match server.open_linked(&path).await {
+                        Ok(Some(index)) => server.get(index).await,
+                        Ok(None) => router::text(
+                            http::StatusCode::SERVICE_UNAVAILABLE,
+                            format!(
+                                "ghmd already has {} linked documents open. To preview this one \
+                                    as well, give it to ghmd on the command line.",
+                                MAX_LINKED,
+                            ),
+                        ),
+                        Err(e) => router::text(
+                            http::StatusCode::INTERNAL_SERVER_ERROR,
+                            redact(&format!("{:?}", e)).into_owned(),
+                        ),
+                    };
+                }
+            }
+        }
+        // Documents' relative sources are rewritten to paths from the working directory.
+        if let Some(path) = files::find(&server.opener.current_dir, req.uri().path()).await {
+            return files::serve(&path, req.headers()).await;
+        }
+    }
+    server.get(0).await
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::http;
+
+    use crate::handlers::tests::TestServer;
+
+    #[tokio::test]
+    async fn serves_files() {
+        let server = TestServer::new("![Image](image.png)\n", false).await;
+        std::fs::write(server.path("image.png"), "png").unwrap();
+        let (status, body) = server.get("/image.png").await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(body, "png");
+    }
+
+    #[tokio::test]
+    async fn opens_linked_documents() {
+        let server = TestServer::new("[Other](other.md)\n", false).await;
+        std::fs::write(server.path("other.md"), "# Other document\n").unwrap();
+        let (status, body) = server.get("/other.md").await;
+        assert_eq!(status, http::StatusCode::OK, "{}", body);
+        assert!(body.contains("Other document"), "{}", body);
+        assert_eq!(server.server.documents().len(), 2);
+
+        // Visiting it again doesn't open it twice.
+        server.get("/other.md").await;
+        assert_eq!(server.server.documents().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn serves_the_page_otherwise() {
+        let server = TestServer::new("Some text\n", false).await;
+        let (status, body) = server.get("/not/a/file").await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(body.contains("Some text"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn shows_unreadable_documents_in_their_tab() {
+        let server = TestServer::new("[Other](other.md)\n", false).await;
+        std::fs::write(server.path("other.md"), "Other text\n").unwrap();
+        assert_eq!(server.get("/other.md").await.0, http::StatusCode::OK);
+
+        std::fs::remove_file(server.path("other.md")).unwrap();
+        let other = server.server.document(1);
+        for _ in 0..100 {
+            if other.markdown().is_err() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(other.markdown().is_err());
+
+        let (status, body) = server.get("/").await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(body.contains("Other"), "{}", body);
+        assert!(body.contains("other.md could not be read"), "{}", body);
+    }
+}
@@ -0,0 +1,210 @@
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use hyper::http;
+use serde::Serialize;
+
+use crate::queue::Priority;
+use crate::router::{self, Query, Response};
+use crate::templater::Liveness;
+use crate::{json_response, DocumentIndex, Server};
+
+/// Show an earlier version of a document from its journal, chosen by its position with an `at`
+/// query parameter, with a slider to move through the others.
+pub(crate) async fn page(
+    server: Arc<Server>,
+    DocumentIndex(index): DocumentIndex,
+    query: Query,
+) -> Response {
+    let document = server.document(index);
+    let journal = match &document.journal {
+        Some(journal) => journal.lock().await,
+        None => {
+            return router::text(
+                http::StatusCode::NOT_FOUND,
+                "versions are only recorded with `--journal`",
+            )
+        }
+    };
+    let entries = journal.entries();
+    if entries.is_empty() {
+        return router::text(
+            http::StatusCode::NOT_FOUND,
+            "no versions have been recorded",
+        );
+    }
+    let at = query
+        .get("at")
+        .and_then(|at| at.parse().ok())
+        .unwrap_or(entries.len() - 1)
+        .min(entries.len() - 1);
+    let count = entries.len();
+    let time = UNIX_EPOCH + Duration::from_secs(entries[at].time);
+    let (added, removed) = entries[at].changed_lines();
+    let markdown = journal.version(at).unwrap();
+    drop(journal);
+
+    let res = async {
+        let rendered = server
+            .renderer
+            .render(&markdown, Priority::Interactive)
+            .await??;
+        let controls = format!(
+            "<form class=\"Box color-bg-secondary p-3 mb-4\" method=\"get\" action=\"/history\">\
+                <input type=\"hidden\" name=\"document\" value=\"{}\">\
+                <input class=\"width-full\" type=\"range\" name=\"at\" min=\"0\" max=\"{}\" value=\"{}\" onchange=\"this.form.submit()\">\
+                <p class=\"mb-0\">Version {} of {}, recorded {}: {} lines added, {} removed</p>\
+            </form>",
+            index,
+            count - 1,
+            at,
+            at + 1,
+            count,
+            humantime::format_rfc3339_seconds(time),
+            added,
+            removed,
+        );
+        server
+            .templater
+            .generate(&(controls + &rendered), Liveness::Static, None)
+            .await
+    }
+    .await;
+    match res {
+        Ok(page) => http::Response::builder()
+            .header("Content-Type", "text/html")
+            .body(router::full(page))
+            .unwrap(),
+        Err(e) => router::text(http::StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)),
+    }
+}
+
+/// List the most recent versions of a document in its journal, newest first.
+pub(crate) async fn list(server: Arc<Server>, DocumentIndex(index): DocumentIndex) -> Response {
+    /// How many versions are listed.
+    const LIMIT: usize = 20;
+
+    let document = server.document(index);
+    let journal = match &document.journal {
+        Some(journal) => journal.lock().await,
+        None => {
+            return router::text(
+                http::StatusCode::NOT_FOUND,
+                "versions are only recorded with `--journal`",
+            )
+        }
+    };
+
+    #[derive(Serialize)]
+    struct Revision<'a> {
+        /// The version's position in the journal, as passed to `/history`.
+        at: usize,
+        hash: &'a str,
+        time: u64,
+        words_added: usize,
+        words_removed: usize,
+    }
+    let revisions: Vec<Revision<'_>> = journal
+        .entries()
+        .iter()
+        .enumerate()
+        .rev()
+        .take(LIMIT)
+        .map(|(at, entry)| {
+            let (words_added, words_removed) = entry.changed_words();
+            Revision {
+                at,
+                hash: &entry.hash,
+                time: entry.time,
+                words_added,
+                words_removed,
+            }
+        })
+        .collect();
+    json_response(Ok(revisions))
+}
+
+/// Show a revision of a document that was shown recently, read-only. These are at
+/// `/rev/<hash>`, so they are reached through the fallback.
+pub(crate) async fn revision(server: &Server, hash: &str) -> Response {
+    let revision = match server.revisions.get(hash) {
+        Some(revision) => revision,
+        None => {
+            return router::text(
+                http::StatusCode::NOT_FOUND,
+                "no revision with that hash was shown recently",
+            )
+        }
+    };
+    let document = server.document(revision.document);
+    let notice = format!(
+        "<div class=\"flash mb-4\">\
+            Revision <code>{}</code> of {}, shown {}. <a href=\"/\">Back to the live preview</a>\
+        </div>",
+        revision.hash,
+        tera::escape_html(&document.title),
+        humantime::format_rfc3339_seconds(revision.shown_at),
+    );
+    let res = server
+        .templater
+        .generate(&(notice + &revision.html), Liveness::Static, None)
+        .await;
+    match res {
+        Ok(page) => http::Response::builder()
+            .header("Content-Type", "text/html")
+            .body(router::full(page))
+            .unwrap(),
+        Err(e) => router::text(http::StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::http;
+
+    use crate::handlers::tests::TestServer;
+
+    #[tokio::test]
+    async fn needs_a_journal() {
+        let server = TestServer::new("Text\n", false).await;
+        assert_eq!(server.get("/history").await.0, http::StatusCode::NOT_FOUND);
+        assert_eq!(
+            server.get("/api/history").await.0,
+            http::StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn versions() {
+        let server = TestServer::new("First\n", true).await;
+        server.record("First\n").await;
+        server.record("Second version\n").await;
+
+        let (status, body) = server.get("/history?at=0").await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(body.contains("Version 1 of 2"), "{}", body);
+        assert!(body.contains("<p>First</p>"), "{}", body);
+        let (_, body) = server.get("/history").await;
+        assert!(body.contains("Version 2 of 2"), "{}", body);
+        assert!(body.contains("<p>Second version</p>"), "{}", body);
+
+        let (status, body) = server.get("/api/history").await;
+        assert_eq!(status, http::StatusCode::OK);
+        let revisions: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let at: Vec<u64> = revisions
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|revision| revision["at"].as_u64().unwrap())
+            .collect();
+        assert_eq!(at, [1, 0]);
+    }
+
+    #[tokio::test]
+    async fn unknown_revision() {
+        let server = TestServer::new("Text\n", false).await;
+        let (status, body) = server.get("/rev/0123456789abcdef").await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+        assert!(body.contains("no revision"), "{}", body);
+    }
+}
@@ -0,0 +1,35 @@
+use std::sync::Arc;
+
+use crate::lint;
+use crate::router::Response;
+use crate::{json_response, DocumentIndex, Server};
+
+/// List the problems found in a document's rendered HTML.
+pub(crate) async fn get(server: Arc<Server>, DocumentIndex(index): DocumentIndex) -> Response {
+    let document = server.document(index);
+    json_response(
+        server
+            .render_current(&document)
+            .await
+            .map(|rendered| lint::run(&rendered)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::http;
+
+    use crate::handlers::tests::TestServer;
+
+    #[tokio::test]
+    async fn finds_dead_anchors() {
+        let server = TestServer::new("# Heading\n\n[Link](#nowhere)\n", false).await;
+        let (status, body) = server.get("/api/lint").await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(body.contains("dead-anchor"), "{}", body);
+        assert!(body.contains("nowhere"), "{}", body);
+
+        let (status, _) = server.get("/api/lint?document=1").await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+    }
+}
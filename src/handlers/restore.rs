@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use hyper::http;
+use tokio::fs;
+
+use crate::router::{self, Query, RequestedByPage, Response, SameOrigin};
+use crate::{json_response, DocumentIndex, Server};
+
+/// Write an earlier version of a document from its journal, chosen by its position with an `at`
+/// query parameter, back to the document's file.
+pub(crate) async fn post(
+    server: Arc<Server>,
+    _: SameOrigin,
+    _: RequestedByPage,
+    DocumentIndex(index): DocumentIndex,
+    query: Query,
+) -> Response {
+    let document = server.document(index);
+    let path = match &document.path {
+        Some(path) => path,
+        None => {
+            return router::text(
+                http::StatusCode::BAD_REQUEST,
+                "only documents read from files can be restored",
+            )
+        }
+    };
+    let journal = match &document.journal {
+        Some(journal) => journal.lock().await,
+        None => {
+            return router::text(
+                http::StatusCode::NOT_FOUND,
+                "versions are only recorded with `--journal`",
+            )
+        }
+    };
+    let at = query.get("at").and_then(|at| at.parse().ok());
+    let markdown = match at.and_then(|at| journal.version(at)) {
+        Some(markdown) => markdown,
+        None => return router::text(http::StatusCode::NOT_FOUND, "no such version"),
+    };
+    // The journal records the restored version itself once the file is seen to change.
+    drop(journal);
+
+    let res = fs::write(&**path, markdown)
+        .await
+        .with_context(|| format!("could not write to `{}`", path));
+    if res.is_ok() {
+        log::info!("Restored an earlier version of {}", document.title);
+    }
+    json_response(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::http;
+
+    use crate::handlers::tests::{holds, TestServer, FROM_PAGE};
+
+    #[tokio::test]
+    async fn restores_versions() {
+        let server = TestServer::new("Old\n", true).await;
+        server.record("Old\n").await;
+        server.record("New\n").await;
+        std::fs::write(server.path("README.md"), "New\n").unwrap();
+
+        // Other sites can't make the page restore a version.
+        let (status, _) = server
+            .send(http::Method::POST, "/history/restore?at=0", &[], "")
+            .await;
+        assert_eq!(status, http::StatusCode::FORBIDDEN);
+        assert!(holds(&server.path("README.md"), "New\n"));
+
+        let headers = [FROM_PAGE];
+        let (status, _) = server
+            .send(http::Method::POST, "/history/restore?at=2", &headers, "")
+            .await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+
+        let (status, _) = server
+            .send(http::Method::POST, "/history/restore?at=0", &headers, "")
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(holds(&server.path("README.md"), "Old\n"));
+    }
+}
@@ -0,0 +1,132 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use hyper::http;
+use serde::Serialize;
+
+use crate::content::{self, Saved};
+use crate::router::{self, Query, Rejection, Request, RequestedByPage, Response, SameOrigin};
+use crate::{json_response, DocumentIndex, Server};
+
+/// Save the request body as the new contents of a document, as long as the file hasn't changed
+/// since the version whose hash is given in the `base` query parameter. Responds with `409
+/// Conflict` if it has, after keeping the edit next to the file and showing a banner.
+///
+/// Editor plugins must send `X-Requested-By: ghmd`, as the page does.
+pub(crate) async fn post(
+    server: Arc<Server>,
+    _: SameOrigin,
+    _: RequestedByPage,
+    DocumentIndex(index): DocumentIndex,
+    query: Query,
+    req: Request,
+) -> Response {
+    let document = server.document(index);
+    let path = match &document.path {
+        Some(path) => path,
+        None => {
+            return router::text(
+                http::StatusCode::BAD_REQUEST,
+                "only documents read from files can be saved",
+            )
+        }
+    };
+    let base = match query.get("base") {
+        Some(base) => base.to_owned(),
+        None => {
+            return router::text(
+                http::StatusCode::BAD_REQUEST,
+                "the hash of the version that was edited must be given as `base`",
+            )
+        }
+    };
+    let edit = match router::read_body(req, content::MAX_BYTES).await {
+        Ok(data) => match String::from_utf8(data.to_vec()) {
+            Ok(edit) => edit,
+            Err(_) => return router::text(http::StatusCode::BAD_REQUEST, "the body must be UTF-8"),
+        },
+        Err(Rejection { status, message }) => return router::text(status, message),
+    };
+
+    #[derive(Serialize)]
+    struct ConflictData<'a> {
+        /// Where the edit was kept.
+        path: &'a Path,
+    }
+    match content::save(Path::new(&**path), &base, edit).await {
+        Ok(Saved::Written) => {
+            document.conflict.send_replace(None);
+            json_response(Ok(()))
+        }
+        Ok(Saved::Conflict(conflict)) => {
+            log::warn!(
+                "An edit to {} conflicted with a change on disk; it was saved to {}",
+                document.title,
+                conflict.path.display(),
+            );
+            let body = serde_json::to_string(&ConflictData {
+                path: &conflict.path,
+            })
+            .unwrap();
+            document.conflict.send_replace(Some(Arc::new(conflict)));
+            http::Response::builder()
+                .status(http::StatusCode::CONFLICT)
+                .header("Content-Type", "application/json")
+                .body(router::full(body))
+                .unwrap()
+        }
+        Err(e) => json_response(Err::<(), _>(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::http;
+
+    use crate::handlers::tests::{holds, TestServer, FROM_PAGE};
+    use crate::renderer;
+
+    #[tokio::test]
+    async fn saves_edits() {
+        let server = TestServer::new("Original\n", false).await;
+        let readme = server.path("README.md");
+
+        let (status, _) = server
+            .send(http::Method::POST, "/content", &[FROM_PAGE], "Edited\n")
+            .await;
+        assert_eq!(status, http::StatusCode::BAD_REQUEST);
+
+        let uri = format!("/content?base={}", renderer::content_hash("Original\n"));
+        // Other sites can't save over the document, even by pointing their own domain at ghmd.
+        let (status, _) = server.send(http::Method::POST, &uri, &[], "Edited\n").await;
+        assert_eq!(status, http::StatusCode::FORBIDDEN);
+        let rebound = [FROM_PAGE, ("host", "attacker.example:1729")];
+        let (status, _) = server
+            .send(http::Method::POST, &uri, &rebound, "Edited\n")
+            .await;
+        assert_eq!(status, http::StatusCode::FORBIDDEN);
+        assert!(holds(&readme, "Original\n"));
+
+        let (status, _) = server
+            .send(http::Method::POST, &uri, &[FROM_PAGE], "Edited\n")
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(holds(&readme, "Edited\n"));
+    }
+
+    #[tokio::test]
+    async fn keeps_conflicting_edits() {
+        let server = TestServer::new("Original\n", false).await;
+        std::fs::write(server.path("README.md"), "Changed on disk\n").unwrap();
+
+        let uri = format!("/content?base={}", renderer::content_hash("Original\n"));
+        let (status, body) = server
+            .send(http::Method::POST, &uri, &[FROM_PAGE], "Edited\n")
+            .await;
+        assert_eq!(status, http::StatusCode::CONFLICT);
+        assert!(body.contains("README.conflict.md"), "{}", body);
+        assert!(holds(&server.path("README.md"), "Changed on disk\n"));
+        assert!(holds(&server.path("README.conflict.md"), "Edited\n"));
+        assert!(server.server.document(0).conflict.borrow().is_some());
+    }
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use hyper::http;
+use percent_encoding::utf8_percent_encode;
+use serde::Serialize;
+
+use crate::router::{self, Query, Rejection, Request, RequestedByPage, Response, SameOrigin};
+use crate::upload;
+use crate::{json_response, DocumentIndex, Server, PATH};
+
+/// Save an image sent as the request body next to the document, and respond with the markdown
+/// that embeds it. The file name can be suggested with a `name` query parameter.
+///
+/// Editor plugins must send `X-Requested-By: ghmd`, as the page does.
+pub(crate) async fn post(
+    server: Arc<Server>,
+    _: SameOrigin,
+    _: RequestedByPage,
+    DocumentIndex(index): DocumentIndex,
+    query: Query,
+    req: Request,
+) -> Response {
+    let document = server.document(index);
+    let content_type = req
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let extension = match upload::extension(content_type) {
+        Some(extension) => extension,
+        None => {
+            return router::text(
+                http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "only PNG, JPEG, GIF, WebP, SVG and AVIF images can be uploaded",
+            )
+        }
+    };
+    let data = match router::read_body(req, upload::MAX_BYTES).await {
+        Ok(data) => data,
+        Err(Rejection { status, message }) => return router::text(status, message),
+    };
+    let name = query.get("name");
+
+    let res = async {
+        let dir = document.base_dir.join(&server.assets_dir);
+        let file_name = upload::save(&dir, name, extension, &data).await?;
+
+        let path = server.assets_dir.join(&file_name);
+        let path = path
+            .iter()
+            .map(|component| utf8_percent_encode(&component.to_string_lossy(), PATH).to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        log::info!("Saved uploaded image to {}", path);
+
+        #[derive(Serialize)]
+        struct Uploaded {
+            path: String,
+            markdown: String,
+        }
+        let alt = Path::new(&file_name).file_stem().unwrap().to_string_lossy();
+        Ok(Uploaded {
+            markdown: format!("![{}]({})", alt, path),
+            path,
+        })
+    }
+    .await;
+    json_response(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::http;
+
+    use crate::handlers::tests::{TestServer, FROM_PAGE};
+
+    #[tokio::test]
+    async fn saves_images() {
+        let server = TestServer::new("Text\n", false).await;
+
+        let text = [FROM_PAGE, ("content-type", "text/plain")];
+        let (status, _) = server
+            .send(http::Method::POST, "/upload", &text, "hello")
+            .await;
+        assert_eq!(status, http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let png = [("content-type", "image/png")];
+        let (status, _) = server
+            .send(http::Method::POST, "/upload", &png, "png")
+            .await;
+        assert_eq!(status, http::StatusCode::FORBIDDEN);
+
+        let png = [FROM_PAGE, ("content-type", "image/png")];
+        let (status, body) = server
+            .send(
+                http::Method::POST,
+                "/upload?name=my%20image.png",
+                &png,
+                "png",
+            )
+            .await;
+        assert_eq!(status, http::StatusCode::OK);
+        let uploaded: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(uploaded["path"], "images/my-image.png");
+        assert_eq!(uploaded["markdown"], "![my-image](images/my-image.png)");
+        assert_eq!(
+            std::fs::read(server.path("images/my-image.png")).unwrap(),
+            b"png"
+        );
+    }
+}
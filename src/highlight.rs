@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use comrak::adapters::SyntaxHighlighterAdapter;
+use comrak::html;
+use once_cell::sync::Lazy;
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+
+/// Names GitHub's Linguist gives languages that syntect knows by another name or extension.
+const ALIASES: &[(&str, &str)] = &[
+    ("shell", "bash"),
+    ("sh", "bash"),
+    ("zsh", "bash"),
+    ("console", "bash"),
+    ("shellsession", "bash"),
+    ("golang", "go"),
+    ("c++", "cpp"),
+    ("c#", "cs"),
+    ("csharp", "cs"),
+    ("objective-c", "m"),
+    ("objc", "m"),
+    ("objective-c++", "mm"),
+    ("yml", "yaml"),
+    ("jsonc", "json"),
+    ("json5", "json"),
+    ("geojson", "json"),
+    ("js", "javascript"),
+    ("node", "javascript"),
+    ("jsx", "javascript"),
+    ("rs", "rust"),
+    ("py", "python"),
+    ("python3", "python"),
+    ("rb", "ruby"),
+    ("tex", "latex"),
+    ("make", "makefile"),
+    ("patch", "diff"),
+    ("udiff", "diff"),
+    ("html+erb", "erb"),
+    ("xhtml", "html"),
+    ("svg", "xml"),
+    ("plist", "xml"),
+];
+
+/// The classes GitHub gives tokens, by the TextMate scope they start with. A token gets the class
+/// of its innermost scope that is listed, checking each scope against the list in order, so more
+/// specific scopes come first. The classes are coloured by the syntax stylesheet of the theme.
+const CLASSES: &[(&str, &str)] = &[
+    ("comment", "pl-c"),
+    ("punctuation.definition.comment", "pl-c"),
+    ("punctuation.definition.string", "pl-pds"),
+    ("punctuation.section.embedded", "pl-pse"),
+    ("string.regexp", "pl-sr"),
+    ("constant.character.escape", "pl-cce"),
+    ("string", "pl-s"),
+    ("constant.other.reference.link", "pl-corl"),
+    ("constant", "pl-c1"),
+    ("variable.other.constant", "pl-c1"),
+    ("variable.language", "pl-c1"),
+    ("support", "pl-c1"),
+    ("meta.property-name", "pl-c1"),
+    ("entity.name.tag", "pl-ent"),
+    ("entity.name", "pl-en"),
+    ("entity", "pl-e"),
+    ("keyword", "pl-k"),
+    ("storage.modifier.import", "pl-smi"),
+    ("storage.modifier.package", "pl-smi"),
+    ("storage", "pl-k"),
+    ("variable.parameter", "pl-smi"),
+    ("variable.other", "pl-smi"),
+    ("variable", "pl-v"),
+    ("invalid.illegal", "pl-ii"),
+    ("invalid.deprecated", "pl-bu"),
+    ("markup.heading", "pl-mh"),
+    ("markup.bold", "pl-mb"),
+    ("markup.italic", "pl-mi"),
+    ("markup.inserted", "pl-mi1"),
+    ("markup.deleted", "pl-md"),
+    ("markup.changed", "pl-mc"),
+    ("markup.list", "pl-ml"),
+    ("markup.raw", "pl-c1"),
+    ("meta.diff.range", "pl-mdr"),
+    ("meta.diff.header", "pl-mdr"),
+];
+
+/// Highlights fenced code blocks for [`gfm::render`](crate::gfm::render) the way GitHub does,
+/// wrapping tokens in spans with GitHub's `pl-*` classes. Code in languages syntect doesn't know
+/// is left as plain text.
+pub(crate) struct Highlighter;
+
+impl SyntaxHighlighterAdapter for Highlighter {
+    fn write_highlighted(
+        &self,
+        output: &mut dyn Write,
+        lang: Option<&str>,
+        code: &str,
+    ) -> io::Result<()> {
+        match lang.and_then(syntax) {
+            Some(syntax) => output.write_all(highlight(syntax, code).as_bytes()),
+            None => html::escape(output, code.as_bytes()),
+        }
+    }
+
+    fn write_pre_tag(
+        &self,
+        output: &mut dyn Write,
+        attributes: HashMap<String, String>,
+    ) -> io::Result<()> {
+        html::write_opening_tag(output, "pre", attributes)
+    }
+
+    fn write_code_tag(
+        &self,
+        output: &mut dyn Write,
+        attributes: HashMap<String, String>,
+    ) -> io::Result<()> {
+        html::write_opening_tag(output, "code", attributes)
+    }
+}
+
+/// The syntax for a code block's language, as written after its opening fence.
+fn syntax(lang: &str) -> Option<&'static SyntaxReference> {
+    let lang = lang.to_ascii_lowercase();
+    let lang = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == lang)
+        .map_or(&*lang, |(_, name)| name);
+    SYNTAXES.find_syntax_by_token(lang)
+}
+
+/// The code as HTML, with each token in a span of its class.
+fn highlight(syntax: &SyntaxReference, code: &str) -> String {
+    let mut html = Html {
+        html: String::with_capacity(code.len() * 2),
+        open: None,
+    };
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut offset = 0;
+    for line in LinesWithEndings::from(code) {
+        let ops = match state.parse_line(line, &SYNTAXES) {
+            Ok(ops) => ops,
+            // Leave the rest unhighlighted rather than lose it.
+            Err(_) => {
+                html.push(None, &code[offset..]);
+                break;
+            }
+        };
+        let mut start = 0;
+        for (end, op) in &ops {
+            html.push(class(&stack), &line[start..*end]);
+            start = *end;
+            if stack.apply(op).is_err() {
+                stack = ScopeStack::new();
+            }
+        }
+        html.push(class(&stack), &line[start..]);
+        offset += line.len();
+    }
+    html.finish()
+}
+
+/// Highlighted HTML being written, which keeps a span open for as long as the tokens written
+/// have the same class.
+struct Html {
+    html: String,
+    /// The class of the span that is open, if any.
+    open: Option<&'static str>,
+}
+
+impl Html {
+    fn push(&mut self, class: Option<&'static str>, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if class != self.open {
+            if self.open.is_some() {
+                self.html.push_str("</span>");
+            }
+            if let Some(class) = class {
+                self.html.push_str("<span class=\"");
+                self.html.push_str(class);
+                self.html.push_str("\">");
+            }
+            self.open = class;
+        }
+        for c in text.chars() {
+            match c {
+                '&' => self.html.push_str("&amp;"),
+                '<' => self.html.push_str("&lt;"),
+                '>' => self.html.push_str("&gt;"),
+                '"' => self.html.push_str("&quot;"),
+                c => self.html.push(c),
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if self.open.is_some() {
+            self.html.push_str("</span>");
+        }
+        self.html
+    }
+}
+
+/// GitHub's class for a token in the given scopes.
+fn class(stack: &ScopeStack) -> Option<&'static str> {
+    stack.as_slice().iter().rev().find_map(|scope| {
+        let scope = scope.build_string();
+        CLASSES.iter().find_map(|(prefix, class)| {
+            let matches = scope
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'));
+            matches.then_some(*class)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{highlight, syntax};
+
+    fn highlight_as(lang: &str, code: &str) -> String {
+        highlight(syntax(lang).unwrap(), code)
+    }
+
+    #[test]
+    fn classes() {
+        let html = highlight_as("rust", "fn main() {\n    let s = \"<b>\"; // hi\n}\n");
+        assert!(
+            html.starts_with("<span class=\"pl-k\">fn</span>"),
+            "{}",
+            html
+        );
+        assert!(
+            html.contains("<span class=\"pl-en\">main</span>"),
+            "{}",
+            html
+        );
+        assert!(html.contains("&lt;b&gt;"), "{}", html);
+        assert!(
+            html.contains("<span class=\"pl-c\">// hi\n</span>"),
+            "{}",
+            html
+        );
+        assert!(html.ends_with("}\n"), "{}", html);
+    }
+
+    #[test]
+    fn text_is_kept() {
+        let code = "if [ \"$a\" = 1 ]; then\n  echo 'a & b' > /dev/null\nfi\n";
+        let html = highlight_as("shell", code);
+        let text = regex::Regex::new("<[^>]*>").unwrap().replace_all(&html, "");
+        let text = text
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&amp;", "&");
+        assert_eq!(text, code);
+    }
+
+    #[test]
+    fn languages() {
+        for lang in [
+            "rust",
+            "Rust",
+            "rs",
+            "js",
+            "JavaScript",
+            "py",
+            "sh",
+            "console",
+            "yml",
+            "diff",
+        ] {
+            assert!(syntax(lang).is_some(), "{}", lang);
+        }
+        for lang in ["", "text", "plaintext", "not-a-language"] {
+            assert!(syntax(lang).is_none(), "{}", lang);
+        }
+    }
+}
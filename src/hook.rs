@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use serde::Serialize;
+use tokio::process::Command;
+
+/// What to do after each render, as configured with `--exec` and `--webhook`.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    pub(crate) exec: Option<Exec>,
+    pub(crate) webhook: Option<Webhook>,
+}
+
+impl Hooks {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.exec.is_none() && self.webhook.is_none()
+    }
+
+    /// Report that the markdown with the given hash was rendered to `output`.
+    pub(crate) async fn rendered(&self, input: &str, hash: &str, output: &Path) {
+        if let Some(webhook) = &self.webhook {
+            webhook.send(input, Some(hash), Ok(())).await;
+        }
+        if let Some(exec) = &self.exec {
+            exec.run(input, output, hash).await;
+        }
+    }
+
+    /// Report that a document failed to render, along with the hash of its markdown if it could
+    /// be read.
+    pub(crate) async fn failed(&self, input: &str, hash: Option<&str>, error: &anyhow::Error) {
+        if let Some(webhook) = &self.webhook {
+            webhook.send(input, hash, Err(error)).await;
+        }
+    }
+}
+
+/// A shell command run after each successful render, such as one that converts the output to a
+/// PDF or checks its links.
+///
+/// It is told about the render through environment variables: `GHMD_INPUT` holds the name of the
+/// document, `GHMD_OUTPUT` the path of the HTML page it was rendered to, and `GHMD_HASH` the hash
+/// of the markdown, as in the page's `ghmd-content-hash` metadata.
+pub(crate) struct Exec {
+    command: String,
+}
+
+impl Exec {
+    pub(crate) fn new(command: String) -> Self {
+        Self { command }
+    }
+
+    /// Run the command for one render, logging rather than returning failures since they don't
+    /// affect the render itself.
+    async fn run(&self, input: &str, output: &Path, hash: &str) {
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+        let status = command
+            .arg(&self.command)
+            .env("GHMD_INPUT", input)
+            .env("GHMD_OUTPUT", output)
+            .env("GHMD_HASH", hash)
+            .status()
+            .await;
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => log::warn!("`{}` exited with {}", self.command, status),
+            Err(e) => log::warn!("Failed to run `{}`: {}", self.command, e),
+        }
+    }
+}
+
+/// A URL that is sent a JSON description of every render as it succeeds or fails, such as:
+///
+/// ```json
+/// {"file": "README.md", "hash": "4c37987f…", "status": "success", "error": null}
+/// ```
+pub(crate) struct Webhook {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Webhook {
+    pub(crate) fn new(client: reqwest::Client, url: String) -> Self {
+        Self { client, url }
+    }
+
+    async fn send(&self, file: &str, hash: Option<&str>, res: Result<(), &anyhow::Error>) {
+        #[derive(Serialize)]
+        struct Payload<'a> {
+            file: &'a str,
+            hash: Option<&'a str>,
+            status: &'static str,
+            error: Option<String>,
+        }
+        let payload = Payload {
+            file,
+            hash,
+            status: if res.is_ok() { "success" } else { "failure" },
+            error: res.err().map(|e| format!("{:#}", e)),
+        };
+        let res = self
+            .client
+            .post(&self.url)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        if let Err(e) = res {
+            // The URL of a webhook often includes a secret.
+            log::warn!("Failed to notify webhook: {}", e.without_url());
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::AsyncWriteExt as _;
+
+use crate::diff::{self, Line};
+use crate::renderer;
+
+/// Every version of a document seen while previewing it, stored as a file of JSON lines with one
+/// entry per version, so that earlier drafts can be looked back through.
+pub(crate) struct Journal {
+    path: PathBuf,
+    entries: Vec<Entry>,
+    /// The contents of the most recent version.
+    latest: String,
+}
+
+/// One version of a document in its journal.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Entry {
+    /// The hash of the version's markdown, as in the page's `ghmd-content-hash` metadata.
+    pub(crate) hash: String,
+    /// When the version was seen, in seconds since the Unix epoch.
+    pub(crate) time: u64,
+    /// How to turn the previous version into this one.
+    diff: Vec<Edit>,
+    /// How many words this version added and removed, which is worked out when the journal is
+    /// read rather than stored.
+    #[serde(skip)]
+    words: (usize, usize),
+}
+
+/// A step in turning one version into the next, working through its lines in order.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Edit {
+    Keep(usize),
+    Remove(usize),
+    Add(Vec<String>),
+}
+
+impl Entry {
+    /// How many lines this version added and removed.
+    pub(crate) fn changed_lines(&self) -> (usize, usize) {
+        self.diff
+            .iter()
+            .fold((0, 0), |(added, removed), edit| match edit {
+                Edit::Keep(_) => (added, removed),
+                Edit::Remove(n) => (added, removed + n),
+                Edit::Add(lines) => (added + lines.len(), removed),
+            })
+    }
+
+    /// How many words this version added and removed.
+    pub(crate) fn changed_words(&self) -> (usize, usize) {
+        self.words
+    }
+}
+
+impl Journal {
+    /// Open the journal of the document with the given key, such as its path, creating it if it
+    /// doesn't exist yet.
+    #[context("failed to open journal")]
+    pub(crate) async fn open(dir: &Path, key: &str) -> anyhow::Result<Self> {
+        let path = dir.join(format!("{}.jsonl", renderer::content_hash(key)));
+        let data = match fs::read_to_string(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read `{}`", path.display()))
+            }
+        };
+        let mut journal = Self {
+            path,
+            entries: Vec::new(),
+            latest: String::new(),
+        };
+        for (i, line) in data.lines().enumerate() {
+            let mut entry: Entry = serde_json::from_str(line).with_context(|| {
+                format!("line {} of `{}` is invalid", i + 1, journal.path.display())
+            })?;
+            entry.words = changed_words(&journal.latest, &entry.diff);
+            journal.latest = apply(&journal.latest, &entry.diff);
+            journal.entries.push(entry);
+        }
+        Ok(journal)
+    }
+
+    /// Add a version to the end of the journal, unless it is the same as the latest one.
+    #[context("failed to write to journal")]
+    pub(crate) async fn record(&mut self, contents: &str) -> anyhow::Result<()> {
+        if !self.entries.is_empty() && contents == self.latest {
+            return Ok(());
+        }
+        let old: Vec<&str> = self.latest.split_inclusive('\n').collect();
+        let new: Vec<&str> = contents.split_inclusive('\n').collect();
+        let mut diff: Vec<Edit> = Vec::new();
+        for line in diff::sequences(&old, &new) {
+            match (line, diff.last_mut()) {
+                (Line::Same(_), Some(Edit::Keep(n))) => *n += 1,
+                (Line::Same(_), _) => diff.push(Edit::Keep(1)),
+                (Line::Removed(_), Some(Edit::Remove(n))) => *n += 1,
+                (Line::Removed(_), _) => diff.push(Edit::Remove(1)),
+                (Line::Added(line), Some(Edit::Add(lines))) => lines.push(line.to_owned()),
+                (Line::Added(line), _) => diff.push(Edit::Add(vec![line.to_owned()])),
+            }
+        }
+        let entry = Entry {
+            hash: renderer::content_hash(contents),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            words: changed_words(&self.latest, &diff),
+            diff,
+        };
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .with_context(|| format!("failed to open `{}`", self.path.display()))?;
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        file.write_all(line.as_bytes()).await?;
+
+        self.entries.push(entry);
+        self.latest = contents.to_owned();
+        Ok(())
+    }
+
+    pub(crate) fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The contents of the document as of an entry.
+    pub(crate) fn version(&self, index: usize) -> Option<String> {
+        let entries = self.entries.get(..=index)?;
+        Some(
+            entries
+                .iter()
+                .fold(String::new(), |text, entry| apply(&text, &entry.diff)),
+        )
+    }
+}
+
+fn apply(old: &str, diff: &[Edit]) -> String {
+    let mut old = old.split_inclusive('\n');
+    let mut new = String::with_capacity(old.clone().map(str::len).sum());
+    for edit in diff {
+        match edit {
+            Edit::Keep(n) => new.extend(old.by_ref().take(*n)),
+            Edit::Remove(n) => {
+                old.by_ref().take(*n).for_each(drop);
+            }
+            Edit::Add(lines) => new.extend(lines.iter().map(String::as_str)),
+        }
+    }
+    new
+}
+
+/// Count the words in the lines added and removed by applying a diff to `old`.
+fn changed_words(old: &str, diff: &[Edit]) -> (usize, usize) {
+    let words = |line: &str| line.split_whitespace().count();
+    let mut old = old.split_inclusive('\n');
+    let (mut added, mut removed) = (0, 0);
+    for edit in diff {
+        match edit {
+            Edit::Keep(n) => {
+                old.by_ref().take(*n).for_each(drop);
+            }
+            Edit::Remove(n) => removed += old.by_ref().take(*n).map(words).sum::<usize>(),
+            Edit::Add(lines) => added += lines.iter().map(|line| words(line)).sum::<usize>(),
+        }
+    }
+    (added, removed)
+}
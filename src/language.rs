@@ -0,0 +1,236 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Give every fenced code block that has no info string a guessed language, so that GitHub
+/// highlights it. Returns the markdown unchanged if no block was labelled.
+///
+/// GitHub itself never guesses, so this makes the preview differ from the real page; it is only
+/// ever enabled explicitly.
+pub(crate) fn label_fences(markdown: &str) -> Cow<'_, str> {
+    let mut res = String::with_capacity(markdown.len());
+    let mut changed = false;
+    let mut lines = markdown.split_inclusive('\n');
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+        let marker = match trimmed.chars().next().filter(|c| matches!(c, '`' | '~')) {
+            Some(marker) => marker,
+            None => {
+                res.push_str(line);
+                continue;
+            }
+        };
+        let len = trimmed.chars().take_while(|&c| c == marker).count();
+        if len < 3 {
+            res.push_str(line);
+            continue;
+        }
+
+        let mut body = String::new();
+        let mut closing = None;
+        for line in lines.by_ref() {
+            let inner = line.trim_start();
+            let inner_len = inner.chars().take_while(|&c| c == marker).count();
+            if inner_len >= len && inner[inner_len..].trim().is_empty() {
+                closing = Some(line);
+                break;
+            }
+            body.push_str(line);
+        }
+
+        match trimmed[len..]
+            .trim()
+            .is_empty()
+            .then(|| guess(&body))
+            .flatten()
+        {
+            Some(language) => {
+                let indent = &line[..line.len() - trimmed.len()];
+                let newline = &trimmed[trimmed.trim_end().len()..];
+                res.push_str(indent);
+                res.push_str(&trimmed[..len]);
+                res.push_str(language);
+                res.push_str(newline);
+                changed = true;
+            }
+            None => res.push_str(line),
+        }
+        res.push_str(&body);
+        res.extend(closing);
+    }
+
+    if changed {
+        Cow::Owned(res)
+    } else {
+        Cow::Borrowed(markdown)
+    }
+}
+
+/// Guess the language of a code block from its contents, using the names GitHub Linguist gives
+/// them.
+fn guess(code: &str) -> Option<&'static str> {
+    /// Each language with patterns characteristic of it. A block must match at least two
+    /// patterns of a language to be given that language.
+    static LANGUAGES: Lazy<Vec<(&str, Vec<Regex>)>> = Lazy::new(|| {
+        [
+            (
+                "rust",
+                &[
+                    r"\bfn \w+(<.*>)?\(",
+                    r"\blet (mut )?\w+",
+                    r"^\s*(pub(\(crate\))? )?(struct|enum|trait|impl|mod) ",
+                    r"^\s*use \w+(::\w+)+",
+                    r"\w+!\(",
+                    r"::<|&mut |-> \w+",
+                ][..],
+            ),
+            (
+                "python",
+                &[
+                    r"^\s*def \w+\(.*\):",
+                    r"^\s*(from [\w.]+ )?import \w+",
+                    r"^\s*class \w+(\(.*\))?:",
+                    r"\bself\.\w+",
+                    r"\bprint\(",
+                    r"^\s*(if|for|while|elif|else|try|except).*:\s*$",
+                ][..],
+            ),
+            (
+                "typescript",
+                &[
+                    r"\binterface \w+ \{",
+                    r"\b(export )?type \w+ = ",
+                    r"\w+\??: (string|number|boolean|any)\b",
+                    r"\b(const|let) \w+: \w+",
+                ][..],
+            ),
+            (
+                "javascript",
+                &[
+                    r"\b(const|let|var) \w+ = ",
+                    r"\bfunction\s*\w*\(",
+                    r"=> ",
+                    r"\bconsole\.\w+\(",
+                    r"\brequire\(|^\s*(import|export) .* from ",
+                    r"\bdocument\.|\bwindow\.",
+                ][..],
+            ),
+            (
+                "go",
+                &[
+                    r"^package \w+",
+                    r"\bfunc (\(.*\) )?\w+\(",
+                    r":= ",
+                    r"\bfmt\.\w+\(",
+                    r#"^import \(|^import ""#,
+                ][..],
+            ),
+            (
+                "c",
+                &[
+                    r"^#include [<\x22]",
+                    r"\bint main\(",
+                    r"\bprintf\(",
+                    r"^#define ",
+                    r"\b(void|char|int|unsigned) \*?\w+\(",
+                ][..],
+            ),
+            (
+                "shell",
+                &[
+                    r"^\$ ",
+                    r"^#!/(usr/)?bin/(env )?(ba|z)?sh",
+                    r"^\s*(sudo|cd|ls|echo|export|cargo|npm|yarn|pip|git|curl|make|apt|brew) ",
+                    r"\$\{?\w+\}?",
+                    r"\s(&&|\|\|)\s|\s\|\s",
+                ][..],
+            ),
+            (
+                "html",
+                &[
+                    r"(?i)^\s*<!doctype html",
+                    r"(?i)<(html|head|body|div|span|p|a|ul|li|script|style)[\s>]",
+                    r"</\w+>",
+                ][..],
+            ),
+            (
+                "css",
+                &[
+                    r"^[\w.#:\-\[\]=, >*]+\s*\{\s*$",
+                    r"^\s*[\w-]+:\s*[^;]+;\s*$",
+                    r"@media |@import ",
+                ][..],
+            ),
+            (
+                "toml",
+                &[
+                    r"^\[[\w.\-]+\]\s*$",
+                    r#"^[\w\-]+ = ("|\d|\{|\[|true|false)"#,
+                ][..],
+            ),
+            (
+                "yaml",
+                &[
+                    r"^[\w\-]+:( |$)",
+                    r"^\s+- \w+",
+                    r"^\s+[\w\-]+: ",
+                    r"^---\s*$",
+                ][..],
+            ),
+            (
+                "sql",
+                &[
+                    r"(?i)\bselect\b.*\bfrom\b",
+                    r"(?i)\b(insert into|update \w+ set|delete from)\b",
+                    r"(?i)\bcreate (table|index|view)\b",
+                    r"(?i)\b(where|join|group by|order by)\b",
+                ][..],
+            ),
+        ]
+        .into_iter()
+        .map(|(language, patterns)| {
+            let patterns = patterns
+                .iter()
+                .map(|pattern| Regex::new(&format!("(?m){}", pattern)).unwrap())
+                .collect();
+            (language, patterns)
+        })
+        .collect()
+    });
+
+    let trimmed = code.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+
+    // Some formats can be recognized with certainty.
+    if matches!(trimmed.as_bytes()[0], b'{' | b'[')
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return Some("json");
+    }
+    if trimmed.lines().any(|line| line.starts_with("@@ "))
+        && trimmed.lines().all(|line| {
+            line.is_empty()
+                || line.starts_with([' ', '+', '-', '@', '\\'])
+                || line.starts_with("diff ")
+        })
+    {
+        return Some("diff");
+    }
+
+    LANGUAGES
+        .iter()
+        .enumerate()
+        .map(|(i, (language, patterns))| {
+            let score = patterns.iter().filter(|p| p.is_match(code)).count();
+            (score, Reverse(i), *language)
+        })
+        .filter(|&(score, ..)| score >= 2)
+        // Ties go to the earliest language, so more specific languages are listed first.
+        .max()
+        .map(|(.., language)| language)
+}
@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+use percent_encoding::percent_decode_str;
+use scraper::{Html, Selector};
+use serde::Serialize;
+
+use crate::slug::{self, Slugger};
+
+/// A problem found in the rendered document.
+#[derive(Serialize)]
+pub(crate) struct Lint {
+    pub(crate) kind: &'static str,
+    pub(crate) message: String,
+    /// The `href` of the offending link, if the problem is with a link.
+    pub(crate) href: Option<String>,
+}
+
+/// Run every lint over rendered HTML.
+pub(crate) fn run(html: &str) -> Vec<Lint> {
+    let mut lints = anchor_lints(html);
+    lints.extend(invisible_in_headings(html));
+    lints.extend(bidi_controls(html));
+    lints.extend(confusable_links(html));
+    lints
+}
+
+fn anchor_lints(html: &str) -> Vec<Lint> {
+    let anchors = heading_anchors(html);
+    dead_anchors(html)
+        .into_iter()
+        .map(|href| {
+            let fragment = percent_decode_str(&href[1..]).decode_utf8_lossy();
+            let mut message = format!("`{}` does not link to any heading or anchor", href);
+            let suggestion = slug::slug(&fragment);
+            if anchors.contains(&suggestion) {
+                message += &format!("; did you mean `#{}`?", suggestion);
+            }
+            Lint {
+                kind: "dead-anchor",
+                message,
+                href: Some(href),
+            }
+        })
+        .collect()
+}
+
+/// Characters that take up no space, which are easy to paste into a heading by accident and then
+/// end up in its anchor, so that links typed by hand don't match it.
+const ZERO_WIDTH: &[char] = &['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}'];
+
+/// Characters that change the direction of the text around them without being visible
+/// themselves, which can make text display differently from how it reads in the source.
+const BIDI_CONTROLS: &[char] = &[
+    '\u{202A}', '\u{202B}', '\u{202C}', '\u{202D}', '\u{202E}', '\u{2066}', '\u{2067}', '\u{2068}',
+    '\u{2069}',
+];
+
+fn invisible_in_headings(html: &str) -> Vec<Lint> {
+    static HEADINGS: Lazy<Selector> =
+        Lazy::new(|| Selector::parse("h1, h2, h3, h4, h5, h6").unwrap());
+    Html::parse_fragment(html)
+        .select(&HEADINGS)
+        .filter_map(|heading| {
+            let text = heading.text().collect::<String>();
+            let c = text.chars().find(|c| ZERO_WIDTH.contains(c))?;
+            Some(Lint {
+                kind: "invisible-in-heading",
+                message: format!(
+                    "the heading `{}` contains the invisible character {}, which becomes part of its anchor",
+                    text.trim().replace(ZERO_WIDTH, ""),
+                    codepoint(c),
+                ),
+                href: None,
+            })
+        })
+        .collect()
+}
+
+fn bidi_controls(html: &str) -> Vec<Lint> {
+    let html = Html::parse_fragment(html);
+    let mut lints = Vec::new();
+    for text in html.root_element().text() {
+        if let Some(c) = text.chars().find(|c| BIDI_CONTROLS.contains(c)) {
+            let text = text.trim().replace(BIDI_CONTROLS, "");
+            let excerpt: String = text.chars().take(40).collect();
+            lints.push(Lint {
+                kind: "bidi-control",
+                message: format!(
+                    "the text `{}` contains the bidirectional control character {}, which changes how it is displayed",
+                    excerpt,
+                    codepoint(c),
+                ),
+                href: None,
+            });
+        }
+    }
+    lints
+}
+
+/// Find links whose host mixes Latin letters with lookalikes from other scripts, such as a
+/// Cyrillic `а` in place of `a`.
+fn confusable_links(html: &str) -> Vec<Lint> {
+    static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a[href]").unwrap());
+
+    let mut lints: Vec<Lint> = Vec::new();
+    for link in Html::parse_fragment(html).select(&LINKS) {
+        let href = link.value().attr("href").unwrap();
+        let url = percent_decode_str(href).decode_utf8_lossy();
+        let host = match url.split_once("://") {
+            Some((_, rest)) => rest.split(['/', '?', '#']).next().unwrap(),
+            None => continue,
+        };
+        // Greek and Cyrillic hosts are fine on their own, but fullwidth forms never are.
+        let latin = host.chars().any(|c| c.is_ascii_alphabetic());
+        let lookalike = host.chars().find(|&c| {
+            (latin && matches!(c, '\u{0370}'..='\u{052F}')) || matches!(c, '\u{FF01}'..='\u{FF5E}')
+        });
+        let c = match lookalike {
+            Some(c) => c,
+            None => continue,
+        };
+        if lints.iter().any(|lint| lint.href.as_deref() == Some(href)) {
+            continue;
+        }
+        lints.push(Lint {
+            kind: "confusable-link",
+            message: format!(
+                "the host of `{}` contains {}, which looks like a Latin letter",
+                url,
+                codepoint(c),
+            ),
+            href: Some(href.to_owned()),
+        });
+    }
+    lints
+}
+
+/// Describe a character by its code point, such as `U+200D`.
+fn codepoint(c: char) -> String {
+    format!("U+{:04X}", u32::from(c))
+}
+
+/// The anchors GitHub generates for the document's headings, in order.
+fn heading_anchors(html: &str) -> Vec<String> {
+    static HEADINGS: Lazy<Selector> =
+        Lazy::new(|| Selector::parse("h1, h2, h3, h4, h5, h6").unwrap());
+    let mut slugger = Slugger::new();
+    Html::parse_fragment(html)
+        .select(&HEADINGS)
+        .map(|heading| slugger.slug(heading.text().collect::<String>().trim()))
+        .collect()
+}
+
+/// Find fragment links that don't resolve to any element in the document.
+///
+/// This works on GitHub's output, so it agrees with GitHub's slugging rules by construction.
+pub(crate) fn dead_anchors(html: &str) -> Vec<String> {
+    static IDS: Lazy<Selector> = Lazy::new(|| Selector::parse("[id], a[name]").unwrap());
+    static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a[href^='#']").unwrap());
+
+    let html = Html::parse_fragment(html);
+
+    let mut targets = HashSet::new();
+    for element in html.select(&IDS) {
+        for attr in ["id", "name"] {
+            if let Some(id) = element.value().attr(attr) {
+                // GitHub prefixes user IDs, and its JavaScript follows unprefixed links to them.
+                targets.insert(id.strip_prefix("user-content-").unwrap_or(id).to_owned());
+            }
+        }
+    }
+
+    let mut dead = Vec::new();
+    for link in html.select(&LINKS) {
+        let href = link.value().attr("href").unwrap();
+        let fragment = percent_decode_str(&href[1..]).decode_utf8_lossy();
+        let fragment = fragment.strip_prefix("user-content-").unwrap_or(&fragment);
+        if fragment.is_empty() || fragment == "top" {
+            continue;
+        }
+        let resolves = targets.contains(fragment) || targets.contains(&fragment.to_lowercase());
+        if !resolves && !dead.iter().any(|d| d == href) {
+            dead.push(href.to_owned());
+        }
+    }
+    dead
+}
+
+/// Mark dead fragment links in rendered HTML so they stand out in the preview.
+pub(crate) fn mark_dead_anchors(html: &str) -> String {
+    let mut html = html.to_owned();
+    for href in dead_anchors(&html) {
+        let escaped = tera::escape_html(&href);
+        html = html.replace(
+            &format!("<a href=\"{}\">", escaped),
+            &format!(
+                "<a href=\"{}\" class=\"ghmd-dead-anchor\" \
+                    style=\"text-decoration: underline wavy var(--color-danger-fg, red)\" \
+                    title=\"No heading or anchor with this ID exists\">",
+                escaped
+            ),
+        );
+    }
+    html
+}
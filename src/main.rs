@@ -1,94 +1,1124 @@
 use std::convert::Infallible;
-use std::error::Error as StdError;
-use std::fmt::{self, Debug, Display, Formatter};
+use std::mem;
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::{NonZeroU32, NonZeroU8, NonZeroUsize};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{anyhow, Context as _};
-use async_stream::try_stream;
+use anyhow::{anyhow, bail, ensure, Context as _};
+use async_stream::stream;
 use clap::Parser;
+use http_body_util::BodyExt as _;
 use hyper::http;
-use hyper::server::conn::Http;
-use hyper::service::service_fn;
+use hyper::server::conn::http1;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hyper_util::service::TowerToHyperService;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::Serialize;
 use tokio::net::TcpListener;
-use tokio::sync::watch;
 use tokio::sync::Notify;
-use tokio::{fs, signal};
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::{fs, signal, time};
+use tower::{ServiceBuilder, ServiceExt as _};
+
+mod audit;
+
+mod badge;
+
+mod clipboard;
+
+mod command;
+
+mod compare;
+
+mod config;
+use config::{DocumentOptions, ProjectConfig};
+
+mod content;
+use content::Conflict;
+
+mod cors;
+use cors::Cors;
+
+mod daemon;
+
+mod diff;
+
+mod directory;
+
+mod dirs;
+
+mod doctor;
+
+mod gfm;
+
+mod files;
+
+mod handlers;
+
+mod highlight;
+
+mod hook;
+use hook::{Exec, Hooks, Webhook};
+
+mod upload;
+
+mod vendor;
 
 mod watcher;
+use watcher::{is_stdin, DocumentState, Watcher};
+
+mod journal;
+use journal::Journal;
+
+mod language;
+
+mod lint;
+
+#[cfg(feature = "mock")]
+mod mock;
+
+mod normalize;
+use normalize::{IgnoreRegions, Normalizer};
+
+mod outline;
+
+mod paths;
+
+mod prose;
+
+mod queue;
+use queue::Priority;
+
+mod quota;
+
+mod rate_limit;
+use rate_limit::RateLimit;
+
+mod raw_html;
+
+mod recording;
+
+mod readme;
+
+mod redact;
+use redact::redact;
 
 mod renderer;
-use renderer::{RateLimited, Renderer};
+use renderer::{AuthError, Octicons, RateLimited, RenderError, Renderer};
+
+mod review;
+
+mod revisions;
+use revisions::Revisions;
+
+mod router;
+use router::{FromRequest, LocalAddr, Query, Rejection, RemoteAddr, Request, Response, Router};
+
+mod scaffold;
+
+mod screenshot;
+
+mod self_test;
+
+mod slug;
+
+mod snapshot;
 
 mod templater;
-use templater::{Liveness, Templater, Theme};
+use templater::{Liveness, PageSettings, Tab, Templater, Theme};
+
+mod terminal;
+
+mod toc;
+
+const USER_AGENT: &str = concat!("ghmd/", env!("CARGO_PKG_VERSION"));
+
+/// Characters that must be escaped in a path in a markdown link.
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'(')
+    .add(b')')
+    .add(b'<')
+    .add(b'>')
+    .add(b'%');
+
+/// Characters that must be escaped in a segment of a URL's path.
+const SEGMENT: &AsciiSet = &PATH.add(b'"').add(b'#').add(b'?').add(b'/');
+
+/// How many documents can be opened by following links, in addition to those given on the
+/// command line. Each is watched and rendered for as long as ghmd runs.
+const MAX_LINKED: usize = 20;
+
+/// Shown above edits rendered locally while the API quota is low, until GitHub renders them.
+const LOCAL_RENDER_NOTICE: &str = "<div class=\"flash mb-3\">Rendered locally because few API \
+    requests remain. GitHub's render replaces this once typing pauses.</div>";
 
 #[derive(Parser)]
 #[clap(about = "GitHub Markdown previewer")]
-#[clap(group(clap::ArgGroup::new("action").args(&["port", "output"])))]
+#[clap(group(clap::ArgGroup::new("action").args(&["port", "output", "self-test", "tui"])))]
+#[clap(setting = clap::AppSettings::SubcommandsNegateReqs)]
 struct Args {
-    /// The markdown file to render.
-    #[clap(parse(from_os_str))]
-    input: PathBuf,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// The markdown file to render, or `-` to read it from standard input. Several files can be
+    /// given to preview them together, each in its own tab, or a directory to browse every
+    /// markdown file in it.
+    #[clap(parse(from_os_str), required_unless_present = "input-command")]
+    input: Vec<PathBuf>,
+
+    /// Instead of reading a file, render the output of this command, such as `cargo readme`. It
+    /// is run again whenever a path given with `--watch` changes. Arguments are split as a shell
+    /// would split them, so they can be quoted, but the command isn't run by a shell.
+    #[clap(
+        long = "command",
+        value_name = "COMMAND",
+        conflicts_with_all = &["input", "daemon"]
+    )]
+    input_command: Option<String>,
+
+    /// How often to run `--command` again, such as `5s`.
+    #[clap(long, parse(try_from_str = humantime::parse_duration), requires = "input-command")]
+    interval: Option<Duration>,
 
     /// The authorization token to use. You can create a personal one at
-    /// <https://github.com/settings/tokens>.
-    #[clap(short, long, env = "GITHUB_TOKEN")]
-    token: String,
+    /// <https://github.com/settings/tokens>. If this isn't given, `GH_TOKEN` is tried as well,
+    /// and no token is needed when rendering offline.
+    #[clap(short, long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Read the token from a file, so that it doesn't appear in process listings or shell
+    /// history. Whitespace around it is ignored. This takes precedence over the environment
+    /// variables.
+    #[clap(long, parse(from_os_str), value_name = "PATH")]
+    token_file: Option<PathBuf>,
 
     /// The theme to generate the resulting page using.
     #[clap(long, arg_enum, ignore_case = true, default_value_t)]
     theme: Theme,
 
+    /// Generate one output file per theme instead of using `--theme`, inserting the theme's name
+    /// before the output file's extension. Useful for checking that images and badges look right
+    /// in every theme.
+    #[clap(
+        long,
+        arg_enum,
+        ignore_case = true,
+        use_delimiter = true,
+        requires = "output",
+        conflicts_with = "theme"
+    )]
+    themes: Vec<Theme>,
+
     /// The title of the page. Defaults to the filename.
     #[clap(long)]
     title: Option<String>,
 
-    /// The port the server should bind to.
+    /// The port the server should bind to, or `0` to use any free port, such as to run several
+    /// previews at once. The URL to visit is printed on startup.
     #[clap(short, long, default_value = "39131")]
     port: u16,
 
+    /// An address the server should listen on, such as `0.0.0.0` to accept connections from other
+    /// machines or an IPv6 address like `::`. May be given multiple times. Defaults to both
+    /// `127.0.0.1` and `::1`.
+    #[clap(long, value_name = "ADDRESS", parse(try_from_str = parse_ip))]
+    bind: Vec<IpAddr>,
+
+    /// An origin allowed to call the `/api/` routes from a browser, such as
+    /// `https://example.com`, or `*` to allow any origin. May be given multiple times.
+    #[clap(long, value_name = "ORIGIN")]
+    cors: Vec<String>,
+
+    /// Limit each client to this many requests to the `/api/` routes per minute, for when the
+    /// server is reachable by others. Requests over the limit get a `429 Too Many Requests`.
+    #[clap(long, value_name = "REQUESTS")]
+    rate_limit: Option<NonZeroU32>,
+
+    /// Additional paths whose changes should cause the page to reload, such as included fragments
+    /// or image directories, or `--command` to be run again. May be given multiple times and may
+    /// contain glob patterns.
+    #[clap(short, long, value_name = "PATH")]
+    watch: Vec<String>,
+
+    /// The maximum number of requests to make to the GitHub API at once. GitHub recommends
+    /// making requests serially to avoid triggering its abuse detection.
+    #[clap(long, alias = "max-concurrent-renders", default_value = "1")]
+    concurrency: NonZeroUsize,
+
+    /// The minimum time between the starts of requests made in the background, such as by
+    /// `export`, `snapshot` and `review`, to keep batches of them under GitHub's secondary rate
+    /// limits. Renders someone is waiting to see are never delayed.
+    #[clap(long, parse(try_from_str = humantime::parse_duration), default_value = "1s")]
+    request_interval: Duration,
+
+    /// When fewer than this many GitHub API requests remain in the current rate limit window,
+    /// render saves offline and only use the API once typing has paused, for long enough that the
+    /// remaining requests last until the window resets.
+    #[clap(long, default_value = "100", value_name = "REQUESTS")]
+    quota_threshold: u32,
+
+    /// Don't re-render the document when the only changes are to HTML comments, frontmatter, or
+    /// text matching a `--trivial-pattern`.
+    #[clap(long)]
+    skip_trivial_changes: bool,
+
+    /// A regular expression matching text whose changes shouldn't cause a re-render, such as a
+    /// generated table of contents. May be given multiple times. Implies `--skip-trivial-changes`.
+    #[clap(long, value_name = "REGEX")]
+    trivial_pattern: Vec<Regex>,
+
+    /// An extra header to send with every request to GitHub and to the octicon CDN, such as
+    /// `X-Foo: bar`. May be given multiple times; a `User-Agent` header replaces the default one.
+    #[clap(long, value_name = "HEADER", parse(try_from_str = parse_header))]
+    header: Vec<(HeaderName, HeaderValue)>,
+
+    /// The GitHub REST API version to request, sent as the `X-GitHub-Api-Version` header. Useful
+    /// for pinning behavior or trying out a newer version.
+    #[clap(long, default_value = renderer::DEFAULT_API_VERSION, value_name = "VERSION")]
+    api_version: String,
+
+    /// Save every GitHub API request and response in this directory, for later use with
+    /// `--replay`.
+    #[clap(long, parse(from_os_str), value_name = "DIR")]
+    record: Option<PathBuf>,
+
+    /// Serve renders from responses saved with `--record` instead of contacting GitHub, so that no
+    /// token or network connection is needed.
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "DIR",
+        conflicts_with = "record"
+    )]
+    replay: Option<PathBuf>,
+
+    /// Render on this machine with comrak instead of GitHub's API, so that ghmd works without a
+    /// network connection or token. The output is close to GitHub's, but code is highlighted by
+    /// syntect, which knows fewer languages, and references like `#123` and emoji shortcodes aren't
+    /// expanded. Octicons aren't fetched either.
+    #[clap(long, conflicts_with_all = &["record", "replay"])]
+    offline: bool,
+
+    /// Render with a built-in imitation of GitHub's API instead of GitHub itself, so that ghmd can
+    /// be tested end to end without a token or network access.
+    #[cfg(feature = "mock")]
+    #[clap(long, conflicts_with_all = &["replay", "offline"])]
+    mock_api: bool,
+
+    /// How long to wait for an octicon to be fetched from the CDN before giving up on it.
+    #[clap(long, default_value = "10", value_name = "SECONDS")]
+    octicon_timeout: u64,
+
+    /// A proxy to fetch octicons through, separate from any proxy configured for the GitHub API
+    /// with the usual environment variables.
+    #[clap(long, value_name = "URL")]
+    octicon_proxy: Option<String>,
+
+    /// Never fetch octicons from the CDN, for when it is unreachable.
+    #[clap(long, conflicts_with_all = &["octicon-timeout", "octicon-proxy"])]
+    offline_octicons: bool,
+
+    /// Make sure pages make no requests to other sites, for machines that are air-gapped or must
+    /// not contact third parties. The stylesheets pages load from a CDN are downloaded once into
+    /// the cache directory and included in the pages, and a Content Security Policy blocks
+    /// anything else, including images hosted elsewhere. ghmd itself still contacts GitHub.
+    #[clap(long)]
+    no_external: bool,
+
+    /// An additional pair of lines delimiting regions that are removed before rendering, like the
+    /// built-in `<!-- ghmd:ignore-start -->` and `<!-- ghmd:ignore-end -->`. May be given multiple
+    /// times. Documents can add their own with `ignore-start` and `ignore-end` in their
+    /// frontmatter.
+    #[clap(long, number_of_values = 2, value_names = &["START", "END"])]
+    ignore_region: Vec<String>,
+
+    /// Guess the language of code blocks that don't specify one, so they are highlighted. GitHub
+    /// never does this, so such blocks will be highlighted in the preview only.
+    #[clap(long)]
+    guess_languages: bool,
+
+    /// Show a banner above the document listing local images that are very large in file size or
+    /// dimensions. The same report is always available as JSON at `/api/audit`.
+    #[clap(long)]
+    audit_banner: bool,
+
+    /// Run a prose linter or spell checker, such as `vale --output=line`, whenever the document
+    /// changes, and underline what it finds in the preview. The document's path is appended to the
+    /// command, which must print each finding as `FILE:LINE:COLUMN:MESSAGE`. Arguments can be
+    /// quoted as they would be in a shell.
+    #[clap(long, value_name = "COMMAND")]
+    prose_lint: Option<String>,
+
+    /// Record every version of each document in a journal in this directory, which can be looked
+    /// back through at `/history` or from the History menu, and restored from there. Journals are
+    /// kept between runs.
+    #[clap(long, parse(from_os_str), value_name = "DIR")]
+    journal: Option<PathBuf>,
+
+    /// Run a shell command after each successful render, such as one that converts the page to a
+    /// PDF. It is given the name of the document in `GHMD_INPUT`, the path of the rendered page in
+    /// `GHMD_OUTPUT` and a hash of the markdown in `GHMD_HASH`. With `--output` the page is the
+    /// output file; otherwise every change is rendered even when no browser is open, and the page
+    /// is saved to a temporary file.
+    #[clap(long, value_name = "COMMAND", conflicts_with_all = &["self-test", "tui"])]
+    exec: Option<String>,
+
+    /// POST a JSON description of each render to a URL as it succeeds or fails, giving the
+    /// document's name, a hash of its markdown, `success` or `failure`, and the error message if
+    /// any. Like `--exec`, this makes the server render every change even with no browser open.
+    #[clap(long, value_name = "URL", conflicts_with_all = &["self-test", "tui"])]
+    webhook: Option<String>,
+
+    /// The directory, relative to the document, that images sent to `/upload` are saved in.
+    /// Defaults to the document's own directory.
+    #[clap(long, parse(from_os_str), value_name = "DIR")]
+    assets_dir: Option<PathBuf>,
+
+    /// Have the server's pages load ghmd's scripts and styles from `/assets/` instead of including
+    /// them, so that browsers cache them and each reload is smaller.
+    #[clap(long, conflicts_with = "output")]
+    asset_routes: bool,
+
+    /// Once the server is listening, print a single line of JSON to standard output describing
+    /// where it can be reached, for use by scripts and editor plugins.
+    #[clap(long)]
+    porcelain: bool,
+
+    /// How far below the top of the window to scroll headings when following links to them, in
+    /// pixels, for when `.ghmd.toml`'s `css` adds a fixed header that would cover them. Documents
+    /// can set `scroll-margin` in their frontmatter instead.
+    #[clap(long, value_name = "PIXELS")]
+    scroll_margin: Option<u32>,
+
+    /// How many columns wide a tab in code should be shown as. GitHub uses 8 unless the
+    /// repository's `.editorconfig` or the reader's settings say otherwise.
+    #[clap(long, value_name = "COLUMNS", default_value = "8")]
+    tab_size: NonZeroU8,
+
+    /// Number headings like `1.`, `1.2.` and `1.2.3.`, in the page and its outline, as for a
+    /// specification. A lone top-level heading is taken to be the title and left unnumbered.
+    /// Can also be turned on with `number_headings` in `.ghmd.toml`.
+    #[clap(long)]
+    number_headings: bool,
+
+    /// Give local images their size before they load, read from their files, and show a
+    /// placeholder in their place until they do, so that documents with many screenshots are laid
+    /// out at once. Can also be turned on with `image_placeholders` in `.ghmd.toml`.
+    #[clap(long)]
+    image_placeholders: bool,
+
+    /// The GitHub repository the documents are in, as `owner/name`. Used to resolve references
+    /// like `#123`, and image paths the way GitHub will. Overrides `repository` in `.ghmd.toml`.
+    #[clap(long, value_name = "OWNER/NAME", parse(try_from_str = parse_repository))]
+    repo: Option<String>,
+
+    /// The branch of the repository the documents will be viewed on, used to resolve image paths
+    /// and links to GitHub. Overrides `branch` in `.ghmd.toml`.
+    #[clap(long)]
+    branch: Option<String>,
+
+    /// Render single newlines as line breaks, as GitHub does in issues and comments. This is
+    /// always the case when a repository is set with `--repo` or in `.ghmd.toml`, since GitHub
+    /// only resolves references in that mode.
+    #[clap(long)]
+    hard_breaks: bool,
+
+    /// Remove HTML tags written in the document before rendering it, keeping the text between
+    /// them, to see how it reads without them.
+    #[clap(long, conflicts_with = "flag-html")]
+    strip_html: bool,
+
+    /// Outline the elements in the preview that were written as HTML in the document, since
+    /// GitHub's sanitizer often changes or removes them.
+    #[clap(long)]
+    flag_html: bool,
+
+    /// Mark where GitHub stops showing the document when it is the README on a repository's
+    /// page, which happens to READMEs over 500 KiB.
+    #[clap(long)]
+    readme: bool,
+
+    /// Show the document in a sandboxed iframe, where it can't run scripts or reach the rest of
+    /// the page, for previewing markdown from people you don't trust.
+    #[clap(long)]
+    untrusted: bool,
+
     /// The HTML file to generate. If this is specified, no server will be started and instead a
     /// single static file will be produced.
     #[clap(short, long)]
     output: Option<PathBuf>,
+
+    /// Render a small known snippet through every stage of the pipeline, report which stages
+    /// work, and exit.
+    #[clap(long)]
+    self_test: bool,
+
+    /// Show the document as styled text in the terminal instead of starting a server, updating it
+    /// whenever the file changes. Useful when a browser isn't available, such as over SSH.
+    #[clap(long)]
+    tui: bool,
+
+    /// Run the server in the background. Use `ghmd status` to see what is running and `ghmd stop`
+    /// to stop it.
+    #[clap(long, conflicts_with_all = &["output", "self-test", "tui"])]
+    daemon: bool,
+
+    /// With `--daemon`, also start the server whenever you log in. Only supported on Windows.
+    #[clap(long, requires = "daemon")]
+    startup: bool,
+
+    /// Used internally by `--daemon`.
+    #[clap(long, hide = true)]
+    pid_file: Option<PathBuf>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Show what servers started with `--daemon` are serving, and where.
+    Status {
+        /// The markdown file the server is rendering. If omitted, every server is shown.
+        #[clap(parse(from_os_str))]
+        input: Option<PathBuf>,
+    },
+    /// Stop a server started with `--daemon`.
+    Stop {
+        /// The markdown file the server is rendering. If omitted, every server is stopped.
+        #[clap(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// Also stop the server from starting at login, if `--startup` was given.
+        #[clap(long)]
+        remove_startup: bool,
+    },
+    /// Save the rendered HTML of a file, keyed by its contents, to later check whether GitHub
+    /// still renders it the same way.
+    Snapshot {
+        /// The markdown file to snapshot.
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Instead of saving a snapshot, re-render the file and compare it against the saved
+        /// one, failing if they differ.
+        #[clap(long)]
+        check: bool,
+
+        /// The directory to store snapshots in. Defaults to ghmd's cache directory.
+        #[clap(long, parse(from_os_str))]
+        dir: Option<PathBuf>,
+    },
+    /// Produce a page showing how the rendered output of two documents differs. Either two files
+    /// can be given, or a single file and one or two git revisions to compare it at (the working
+    /// tree is used if only one revision is given).
+    Diff {
+        /// The markdown files to compare.
+        #[clap(parse(from_os_str), required = true, max_values = 2)]
+        files: Vec<PathBuf>,
+
+        /// A git revision to read the file at. May be given up to twice.
+        #[clap(long, max_occurrences = 2)]
+        rev: Vec<String>,
+
+        /// The HTML file to write the comparison to, or `-` for standard output.
+        #[clap(short, long, parse(from_os_str), default_value = "-")]
+        output: PathBuf,
+    },
+    /// Render a markdown file with both GitHub's API and the local renderer used by `--offline`,
+    /// and produce a page showing the blocks where they differ, to find where offline previews
+    /// can't be trusted.
+    Compare {
+        /// The markdown file to compare the renders of.
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+
+        /// The HTML file to write the comparison to, or `-` for standard output.
+        #[clap(short, long, parse(from_os_str), default_value = "-")]
+        output: PathBuf,
+    },
+    /// Render a markdown file once and write it as a standalone HTML page, without starting a
+    /// server. This is the same as giving `--output`.
+    Export {
+        /// The markdown file to render, or `-` to read it from standard input.
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+
+        /// The HTML file to write, or `-` for standard output.
+        #[clap(short, long, parse(from_os_str), default_value = "-")]
+        output: PathBuf,
+    },
+    /// Render a markdown file and save a screenshot of the page as a PNG, using a headless
+    /// Chromium or Chrome, for release checklists and visual regression tests.
+    Screenshot {
+        /// The markdown file to render, or `-` to read it from standard input.
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+
+        /// The PNG file to write.
+        #[clap(short, long, parse(from_os_str))]
+        output: PathBuf,
+
+        /// The browser to use. Defaults to the first of Chromium, Chrome and Edge on the `PATH`.
+        #[clap(long, parse(from_os_str))]
+        browser: Option<PathBuf>,
+
+        /// The width of the window, in pixels.
+        #[clap(long, default_value = "1280")]
+        width: u32,
+
+        /// The height of the window, in pixels. Only this much of the page is captured.
+        #[clap(long, default_value = "1600")]
+        height: u32,
+    },
+    /// Render a markdown file and copy the result to the clipboard as HTML with GitHub's styles
+    /// inlined, ready to paste into an email or document.
+    Copy {
+        /// The markdown file to render.
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Print a table of contents for a markdown file, linking to each heading by the anchor GitHub
+    /// gives it.
+    Toc {
+        /// The markdown file to generate a table of contents for.
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Write the table of contents into the file, between `<!-- toc -->` and
+        /// `<!-- /toc -->` comments, instead of printing it.
+        #[clap(long)]
+        write: bool,
+    },
+    /// Print markdown for the badges that suit the current repository, found from its Git remote:
+    /// GitHub Actions workflows, crates.io and docs.rs for a published crate, and its license.
+    Badge {
+        /// The Git remote to find the repository from. Ignored if `--repo` is given.
+        #[clap(long, default_value = "origin")]
+        remote: String,
+
+        /// Also write a page showing the badges rendered to this HTML file, to check that they
+        /// all load.
+        #[clap(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Start a README or contributing guide from a template, filled in with the project's name,
+    /// badges and license, and preview it.
+    New {
+        /// The kind of document to create.
+        #[clap(arg_enum)]
+        kind: scaffold::Kind,
+
+        /// Where to write the document. Defaults to `README.md` or `CONTRIBUTING.md` in the
+        /// current directory.
+        #[clap(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Replace the file if it already exists.
+        #[clap(long)]
+        force: bool,
+
+        /// The Git remote to find the repository from. Ignored if `--repo` is given.
+        #[clap(long, default_value = "origin")]
+        remote: String,
+    },
+    /// Serve a dashboard of every markdown file with uncommitted changes in the current git
+    /// repository, highlighting how each differs from `HEAD`.
+    Review,
+    /// Check the token, the network, file watching, the port and the cache directory, printing
+    /// what is wrong with any of them and how to fix it.
+    Doctor {
+        /// Markdown files to check the permissions of.
+        #[clap(parse(from_os_str))]
+        inputs: Vec<PathBuf>,
+    },
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() {
     std::env::set_var("RUST_LOG", "INFO");
-    pretty_env_logger::init();
+    redact::init_logger();
+
+    if let Err(e) = run(Args::parse()).await {
+        eprintln!("Error: {}", redact(&format!("{:?}", e)));
+        std::process::exit(1);
+    }
+}
+
+async fn run(mut args: Args) -> anyhow::Result<()> {
+    if let Some(Command::Export { input, output }) = &mut args.command {
+        args.input = vec![mem::take(input)];
+        args.output = Some(mem::take(output));
+        args.command = None;
+    }
+    let screenshot = match args.command.take() {
+        Some(Command::Screenshot {
+            input,
+            output,
+            browser,
+            width,
+            height,
+        }) => {
+            args.input = vec![input];
+            Some(screenshot::Options {
+                output,
+                browser,
+                width,
+                height,
+            })
+        }
+        command => {
+            args.command = command;
+            None
+        }
+    };
+
+    match &args.command {
+        Some(Command::Status { input }) => return daemon::status(input.as_deref()),
+        Some(Command::Stop {
+            input,
+            remove_startup,
+        }) => return daemon::stop(input.as_deref(), *remove_startup),
+        _ => {}
+    }
+
+    #[cfg(feature = "mock")]
+    let mock_api = args.mock_api;
+    #[cfg(not(feature = "mock"))]
+    let mock_api = false;
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in args.header {
+        headers.append(name, value);
+    }
+    let client = reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .default_headers(headers.clone())
+        .build()
+        .context("failed to create HTTP client")?;
+
+    let octicon_client = if args.offline_octicons || args.offline {
+        None
+    } else {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .default_headers(headers)
+            .timeout(Duration::from_secs(args.octicon_timeout));
+        if let Some(proxy) = &args.octicon_proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy).context("invalid octicon proxy")?);
+        }
+        Some(builder.build().context("failed to create HTTP client")?)
+    };
+
+    let found_token = find_token(args.token.take());
+    let found_token = match args.token_file.take() {
+        Some(_) if matches!(found_token, Some((_, "--token"))) => {
+            bail!("`--token` and `--token-file` cannot both be given")
+        }
+        Some(path) => Some((read_token_file(&path).await?, "--token-file")),
+        None => found_token,
+    };
+    if let Some(Command::Doctor { inputs }) = &args.command {
+        if let Some((token, _)) = &found_token {
+            redact::set_token(token);
+        }
+        return doctor::run(doctor::Options {
+            client: &client,
+            octicon_client: octicon_client.as_ref(),
+            api_url: renderer::API_URL,
+            api_version: &args.api_version,
+            token: found_token.as_ref().map(|(token, _)| &**token),
+            bind: &args.bind,
+            port: args.port,
+            inputs,
+        })
+        .await;
+    }
+
+    let vendored = match args.no_external {
+        true => Some(Arc::new(
+            vendor::load(octicon_client.as_ref().unwrap_or(&client)).await?,
+        )),
+        false => None,
+    };
+
+    let (token, token_source) = match found_token {
+        Some(found) => found,
+        // Offline, replayed and mock renders don't need authenticating.
+        None if args.offline || args.replay.is_some() || mock_api => (String::new(), "none needed"),
+        None => bail!("a token is required; pass `--token` or set `GITHUB_TOKEN` or `GH_TOKEN`"),
+    };
+    redact::set_token(&token);
+
+    let hooks = Hooks {
+        exec: args.exec.map(Exec::new),
+        webhook: match args.webhook {
+            Some(url) => {
+                // Not the API client, whose headers may carry credentials meant for GitHub.
+                let client = reqwest::Client::builder()
+                    .user_agent(USER_AGENT)
+                    .timeout(Duration::from_secs(10))
+                    .build()
+                    .context("failed to create HTTP client")?;
+                Some(Webhook::new(client, url))
+            }
+            None => None,
+        },
+    };
+
+    let mode = match (&args.record, &args.replay) {
+        _ if mock_api => "offline, using the mock API".to_owned(),
+        _ if args.offline => "offline, rendering with comrak".to_owned(),
+        (_, Some(dir)) => format!("offline, replaying responses from `{}`", dir.display()),
+        (Some(dir), _) => format!("online, recording responses to `{}`", dir.display()),
+        (None, None) => "online".to_owned(),
+    };
+    let mode = match args.offline_octicons || args.offline {
+        true => mode + " with built-in octicons",
+        false => mode,
+    };
+    let mode = match args.no_external {
+        true => mode + ", and pages make no external requests",
+        false => mode,
+    };
+
+    let renderer = Renderer::new(
+        client,
+        Octicons::new(octicon_client),
+        args.api_version,
+        token,
+        args.concurrency.get(),
+        // Nothing is sent to GitHub that could be limited.
+        match mock_api || args.offline || args.replay.is_some() {
+            true => Duration::ZERO,
+            false => args.request_interval,
+        },
+        args.quota_threshold,
+        (args.skip_trivial_changes || !args.trivial_pattern.is_empty())
+            .then(|| Normalizer::new(args.trivial_pattern)),
+        IgnoreRegions::new(
+            args.ignore_region
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone())),
+        ),
+        args.guess_languages,
+        match (args.record, args.replay) {
+            (Some(dir), _) => Some(recording::Mode::Record(dir)),
+            (_, Some(dir)) => Some(recording::Mode::Replay(dir)),
+            (None, None) => None,
+        },
+    );
+    let mut project = ProjectConfig::discover(
+        args.input
+            .first()
+            .map(PathBuf::as_path)
+            .filter(|input| !is_stdin(input))
+            .unwrap_or_else(|| Path::new(".")),
+    )
+    .await?;
+    if let Some(repo) = args.repo {
+        project.repository = Some(repo);
+    }
+    if let Some(branch) = args.branch {
+        project.branch = Some(branch);
+    }
+    if args.number_headings {
+        project.number_headings = true;
+    }
+    if args.image_placeholders {
+        project.image_placeholders = true;
+    }
+    let renderer = renderer
+        .with_repository(project.repository.clone())
+        .with_hard_breaks(args.hard_breaks)
+        .with_readme(args.readme)
+        .with_offline(args.offline)
+        .with_raw_html(if args.strip_html {
+            raw_html::Mode::Strip
+        } else if args.flag_html {
+            raw_html::Mode::Flag
+        } else {
+            raw_html::Mode::Keep
+        });
+    #[cfg(feature = "mock")]
+    let renderer = match mock_api {
+        true => renderer.with_api_url(mock::spawn().await?),
+        false => renderer,
+    };
 
-    let args = Args::parse();
+    match args.command {
+        Some(Command::Snapshot { input, check, dir }) => {
+            return snapshot::run(&renderer, &input, check, dir).await;
+        }
+        Some(Command::Diff { files, rev, output }) => {
+            let sources = compare::sources(files, rev)?;
+            return compare::run(&renderer, args.theme, sources, &output).await;
+        }
+        Some(Command::Compare { input, output }) => {
+            ensure!(
+                !args.offline,
+                "`compare` compares GitHub's render with the offline one, so it needs the API"
+            );
+            return compare::run_renderers(&renderer, args.theme, &input, &output).await;
+        }
+        Some(Command::Copy { input }) => return clipboard::run(&renderer, &input).await,
+        Some(Command::Toc { input, write }) => return toc::run(&renderer, &input, write).await,
+        Some(Command::Badge { remote, output }) => {
+            return badge::run(&renderer, &project, args.theme, &remote, output.as_deref()).await
+        }
+        Some(Command::New {
+            kind,
+            output,
+            force,
+            remote,
+        }) => {
+            args.input = vec![scaffold::run(&project, kind, output, force, &remote).await?];
+        }
+        Some(Command::Review) => {
+            return review::run(renderer, project, args.theme, &args.bind, args.port).await
+        }
+        Some(
+            Command::Status { .. }
+            | Command::Stop { .. }
+            | Command::Doctor { .. }
+            | Command::Export { .. }
+            | Command::Screenshot { .. },
+        )
+        | None => {}
+    }
+
+    // One of these is required when there is no subcommand.
+    let sources: Vec<Source> = match args.input_command {
+        Some(command) => vec![Source::Command {
+            command: command::split(&command)?,
+            interval: args.interval,
+        }],
+        None => args.input.into_iter().map(Source::File).collect(),
+    };
+    ensure!(
+        sources.iter().filter(|source| source.is_stdin()).count() <= 1,
+        "standard input can only be read once"
+    );
 
-    let renderer = Renderer::new(reqwest::Client::new(), args.token);
+    // A background instance is identified by its first file.
+    if args.daemon {
+        let input = sources[0]
+            .path()
+            .context("cannot run in the background when reading from standard input")?;
+        return daemon::spawn(input, args.startup);
+    }
+    let (_pid_file, control) = match (args.pid_file, sources[0].path()) {
+        (Some(path), Some(input)) => {
+            let (pid_file, control) = daemon::PidFile::create(&path, input)?;
+            (Some(pid_file), Some(control))
+        }
+        _ => (None, None),
+    };
     let templater = Templater::new(
-        args.title
-            .map(String::into_boxed_str)
-            .unwrap_or_else(|| args.input.to_string_lossy().into()),
+        args.title.map(String::into_boxed_str).unwrap_or_else(|| {
+            let names: Vec<String> = sources.iter().map(Source::name).collect();
+            names.join(", ").into()
+        }),
         args.theme,
-    );
+    )
+    .with_css(project.css.clone())
+    .with_sandbox(args.untrusted)
+    .with_tab_size(args.tab_size.get())
+    .with_scroll_margin(args.scroll_margin)
+    .with_vendored(vendored);
 
-    if let Some(output) = args.output {
-        gen_output(&args.input, renderer, templater, &output).await?;
+    // A directory is browsed, with each markdown file in it rendered when it is opened.
+    if let [Source::File(dir)] = &*sources {
+        let serving = args.output.is_none() && !args.tui && !args.self_test;
+        if serving
+            && fs::metadata(dir)
+                .await
+                .is_ok_and(|metadata| metadata.is_dir())
+        {
+            return directory::run(renderer, project, templater, dir, &args.bind, args.port).await;
+        }
+    }
+
+    if let Some(options) = screenshot {
+        screenshot::run(&sources[0], renderer, templater, &project, &options).await?;
+    } else if args.self_test {
+        self_test::run_verbose(&renderer, &templater).await?;
+    } else if args.tui {
+        ensure!(sources.len() == 1, "`--tui` can only show one document");
+        let watcher = Watcher::new()?;
+        let file = sources[0]
+            .watch(&watcher, watcher.add_globs(&args.watch)?)
+            .await?;
+        terminal::run(&renderer, file, args.tab_size).await?;
+    } else if let Some(output) = args.output {
+        ensure!(
+            sources.len() == 1,
+            "`--output` can only render one document"
+        );
+        let res = gen_output(
+            &sources[0],
+            renderer,
+            templater,
+            &project,
+            &output,
+            &args.themes,
+            &hooks,
+        )
+        .await;
+        if let Err(e) = &res {
+            hooks.failed(&sources[0].name(), None, e).await;
+        }
+        res?;
     } else {
-        run_server(&args.input, renderer, templater, args.port).await?;
+        let options = ServerOptions {
+            bind: args.bind,
+            port: args.port,
+            cors: args.cors,
+            rate_limit: args.rate_limit,
+            porcelain: args.porcelain,
+            audit_banner: args.audit_banner,
+            prose_lint: args.prose_lint.as_deref().map(command::split).transpose()?,
+            assets_dir: args.assets_dir.unwrap_or_default(),
+            hooks,
+            journal: args.journal,
+            control,
+            summary: Summary {
+                theme: args.theme,
+                token: token_source,
+                mode,
+            },
+        };
+        let templater = templater.with_asset_routes(args.asset_routes);
+        run_server(&sources, &args.watch, renderer, templater, project, options).await?;
     }
 
     Ok(())
 }
 
+fn parse_header(s: &str) -> anyhow::Result<(HeaderName, HeaderValue)> {
+    let (name, value) = s
+        .split_once(':')
+        .context("headers must be of the form `NAME: VALUE`")?;
+    Ok((name.trim().parse()?, value.trim().parse()?))
+}
+
+/// Where the document being previewed comes from.
+enum Source {
+    /// A file, or standard input if the path is `-`.
+    File(PathBuf),
+    /// The output of a command, run again every `interval` and whenever a watched path changes.
+    Command {
+        command: Vec<String>,
+        interval: Option<Duration>,
+    },
+}
+
+impl Source {
+    fn is_stdin(&self) -> bool {
+        matches!(self, Self::File(path) if is_stdin(path))
+    }
+
+    /// The file the document is read from, if it is one on disk.
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Self::File(path) if !is_stdin(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// A name for the document, used as the default title and to label its tab.
+    fn name(&self) -> String {
+        match self {
+            Self::File(path) if is_stdin(path) => "stdin".to_owned(),
+            Self::File(path) => paths::display(path),
+            Self::Command { command, .. } => command.join(" "),
+        }
+    }
+
+    /// Read the document once.
+    async fn read(&self) -> anyhow::Result<String> {
+        match self {
+            Self::File(path) if is_stdin(path) => watcher::read_stdin().await,
+            Self::File(path) => Ok(fs::read_to_string(path).await?),
+            Self::Command { command, .. } => command::run(command).await,
+        }
+    }
+
+    /// Publish every version of the document. `extra` is notified when a path given with
+    /// `--watch` changes.
+    async fn watch(
+        &self,
+        watcher: &Watcher,
+        extra: watch::Receiver<()>,
+    ) -> anyhow::Result<watch::Receiver<DocumentState>> {
+        Ok(match self {
+            Self::File(path) => watcher.add_file(path).await?,
+            Self::Command { command, interval } => {
+                command::spawn(command.clone(), *interval, extra).await
+            }
+        })
+    }
+}
+
 async fn gen_output(
-    input: &Path,
+    source: &Source,
     renderer: Renderer,
     templater: Templater,
+    project: &ProjectConfig,
     output: &Path,
+    themes: &[Theme],
+    hooks: &Hooks,
 ) -> anyhow::Result<()> {
-    let markdown = fs::read_to_string(input).await?;
-    let rendered = renderer.render(&markdown).await??;
-    let page = templater.generate(&rendered, Liveness::Static).await?;
+    ensure!(
+        hooks.exec.is_none() || output.to_str() != Some("-"),
+        "`--exec` needs an output file rather than standard output"
+    );
+    let markdown = source.read().await?;
+    let rendered = renderer.render(&markdown, Priority::Background).await??;
+    let rendered = project.rewrite_links(&rendered);
+    let rendered = project.number_headings(&rendered);
+    let base_dir = match source.path() {
+        Some(path) => path.parent().unwrap_or(Path::new(".")).to_owned(),
+        None => std::env::current_dir().context("failed to get current directory")?,
+    };
+    let checkout = source.path().and_then(config::checkout_root);
+    let rendered = project.defer_images(&rendered, &base_dir, checkout).await;
+    let provenance = renderer.provenance(&markdown).await;
+    let templater = templater.for_document(&DocumentOptions::parse(&markdown));
+
+    if !themes.is_empty() {
+        ensure!(
+            output.to_str() != Some("-"),
+            "cannot write multiple themes to standard output"
+        );
+        for &theme in themes {
+            let page = templater
+                .with_theme(theme)
+                .generate(&rendered, Liveness::Static, provenance.as_ref())
+                .await?;
+            let mut file_name = output.file_stem().unwrap_or_default().to_owned();
+            file_name.push(".");
+            file_name.push(theme.as_str());
+            if let Some(extension) = output.extension() {
+                file_name.push(".");
+                file_name.push(extension);
+            }
+            let output = output.with_file_name(file_name);
+            fs::write(&output, page)
+                .await
+                .with_context(|| format!("could not write to `{}`", output.display()))?;
+            hooks
+                .rendered(&source.name(), &renderer::content_hash(&markdown), &output)
+                .await;
+        }
+        return Ok(());
+    }
+
+    let page = templater
+        .generate(&rendered, Liveness::Static, provenance.as_ref())
+        .await?;
     if output.to_str() == Some("-") {
         print!("{}", page);
     } else {
@@ -96,60 +1126,359 @@ async fn gen_output(
             .await
             .context("could not write to output file")?;
     }
+    hooks
+        .rendered(&source.name(), &renderer::content_hash(&markdown), output)
+        .await;
     Ok(())
 }
 
+/// Settings for the preview server that don't affect how documents are rendered.
+struct ServerOptions {
+    bind: Vec<IpAddr>,
+    port: u16,
+    /// The origins allowed to make cross-origin requests to the API.
+    cors: Vec<String>,
+    /// How many API requests each client may make per minute.
+    rate_limit: Option<NonZeroU32>,
+    porcelain: bool,
+    audit_banner: bool,
+    /// The prose linter command and its arguments, if any.
+    prose_lint: Option<Vec<String>>,
+    /// Where uploaded images are saved, relative to the document.
+    assets_dir: PathBuf,
+    hooks: Hooks,
+    /// The directory journals of each document's versions are kept in, if any.
+    journal: Option<PathBuf>,
+    /// The control socket to answer `ghmd status` and `ghmd stop` on, if running in the
+    /// background.
+    control: Option<daemon::Control>,
+    summary: Summary,
+}
+
+/// The settings that most often explain why ghmd behaves differently on two machines, logged
+/// when the server starts.
+struct Summary {
+    theme: Theme,
+    /// Where the token came from.
+    token: &'static str,
+    /// Whether and how GitHub is reached.
+    mode: String,
+}
+
+impl Summary {
+    fn log(&self, sources: &[Source], listeners: &[TcpListener]) -> anyhow::Result<()> {
+        let documents: Vec<String> = sources.iter().map(Source::name).collect();
+        let url = url(listeners[0].local_addr()?);
+        let cache = match dirs::cache_dir() {
+            Ok(dir) => dir.display().to_string(),
+            Err(e) => e.to_string(),
+        };
+        log::info!("Documents: {}", documents.join(", "));
+        log::info!("Watcher:   {}", watcher::backend());
+        log::info!("URL:       {}", url);
+        log::info!("Theme:     {}", self.theme.as_str());
+        log::info!("Token:     {}", self.token);
+        log::info!("Cache:     {}", cache);
+        log::info!("Mode:      {}", self.mode);
+        Ok(())
+    }
+}
+
 async fn run_server(
-    input: &Path,
+    sources: &[Source],
+    extra_paths: &[String],
     renderer: Renderer,
     templater: Templater,
-    port: u16,
+    project: ProjectConfig,
+    options: ServerOptions,
 ) -> anyhow::Result<()> {
+    let ServerOptions {
+        bind: bind_addresses,
+        port,
+        cors,
+        rate_limit,
+        porcelain,
+        audit_banner,
+        prose_lint,
+        assets_dir,
+        hooks,
+        journal,
+        control,
+        summary,
+    } = options;
+    let watcher = Watcher::new()?;
+    let extra = watcher.add_globs(extra_paths)?;
+    let extra_watcher = match sources {
+        // The paths re-run the command, so they don't need to cause a reload as well.
+        [Source::Command { .. }] => watcher.add_globs(&[])?,
+        _ => extra.clone(),
+    };
+    let opener = Opener {
+        watcher,
+        extra,
+        current_dir: paths::simplify(
+            fs::canonicalize(".")
+                .await
+                .context("failed to get current directory")?,
+        ),
+        prose_lint,
+        journal,
+        hooks: (!hooks.is_empty()).then(|| Arc::new(hooks)),
+    };
+    let mut documents = Vec::with_capacity(sources.len());
+    for source in sources {
+        documents.push(Arc::new(opener.open(source, &project).await?));
+    }
     let server = Arc::new(Server {
+        audit_banner,
+        assets_dir,
         renderer,
         templater,
-        watcher: watcher::watch_file(&input).await?,
-        shutdown: Notify::new(),
+        project,
+        max_documents: documents.len() + MAX_LINKED,
+        opened: watch::channel(documents.len()).0,
+        documents: RwLock::new(documents),
+        opener,
+        revisions: Revisions::new(),
+        extra_watcher,
     });
 
-    let http = Http::new();
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", port))
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            // The test costs an API request, which would only be refused.
+            if server.renderer.rate_limited().await.is_some() {
+                log::debug!("Skipping the self-test because of the rate limit");
+                return;
+            }
+            let res = self_test::run(
+                &server.renderer,
+                &server.templater,
+                self_test::Depth::Light,
+                |_| {},
+            )
+            .await;
+            if let Err(failure) = res {
+                log::warn!("{}", failure);
+                log::warn!("Run with `--self-test` for a more thorough diagnosis");
+            }
+        }
+    });
+
+    for index in 0..sources.len() {
+        server.start_tasks(index);
+    }
+
+    let listeners = bind(&bind_addresses, port).await?;
+    summary.log(sources, &listeners)?;
+    let stop = match control {
+        Some(control) => Some(
+            control.spawn(daemon::Status {
+                documents: sources.iter().map(Source::name).collect(),
+                url: url(listeners[0].local_addr()?),
+                started: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |now| now.as_secs()),
+            })?,
+        ),
+        None => None,
+    };
+
+    let router = Server::router();
+
+    if porcelain {
+        let address = listeners[0].local_addr()?;
+        let mut routes = vec!["/"];
+        routes.extend(router.paths());
+        #[derive(Serialize)]
+        struct Porcelain<'a> {
+            address: String,
+            port: u16,
+            url: &'a str,
+            pid: u32,
+            routes: &'a [&'a str],
+        }
+        println!(
+            "{}",
+            serde_json::to_string(&Porcelain {
+                address: address.ip().to_string(),
+                port: address.port(),
+                url: &url(address),
+                pid: std::process::id(),
+                routes: &routes,
+            })
+            .unwrap()
+        );
+    }
+
+    let service = ServiceBuilder::new()
+        .option_layer((!cors.is_empty()).then(|| Cors::new(cors)))
+        .option_layer(rate_limit.map(RateLimit::new))
+        .service(router.into_service(server));
+    serve(listeners, service, stop).await
+}
+
+/// The token given with `--token` or `GITHUB_TOKEN`, or failing that the one in `GH_TOKEN` as
+/// used by the GitHub CLI, along with where it came from.
+fn find_token(token: Option<String>) -> Option<(String, &'static str)> {
+    match token.filter(|token| !token.is_empty()) {
+        Some(token) if std::env::var("GITHUB_TOKEN").ok().as_ref() == Some(&token) => {
+            Some((token, "the GITHUB_TOKEN environment variable"))
+        }
+        Some(token) => Some((token, "--token")),
+        None => std::env::var("GH_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty())
+            .map(|token| (token, "the GH_TOKEN environment variable")),
+    }
+}
+
+/// The token kept in a file, without the newline it usually ends with.
+async fn read_token_file(path: &Path) -> anyhow::Result<String> {
+    let token = fs::read_to_string(path)
         .await
-        .context("failed to bind server")?;
+        .with_context(|| format!("failed to read token from `{}`", path.display()))?;
+    let token = token.trim();
+    ensure!(!token.is_empty(), "`{}` is empty", path.display());
+    Ok(token.to_owned())
+}
+
+/// The URL to visit a server listening on `address` at.
+fn url(address: SocketAddr) -> String {
+    match address.ip() {
+        ip if ip.is_loopback() || ip.is_unspecified() => {
+            format!("http://localhost:{}/", address.port())
+        }
+        _ => format!("http://{}/", address),
+    }
+}
+
+/// Listen on `port` at every address, or at both loopback addresses if none are given.
+async fn bind(addresses: &[IpAddr], mut port: u16) -> anyhow::Result<Vec<TcpListener>> {
+    let loopback = [Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()];
+    let defaulted = addresses.is_empty();
+    let addresses = if defaulted { &loopback } else { addresses };
+
+    let mut listeners = Vec::with_capacity(addresses.len());
+    for &address in addresses {
+        match TcpListener::bind((address, port)).await {
+            Ok(listener) => {
+                // If the port was chosen by the OS, use the same one for every address.
+                port = listener.local_addr()?.port();
+                listeners.push(listener);
+            }
+            // Not every system has IPv6, which is fine as long as IPv4 works.
+            Err(e) if defaulted && address.is_ipv6() && !listeners.is_empty() => {
+                log::debug!("Not listening on {}: {}", address, e);
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!(
+                        "failed to bind server to {}",
+                        SocketAddr::new(address, port)
+                    )
+                })
+            }
+        }
+    }
+
+    for listener in &listeners {
+        let address = listener.local_addr()?;
+        log::info!("Now listening on http://{}/", address);
+        if !address.ip().is_loopback() {
+            log::warn!(
+                "{} is reachable from other machines, which can read the documents and edit them \
+                    through the server; pass `--bind 127.0.0.1` to keep it local",
+                address.ip(),
+            );
+        }
+    }
+
+    Ok(listeners)
+}
 
-    log::info!(
-        "Now listening on http://localhost:{}/",
-        listener.local_addr()?.port()
+fn parse_repository(s: &str) -> anyhow::Result<String> {
+    ensure!(
+        s.split('/').count() == 2,
+        "the repository must be of the form `owner/name`"
     );
+    Ok(s.to_owned())
+}
 
-    let server_task = tokio::spawn({
-        let server = server.clone();
-        async move {
+/// Parse an IP address, allowing IPv6 addresses to be surrounded by brackets as in URLs.
+fn parse_ip(s: &str) -> Result<IpAddr, AddrParseError> {
+    s.strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(s)
+        .parse()
+}
+
+/// The most headers a request may have.
+const MAX_HEADERS: usize = 64;
+
+/// The most bytes a request's line and headers may take up.
+const MAX_HEAD_BYTES: usize = 64 * 1024;
+
+/// How long a client may take to send a request's headers before its connection is closed.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve HTTP connections accepted from the listeners until Ctrl+C is pressed, or `stop` is
+/// notified.
+async fn serve<S>(
+    listeners: Vec<TcpListener>,
+    service: S,
+    stop: Option<Arc<Notify>>,
+) -> anyhow::Result<()>
+where
+    S: tower::Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let shutdown = Arc::new(Notify::new());
+
+    let mut http = http1::Builder::new();
+    http.max_headers(MAX_HEADERS)
+        .max_buf_size(MAX_HEAD_BYTES)
+        .timer(TokioTimer::new())
+        .header_read_timeout(HEADER_TIMEOUT);
+
+    let mut server_tasks = Vec::with_capacity(listeners.len());
+    for listener in listeners {
+        let http = http.clone();
+        let service = service.clone();
+        let shutdown = shutdown.clone();
+        server_tasks.push(tokio::spawn(async move {
             loop {
-                let (connection, _address) = match listener.accept().await {
+                let (connection, address) = match listener.accept().await {
                     Ok(t) => t,
                     Err(e) => {
                         log::error!("{:?}", anyhow!(e).context("failed to accept connection"));
                         continue;
                     }
                 };
-                let connection = http.serve_connection(
-                    connection,
-                    service_fn({
-                        let server = server.clone();
-                        move |req| {
-                            let server = server.clone();
-                            async move { Ok::<_, Infallible>(server.handle_request(req).await) }
-                        }
-                    }),
+                let local = match connection.local_addr() {
+                    Ok(local) => local,
+                    Err(e) => {
+                        log::error!("{:?}", anyhow!(e).context("failed to accept connection"));
+                        continue;
+                    }
+                };
+                let service = service.clone().map_request(
+                    move |req: http::Request<hyper::body::Incoming>| {
+                        let mut req = req.map(|body| body.boxed_unsync());
+                        req.extensions_mut().insert(RemoteAddr(address));
+                        req.extensions_mut().insert(LocalAddr(local));
+                        req
+                    },
                 );
+                let connection = http
+                    .serve_connection(TokioIo::new(connection), TowerToHyperService::new(service));
 
-                let server = server.clone();
+                let shutdown = shutdown.clone();
                 tokio::spawn(async move {
                     tokio::pin!(connection);
                     let res = tokio::select! {
                         res = &mut connection => { res }
-                        _ = server.shutdown.notified() => {
+                        _ = shutdown.notified() => {
                             connection.as_mut().graceful_shutdown();
                             connection.await
                         }
@@ -159,70 +1488,533 @@ async fn run_server(
                     }
                 });
             }
-        }
-    });
+        }));
+    }
 
-    signal::ctrl_c().await?;
+    let stopped = async {
+        match &stop {
+            Some(stop) => stop.notified().await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        res = signal::ctrl_c() => res?,
+        () = stopped => {}
+    }
 
-    server.shutdown.notify_waiters();
-    server_task.abort();
+    shutdown.notify_waiters();
+    for task in server_tasks {
+        task.abort();
+    }
 
     Ok(())
 }
 
 struct Server {
+    audit_banner: bool,
+    /// Where uploaded images are saved, relative to the document's directory.
+    assets_dir: PathBuf,
     renderer: Renderer,
     templater: Templater,
-    watcher: watch::Receiver<anyhow::Result<Arc<str>>>,
-    shutdown: Notify,
+    project: ProjectConfig,
+    /// Every document being previewed, each of which is shown in its own tab. Documents are only
+    /// ever added, so their indices stay the same.
+    documents: RwLock<Vec<Arc<Document>>>,
+    max_documents: usize,
+    /// The number of documents, sent again whenever one is opened so that event streams start
+    /// following it.
+    opened: watch::Sender<usize>,
+    /// Opens documents linked to from the others when they are first visited.
+    opener: Opener,
+    /// What the documents looked like recently.
+    revisions: Revisions,
+    extra_watcher: watch::Receiver<()>,
+}
+
+struct Document {
+    title: Box<str>,
+    /// The absolute path of the file the document is read from, if it is one.
+    path: Option<Box<str>>,
+    /// The URL path the document has its own page at, from its path relative to the working
+    /// directory, if it is in it.
+    route: Option<Box<str>>,
+    /// Where the document can be found on GitHub, if known.
+    github_url: Option<Box<str>>,
+    /// The root of the Git checkout the document is in, if any.
+    checkout: Option<PathBuf>,
+    /// The directory relative paths in the document are resolved against.
+    base_dir: PathBuf,
+    watcher: watch::Receiver<DocumentState>,
+    /// Findings of the prose linter, if one is configured.
+    prose: Option<watch::Receiver<Arc<[prose::Finding]>>>,
+    /// Every version of the document, if they are being recorded.
+    journal: Option<Mutex<Journal>>,
+    /// The last edit sent to `POST /content` that conflicted with a change on disk, until an edit
+    /// is saved successfully.
+    conflict: watch::Sender<Option<Arc<Conflict>>>,
+}
+
+impl Document {
+    fn markdown(&self) -> anyhow::Result<Arc<str>> {
+        self.watcher.borrow().contents()
+    }
+}
+
+/// What is needed to start previewing a document.
+struct Opener {
+    watcher: Watcher,
+    /// Notified when a path given with `--watch` changes.
+    extra: watch::Receiver<()>,
+    /// The directory documents' routes are relative to.
+    current_dir: PathBuf,
+    prose_lint: Option<Vec<String>>,
+    /// The directory to record every version of each document in, if any.
+    journal: Option<PathBuf>,
+    hooks: Option<Arc<Hooks>>,
+}
+
+impl Opener {
+    async fn open(&self, source: &Source, project: &ProjectConfig) -> anyhow::Result<Document> {
+        let path = match source.path() {
+            Some(input) => Some(paths::simplify(fs::canonicalize(input).await?)),
+            None => None,
+        };
+        let base_dir = match &path {
+            Some(path) => path.parent().context("file has no parent")?.to_owned(),
+            None => std::env::current_dir().context("failed to get current directory")?,
+        };
+        let file = source.watch(&self.watcher, self.extra.clone()).await?;
+        let prose = match (&self.prose_lint, source.path()) {
+            (Some(command), Some(path)) => {
+                Some(prose::spawn(command.clone(), path.to_owned(), file.clone()))
+            }
+            (Some(_), None) => bail!("`--prose-lint` can only be used when reading from a file"),
+            (None, _) => None,
+        };
+        let journal = match &self.journal {
+            Some(dir) => {
+                let key = path
+                    .as_deref()
+                    .map_or_else(|| source.name(), |path| path.to_string_lossy().into_owned());
+                Some(Mutex::new(Journal::open(dir, &key).await?))
+            }
+            None => None,
+        };
+        Ok(Document {
+            title: source.name().into(),
+            journal,
+            github_url: path
+                .as_deref()
+                .and_then(|path| project.github_url(path))
+                .map(Into::into),
+            checkout: path
+                .as_deref()
+                .and_then(config::checkout_root)
+                .map(Path::to_owned),
+            route: path
+                .as_deref()
+                .and_then(|path| path.strip_prefix(&self.current_dir).ok())
+                .map(|relative| {
+                    relative
+                        .iter()
+                        .map(|component| {
+                            format!(
+                                "/{}",
+                                utf8_percent_encode(&component.to_string_lossy(), SEGMENT)
+                            )
+                        })
+                        .collect::<String>()
+                        .into()
+                }),
+            path: path.map(|path| path.to_string_lossy().into()),
+            base_dir,
+            watcher: file,
+            prose,
+            conflict: watch::channel(None).0,
+        })
+    }
 }
 
 impl Server {
-    async fn handle_request(
-        self: &Arc<Self>,
-        req: http::Request<hyper::Body>,
-    ) -> http::Response<hyper::Body> {
-        if req
-            .headers()
-            .get("accept")
-            .map_or(false, |val| val == "text/event-stream")
-        {
-            self.clone().event_stream().await
-        } else {
-            self.get().await
+    fn router() -> Router<Self> {
+        let mut router = Router::new(handlers::fallback::handle);
+        for asset in &templater::ASSETS {
+            router = router.route(
+                http::Method::GET,
+                asset.path,
+                move |_: Arc<Self>, query: Query, req: Request| async move {
+                    files::serve_asset(asset, &query, req.headers()).await
+                },
+            );
+        }
+        router
+            .events(|server: Arc<Self>| async move { server.event_stream() })
+            .route(http::Method::GET, "/api/audit", handlers::audit::get)
+            .route(http::Method::GET, "/api/lint", handlers::lint::get)
+            .route(http::Method::GET, "/api/anchors", handlers::anchors::get)
+            .route(http::Method::GET, "/history", handlers::history::page)
+            .route(http::Method::GET, "/api/history", handlers::history::list)
+            .route(
+                http::Method::POST,
+                "/history/restore",
+                handlers::restore::post,
+            )
+            .route(http::Method::POST, "/content", handlers::save::post)
+            .route(http::Method::POST, "/upload", handlers::upload::post)
+    }
+
+    /// Apply ghmd's own changes to GitHub's rendered HTML.
+    async fn post_process(&self, document: &Document, rendered: &str) -> String {
+        let rendered = &*self.project.rewrite_links(rendered);
+        let rendered = &*lint::mark_dead_anchors(rendered);
+        let marked;
+        let rendered = match document.markdown() {
+            Ok(markdown) => {
+                marked = outline::mark_source_lines(rendered, &markdown);
+                &*marked
+            }
+            Err(_) => rendered,
+        };
+        let rendered = &*self.project.number_headings(rendered);
+        let deferred = self
+            .project
+            .defer_images(rendered, &document.base_dir, document.checkout.as_deref())
+            .await;
+        let rendered = &*deferred;
+        let routed;
+        let rendered = match document.route.as_deref() {
+            Some(route) => {
+                routed = files::absolute_srcs(rendered, route);
+                &*routed
+            }
+            None => rendered,
+        };
+
+        let mut banners = String::new();
+        let conflict = document.conflict.borrow().clone();
+        if let (Some(conflict), Ok(markdown)) = (conflict, document.markdown()) {
+            banners += &content::banner(&conflict, &markdown);
+        }
+        if let Ok(markdown) = document.markdown() {
+            let removals: Vec<String> = raw_html::removals(&markdown)
+                .into_iter()
+                .map(|removal| match removal.attribute {
+                    Some(attribute) => format!(
+                        "<li>Line {}: the <code>{}</code> attribute of <code>&lt;{}&gt;</code></li>",
+                        removal.line,
+                        tera::escape_html(&attribute),
+                        tera::escape_html(&removal.element),
+                    ),
+                    None => format!(
+                        "<li>Line {}: <code>&lt;{}&gt;</code></li>",
+                        removal.line,
+                        tera::escape_html(&removal.element),
+                    ),
+                })
+                .collect();
+            if !removals.is_empty() {
+                banners += &format!(
+                    "<div class=\"flash flash-warn mb-3\"><strong>GitHub will remove this HTML</strong><ul class=\"ml-3\">{}</ul></div>",
+                    removals.concat(),
+                );
+            }
+        }
+
+        if !self.audit_banner {
+            return banners + rendered;
+        }
+        let warnings: Vec<String> = audit::images(
+            rendered,
+            &document.base_dir,
+            self.repository(document).as_ref(),
+        )
+        .await
+        .into_iter()
+        .flat_map(|report| {
+            let src = report.src;
+            report.warnings.into_iter().map(move |warning| {
+                format!(
+                    "<li><code>{}</code>: {}</li>",
+                    tera::escape_html(&src),
+                    warning
+                )
+            })
+        })
+        .collect();
+        if !warnings.is_empty() {
+            banners += &format!(
+                "<div class=\"flash flash-warn mb-3\"><strong>Image audit</strong><ul class=\"ml-3\">{}</ul></div>",
+                warnings.concat(),
+            );
         }
+        banners + rendered
     }
 
-    async fn get(&self) -> hyper::Response<hyper::Body> {
+    /// Render every version of a document as it changes, whether or not anyone is viewing it, and
+    /// run the `--exec` and `--webhook` hooks after each render.
+    async fn run_hooks(self: Arc<Self>, index: usize, hooks: Arc<Hooks>) {
+        let document = self.document(index);
+        let output =
+            std::env::temp_dir().join(format!("ghmd-{}-{}.html", std::process::id(), index));
+        let mut watcher = document.watcher.clone();
+        loop {
+            watcher.borrow_and_update();
+            match document.markdown() {
+                Ok(markdown) => {
+                    let hash = renderer::content_hash(&markdown);
+                    let res = async {
+                        let rendered = self
+                            .renderer
+                            .render(&markdown, Priority::Background)
+                            .await??;
+                        let html = self.post_process(&document, &rendered).await;
+                        let provenance = self.renderer.provenance(&markdown).await;
+                        let page = self
+                            .templater
+                            .for_document(&DocumentOptions::parse(&markdown))
+                            .generate(&html, Liveness::Static, provenance.as_ref())
+                            .await?;
+                        fs::write(&output, page)
+                            .await
+                            .with_context(|| format!("could not write to `{}`", output.display()))
+                    }
+                    .await;
+                    match res {
+                        Ok(()) => hooks.rendered(&document.title, &hash, &output).await,
+                        Err(e) => hooks.failed(&document.title, Some(&hash), &e).await,
+                    }
+                }
+                Err(e) => hooks.failed(&document.title, None, &e).await,
+            }
+            if watcher.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Add every version of a document to its journal as it changes.
+    async fn record_journal(self: Arc<Self>, index: usize) {
+        let document = self.document(index);
+        let journal = document.journal.as_ref().unwrap();
+        let mut watcher = document.watcher.clone();
+        loop {
+            watcher.borrow_and_update();
+            if let Ok(markdown) = document.markdown() {
+                if let Err(e) = journal.lock().await.record(&markdown).await {
+                    log::error!("{:?}", e);
+                }
+            }
+            if watcher.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// The repository a document is in, if it is configured and the document is in a checkout.
+    fn repository<'a>(&'a self, document: &'a Document) -> Option<audit::Repository<'a>> {
+        Some(audit::Repository {
+            name: self.project.repository.as_deref()?,
+            branch: self.project.branch.as_deref(),
+            root: document.checkout.as_deref()?,
+        })
+    }
+
+    /// Render the current version of a document.
+    async fn render_current(&self, document: &Document) -> anyhow::Result<Arc<str>> {
+        let markdown = document.markdown()?;
+        Ok(self
+            .renderer
+            .render(&markdown, Priority::Interactive)
+            .await??)
+    }
+
+    /// The document at `index`, which must exist.
+    fn document(&self, index: usize) -> Arc<Document> {
+        self.documents.read().unwrap()[index].clone()
+    }
+
+    /// Every document, as they are now.
+    fn documents(&self) -> Vec<Arc<Document>> {
+        self.documents.read().unwrap().clone()
+    }
+
+    /// Start recording versions of the document at `index` and running hooks after it is
+    /// rendered, if either is configured.
+    fn start_tasks(self: &Arc<Self>, index: usize) {
+        if self.document(index).journal.is_some() {
+            tokio::spawn(self.clone().record_journal(index));
+        }
+        if let Some(hooks) = &self.opener.hooks {
+            tokio::spawn(self.clone().run_hooks(index, hooks.clone()));
+        }
+    }
+
+    /// The index of the document read from the markdown file at `path`, which is opened and
+    /// watched if it isn't one of the documents yet. `None` if too many documents are open.
+    async fn open_linked(self: &Arc<Self>, path: &Path) -> anyhow::Result<Option<usize>> {
+        let path = paths::simplify(fs::canonicalize(path).await?);
+        let key = path.to_string_lossy();
+        let find = |documents: &[Arc<Document>]| {
+            documents
+                .iter()
+                .position(|document| document.path.as_deref() == Some(&*key))
+        };
+        let documents = self.documents();
+        if let Some(index) = find(&documents) {
+            return Ok(Some(index));
+        }
+        if documents.len() >= self.max_documents {
+            return Ok(None);
+        }
+
+        let relative = path.strip_prefix(&self.opener.current_dir).unwrap_or(&path);
+        let source = Source::File(relative.to_owned());
+        let document = self.opener.open(&source, &self.project).await?;
+        let index = {
+            let mut documents = self.documents.write().unwrap();
+            // It may have been opened by another request in the meantime.
+            if let Some(index) = find(&documents) {
+                return Ok(Some(index));
+            }
+            if documents.len() >= self.max_documents {
+                return Ok(None);
+            }
+            documents.push(Arc::new(document));
+            self.opened.send_replace(documents.len());
+            documents.len() - 1
+        };
+        log::info!("Opened {}, which another document links to", source.name());
+        self.start_tasks(index);
+        Ok(Some(index))
+    }
+
+    /// The page showing every document, with the one at `selected` shown first. Only that one is
+    /// rendered for the page; the others are included if their render is cached, and otherwise
+    /// sent over the event stream once the page connects to it.
+    async fn get(&self, selected: usize) -> Response {
         let res: anyhow::Result<_> = async move {
-            let markdown = self.watcher.borrow().as_ref().map_err(clone_error)?.clone();
-
-            let rendered = match self.renderer.render(&markdown).await? {
-                Ok(rendered) => rendered,
-                Err(rate_limited) => {
-                    // TODO: handle errors better
-                    return Ok(http::Response::builder()
-                        .status(http::StatusCode::FORBIDDEN)
-                        .header("Content-Type", "text/plain")
-                        .body(hyper::Body::from(format!(
-                            "\
-                                Rate Limited\n\
-                                ============\n\
-                                \
-                                {}
-                            ",
-                            rate_limited,
-                        )))
-                        .unwrap());
+            let documents = self.documents();
+            let mut contents = Vec::with_capacity(documents.len());
+            for (index, document) in documents.iter().enumerate() {
+                let markdown = match document.markdown() {
+                    Ok(markdown) => markdown,
+                    // One document that can't be read doesn't stop the others being shown.
+                    Err(e) => {
+                        let html = format!(
+                            "<div class=\"flash flash-error mb-3\">\
+                                <strong>{} could not be read</strong>\
+                                <pre class=\"color-bg-primary p-2\">{}</pre>\
+                            </div>",
+                            tera::escape_html(&document.title),
+                            tera::escape_html(&redact(&format!("{:?}", e))),
+                        );
+                        contents.push((None, (html, None)));
+                        continue;
+                    }
+                };
+                let title = DocumentOptions::parse(&markdown).title;
+                if index != selected {
+                    let content = match self.renderer.cached(&markdown).await {
+                        Some(rendered) => {
+                            let html = self.post_process(document, &rendered).await;
+                            let revision = self.revisions.insert(index, &markdown, &html);
+                            (html, Some(revision))
+                        }
+                        None => (String::new(), None),
+                    };
+                    contents.push((title, content));
+                    continue;
                 }
-            };
+                let rendered = match self
+                    .renderer
+                    .render(&markdown, Priority::Interactive)
+                    .await?
+                {
+                    Ok(rendered) => rendered,
+                    Err(RenderError::RateLimited(rate_limited)) => {
+                        // TODO: handle errors better
+                        return Ok(http::Response::builder()
+                            .status(http::StatusCode::FORBIDDEN)
+                            .header("Content-Type", "text/plain")
+                            .body(router::full(format!(
+                                "\
+                                    Rate Limited\n\
+                                    ============\n\
+                                    \
+                                    {}
+                                ",
+                                rate_limited,
+                            )))
+                            .unwrap());
+                    }
+                    Err(RenderError::Auth(auth_error)) => {
+                        return Ok(http::Response::builder()
+                            .status(http::StatusCode::UNAUTHORIZED)
+                            .header("Content-Type", "text/plain")
+                            .body(router::full(format!(
+                                "\
+                                    Token Rejected\n\
+                                    ==============\n\
+                                    \
+                                    {}
+                                ",
+                                auth_error,
+                            )))
+                            .unwrap());
+                    }
+                    Err(e) => {
+                        return Ok(http::Response::builder()
+                            .status(http::StatusCode::BAD_GATEWAY)
+                            .header("Content-Type", "text/plain")
+                            .body(router::full(format!(
+                                "\
+                                    Render Failed\n\
+                                    =============\n\
+                                    \
+                                    {}
+                                ",
+                                redact(&e.to_string()),
+                            )))
+                            .unwrap());
+                    }
+                };
+                let html = self.post_process(document, &rendered).await;
+                let revision = self.revisions.insert(index, &markdown, &html);
+                contents.push((title, (html, Some(revision))));
+            }
+            let tabs: Vec<Tab<'_>> = documents
+                .iter()
+                .zip(&contents)
+                .enumerate()
+                .map(|(index, (document, (title, (content, revision))))| Tab {
+                    title: title.as_deref().unwrap_or(&document.title),
+                    path: document.path.as_deref(),
+                    route: document.route.as_deref(),
+                    selected: index == selected,
+                    github_url: document.github_url.as_deref(),
+                    revision: revision.as_deref(),
+                    content,
+                })
+                .collect();
 
-            let page = self.templater.generate(&rendered, Liveness::Live).await?;
+            // Settings that affect the whole page come from the first document.
+            let (options, provenance) = match documents[0].markdown() {
+                Ok(markdown) => (
+                    DocumentOptions::parse(&markdown),
+                    self.renderer.provenance(&markdown).await,
+                ),
+                Err(_) => (DocumentOptions::default(), None),
+            };
+            let page = self
+                .templater
+                .for_document(&options)
+                .generate_tabs(&tabs, Liveness::Live, provenance.as_ref())
+                .await?;
 
             Ok(http::Response::builder()
                 .status(http::StatusCode::OK)
                 .header("Content-Type", "text/html")
-                .body(hyper::Body::from(page))
+                .body(router::full(page))
                 .unwrap())
         }
         .await;
@@ -231,56 +2023,63 @@ impl Server {
             http::Response::builder()
                 .status(http::StatusCode::INTERNAL_SERVER_ERROR)
                 .header("Content-Type", "text/plain")
-                .body(hyper::Body::from(format!(
+                .body(router::full(format!(
                     "\
                             Internal Server Error\n\
                             =====================\n\
                             \
-                            {:?}\
+                            {}\
                         ",
-                    e,
+                    redact(&format!("{:?}", e)),
                 )))
                 .unwrap()
         })
     }
 
-    async fn event_stream(self: Arc<Self>) -> hyper::Response<hyper::Body> {
-        let mut watcher = self.watcher.clone();
-        let stream = hyper::Body::wrap_stream::<_, _, Infallible>(try_stream! {
+    /// Stream events about every document over a single connection.
+    fn event_stream(self: Arc<Self>) -> Response {
+        let (sender, mut events) = mpsc::channel(1);
+        let mut opened = self.opened.subscribe();
+        let mut count = *opened.borrow_and_update();
+        for index in 0..count {
+            self.spawn_document_events(index, sender.clone());
+        }
+        // Documents opened from now on are followed too.
+        tokio::spawn({
+            let server = self.clone();
+            let sender = sender.clone();
+            async move {
+                loop {
+                    tokio::select! {
+                        res = opened.changed() => if res.is_err() { break },
+                        () = sender.closed() => break,
+                    }
+                    let new_count = *opened.borrow_and_update();
+                    for index in count..new_count {
+                        server.spawn_document_events(index, sender.clone());
+                    }
+                    count = new_count;
+                }
+            }
+        });
+        let mut extra_watcher = self.extra_watcher.clone();
+        tokio::spawn(async move {
             loop {
-                if watcher.changed().await.is_err() {
-                    return;
+                tokio::select! {
+                    res = extra_watcher.changed() => if res.is_err() { break },
+                    () = sender.closed() => break,
                 }
+                if sender.send(Event::page("reload")).await.is_err() {
+                    break;
+                }
+            }
+        });
 
-                let res = match &*watcher.borrow_and_update() {
-                    Ok(markdown) => Ok(markdown.clone()),
-                    Err(e) => Err(format!("{:?}", e)),
-                };
-
-                let markdown = match res {
-                    Ok(markdown) => markdown,
-                    Err(e) => {
-                        yield sse("render_error", &format!("{:?}", e));
-                        continue
-                    },
-                };
-
-                yield match self.renderer.render(&markdown).await {
-                    Ok(Ok(rendered)) => sse("update", &rendered),
-                    Ok(Err(RateLimited { limit, reset })) => {
-                        #[derive(Serialize)]
-                        struct MessageData {
-                            limit: u32,
-                            reset: u64,
-                        }
-                        let data = MessageData {
-                            limit,
-                            reset: reset.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(),
-                        };
-                        sse("rate_limited", &serde_json::to_string(&data).unwrap())
-                    }
-                    Err(e) => sse("render_error", &format!("{:?}", e)),
-                };
+        let stream = router::stream(stream! {
+            let mut seq = 0;
+            while let Some(event) = events.recv().await {
+                yield event.into_sse(seq);
+                seq += 1;
             }
         });
 
@@ -290,6 +2089,364 @@ impl Server {
             .body(stream)
             .unwrap()
     }
+
+    /// Send the events about the document at `index` on a stream until it is closed.
+    fn spawn_document_events(self: &Arc<Self>, index: usize, sender: mpsc::Sender<Event>) {
+        let server = self.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                () = server.document_events(index, &sender) => {}
+                () = sender.closed() => {}
+            }
+        });
+    }
+
+    /// The event showing a new render of a document.
+    async fn update(
+        &self,
+        document: &Document,
+        index: usize,
+        markdown: &str,
+        rendered: &str,
+    ) -> Event {
+        #[derive(Serialize)]
+        struct Update<'a> {
+            html: &'a str,
+            revision: &'a str,
+        }
+        let html = self.post_process(document, rendered).await;
+        let revision = self.revisions.insert(index, markdown, &html);
+        let update = Update {
+            html: &html,
+            revision: &revision,
+        };
+        Event::document("update", index, update)
+    }
+
+    /// Send the events about a single document until it stops being watched.
+    async fn document_events(&self, index: usize, sender: &mpsc::Sender<Event>) {
+        let document = self.document(index);
+        #[derive(Serialize)]
+        struct Prose<'a> {
+            findings: &'a [prose::Finding],
+        }
+
+        let mut watcher = document.watcher.clone();
+        let mut prose = document.prose.clone();
+        let mut conflict = document.conflict.subscribe();
+        let mut options = match watcher.borrow().file() {
+            Some(file) => DocumentOptions::parse(&file.contents),
+            None => DocumentOptions::default(),
+        };
+        if let Some(prose) = &mut prose {
+            let findings = prose.borrow_and_update().clone();
+            let _ = sender
+                .send(Event::document(
+                    "prose",
+                    index,
+                    Prose {
+                        findings: &findings,
+                    },
+                ))
+                .await;
+        }
+        // A document that wasn't rendered for the page is rendered straight away.
+        let contents = watcher.borrow().file().map(|file| file.contents.clone());
+        let mut unrendered = match contents {
+            Some(contents) => self.renderer.cached(&contents).await.is_none(),
+            None => false,
+        };
+        loop {
+            let changed = tokio::select! {
+                () = async {}, if mem::take(&mut unrendered) => Ok(Changed::Unrendered),
+                res = watcher.changed() => res.map(|()| Changed::Document),
+                // The banner is part of the document, so it is rendered again.
+                Ok(()) = conflict.changed() => Ok(Changed::Document),
+                res = async { prose.as_mut().unwrap().changed().await }, if prose.is_some() => {
+                    res.map(|()| Changed::Prose)
+                }
+            };
+            let saved = matches!(changed, Ok(Changed::Document));
+            match changed {
+                Ok(Changed::Document | Changed::Unrendered) => {}
+                Ok(Changed::Prose) => {
+                    let findings = prose.as_mut().unwrap().borrow_and_update().clone();
+                    let _ = sender
+                        .send(Event::document(
+                            "prose",
+                            index,
+                            Prose {
+                                findings: &findings,
+                            },
+                        ))
+                        .await;
+                    continue;
+                }
+                Err(_) => return,
+            }
+
+            if let Some(debounce) = self.renderer.debounce() {
+                // Wait for the user to stop typing so that intermediate saves don't use up
+                // what remains of the quota, showing them rendered offline in the meantime.
+                let closed = loop {
+                    let markdown = match &*watcher.borrow_and_update() {
+                        DocumentState::Content(file) => Some(file.contents.clone()),
+                        DocumentState::Missing | DocumentState::Error(_) => None,
+                    };
+                    if let Some(markdown) = markdown {
+                        let rendered = format!(
+                            "{}{}",
+                            LOCAL_RENDER_NOTICE,
+                            self.renderer.render_locally(&markdown).await,
+                        );
+                        let update = self.update(&document, index, &markdown, &rendered).await;
+                        let _ = sender.send(update).await;
+                    }
+                    tokio::select! {
+                        res = watcher.changed() => if res.is_err() { break true },
+                        () = time::sleep(debounce) => break false,
+                    }
+                };
+                if closed {
+                    return;
+                }
+            }
+
+            let state = watcher.borrow_and_update().clone();
+
+            #[derive(Serialize)]
+            struct ErrorData<'a> {
+                /// What went wrong: `missing` if the document's file doesn't exist, `file` if it
+                /// couldn't be read, `internal` for bugs and misconfiguration, or otherwise the
+                /// kind of [`RenderError`].
+                kind: &'a str,
+                message: &'a str,
+            }
+
+            let file = match state {
+                DocumentState::Content(file) => file,
+                DocumentState::Missing | DocumentState::Error(_) => {
+                    let kind = match state {
+                        DocumentState::Missing => "missing",
+                        _ => "file",
+                    };
+                    let message = format!("{:?}", state.contents().unwrap_err());
+                    let _ = sender
+                        .send(Event::document(
+                            "render_error",
+                            index,
+                            ErrorData {
+                                kind,
+                                message: &redact(&message),
+                            },
+                        ))
+                        .await;
+                    continue;
+                }
+            };
+
+            #[derive(Serialize)]
+            struct SavedData {
+                revision: u64,
+                modified: Option<u64>,
+            }
+            let data = SavedData {
+                revision: file.revision,
+                modified: file
+                    .modified
+                    .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map(|time| time.as_secs()),
+            };
+            if saved {
+                let _ = sender.send(Event::document("saved", index, data)).await;
+            }
+
+            let markdown = file.contents;
+
+            let new_options = DocumentOptions::parse(&markdown);
+            if new_options != options {
+                // The scroll margin is part of the page's stylesheet, so the page is generated
+                // again for it; everything else the page applies itself.
+                let reload = new_options.scroll_margin != options.scroll_margin;
+                options = new_options;
+                if reload {
+                    let _ = sender.send(Event::page("reload")).await;
+                    continue;
+                }
+                #[derive(Serialize)]
+                struct Config<'a> {
+                    tab_title: &'a str,
+                    /// Settings of the whole page, which come from the first document.
+                    page: Option<PageSettings<'a>>,
+                }
+                let templater = self.templater.for_document(&options);
+                let config = Config {
+                    tab_title: options.title.as_deref().unwrap_or(&document.title),
+                    page: (index == 0).then(|| templater.page_settings()),
+                };
+                let _ = sender.send(Event::document("config", index, config)).await;
+            }
+
+            let _ = match self.renderer.render(&markdown, Priority::Interactive).await {
+                Ok(Ok(rendered)) => {
+                    let update = self.update(&document, index, &markdown, &rendered).await;
+                    sender.send(update).await
+                }
+                Ok(Err(RenderError::RateLimited(RateLimited {
+                    limit,
+                    reset,
+                    secondary,
+                }))) => {
+                    #[derive(Serialize)]
+                    struct MessageData {
+                        limit: u32,
+                        reset: u64,
+                        secondary: bool,
+                    }
+                    let data = MessageData {
+                        limit,
+                        secondary,
+                        reset: reset
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs(),
+                    };
+                    sender
+                        .send(Event::document("rate_limited", index, data))
+                        .await
+                }
+                Ok(Err(RenderError::Auth(AuthError {
+                    status,
+                    message,
+                    request_id,
+                }))) => {
+                    #[derive(Serialize)]
+                    struct MessageData<'a> {
+                        status: u16,
+                        message: &'a str,
+                        request_id: Option<&'a str>,
+                        help: &'a str,
+                    }
+                    let data = MessageData {
+                        status: status.as_u16(),
+                        message: &message,
+                        request_id: request_id.as_deref(),
+                        help: "Generate a new token at https://github.com/settings/tokens",
+                    };
+                    sender
+                        .send(Event::document("auth_error", index, data))
+                        .await
+                }
+                Ok(Err(e)) => {
+                    let message = e.to_string();
+                    let data = ErrorData {
+                        kind: e.kind(),
+                        message: &redact(&message),
+                    };
+                    sender
+                        .send(Event::document("render_error", index, data))
+                        .await
+                }
+                Err(e) => {
+                    let message = format!("{:?}", e);
+                    let data = ErrorData {
+                        kind: "internal",
+                        message: &redact(&message),
+                    };
+                    sender
+                        .send(Event::document("render_error", index, data))
+                        .await
+                }
+            };
+        }
+    }
+}
+
+/// What woke up a document's event stream.
+enum Changed {
+    Document,
+    /// The document needs rendering because the page was sent without it.
+    Unrendered,
+    Prose,
+}
+
+/// A message on an event stream. The data of every message is a JSON object containing its
+/// sequence number on the stream, `seq`, and the index of the `document` it concerns, which is
+/// `null` for messages about the whole page.
+pub(crate) struct Event {
+    kind: &'static str,
+    document: Option<usize>,
+    /// The fields specific to this kind of message.
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Event {
+    pub(crate) fn page(kind: &'static str) -> Self {
+        Self {
+            kind,
+            document: None,
+            fields: serde_json::Map::new(),
+        }
+    }
+
+    fn document(kind: &'static str, document: usize, fields: impl Serialize) -> Self {
+        let fields = match serde_json::to_value(fields).unwrap() {
+            serde_json::Value::Object(fields) => fields,
+            _ => panic!("event fields must be an object"),
+        };
+        Self {
+            kind,
+            document: Some(document),
+            fields,
+        }
+    }
+
+    /// Format the message for the wire as number `seq` on its stream.
+    pub(crate) fn into_sse(mut self, seq: u64) -> String {
+        self.fields.insert("seq".to_owned(), seq.into());
+        self.fields
+            .insert("document".to_owned(), self.document.into());
+        sse(
+            self.kind,
+            &serde_json::Value::Object(self.fields).to_string(),
+        )
+    }
+}
+
+/// The document a request is about, chosen by its index with a `document` query parameter and
+/// defaulting to the first.
+struct DocumentIndex(usize);
+
+impl FromRequest<Server> for DocumentIndex {
+    fn from_request(server: &Server, req: &mut Request) -> Result<Self, Rejection> {
+        let query = Query::from_request(server, req)?;
+        let index = match query.get("document") {
+            Some(index) => index.parse().ok(),
+            None => Some(0),
+        };
+        match index {
+            Some(index) if index < server.documents.read().unwrap().len() => Ok(Self(index)),
+            _ => Err(Rejection {
+                status: http::StatusCode::NOT_FOUND,
+                message: "no such document",
+            }),
+        }
+    }
+}
+
+fn json_response(res: anyhow::Result<impl Serialize>) -> Response {
+    match res {
+        Ok(data) => http::Response::builder()
+            .status(http::StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(router::full(serde_json::to_string(&data).unwrap()))
+            .unwrap(),
+        Err(e) => http::Response::builder()
+            .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "text/plain")
+            .body(router::full(redact(&format!("{:?}", e)).into_owned()))
+            .unwrap(),
+    }
 }
 
 fn sse(kind: &str, data: &str) -> String {
@@ -306,39 +2463,3 @@ fn sse(kind: &str, data: &str) -> String {
     event.push_str("\n\n");
     event
 }
-
-fn clone_error(e: &anyhow::Error) -> anyhow::Error {
-    struct ErrorData {
-        debug: String,
-        display: String,
-        source: Option<Box<Self>>,
-    }
-    impl ErrorData {
-        fn new(e: impl StdError) -> Self {
-            Self {
-                debug: format!("{:?}", e),
-                display: e.to_string(),
-                source: e.source().map(|e| Box::new(Self::new(e))),
-            }
-        }
-    }
-    impl Debug for ErrorData {
-        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-            f.write_str(&self.debug)
-        }
-    }
-    impl Display for ErrorData {
-        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-            f.write_str(&self.display)
-        }
-    }
-    impl StdError for ErrorData {
-        fn source(&self) -> Option<&(dyn StdError + 'static)> {
-            self.source
-                .as_deref()
-                .map(|e| -> &(dyn StdError + 'static) { e })
-        }
-    }
-
-    anyhow!(ErrorData::new(e.chain().next().unwrap()))
-}
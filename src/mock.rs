@@ -0,0 +1,119 @@
+//! A stand-in for GitHub's markdown API, so the whole pipeline can be exercised end to end
+//! without a token or network access.
+
+use std::convert::Infallible;
+
+use anyhow::Context as _;
+use http_body_util::BodyExt as _;
+use hyper::body::Incoming;
+use hyper::http;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use tokio::net::TcpListener;
+
+use crate::router::{self, Response};
+use crate::slug::Slugger;
+
+/// Start the mock API on an ephemeral local port, returning its base URL.
+pub(crate) async fn spawn() -> anyhow::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("failed to bind mock API server")?;
+    let url = format!("http://{}", listener.local_addr()?);
+    log::info!("Mock GitHub API listening on {}", url);
+
+    tokio::spawn(async move {
+        loop {
+            let connection = match listener.accept().await {
+                Ok((connection, _)) => connection,
+                Err(e) => {
+                    log::error!(
+                        "{:?}",
+                        anyhow::anyhow!(e).context("mock API failed to accept")
+                    );
+                    continue;
+                }
+            };
+            tokio::spawn(http1::Builder::new().serve_connection(
+                TokioIo::new(connection),
+                service_fn(|req| async { Ok::<_, Infallible>(handle(req).await) }),
+            ));
+        }
+    });
+
+    Ok(url)
+}
+
+async fn handle(req: http::Request<Incoming>) -> Response {
+    #[derive(Deserialize)]
+    struct Body {
+        text: String,
+        #[serde(default)]
+        mode: Option<String>,
+    }
+
+    let response = http::Response::builder()
+        .header("X-RateLimit-Limit", "5000")
+        .header("X-RateLimit-Remaining", "4999")
+        .header("X-RateLimit-Reset", "0")
+        .header("X-GitHub-Request-Id", "0000:0000:0000000:0000000:00000000");
+
+    if req.method() != http::Method::POST || req.uri().path() != "/markdown" {
+        return response
+            .status(http::StatusCode::NOT_FOUND)
+            .body(router::full(r#"{"message":"Not Found"}"#))
+            .unwrap();
+    }
+
+    let body = req
+        .into_body()
+        .collect()
+        .await
+        .ok()
+        .map(|body| body.to_bytes());
+    match body.and_then(|body| serde_json::from_slice::<Body>(&body).ok()) {
+        Some(body) => response
+            .status(http::StatusCode::OK)
+            .header("Content-Type", "text/html;charset=utf-8")
+            .body(router::full(render(
+                &body.text,
+                body.mode.as_deref() == Some("gfm"),
+            )))
+            .unwrap(),
+        None => response
+            .status(http::StatusCode::BAD_REQUEST)
+            .body(router::full(r#"{"message":"Problems parsing JSON"}"#))
+            .unwrap(),
+    }
+}
+
+/// A crude imitation of GitHub's rendering: ATX headings get anchors like GitHub's, and every
+/// other paragraph is escaped verbatim. In comment mode, newlines within paragraphs become line
+/// breaks.
+fn render(markdown: &str, comment_mode: bool) -> String {
+    let mut slugger = Slugger::new();
+    let mut html = String::new();
+    for block in markdown.split("\n\n") {
+        let block = block.trim();
+        let level = block.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&level) && block[level..].starts_with(' ') && !block.contains('\n') {
+            let text = block[level..].trim();
+            let slug = slugger.slug(text);
+            html.push_str(&format!(
+                "<h{level}><a id=\"user-content-{slug}\" class=\"anchor\" aria-hidden=\"true\" href=\"#{slug}\"><span aria-hidden=\"true\" class=\"octicon octicon-link\"></span></a>{text}</h{level}>\n",
+                level = level,
+                slug = tera::escape_html(&slug),
+                text = tera::escape_html(text),
+            ));
+        } else if !block.is_empty() {
+            let mut text = tera::escape_html(block);
+            if comment_mode {
+                text = text.replace('\n', "<br>\n");
+            }
+            html.push_str(&format!("<p>{}</p>\n", text));
+        }
+    }
+    html
+}
@@ -0,0 +1,214 @@
+use std::borrow::Cow;
+
+use regex::Regex;
+
+use crate::config;
+
+/// Reduces markdown to the parts of it that affect how it renders, so that edits which only
+/// touch comments, frontmatter or user-specified regions don't cost an API request.
+///
+/// This is a heuristic: only fenced code blocks are recognized, so a comment-like sequence in an
+/// indented code block or inline code span is still treated as a comment.
+pub(crate) struct Normalizer {
+    patterns: Vec<Regex>,
+}
+
+impl Normalizer {
+    pub(crate) fn new(patterns: Vec<Regex>) -> Self {
+        Self { patterns }
+    }
+
+    pub(crate) fn normalize<'a>(&self, markdown: &'a str) -> Cow<'a, str> {
+        let mut markdown = Cow::Borrowed(strip_frontmatter(markdown));
+        for pattern in &self.patterns {
+            if let Cow::Owned(replaced) = pattern.replace_all(&markdown, "") {
+                markdown = Cow::Owned(replaced);
+            }
+        }
+        match strip_comments(&markdown) {
+            Some(stripped) => Cow::Owned(stripped),
+            None => markdown,
+        }
+    }
+}
+
+fn strip_frontmatter(markdown: &str) -> &str {
+    let frontmatter = match frontmatter(markdown) {
+        Some(frontmatter) => frontmatter,
+        None => return markdown,
+    };
+    let opening = if markdown.starts_with("---\r\n") {
+        5
+    } else {
+        4
+    };
+    let closing = &markdown[opening + frontmatter.len()..];
+    closing.split_once('\n').map_or("", |(_, rest)| rest)
+}
+
+/// The YAML frontmatter of a document, without its delimiters.
+pub(crate) fn frontmatter(markdown: &str) -> Option<&str> {
+    let rest = markdown
+        .strip_prefix("---\n")
+        .or_else(|| markdown.strip_prefix("---\r\n"))?;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            return Some(&rest[..offset]);
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Remove HTML comments outside of fenced code blocks, returning `None` if there were none.
+fn strip_comments(markdown: &str) -> Option<String> {
+    let mut res = String::with_capacity(markdown.len());
+    let mut changed = false;
+    let mut in_comment = false;
+    let mut fence = None;
+
+    for mut line in markdown.split_inclusive('\n') {
+        if !in_comment && in_fenced_code(line, &mut fence) {
+            res.push_str(line);
+            continue;
+        }
+
+        while !line.is_empty() {
+            if in_comment {
+                match line.find("-->") {
+                    Some(end) => {
+                        line = &line[end + 3..];
+                        in_comment = false;
+                    }
+                    None => line = "",
+                }
+            } else {
+                match line.find("<!--") {
+                    Some(start) => {
+                        res.push_str(&line[..start]);
+                        line = &line[start + 4..];
+                        in_comment = true;
+                        changed = true;
+                    }
+                    None => {
+                        res.push_str(line);
+                        line = "";
+                    }
+                }
+            }
+        }
+    }
+
+    changed.then_some(res)
+}
+
+/// Follow fenced code blocks through a document one line at a time, returning whether `line` is
+/// part of one, fences included. `fence` holds the character and length of the open fence, if any,
+/// and should start as `None`.
+pub(crate) fn in_fenced_code(line: &str, fence: &mut Option<(char, usize)>) -> bool {
+    let trimmed = line.trim_start();
+    let marker = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'));
+    if let Some(marker) = marker {
+        let len = trimmed.chars().take_while(|&c| c == marker).count();
+        if len >= 3 {
+            match *fence {
+                None => *fence = Some((marker, len)),
+                Some((open, open_len))
+                    if open == marker && len >= open_len && trimmed[len..].trim().is_empty() =>
+                {
+                    *fence = None;
+                }
+                Some(_) => {}
+            }
+            return true;
+        }
+    }
+    fence.is_some()
+}
+
+/// Removes regions delimited by marker lines, such as
+/// `<!-- ghmd:ignore-start -->` … `<!-- ghmd:ignore-end -->`, before markdown is rendered.
+pub(crate) struct IgnoreRegions {
+    markers: Vec<(String, String)>,
+}
+
+impl IgnoreRegions {
+    /// Create a set of ignored regions from extra marker pairs, in addition to ghmd's own.
+    pub(crate) fn new(extra_markers: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut markers = vec![(
+            "<!-- ghmd:ignore-start -->".to_owned(),
+            "<!-- ghmd:ignore-end -->".to_owned(),
+        )];
+        markers.extend(extra_markers);
+        Self { markers }
+    }
+
+    /// Strip every ignored region, including those delimited by the document's own markers.
+    /// Markers must be on their own line; a region that is never closed extends to the end of the
+    /// document.
+    pub(crate) fn strip<'a>(&self, markdown: &'a str) -> Cow<'a, str> {
+        let own = config::ignore_markers(markdown);
+        let markers = || self.markers.iter().chain(&own);
+        if !markers().any(|(start, _)| markdown.contains(&**start)) {
+            return Cow::Borrowed(markdown);
+        }
+
+        let mut res = String::with_capacity(markdown.len());
+        let mut end_marker: Option<&str> = None;
+        for line in markdown.split_inclusive('\n') {
+            let trimmed = line.trim();
+            match end_marker {
+                Some(end) => {
+                    if trimmed == end {
+                        end_marker = None;
+                    }
+                }
+                None => match markers().find(|(start, _)| trimmed == start) {
+                    Some((_, end)) => end_marker = Some(end),
+                    None => res.push_str(line),
+                },
+            }
+        }
+        Cow::Owned(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IgnoreRegions;
+
+    #[test]
+    fn ignore_regions() {
+        let regions = IgnoreRegions::new([("BEGIN".to_owned(), "END".to_owned())]);
+        let strip = |markdown| regions.strip(markdown).into_owned();
+        assert_eq!(
+            strip("a\n<!-- ghmd:ignore-start -->\nb\n<!-- ghmd:ignore-end -->\nc\n"),
+            "a\nc\n"
+        );
+        assert_eq!(strip("a\n  BEGIN\nb\nEND\nc\n"), "a\nc\n");
+        assert_eq!(strip("a\nBEGIN\nb\n"), "a\n");
+        assert_eq!(strip("a BEGIN\nb END\n"), "a BEGIN\nb END\n");
+    }
+
+    #[test]
+    fn document_markers() {
+        let regions = IgnoreRegions::new([]);
+        let markdown = "\
+            ---\n\
+            ghmd:\n  \
+              ignore-start: \"<!-- begin generated -->\"\n  \
+              ignore-end: \"<!-- end generated -->\"\n\
+            ---\n\
+            a\n\
+            <!-- begin generated -->\n\
+            b\n\
+            <!-- end generated -->\n\
+            c\n\
+        ";
+        let stripped = regions.strip(markdown);
+        assert!(stripped.ends_with("---\na\nc\n"), "{}", stripped);
+        let other = "a\n<!-- begin generated -->\nb\n";
+        assert_eq!(regions.strip(other), other);
+    }
+}
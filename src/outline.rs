@@ -0,0 +1,194 @@
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use scraper::{Html, Selector};
+use serde::Serialize;
+
+use crate::normalize::in_fenced_code;
+use crate::slug::{self, Slugger};
+
+/// A heading in the rendered document.
+#[derive(Serialize)]
+pub(crate) struct Heading {
+    /// The heading's text, without any formatting.
+    pub(crate) heading: String,
+    /// The anchor GitHub gives the heading, without the leading `#`.
+    pub(crate) slug: String,
+    pub(crate) level: u8,
+    /// The line of the markdown the heading was written on, counting from 1, if it could be
+    /// found.
+    pub(crate) source_line: Option<usize>,
+}
+
+/// List the headings of a document in order, given both its rendered HTML and its markdown.
+///
+/// Headings are taken from the HTML, so that headings written in raw HTML are included, and then
+/// matched up with the ATX and setext headings of the markdown to find their lines: a heading is
+/// matched with the next one in the markdown with the same level and anchor, or failing that just
+/// the same level.
+pub(crate) fn headings(html: &str, markdown: &str) -> Vec<Heading> {
+    static HEADINGS: Lazy<Selector> =
+        Lazy::new(|| Selector::parse("h1, h2, h3, h4, h5, h6").unwrap());
+
+    let source = source_headings(markdown);
+    let mut next_source = 0;
+    let mut slugger = Slugger::new();
+    Html::parse_fragment(html)
+        .select(&HEADINGS)
+        .map(|element| {
+            let heading = element.text().collect::<String>().trim().to_owned();
+            let level = element.value().name()[1..].parse().unwrap();
+            let rest = &source[next_source..];
+            let slug = slug::slug(&heading);
+            let source_line = rest
+                .iter()
+                .position(|s| s.level == level && slug::slug(&s.text) == slug)
+                .or_else(|| rest.iter().position(|s| s.level == level))
+                .map(|i| {
+                    next_source += i + 1;
+                    source[next_source - 1].line
+                });
+            Heading {
+                slug: slugger.slug(&heading),
+                heading,
+                level,
+                source_line,
+            }
+        })
+        .collect()
+}
+
+/// Give each heading in rendered HTML whose source could be found a `data-source-lines` attribute
+/// holding the lines of markdown its section spans, such as `3-10`.
+pub(crate) fn mark_source_lines(html: &str, markdown: &str) -> String {
+    static START_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<h[1-6]\b").unwrap());
+
+    let headings = headings(html, markdown);
+    let last_line = markdown.lines().count();
+    let mut i = 0;
+    START_TAG
+        .replace_all(html, |captures: &Captures<'_>| {
+            let tag = &captures[0];
+            i += 1;
+            let (heading, start) = match headings.get(i - 1) {
+                Some(
+                    heading @ Heading {
+                        source_line: Some(start),
+                        ..
+                    },
+                ) => (heading, *start),
+                _ => return tag.to_owned(),
+            };
+            // The section ends where the next heading of the same or a higher level starts.
+            let end = headings[i..]
+                .iter()
+                .filter(|next| next.level <= heading.level)
+                .find_map(|next| next.source_line)
+                .map_or(last_line, |next| next - 1)
+                .max(start);
+            format!("{} data-source-lines=\"{}-{}\"", tag, start, end)
+        })
+        .into_owned()
+}
+
+/// Put each heading's number, such as `1.2.3.`, in front of it. A lone `h1` at the start is taken
+/// to be the document's title and left unnumbered, so that its sections are numbered from 1.
+pub(crate) fn number_headings(html: &str) -> String {
+    static START_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<h([1-6])\b[^>]*>").unwrap());
+
+    let levels: Vec<usize> = START_TAG
+        .captures_iter(html)
+        .map(|captures| captures[1].parse().unwrap())
+        .collect();
+    let titled =
+        levels.first() == Some(&1) && levels.iter().filter(|&&level| level == 1).count() == 1;
+    let top = levels
+        .iter()
+        .copied()
+        .filter(|&level| !(titled && level == 1))
+        .min()
+        .unwrap_or(1);
+    let mut counts = [0_u32; 6];
+    START_TAG
+        .replace_all(html, |captures: &Captures<'_>| {
+            let tag = &captures[0];
+            let level: usize = captures[1].parse().unwrap();
+            if level < top {
+                return tag.to_owned();
+            }
+            counts[level - 1] += 1;
+            counts[level..].fill(0);
+            // Levels skipped over, as in an `h4` straight after an `h2`, are left out.
+            let number: String = counts[top - 1..level]
+                .iter()
+                .filter(|&&count| count != 0)
+                .map(|count| format!("{}.", count))
+                .collect();
+            format!(
+                "{}<span class=\"ghmd-heading-number\">{} </span>",
+                tag, number
+            )
+        })
+        .into_owned()
+}
+
+/// A heading as written in markdown.
+struct SourceHeading {
+    level: u8,
+    /// The line it starts on, counting from 1.
+    line: usize,
+    /// Its text, including any formatting.
+    text: String,
+}
+
+/// Find each ATX and setext heading in markdown, outside of fenced code blocks.
+fn source_headings(markdown: &str) -> Vec<SourceHeading> {
+    let mut headings = Vec::new();
+    let mut fence = None;
+    // The paragraph line before this one and its line number, which a setext underline turns
+    // into a heading.
+    let mut paragraph: Option<(usize, &str)> = None;
+    for (i, line) in markdown.lines().enumerate() {
+        if in_fenced_code(line, &mut fence) {
+            paragraph = None;
+            continue;
+        }
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let trimmed = line.trim();
+        if indent > 3 || trimmed.is_empty() {
+            paragraph = None;
+            continue;
+        }
+
+        let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+        let underline = trimmed
+            .chars()
+            .next()
+            .filter(|&c| (c == '=' || c == '-') && trimmed.chars().all(|other| other == c));
+        if (1..=6).contains(&hashes)
+            && trimmed[hashes..]
+                .chars()
+                .next()
+                .is_none_or(char::is_whitespace)
+        {
+            headings.push(SourceHeading {
+                level: hashes as u8,
+                line: i + 1,
+                text: trimmed[hashes..].trim().trim_end_matches('#').to_owned(),
+            });
+            paragraph = None;
+        } else if let (Some(underline), Some((line, text))) = (underline, paragraph) {
+            headings.push(SourceHeading {
+                level: if underline == '=' { 1 } else { 2 },
+                line,
+                text: text.to_owned(),
+            });
+            paragraph = None;
+        } else if underline.is_some() {
+            // A thematic break.
+            paragraph = None;
+        } else if paragraph.is_none() {
+            paragraph = Some((i + 1, trimmed));
+        }
+    }
+    headings
+}
@@ -0,0 +1,55 @@
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf, Prefix};
+
+/// The longest path most Windows programs accept without the `\\?\` prefix.
+const MAX_PATH: usize = 260;
+
+/// Turn a path from `canonicalize` back into the form people write.
+///
+/// On Windows, canonical paths are verbatim paths like `\\?\C:\docs\README.md` or
+/// `\\?\UNC\server\share\README.md`, which look odd in titles and logs and confuse some programs.
+/// They are converted to `C:\docs\README.md` and `\\server\share\README.md`, unless the path is
+/// too long to work without the prefix. Paths on other platforms are returned unchanged.
+pub(crate) fn simplify(path: PathBuf) -> PathBuf {
+    let mut components = path.components();
+    let prefix = match components.next() {
+        Some(Component::Prefix(prefix)) => prefix,
+        _ => return path,
+    };
+    let mut simplified = match prefix.kind() {
+        Prefix::VerbatimDisk(letter) => OsString::from(format!("{}:", char::from(letter))),
+        Prefix::VerbatimUNC(server, share) => {
+            let mut unc = OsString::from(r"\\");
+            unc.push(server);
+            unc.push(r"\");
+            unc.push(share);
+            unc
+        }
+        _ => return path,
+    };
+    let rest = components.as_path();
+    // A verbatim path's components are taken literally, so ones that would be normalized away
+    // without the prefix can't be written any other way.
+    let literal = rest.components().all(|component| match component {
+        Component::Normal(name) => {
+            let name = name.to_string_lossy();
+            !name.ends_with(['.', ' ']) && !name.contains('/')
+        }
+        Component::RootDir => true,
+        _ => false,
+    });
+    if !literal {
+        return path;
+    }
+    simplified.push(rest.as_os_str());
+    let simplified = PathBuf::from(simplified);
+    if simplified.as_os_str().len() >= MAX_PATH {
+        return path;
+    }
+    simplified
+}
+
+/// How to show a path given by the user: as they wrote it, unless it is a verbatim path.
+pub(crate) fn display(path: &Path) -> String {
+    simplify(path.to_owned()).to_string_lossy().into_owned()
+}
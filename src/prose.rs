@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, ensure};
+use fn_error_context::context;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use tokio::process::Command;
+use tokio::sync::watch;
+
+use crate::watcher::DocumentState;
+
+/// Something an external prose linter or spell checker complained about.
+#[derive(Serialize)]
+pub(crate) struct Finding {
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    /// The word at the finding's position in the markdown, which is what gets underlined.
+    pub(crate) word: String,
+    pub(crate) message: String,
+}
+
+/// Run the linter every time the document changes, in the background so it never holds up
+/// rendering. Each run's findings are summarized in the log as well as published.
+pub(crate) fn spawn(
+    command: Vec<String>,
+    path: PathBuf,
+    mut file: watch::Receiver<DocumentState>,
+) -> watch::Receiver<Arc<[Finding]>> {
+    let (sender, receiver) = watch::channel(<Arc<[Finding]>>::from(Vec::new()));
+    tokio::spawn(async move {
+        loop {
+            let contents = file
+                .borrow_and_update()
+                .file()
+                .map(|file| file.contents.clone());
+            if let Some(contents) = contents {
+                match check(&command, &path, &contents).await {
+                    Ok(findings) => {
+                        for finding in &findings {
+                            log::warn!(
+                                "{}:{}:{}: {}",
+                                path.display(),
+                                finding.line,
+                                finding.column,
+                                finding.message
+                            );
+                        }
+                        log::info!("Prose linter found {} problem(s)", findings.len());
+                        if sender.send(findings.into()).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => log::error!("{:?}", e),
+                }
+            }
+            tokio::select! {
+                res = file.changed() => if res.is_err() { return },
+                () = sender.closed() => return,
+            }
+        }
+    });
+    receiver
+}
+
+/// Run the linter on the file, which must print its findings as `FILE:LINE:COLUMN:MESSAGE`.
+#[context("failed to run prose linter `{}`", shell_words::join(command))]
+async fn check(command: &[String], path: &Path, contents: &str) -> anyhow::Result<Vec<Finding>> {
+    static FINDING: Lazy<Regex> = Lazy::new(|| Regex::new(r"^.*?:(\d+):(\d+):\s*(.*)$").unwrap());
+
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("the command is empty"))?;
+    let output = Command::new(program).args(args).arg(path).output().await?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Linters usually exit unsuccessfully when they find something, so only treat failure as an
+    // error if nothing was reported.
+    ensure!(
+        output.status.success() || !stdout.trim().is_empty(),
+        "linter exited with {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim(),
+    );
+
+    let lines: Vec<&str> = contents.lines().collect();
+    Ok(stdout
+        .lines()
+        .filter_map(|finding| {
+            let captures = FINDING.captures(finding)?;
+            let line: usize = captures[1].parse().ok()?;
+            let column: usize = captures[2].parse().ok()?;
+            let word = lines
+                .get(line.checked_sub(1)?)?
+                .chars()
+                .skip(column.saturating_sub(1))
+                .take_while(|&c| c.is_alphanumeric() || c == '\'' || c == '-')
+                .collect();
+            Some(Finding {
+                line,
+                column,
+                word,
+                message: captures[3].to_owned(),
+            })
+        })
+        .collect())
+}
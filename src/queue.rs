@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tokio::time::{self, Instant};
+
+/// A semaphore whose waiters are woken in order of priority, then in order of arrival.
+///
+/// Background renders are also paced, starting at least `interval` after the render before them,
+/// so that batches of them don't trip GitHub's secondary rate limits.
+pub(crate) struct RenderQueue {
+    state: Mutex<State>,
+    interval: Duration,
+    /// When the most recent render started.
+    last_start: Mutex<Option<Instant>>,
+}
+
+struct State {
+    available: usize,
+    /// Waiters for each priority level, indexed by `Priority as usize`.
+    waiting: [VecDeque<oneshot::Sender<()>>; 2],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Priority {
+    /// A render that someone is actively waiting to see, such as a page load.
+    Interactive = 0,
+    /// A render that nobody is directly waiting on, such as exports.
+    Background = 1,
+}
+
+impl RenderQueue {
+    pub(crate) fn new(concurrency: usize, interval: Duration) -> Self {
+        Self {
+            state: Mutex::new(State {
+                available: concurrency,
+                waiting: [VecDeque::new(), VecDeque::new()],
+            }),
+            interval,
+            last_start: Mutex::new(None),
+        }
+    }
+
+    /// Wait for a slot to become available, after waiting for the render to be paced if it is in
+    /// the background. The slot is released when the returned permit is dropped.
+    ///
+    /// Paced renders don't hold a slot while they wait, so renders someone is waiting to see are
+    /// never held up by them.
+    pub(crate) async fn acquire(&self, priority: Priority) -> Permit<'_> {
+        loop {
+            if let Priority::Background = priority {
+                let next = self
+                    .last_start
+                    .lock()
+                    .unwrap()
+                    .map(|last| last + self.interval);
+                if let Some(next) = next.filter(|&next| next > Instant::now()) {
+                    time::sleep_until(next).await;
+                    continue;
+                }
+            }
+            let permit = self.acquire_slot(priority).await;
+            let mut last_start = self.last_start.lock().unwrap();
+            let now = Instant::now();
+            // Another render may have started while this one waited for the slot, so pacing is
+            // checked again now that it has one.
+            if let (Priority::Background, Some(last)) = (priority, *last_start) {
+                if now < last + self.interval {
+                    drop(last_start);
+                    drop(permit);
+                    continue;
+                }
+            }
+            *last_start = Some(last_start.map_or(now, |last| last.max(now)));
+            return permit;
+        }
+    }
+
+    async fn acquire_slot(&self, priority: Priority) -> Permit<'_> {
+        let mut waiter = {
+            let mut state = self.state.lock().unwrap();
+            if state.available > 0 {
+                state.available -= 1;
+                return Permit { queue: self };
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.waiting[priority as usize].push_back(sender);
+            Waiter {
+                queue: self,
+                receiver,
+            }
+        };
+
+        // The sender is only ever dropped after sending, since the queue lives at least as long
+        // as this future.
+        (&mut waiter.receiver).await.unwrap();
+        Permit { queue: self }
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        for waiting in &mut state.waiting {
+            while let Some(waiter) = waiting.pop_front() {
+                // If the waiter has been cancelled the permit is passed on to the next one.
+                if waiter.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+        state.available += 1;
+    }
+}
+
+/// Releases the permit if we were cancelled after being handed it but before receiving it.
+struct Waiter<'a> {
+    queue: &'a RenderQueue,
+    receiver: oneshot::Receiver<()>,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        self.receiver.close();
+        if self.receiver.try_recv().is_ok() {
+            self.queue.release();
+        }
+    }
+}
+
+pub(crate) struct Permit<'a> {
+    queue: &'a RenderQueue,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.queue.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{Priority, RenderQueue};
+
+    #[tokio::test]
+    async fn pacing_holds_no_slot() {
+        let interval = Duration::from_millis(300);
+        let queue = RenderQueue::new(1, interval);
+        drop(queue.acquire(Priority::Background).await);
+
+        let start = Instant::now();
+        let (background, interactive) = tokio::join!(
+            async {
+                let permit = queue.acquire(Priority::Background).await;
+                let elapsed = start.elapsed();
+                drop(permit);
+                elapsed
+            },
+            async {
+                tokio::task::yield_now().await;
+                let permit = queue.acquire(Priority::Interactive).await;
+                let elapsed = start.elapsed();
+                drop(permit);
+                elapsed
+            },
+        );
+        assert!(interactive < interval / 2, "{:?}", interactive);
+        assert!(background >= interval, "{:?}", background);
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::dirs;
+use crate::renderer::{self, RateLimited};
+
+/// When GitHub's rate limit for a token last ran out and when it resets, kept in the cache
+/// directory so that a restarted ghmd doesn't spend a request finding out it is still limited.
+pub(crate) struct Exhaustion {
+    /// The file it is kept in, or `None` if there is no cache directory.
+    path: Option<PathBuf>,
+    current: Mutex<Option<Stored>>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Stored {
+    limit: u32,
+    /// When the limit resets, in seconds since the Unix epoch.
+    reset: u64,
+    #[serde(default)]
+    secondary: bool,
+}
+
+impl Exhaustion {
+    /// Read what is known about the limit of `token` on the API at `api_url`. Each token has its
+    /// own file, named by a hash so that the token itself isn't written to disk.
+    pub(crate) async fn load(api_url: &str, token: &str) -> Self {
+        let path = dirs::cache_dir().ok().map(|dir| {
+            let hash = renderer::content_hash(&format!("{}\n{}", api_url, token));
+            dir.join("rate-limit").join(format!("{}.json", hash))
+        });
+        let stored = match &path {
+            Some(path) => match fs::read_to_string(path).await {
+                Ok(contents) => serde_json::from_str::<Stored>(&contents).ok(),
+                Err(_) => None,
+            },
+            None => None,
+        };
+        let this = Self {
+            path,
+            current: Mutex::new(stored),
+        };
+        if let Some(limited) = this.get() {
+            log::warn!(
+                "Still rate limited by GitHub from a previous run; not sending requests for {}",
+                humantime::format_duration(Duration::from_secs(
+                    limited
+                        .reset
+                        .duration_since(SystemTime::now())
+                        .unwrap_or_default()
+                        .as_secs()
+                )),
+            );
+        }
+        this
+    }
+
+    /// The rate limit, if it is known to be used up and hasn't reset yet.
+    pub(crate) fn get(&self) -> Option<RateLimited> {
+        let stored = (*self.current.lock().unwrap())?;
+        let reset = UNIX_EPOCH + Duration::from_secs(stored.reset);
+        (reset > SystemTime::now()).then_some(RateLimited {
+            limit: stored.limit,
+            reset,
+            secondary: stored.secondary,
+        })
+    }
+
+    /// Record that the limit has been used up until `limited.reset`.
+    pub(crate) async fn set(&self, limited: &RateLimited) {
+        let stored = Stored {
+            limit: limited.limit,
+            reset: limited
+                .reset
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            secondary: limited.secondary,
+        };
+        *self.current.lock().unwrap() = Some(stored);
+        if let Err(e) = self.write(Some(stored)).await {
+            log::warn!("{:?}", e);
+        }
+    }
+
+    /// Record that requests are getting through again.
+    pub(crate) async fn clear(&self) {
+        if self.current.lock().unwrap().take().is_none() {
+            return;
+        }
+        if let Err(e) = self.write(None).await {
+            log::warn!("{:?}", e);
+        }
+    }
+
+    async fn write(&self, stored: Option<Stored>) -> anyhow::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        match stored {
+            Some(stored) => {
+                fs::create_dir_all(path.parent().unwrap()).await?;
+                fs::write(path, serde_json::to_string(&stored)?)
+                    .await
+                    .context("failed to save the rate limit reset time")
+            }
+            None => match fs::remove_file(path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(e).context("failed to forget the rate limit reset time")
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+}
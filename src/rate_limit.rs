@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use hyper::http;
+
+use crate::router::{self, RemoteAddr, Request, Response};
+
+/// Once this many clients are being tracked, those whose buckets have refilled are forgotten.
+const MAX_CLIENTS: usize = 1024;
+
+/// Limits how often each client can call the `/api/` routes, so that a client can't use up the
+/// GitHub API quota by rendering many different documents. Other routes are left alone.
+///
+/// Each client, identified by its IP address, has a bucket of `per_minute` requests that refills
+/// steadily over a minute, so short bursts are allowed as long as the average rate is kept to.
+#[derive(Clone)]
+pub(crate) struct RateLimit {
+    per_minute: NonZeroU32,
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    pub(crate) fn new(per_minute: NonZeroU32) -> Self {
+        Self {
+            per_minute,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Take a request from the client's bucket, or return how long it must wait until it may
+    /// make another one.
+    fn take(&self, client: IpAddr) -> Result<(), Duration> {
+        let capacity = f64::from(self.per_minute.get());
+        let refill = |bucket: &Bucket, now: Instant| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * capacity / 60.0).min(capacity)
+        };
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_CLIENTS {
+            buckets.retain(|_, bucket| refill(bucket, now) < capacity);
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = refill(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) * 60.0 / capacity,
+            ))
+        }
+    }
+}
+
+impl<S> tower::Layer<S> for RateLimit {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            limit: self.clone(),
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct RateLimitService<S> {
+    limit: RateLimit,
+    inner: S,
+}
+
+impl<S> tower::Service<Request> for RateLimitService<S>
+where
+    S: tower::Service<Request, Response = Response, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let client = match req.extensions().get::<RemoteAddr>() {
+            Some(RemoteAddr(address)) if req.uri().path().starts_with("/api/") => {
+                address.ip().to_canonical()
+            }
+            _ => return Box::pin(self.inner.call(req)),
+        };
+
+        match self.limit.take(client) {
+            Ok(()) => Box::pin(self.inner.call(req)),
+            Err(wait) => {
+                log::debug!("Rate limited {} for {:?}", client, wait);
+                let mut response =
+                    router::text(http::StatusCode::TOO_MANY_REQUESTS, "too many requests");
+                let retry_after = wait.as_secs_f64().ceil().to_string();
+                response
+                    .headers_mut()
+                    .insert("Retry-After", retry_after.parse().unwrap());
+                Box::pin(async move { Ok(response) })
+            }
+        }
+    }
+}
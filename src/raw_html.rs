@@ -0,0 +1,410 @@
+use std::borrow::Cow;
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use crate::normalize::in_fenced_code;
+
+/// What to do with HTML written directly in a document, which GitHub's sanitizer often changes or
+/// removes.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Mode {
+    Keep,
+    /// Remove every tag before rendering, keeping the text between them.
+    Strip,
+    /// Outline every element that came from raw HTML in the preview.
+    Flag,
+}
+
+/// Placed after each raw start tag by [`mark`], to be found again in the rendered HTML by
+/// [`flag`]. It is in a private use area so it can't clash with anything a document contains.
+const MARKER: char = '\u{E000}';
+
+/// The attribute [`flag`] gives elements that came from raw HTML.
+const ATTRIBUTE: &str = "data-ghmd-html";
+
+/// An HTML start tag, end tag or self-closing tag. Autolinks like `<https://example.com>` don't
+/// match, because of the character after the scheme.
+static TAG: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"<(/?)([A-Za-z][A-Za-z0-9-]*)(\s[^<>]*)?/?>"#).unwrap());
+
+/// Tags that start an HTML block even when followed by other content on the same line, so adding
+/// a marker after them doesn't change how the document is parsed.
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "base",
+    "basefont",
+    "blockquote",
+    "body",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "frame",
+    "frameset",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "iframe",
+    "legend",
+    "li",
+    "link",
+    "main",
+    "menu",
+    "menuitem",
+    "nav",
+    "noframes",
+    "ol",
+    "optgroup",
+    "option",
+    "p",
+    "param",
+    "search",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "track",
+    "ul",
+];
+
+/// Remove every HTML tag outside of code.
+///
+/// This is a heuristic: only fenced code blocks and code spans within a single line are
+/// recognized as code.
+pub(crate) fn strip(markdown: &str) -> Cow<'_, str> {
+    if !TAG.is_match(markdown) {
+        return Cow::Borrowed(markdown);
+    }
+    Cow::Owned(outside_code(markdown, |text, _, _, res| {
+        res.push_str(&TAG.replace_all(text, ""));
+    }))
+}
+
+/// Put a marker after every HTML start tag outside of code, so that [`flag`] can find the
+/// elements they became after rendering.
+pub(crate) fn mark(markdown: &str) -> Cow<'_, str> {
+    if !TAG.is_match(markdown) {
+        return Cow::Borrowed(markdown);
+    }
+    Cow::Owned(outside_code(markdown, |text, line, _, res| {
+        let marked = TAG.replace_all(text, |captures: &Captures<'_>| {
+            let tag = &captures[0];
+            let name = captures[2].to_ascii_lowercase();
+            // A line holding nothing but a tag of any other kind is an HTML block only as long
+            // as nothing follows it.
+            let alone = line.trim() == tag;
+            if &captures[1] == "/" || (alone && !BLOCK_TAGS.contains(&&*name)) {
+                tag.to_owned()
+            } else {
+                format!("{}{}", tag, MARKER)
+            }
+        });
+        res.push_str(&marked);
+    }))
+}
+
+/// Replace each marker left by [`mark`] in rendered HTML with an attribute on the element it
+/// follows. Markers whose tag the sanitizer removed are dropped.
+pub(crate) fn flag(html: &str) -> Cow<'_, str> {
+    if !html.contains(MARKER) {
+        return Cow::Borrowed(html);
+    }
+    let mut res = String::with_capacity(html.len());
+    for (i, piece) in html.split(MARKER).enumerate() {
+        if i != 0 && res.ends_with('>') {
+            let start = res.rfind('<').unwrap();
+            let is_start_tag = res[start + 1..].starts_with(|c: char| c.is_ascii_alphabetic());
+            if is_start_tag {
+                let end = res.len() - if res.ends_with("/>") { 2 } else { 1 };
+                let end = res[..end].trim_end().len();
+                res.insert_str(end, &format!(" {}", ATTRIBUTE));
+            }
+        }
+        res.push_str(piece);
+    }
+    Cow::Owned(res)
+}
+
+/// Elements that GitHub's sanitizer keeps.
+const ALLOWED_ELEMENTS: &[&str] = &[
+    "a",
+    "abbr",
+    "b",
+    "bdo",
+    "blockquote",
+    "br",
+    "caption",
+    "cite",
+    "code",
+    "dd",
+    "del",
+    "details",
+    "dfn",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "ins",
+    "kbd",
+    "li",
+    "mark",
+    "ol",
+    "p",
+    "picture",
+    "pre",
+    "q",
+    "rp",
+    "rt",
+    "ruby",
+    "s",
+    "samp",
+    "small",
+    "source",
+    "span",
+    "strike",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "time",
+    "tr",
+    "tt",
+    "ul",
+    "var",
+    "wbr",
+];
+
+/// Attributes that GitHub's sanitizer keeps on any element.
+const ALLOWED_ATTRIBUTES: &[&str] = &[
+    "abbr",
+    "accept",
+    "accept-charset",
+    "accesskey",
+    "action",
+    "align",
+    "alt",
+    "aria-describedby",
+    "aria-hidden",
+    "aria-label",
+    "aria-labelledby",
+    "axis",
+    "border",
+    "cellpadding",
+    "cellspacing",
+    "char",
+    "charoff",
+    "charset",
+    "checked",
+    "clear",
+    "color",
+    "cols",
+    "colspan",
+    "compact",
+    "coords",
+    "datetime",
+    "dir",
+    "disabled",
+    "enctype",
+    "for",
+    "frame",
+    "headers",
+    "height",
+    "hreflang",
+    "hspace",
+    "id",
+    "ismap",
+    "itemprop",
+    "label",
+    "lang",
+    "maxlength",
+    "media",
+    "method",
+    "multiple",
+    "name",
+    "nohref",
+    "noshade",
+    "nowrap",
+    "open",
+    "progress",
+    "prompt",
+    "readonly",
+    "rel",
+    "rev",
+    "role",
+    "rows",
+    "rowspan",
+    "rules",
+    "scope",
+    "selected",
+    "shape",
+    "size",
+    "span",
+    "start",
+    "summary",
+    "tabindex",
+    "target",
+    "title",
+    "type",
+    "usemap",
+    "valign",
+    "value",
+    "vspace",
+    "width",
+];
+
+/// Whether GitHub's sanitizer keeps an attribute on an element.
+fn allowed_attribute(element: &str, attribute: &str) -> bool {
+    ALLOWED_ATTRIBUTES.contains(&attribute)
+        || matches!(
+            (element, attribute),
+            ("a", "href")
+                | ("img", "src" | "longdesc")
+                | ("source", "srcset")
+                | ("div", "itemscope" | "itemtype")
+                | ("blockquote" | "del" | "ins" | "q", "cite")
+        )
+}
+
+/// Something in the document's HTML that GitHub will remove when rendering it.
+pub(crate) struct Removal {
+    /// The line it is on, counting from 1.
+    pub(crate) line: usize,
+    pub(crate) element: String,
+    /// The attribute that will be removed, or `None` if the whole element will be.
+    pub(crate) attribute: Option<String>,
+}
+
+/// Find the elements and attributes written in the document that GitHub's sanitizer will remove.
+///
+/// This compares them against an approximation of GitHub's allowlist, so it doesn't catch
+/// everything; for example, links with disallowed protocols are kept.
+pub(crate) fn removals(markdown: &str) -> Vec<Removal> {
+    static ATTRIBUTE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"([A-Za-z_:][-A-Za-z0-9_:.]*)(?:\s*=\s*(?:"[^"]*"|'[^']*'|[^\s"'=<>`]+))?"#)
+            .unwrap()
+    });
+
+    let mut removals = Vec::new();
+    if !TAG.is_match(markdown) {
+        return removals;
+    }
+    outside_code(markdown, |text, _, line, _| {
+        for captures in TAG.captures_iter(text) {
+            if &captures[1] == "/" {
+                continue;
+            }
+            let element = captures[2].to_ascii_lowercase();
+            if !ALLOWED_ELEMENTS.contains(&&*element) {
+                removals.push(Removal {
+                    line,
+                    element,
+                    attribute: None,
+                });
+                continue;
+            }
+            let attributes = captures.get(3).map_or("", |m| m.as_str());
+            for attribute in ATTRIBUTE.captures_iter(attributes) {
+                let attribute = attribute[1].to_ascii_lowercase();
+                if !allowed_attribute(&element, &attribute) {
+                    removals.push(Removal {
+                        line,
+                        element: element.clone(),
+                        attribute: Some(attribute),
+                    });
+                }
+            }
+        }
+    });
+    removals
+}
+
+/// Rebuild markdown by passing each stretch of text outside fenced code blocks and code spans to
+/// `f` along with its line and line number, copying the code through unchanged.
+fn outside_code(markdown: &str, mut f: impl FnMut(&str, &str, usize, &mut String)) -> String {
+    let mut res = String::with_capacity(markdown.len());
+    let mut fence = None;
+    for (i, line) in markdown.split_inclusive('\n').enumerate() {
+        if in_fenced_code(line, &mut fence) {
+            res.push_str(line);
+            continue;
+        }
+
+        let mut rest = line;
+        while let Some(start) = rest.find('`') {
+            f(&rest[..start], line, i + 1, &mut res);
+            let ticks = rest[start..].len() - rest[start..].trim_start_matches('`').len();
+            let after = &rest[start + ticks..];
+            match closing_ticks(after, ticks) {
+                Some(end) => {
+                    res.push_str(&rest[start..start + ticks + end + ticks]);
+                    rest = &after[end + ticks..];
+                }
+                None => {
+                    res.push_str(&rest[start..start + ticks]);
+                    rest = after;
+                }
+            }
+        }
+        f(rest, line, i + 1, &mut res);
+    }
+    res
+}
+
+/// The position of the next run of exactly `len` backticks, which closes a code span.
+fn closing_ticks(s: &str, len: usize) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(start) = s[offset..].find('`') {
+        let start = offset + start;
+        let run = s[start..].len() - s[start..].trim_start_matches('`').len();
+        if run == len {
+            return Some(start);
+        }
+        offset = start + run;
+    }
+    None
+}
@@ -0,0 +1,48 @@
+use std::borrow::Cow;
+
+use crate::normalize::in_fenced_code;
+
+/// READMEs longer than this many bytes are cut off on the repository's page.
+const MAX_BYTES: usize = 500 * 1024;
+
+/// Placed where the README is cut off by [`mark_cutoff`], to be found again in the rendered HTML
+/// by [`show_cutoff`]. It is in a private use area so it can't clash with anything a document
+/// contains.
+const MARKER: char = '\u{E001}';
+
+/// Insert a marker where GitHub cuts off a long README, or as close after it as it can go without
+/// changing how the rest of the document is parsed: at the next blank line outside fenced code.
+pub(crate) fn mark_cutoff(markdown: &str) -> Cow<'_, str> {
+    if markdown.len() <= MAX_BYTES {
+        return Cow::Borrowed(markdown);
+    }
+    let mut fence = None;
+    let mut offset = 0;
+    for line in markdown.split_inclusive('\n') {
+        let in_code = in_fenced_code(line, &mut fence);
+        offset += line.len();
+        if offset > MAX_BYTES && !in_code && line.trim().is_empty() {
+            return Cow::Owned(format!(
+                "{}{}\n\n{}",
+                &markdown[..offset],
+                MARKER,
+                &markdown[offset..]
+            ));
+        }
+    }
+    Cow::Borrowed(markdown)
+}
+
+/// Replace the marker left by [`mark_cutoff`] in rendered HTML with a line across the page.
+pub(crate) fn show_cutoff(html: &str) -> Cow<'_, str> {
+    if !html.contains(MARKER) {
+        return Cow::Borrowed(html);
+    }
+    Cow::Owned(html.replace(
+        MARKER,
+        "<span class=\"ghmd-readme-cutoff\" \
+            style=\"display: block; border-top: 2px dashed var(--color-danger-fg, red); \
+            color: var(--color-danger-fg, red); font-size: 12px; padding-top: 4px\">\
+            GitHub stops showing the README here</span>",
+    ))
+}
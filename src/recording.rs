@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as _};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use tokio::fs;
+
+/// Whether GitHub API exchanges are being saved or served from disk.
+pub(crate) enum Mode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+/// A request to render markdown and GitHub's response to it.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Exchange {
+    pub(crate) markdown: String,
+    pub(crate) status: u16,
+    /// Response headers, with lowercase names.
+    pub(crate) headers: BTreeMap<String, String>,
+    pub(crate) body: String,
+}
+
+impl Exchange {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(|s| &**s)
+    }
+}
+
+fn path(dir: &Path, markdown: &str) -> PathBuf {
+    let hash = Sha512::digest(markdown.as_bytes());
+    dir.join(format!("{}.json", &format!("{:x}", hash)[..32]))
+}
+
+#[context("failed to record GitHub API response")]
+pub(crate) async fn save(dir: &Path, exchange: &Exchange) -> anyhow::Result<()> {
+    fs::create_dir_all(dir).await?;
+    let path = path(dir, &exchange.markdown);
+    fs::write(&path, serde_json::to_string_pretty(exchange)?)
+        .await
+        .with_context(|| format!("failed to write `{}`", path.display()))
+}
+
+#[context("failed to replay GitHub API response")]
+pub(crate) async fn load(dir: &Path, markdown: &str) -> anyhow::Result<Exchange> {
+    let path = path(dir, markdown);
+    let data = match fs::read_to_string(&path).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            bail!(
+                "there is no recording of this markdown in `{}`",
+                dir.display()
+            )
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to read `{}`", path.display())),
+    };
+    serde_json::from_str(&data).with_context(|| format!("`{}` is invalid", path.display()))
+}
@@ -0,0 +1,75 @@
+use std::borrow::Cow;
+
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
+
+/// The token ghmd was configured with.
+static TOKEN: OnceCell<Box<str>> = OnceCell::new();
+
+/// Anything that looks like a GitHub token, even if it isn't the configured one.
+static TOKEN_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\b(?:gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{22,})\b").unwrap()
+});
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Register the configured token so that it is scrubbed from all future output.
+pub(crate) fn set_token(token: &str) {
+    // Very short "tokens" can't be real, and replacing them would mangle unrelated output.
+    if token.len() >= 8 {
+        let _ = TOKEN.set(token.into());
+    }
+}
+
+/// Remove every token from a string that is about to be shown to the user.
+pub(crate) fn redact(s: &str) -> Cow<'_, str> {
+    let s = match TOKEN.get() {
+        Some(token) if s.contains(&**token) => Cow::Owned(s.replace(&**token, REDACTED)),
+        _ => Cow::Borrowed(s),
+    };
+    match TOKEN_PATTERN.replace_all(&s, REDACTED) {
+        Cow::Borrowed(_) => s,
+        Cow::Owned(redacted) => Cow::Owned(redacted),
+    }
+}
+
+/// Install the global logger, redacting tokens from every record.
+pub(crate) fn init_logger() {
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    let inner = builder.build();
+    log::set_max_level(inner.filter());
+    log::set_boxed_logger(Box::new(Logger { inner })).unwrap();
+}
+
+struct Logger {
+    inner: env_logger::Logger,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        let message = record.args().to_string();
+        self.inner.log(
+            &log::Record::builder()
+                .args(format_args!("{}", redact(&message)))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
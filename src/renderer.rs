@@ -1,132 +1,697 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 use std::str::FromStr;
+use std::sync::atomic::{self, AtomicU32, AtomicU64};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::{bail, ensure, Context as _};
+use anyhow::Context as _;
 use fn_error_context::context;
 use once_cell::sync::Lazy;
-use reqwest::header::HeaderValue;
 use scraper::{node, Html, Node, Selector};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha512};
 use tokio::runtime;
 use tokio::sync::oneshot;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::gfm;
+use crate::language;
+use crate::normalize::{IgnoreRegions, Normalizer};
+use crate::queue::{Priority, RenderQueue};
+use crate::quota::Exhaustion;
+use crate::raw_html;
+use crate::readme;
+use crate::recording;
+
+/// Where GitHub's REST API is.
+pub(crate) const API_URL: &str = "https://api.github.com";
+
+/// The REST API version requested when none is configured.
+pub(crate) const DEFAULT_API_VERSION: &str = "2022-11-28";
+
+/// How many renders are cached before the cache is cleared.
+pub(crate) const CACHE_SIZE: usize = 100;
+
+/// The shortest and longest that edits are waited for to stop while the API quota is low.
+const MIN_DEBOUNCE: Duration = Duration::from_secs(1);
+const MAX_DEBOUNCE: Duration = Duration::from_secs(60);
+
+type Hash = sha2::digest::Output<Sha512>;
+
+struct Cached {
+    html: Arc<str>,
+    rendered_at: SystemTime,
+    request_id: Option<String>,
+}
+
+/// A successful response from the markdown API.
+struct Response {
+    html: String,
+    /// GitHub's `X-GitHub-Request-Id`, which GitHub support can use to find the request.
+    request_id: Option<String>,
+}
 
 pub(crate) struct Renderer {
     client: reqwest::Client,
+    /// The base URL of the GitHub API.
+    api_url: Box<str>,
+    /// The value of the `X-GitHub-Api-Version` header.
+    api_version: Box<str>,
     token: Box<str>,
-    cache: Mutex<HashMap<sha2::digest::Output<Sha512>, Arc<str>>>,
+    /// Renders keyed by the hash of the markdown.
+    cache: Mutex<HashMap<Hash, Cached>>,
+    queue: RenderQueue,
     octicons: Octicons,
+    /// The number of API requests remaining in the current rate limit window, as of the last
+    /// response. `u32::MAX` if unknown.
+    remaining_quota: AtomicU32,
+    /// When the rate limit window resets, in seconds since the Unix epoch, as of the last
+    /// response. 0 if unknown.
+    quota_reset: AtomicU64,
+    quota_threshold: u32,
+    /// Whether the rate limit is used up, loaded from the cache directory when it is first needed.
+    exhaustion: OnceCell<Exhaustion>,
+    /// If set, markdown is cached by its normalized form, so that insignificant changes reuse the
+    /// previous render.
+    normalizer: Option<Normalizer>,
+    ignore_regions: IgnoreRegions,
+    /// Whether to label unlabelled code blocks with a guessed language.
+    guess_languages: bool,
+    recording: Option<recording::Mode>,
+    /// The `owner/repo` that issue references and mentions are resolved against.
+    repository: Option<Box<str>>,
+    raw_html: raw_html::Mode,
+    /// Whether single newlines become line breaks, as they do in issues and comments.
+    hard_breaks: bool,
+    /// Whether to mark where GitHub cuts off the document when showing it as a README.
+    readme: bool,
+    /// Whether to render locally with comrak instead of through the API.
+    offline: bool,
 }
 
 impl Renderer {
-    pub(crate) fn new(client: reqwest::Client, token: impl Into<Box<str>>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        client: reqwest::Client,
+        octicons: Octicons,
+        api_version: impl Into<Box<str>>,
+        token: impl Into<Box<str>>,
+        concurrency: usize,
+        request_interval: Duration,
+        quota_threshold: u32,
+        normalizer: Option<Normalizer>,
+        ignore_regions: IgnoreRegions,
+        guess_languages: bool,
+        recording: Option<recording::Mode>,
+    ) -> Self {
         Self {
-            client: client.clone(),
+            client,
+            api_url: API_URL.into(),
+            api_version: api_version.into(),
             token: token.into(),
             cache: Mutex::new(HashMap::new()),
-            octicons: Octicons::new(client),
+            queue: RenderQueue::new(concurrency, request_interval),
+            octicons,
+            remaining_quota: AtomicU32::new(u32::MAX),
+            quota_reset: AtomicU64::new(0),
+            quota_threshold,
+            exhaustion: OnceCell::new(),
+            normalizer,
+            ignore_regions,
+            guess_languages,
+            recording,
+            repository: None,
+            raw_html: raw_html::Mode::Keep,
+            hard_breaks: false,
+            readme: false,
+            offline: false,
         }
     }
+
+    /// Render in the context of a repository, so that references like `#123` become links.
+    pub(crate) fn with_repository(self, repository: Option<impl Into<Box<str>>>) -> Self {
+        Self {
+            repository: repository.map(Into::into),
+            ..self
+        }
+    }
+
+    /// Render single newlines as line breaks, like GitHub does in issues and comments.
+    pub(crate) fn with_hard_breaks(self, hard_breaks: bool) -> Self {
+        Self {
+            hard_breaks,
+            ..self
+        }
+    }
+
+    /// Strip or flag the raw HTML in documents.
+    pub(crate) fn with_raw_html(self, raw_html: raw_html::Mode) -> Self {
+        Self { raw_html, ..self }
+    }
+
+    /// Mark where GitHub stops showing the document when it is a repository's README.
+    pub(crate) fn with_readme(self, readme: bool) -> Self {
+        Self { readme, ..self }
+    }
+
+    /// Render on this machine with comrak instead of through GitHub's API, so that no network
+    /// connection or token is needed. See [`gfm::render`] for how the output differs.
+    pub(crate) fn with_offline(self, offline: bool) -> Self {
+        Self { offline, ..self }
+    }
+
+    /// Send API requests to another server, such as the mock API, instead of GitHub.
+    #[cfg(feature = "mock")]
+    pub(crate) fn with_api_url(self, api_url: impl Into<Box<str>>) -> Self {
+        Self {
+            api_url: api_url.into(),
+            ..self
+        }
+    }
+
+    /// Whether the remaining API quota has dropped below the configured threshold, meaning
+    /// renders should be saved for when they matter.
+    pub(crate) fn quota_is_low(&self) -> bool {
+        self.remaining_quota.load(atomic::Ordering::Relaxed) < self.quota_threshold
+    }
+
+    /// How long a document must go unchanged before it is rendered through the API, if the quota
+    /// is low. This is long enough that, if edits keep coming, what remains of the quota lasts
+    /// until the window resets.
+    pub(crate) fn debounce(&self) -> Option<Duration> {
+        if !self.quota_is_low() {
+            return None;
+        }
+        let remaining = self.remaining_quota.load(atomic::Ordering::Relaxed);
+        let reset = self.quota_reset.load(atomic::Ordering::Relaxed);
+        let until_reset = (SystemTime::UNIX_EPOCH + Duration::from_secs(reset))
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        Some(debounce(remaining, until_reset))
+    }
     #[context("failed to render markdown")]
     pub(crate) async fn render(
         &self,
         markdown: &str,
-    ) -> anyhow::Result<Result<Arc<str>, RateLimited>> {
-        let hash = Sha512::digest(markdown.as_bytes());
+        priority: Priority,
+    ) -> anyhow::Result<Result<Arc<str>, RenderError>> {
+        let markdown = &*self.preprocess(markdown);
+        let hash = self.hash(markdown);
+
+        if let Some(cached) = self.cache.lock().await.get(&hash) {
+            return Ok(Ok(cached.html.clone()));
+        }
+
+        let _permit = self.queue.acquire(priority).await;
+
+        // Someone else may have rendered the same markdown while we were waiting.
+        if let Some(cached) = self.cache.lock().await.get(&hash) {
+            return Ok(Ok(cached.html.clone()));
+        }
+
+        let Response { html, request_id } = match self.send_request(markdown).await? {
+            Ok(response) => response,
+            Err(e) => return Ok(Err(e)),
+        };
+
+        let rendered = <Arc<str>>::from(self.finish(html).await);
 
         let mut cache = self.cache.lock().await;
+        if cache.len() >= CACHE_SIZE {
+            cache.clear();
+        }
+        let cached = Cached {
+            html: rendered.clone(),
+            rendered_at: SystemTime::now(),
+            request_id,
+        };
+        cache.insert(hash, cached);
+
+        Ok(Ok(rendered))
+    }
+
+    /// The render of the markdown, if it is cached.
+    pub(crate) async fn cached(&self, markdown: &str) -> Option<Arc<str>> {
+        let hash = self.hash(&self.preprocess(markdown));
+        let cache = self.cache.lock().await;
+        cache.get(&hash).map(|cached| cached.html.clone())
+    }
+
+    /// Render markdown on this machine with comrak, as `--offline` does, but with the same changes
+    /// to the markdown and output as any other render. Raw HTML is sanitized, so this is safe to
+    /// show in place of GitHub's render. It isn't cached, so that it doesn't take the place of
+    /// GitHub's render of the same markdown.
+    pub(crate) async fn render_locally(&self, markdown: &str) -> Arc<str> {
+        let html = gfm::render(&self.preprocess(markdown), self.hard_breaks);
+        self.finish(html).await.into()
+    }
+
+    /// Apply ghmd's own changes to rendered HTML.
+    async fn finish(&self, html: String) -> String {
+        let mut rendered = self.octicons.populate(html).await;
+        if self.raw_html == raw_html::Mode::Flag {
+            if let Cow::Owned(flagged) = raw_html::flag(&rendered) {
+                rendered = flagged;
+            }
+        }
+        if self.readme {
+            if let Cow::Owned(shown) = readme::show_cutoff(&rendered) {
+                rendered = shown;
+            }
+        }
+        rendered
+    }
+
+    /// The rate limit, if it is known to be used up without sending a request, such as from a
+    /// previous run.
+    pub(crate) async fn rate_limited(&self) -> Option<RateLimited> {
+        if self.offline {
+            return None;
+        }
+        self.exhaustion().await.and_then(Exhaustion::get)
+    }
+
+    async fn exhaustion(&self) -> Option<&Exhaustion> {
+        // Replayed responses don't count against the limit, so it isn't checked or remembered.
+        match &self.recording {
+            Some(recording::Mode::Replay(_)) => None,
+            _ => Some(
+                self.exhaustion
+                    .get_or_init(|| Exhaustion::load(&self.api_url, &self.token))
+                    .await,
+            ),
+        }
+    }
+
+    /// Describe how the markdown's most recent render was produced, if it has been rendered.
+    pub(crate) async fn provenance(&self, markdown: &str) -> Option<Provenance> {
+        let hash = self.hash(&self.preprocess(markdown));
+        let cache = self.cache.lock().await;
+        let cached = cache.get(&hash)?;
+        Some(Provenance {
+            renderer: match self.recording {
+                _ if self.offline => "comrak, offline",
+                Some(recording::Mode::Replay(_)) => "GitHub API, replayed from a recording",
+                _ => "GitHub API",
+            },
+            api_version: (!self.offline).then(|| self.api_version.to_string()),
+            content_hash: content_hash(markdown),
+            rendered_at: humantime::format_rfc3339_seconds(cached.rendered_at).to_string(),
+            request_id: cached.request_id.clone(),
+        })
+    }
+
+    /// Apply ghmd's own changes to markdown before it is rendered.
+    fn preprocess<'a>(&self, markdown: &'a str) -> Cow<'a, str> {
+        // The cutoff is found before anything else changes the document's length.
+        let marked = if self.readme {
+            readme::mark_cutoff(markdown)
+        } else {
+            Cow::Borrowed(markdown)
+        };
+        let mut stripped = match self.ignore_regions.strip(&marked) {
+            Cow::Owned(stripped) => Cow::Owned(stripped),
+            Cow::Borrowed(_) => marked,
+        };
+        let processed = match self.raw_html {
+            raw_html::Mode::Keep => None,
+            raw_html::Mode::Strip => Some(raw_html::strip(&stripped)),
+            raw_html::Mode::Flag => Some(raw_html::mark(&stripped)),
+        };
+        if let Some(Cow::Owned(processed)) = processed {
+            stripped = Cow::Owned(processed);
+        }
+        if !self.guess_languages {
+            return stripped;
+        }
+        match language::label_fences(&stripped) {
+            Cow::Owned(labelled) => Cow::Owned(labelled),
+            Cow::Borrowed(_) => stripped,
+        }
+    }
+
+    /// The cache key for preprocessed markdown.
+    fn hash(&self, markdown: &str) -> Hash {
+        match &self.normalizer {
+            Some(normalizer) => Sha512::digest(normalizer.normalize(markdown).as_bytes()),
+            None => Sha512::digest(markdown.as_bytes()),
+        }
+    }
+
+    /// Render markdown through the GitHub API only, bypassing the cache and leaving octicons
+    /// unpopulated.
+    pub(crate) async fn request(
+        &self,
+        markdown: &str,
+        priority: Priority,
+    ) -> anyhow::Result<Result<String, RenderError>> {
+        let _permit = self.queue.acquire(priority).await;
+        Ok(self
+            .send_request(markdown)
+            .await?
+            .map(|response| response.html))
+    }
+
+    /// Replace the octicon placeholders in rendered HTML with their SVGs.
+    pub(crate) async fn populate_octicons(&self, html: String) -> String {
+        self.octicons.populate(html).await
+    }
+
+    /// Whether octicons can actually be fetched, rather than only taken from the built-in
+    /// fallbacks.
+    pub(crate) async fn octicons_reachable(&self) -> bool {
+        self.octicons.fetch("link-16").await.is_some()
+    }
+
+    async fn send_request(&self, markdown: &str) -> anyhow::Result<Result<Response, RenderError>> {
+        if self.offline {
+            return Ok(Ok(Response {
+                html: gfm::render(markdown, self.hard_breaks),
+                request_id: None,
+            }));
+        }
+        let exhaustion = self.exhaustion().await;
+        if let Some(limited) = exhaustion.and_then(Exhaustion::get) {
+            return Ok(Err(RenderError::RateLimited(limited)));
+        }
+
+        let res = match &self.recording {
+            Some(recording::Mode::Replay(dir)) => recording::load(dir, markdown).await?,
+            Some(recording::Mode::Record(dir)) => {
+                let res = match self.fetch(markdown).await {
+                    Ok(res) => res,
+                    Err(e) => return Ok(Err(RenderError::Network(e.without_url()))),
+                };
+                recording::save(dir, &res).await?;
+                res
+            }
+            None => match self.fetch(markdown).await {
+                Ok(res) => res,
+                Err(e) => return Ok(Err(RenderError::Network(e.without_url()))),
+            },
+        };
+
+        let request_id = res.header("X-GitHub-Request-Id").map(str::to_owned);
+        log::debug!(
+            "GitHub responded with {} (request ID {})",
+            res.status,
+            request_id.as_deref().unwrap_or("unknown"),
+        );
+
+        if let Some(Ok(reset)) = res.header("X-RateLimit-Reset").map(str::parse::<u64>) {
+            self.quota_reset.store(reset, atomic::Ordering::Relaxed);
+        }
+        if let Some(remaining) = res.header("X-RateLimit-Remaining") {
+            if let Ok(remaining) = remaining.parse::<u32>() {
+                let was_low = self.quota_is_low();
+                self.remaining_quota
+                    .store(remaining, atomic::Ordering::Relaxed);
+                if !was_low && self.quota_is_low() {
+                    log::warn!(
+                        "Only {} API requests remain; rendering edits offline until typing pauses",
+                        remaining
+                    );
+                }
+            }
+        }
+
+        let res = (|| -> anyhow::Result<_> {
+            let status = reqwest::StatusCode::from_u16(res.status)?;
+            let response_error = || ResponseError {
+                status,
+                message: error_message(&res.body),
+                request_id: request_id.clone(),
+            };
+
+            if let Some(limited) = rate_limited(status, &res, SystemTime::now())? {
+                return Ok(Err(RenderError::RateLimited(limited)));
+            }
 
-        if let Some(data) = cache.get(&hash) {
-            return Ok(Ok(data.clone()));
+            if status == reqwest::StatusCode::UNAUTHORIZED
+                || status == reqwest::StatusCode::FORBIDDEN
+            {
+                let ResponseError {
+                    status,
+                    message,
+                    request_id,
+                } = response_error();
+                return Ok(Err(RenderError::Auth(AuthError {
+                    status,
+                    message,
+                    request_id,
+                })));
+            }
+
+            if status.is_client_error() {
+                return Ok(Err(RenderError::Validation(response_error())));
+            }
+            if !status.is_success() {
+                return Ok(Err(RenderError::Server(response_error())));
+            }
+
+            Ok(Ok(Response {
+                html: res.body.clone(),
+                request_id: request_id.clone(),
+            }))
+        })()
+        .with_context(|| match &request_id {
+            Some(id) => format!("GitHub API response was unexpected (request ID {})", id),
+            None => "GitHub API response was unexpected".to_owned(),
+        })?;
+
+        if let Some(exhaustion) = exhaustion {
+            match &res {
+                Err(RenderError::RateLimited(limited)) => exhaustion.set(limited).await,
+                Ok(_) => exhaustion.clear().await,
+                Err(_) => {}
+            }
         }
 
+        Ok(res)
+    }
+
+    /// Send a render request to GitHub.
+    async fn fetch(&self, markdown: &str) -> reqwest::Result<recording::Exchange> {
         #[derive(Serialize)]
         struct Body<'a> {
             text: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            mode: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            context: Option<&'a str>,
         }
         let res = self
             .client
-            .post("https://api.github.com/markdown")
-            .header("Accept", "application/vnd.github.v3+json")
-            .header("User-Agent", "markdown previewer")
+            .post(format!("{}/markdown", self.api_url))
+            .header("Accept", "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", &*self.api_version)
             .bearer_auth(&self.token)
-            .json(&Body { text: markdown })
+            .json(&Body {
+                text: markdown,
+                // Comment mode, which both resolves references and breaks lines.
+                mode: (self.repository.is_some() || self.hard_breaks).then_some("gfm"),
+                context: self.repository.as_deref(),
+            })
             .send()
             .await?;
 
-        let res = async {
-            if res.status() == reqwest::StatusCode::FORBIDDEN {
-                let limit: u32 = parse_header_value(
-                    res.headers()
-                        .get("X-RateLimit-Limit")
-                        .context("no ratelimit limit header")?,
-                )
-                .context("ratelimit limit header was invalid")?;
-                let reset: SystemTime = SystemTime::UNIX_EPOCH
-                    + Duration::from_secs(
-                        parse_header_value(
-                            res.headers()
-                                .get("X-RateLimit-Reset")
-                                .context("no ratelimit reset header")?,
-                        )
-                        .context("ratelimit reset header was invalid")?,
-                    );
-                return Ok(Err(RateLimited { limit, reset }));
-            }
+        Ok(recording::Exchange {
+            markdown: markdown.to_owned(),
+            status: res.status().as_u16(),
+            headers: res
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_owned()))
+                })
+                .collect(),
+            body: res.text().await?,
+        })
+    }
+}
 
-            #[derive(Deserialize)]
-            struct ErrorResponse {
-                message: String,
-            }
-            if res.status().is_client_error() {
-                bail!(res.json::<ErrorResponse>().await?.message);
-            }
+/// How long to wait after hitting a secondary rate limit if GitHub doesn't say.
+const SECONDARY_RETRY: Duration = Duration::from_secs(60);
+
+/// The rate limit a response says has been hit, if any: either the hourly quota running out, or
+/// one of GitHub's secondary rate limits on bursts of requests, which come with a `Retry-After`
+/// header instead.
+fn rate_limited(
+    status: reqwest::StatusCode,
+    res: &recording::Exchange,
+    now: SystemTime,
+) -> anyhow::Result<Option<RateLimited>> {
+    let limited = matches!(
+        status,
+        reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::TOO_MANY_REQUESTS
+    );
+    if !limited {
+        return Ok(None);
+    }
 
-            ensure!(
-                res.status().is_success(),
-                "GitHub request failed with {}",
-                res.status()
+    if res.header("X-RateLimit-Remaining") == Some("0") {
+        let limit: u32 = parse_header_value(
+            res.header("X-RateLimit-Limit")
+                .context("no ratelimit limit header")?,
+        )
+        .context("ratelimit limit header was invalid")?;
+        let reset: SystemTime = SystemTime::UNIX_EPOCH
+            + Duration::from_secs(
+                parse_header_value(
+                    res.header("X-RateLimit-Reset")
+                        .context("no ratelimit reset header")?,
+                )
+                .context("ratelimit reset header was invalid")?,
             );
+        return Ok(Some(RateLimited {
+            limit,
+            reset,
+            secondary: false,
+        }));
+    }
 
-            Ok(Ok(res.text().await?))
-        }
-        .await
-        .context("GitHub API response was unexpected")?;
+    let retry_after = res.header("Retry-After");
+    if status == reqwest::StatusCode::FORBIDDEN && retry_after.is_none() {
+        return Ok(None);
+    }
+    let retry_after = match retry_after {
+        Some(retry_after) => Duration::from_secs(
+            parse_header_value(retry_after.trim()).context("retry-after header was invalid")?,
+        ),
+        None => SECONDARY_RETRY,
+    };
+    Ok(Some(RateLimited {
+        limit: res
+            .header("X-RateLimit-Limit")
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(0),
+        reset: now + retry_after,
+        secondary: true,
+    }))
+}
 
-        let rendered = match res {
-            Ok(rendered) => rendered,
-            Err(e) => return Ok(Err(e)),
-        };
+fn parse_header_value<T: FromStr>(value: &str) -> anyhow::Result<T>
+where
+    T::Err: Send + Sync + std::error::Error + 'static,
+{
+    Ok(value.parse()?)
+}
+
+/// How long to wait for edits to stop so that `remaining` requests are spread over the time until
+/// the rate limit resets, one per edit. If that time isn't known, the shortest wait is used.
+fn debounce(remaining: u32, until_reset: Duration) -> Duration {
+    // One request is kept back for when the edits stop.
+    let interval = until_reset / remaining.saturating_add(1);
+    interval.clamp(MIN_DEBOUNCE, MAX_DEBOUNCE)
+}
 
-        let rendered = self.octicons.populate(rendered).await;
+/// A short hash identifying a version of a document, for telling renders apart.
+pub(crate) fn content_hash(markdown: &str) -> String {
+    format!("{:x}", Sha512::digest(markdown.as_bytes()))[..32].to_owned()
+}
 
-        let rendered = <Arc<str>>::from(rendered);
+/// Why GitHub didn't render a document.
+#[derive(Debug)]
+pub(crate) enum RenderError {
+    /// GitHub rejected the token.
+    Auth(AuthError),
+    RateLimited(RateLimited),
+    /// GitHub refused the request itself, such as because the document is too long.
+    Validation(ResponseError),
+    /// GitHub couldn't be reached.
+    Network(reqwest::Error),
+    /// GitHub failed to handle the request.
+    Server(ResponseError),
+}
 
-        if cache.len() > 100 {
-            cache.clear();
+impl RenderError {
+    /// A short name for the kind of error, for clients of the event stream to branch on.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Self::Auth(_) => "auth",
+            Self::RateLimited(_) => "rate_limited",
+            Self::Validation(_) => "validation",
+            Self::Network(_) => "network",
+            Self::Server(_) => "server",
         }
-        cache.insert(hash, rendered.clone());
+    }
+}
 
-        Ok(Ok(rendered))
+impl Display for RenderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auth(e) => Display::fmt(e, f),
+            Self::RateLimited(e) => Display::fmt(e, f),
+            Self::Validation(e) => write!(f, "GitHub refused to render the document: {}", e),
+            Self::Network(e) => write!(f, "could not reach GitHub: {}", e),
+            Self::Server(e) => write!(f, "GitHub failed to render the document: {}", e),
+        }
     }
 }
 
-fn parse_header_value<T: FromStr>(value: &HeaderValue) -> anyhow::Result<T>
-where
-    T::Err: Send + Sync + std::error::Error + 'static,
-{
-    Ok(value.to_str()?.parse()?)
+impl Error for RenderError {}
+
+/// An error response from GitHub other than an authentication or rate limiting failure.
+#[derive(Debug)]
+pub(crate) struct ResponseError {
+    pub(crate) status: reqwest::StatusCode,
+    pub(crate) message: String,
+    pub(crate) request_id: Option<String>,
+}
+
+impl Display for ResponseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.status)?;
+        if let Some(request_id) = &self.request_id {
+            write!(f, " [request ID {}]", request_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Find the message in the body of an error response.
+///
+/// GitHub normally sends JSON with a `message` field, but proxies and outages can produce HTML
+/// pages or plain text instead, so those are made the best of.
+fn error_message(body: &str) -> String {
+    /// The most of a body that isn't JSON to include.
+    const MAX_CHARS: usize = 200;
+    static TITLE: Lazy<Selector> = Lazy::new(|| Selector::parse("title").unwrap());
+
+    #[derive(Deserialize)]
+    struct ErrorResponse {
+        message: String,
+    }
+    if let Ok(response) = serde_json::from_str::<ErrorResponse>(body) {
+        return response.message;
+    }
+    let body = body.trim();
+    if body.starts_with('<') {
+        let title = Html::parse_document(body)
+            .select(&TITLE)
+            .next()
+            .map(|title| title.text().collect::<String>());
+        if let Some(title) = title.filter(|title| !title.trim().is_empty()) {
+            return title.trim().to_owned();
+        }
+    }
+    match body.char_indices().nth(MAX_CHARS) {
+        _ if body.is_empty() => "no message was given".to_owned(),
+        Some((end, _)) => format!("{}…", &body[..end]),
+        None => body.to_owned(),
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct RateLimited {
     pub(crate) limit: u32,
     pub(crate) reset: SystemTime,
+    /// Whether this is one of GitHub's secondary rate limits, on too many requests in a short
+    /// time, rather than the hourly quota of `limit` requests being used up.
+    pub(crate) secondary: bool,
 }
 
 impl Display for RateLimited {
@@ -136,31 +701,104 @@ impl Display for RateLimited {
             .duration_since(SystemTime::now())
             .unwrap_or_else(|_| Duration::default());
 
+        if self.secondary {
+            write!(
+                f,
+                "\
+                    The GitHub API is limiting requests because too many were sent in a short \
+                    time.\n\
+                    \n\
+                    You may continue to send requests in {:?}.\
+                ",
+                time,
+            )
+        } else {
+            write!(
+                f,
+                "\
+                    You have used your quota of {} requests and are now rate limited\
+                    by the GitHub API.\n\
+                    \n\
+                    You may continue to send requests in {:?}.\
+                ",
+                self.limit, time,
+            )
+        }
+    }
+}
+
+impl Error for RateLimited {}
+
+/// GitHub rejected the token, because it is invalid, revoked or missing permissions.
+#[derive(Debug)]
+pub(crate) struct AuthError {
+    pub(crate) status: reqwest::StatusCode,
+    pub(crate) message: String,
+    pub(crate) request_id: Option<String>,
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(
             f,
             "\
-                You have used your quota of {} requests and are now rate limited\
-                by the GitHub API.\n\
+                GitHub rejected the token ({}): {}\n\
                 \n\
-                You may continue to send requests in {:?}.\
+                The token may have expired, been revoked, or lack the required permissions. You \
+                can generate a new one at <https://github.com/settings/tokens>; it does not need \
+                any scopes.\
             ",
-            self.limit, time,
-        )
+            self.status, self.message,
+        )?;
+        if let Some(request_id) = &self.request_id {
+            write!(f, "\n\nGitHub request ID: {}", request_id)?;
+        }
+        Ok(())
     }
 }
 
-impl Error for RateLimited {}
+impl Error for AuthError {}
+
+/// How a page was rendered, embedded in it so that odd output can be traced to its source.
+#[derive(Serialize)]
+pub(crate) struct Provenance {
+    pub(crate) renderer: &'static str,
+    /// The version of the API that rendered the page, unless it was rendered offline.
+    pub(crate) api_version: Option<String>,
+    /// A prefix of the SHA-512 hash of the markdown source.
+    pub(crate) content_hash: String,
+    pub(crate) rendered_at: String,
+    pub(crate) request_id: Option<String>,
+}
 
-struct Octicons {
-    client: reqwest::Client,
+/// Fetches the SVGs for the octicon placeholders GitHub leaves in rendered HTML.
+pub(crate) struct Octicons {
+    /// The client for the octicon CDN, or `None` to never fetch icons.
+    client: Option<reqwest::Client>,
     cache: Mutex<HashMap<Box<str>, Arc<str>>>,
+    /// Icons that have failed to be fetched and when they last did, so that the failure is only
+    /// logged once and renders don't wait on the CDN again until [`OCTICON_RETRY`] has passed.
+    failed: Mutex<HashMap<Box<str>, Instant>>,
 }
 
+/// How long to use the fallback for an icon that couldn't be fetched before trying again.
+const OCTICON_RETRY: Duration = Duration::from_secs(60);
+
+/// Path data for the icons GitHub uses most, used when the CDN can't be reached so that alerts
+/// and heading anchors still look right.
+const FALLBACK_OCTICONS: &[(&str, &str)] = &[
+    ("info-16", "M0 8a8 8 0 1 1 16 0A8 8 0 0 1 0 8Zm8-6.5a6.5 6.5 0 1 0 0 13 6.5 6.5 0 0 0 0-13ZM6.5 7.75A.75.75 0 0 1 7.25 7h1a.75.75 0 0 1 .75.75v2.75h.25a.75.75 0 0 1 0 1.5h-2a.75.75 0 0 1 0-1.5h.25v-2h-.25a.75.75 0 0 1-.75-.75ZM8 6a1 1 0 1 1 0-2 1 1 0 0 1 0 2Z"),
+    ("alert-16", "M6.457 1.047c.659-1.234 2.427-1.234 3.086 0l6.082 11.378A1.75 1.75 0 0 1 14.082 15H1.918a1.75 1.75 0 0 1-1.543-2.575Zm1.763.707a.25.25 0 0 0-.44 0L1.698 13.132a.25.25 0 0 0 .22.368h12.164a.25.25 0 0 0 .22-.368Zm.53 3.996v2.5a.75.75 0 0 1-1.5 0v-2.5a.75.75 0 0 1 1.5 0ZM9 11a1 1 0 1 1-2 0 1 1 0 0 1 2 0Z"),
+    ("stop-16", "M4.47.22A.749.749 0 0 1 5 0h6c.199 0 .389.079.53.22l4.25 4.25c.141.14.22.331.22.53v6a.749.749 0 0 1-.22.53l-4.25 4.25A.749.749 0 0 1 11 16H5a.749.749 0 0 1-.53-.22L.22 11.53A.749.749 0 0 1 0 11V5c0-.199.079-.389.22-.53Zm.84 1.28L1.5 5.31v5.38l3.81 3.81h5.38l3.81-3.81V5.31L10.69 1.5ZM8 4a.75.75 0 0 1 .75.75v3.5a.75.75 0 0 1-1.5 0v-3.5A.75.75 0 0 1 8 4Zm0 8a1 1 0 1 1 0-2 1 1 0 0 1 0 2Z"),
+    ("link-16", "m7.775 3.275 1.25-1.25a3.5 3.5 0 1 1 4.95 4.95l-2.5 2.5a3.5 3.5 0 0 1-4.95 0 .751.751 0 0 1 .018-1.042.751.751 0 0 1 1.042-.018 1.998 1.998 0 0 0 2.83 0l2.5-2.5a2.002 2.002 0 0 0-2.83-2.83l-1.25 1.25a.751.751 0 0 1-1.042-.018.751.751 0 0 1-.018-1.042Zm-4.69 9.64a1.998 1.998 0 0 0 2.83 0l1.25-1.25a.751.751 0 0 1 1.042.018.751.751 0 0 1 .018 1.042l-1.25 1.25a3.5 3.5 0 1 1-4.95-4.95l2.5-2.5a3.5 3.5 0 0 1 4.95 0 .751.751 0 0 1-.018 1.042.751.751 0 0 1-1.042.018 1.998 1.998 0 0 0-2.83 0l-2.5 2.5a1.998 1.998 0 0 0 0 2.83Z"),
+];
+
 impl Octicons {
-    fn new(client: reqwest::Client) -> Self {
+    pub(crate) fn new(client: Option<reqwest::Client>) -> Self {
         Self {
             client,
             cache: Mutex::new(HashMap::new()),
+            failed: Mutex::new(HashMap::new()),
         }
     }
 
@@ -171,13 +809,63 @@ impl Octicons {
             return Some(data.clone());
         }
 
+        let retry = self
+            .failed
+            .lock()
+            .await
+            .get(name)
+            .is_none_or(|failed| failed.elapsed() >= OCTICON_RETRY);
+        if retry {
+            if let Some(svg) = self.fetch(name).await {
+                let svg = <Arc<str>>::from(svg);
+                cache.insert(Box::from(name), svg.clone());
+                return Some(svg);
+            }
+        }
+        drop(cache);
+
+        // Fallbacks aren't cached, so that the real icon is used once the CDN is reachable again.
+        let fallback = FALLBACK_OCTICONS
+            .iter()
+            .find(|(fallback, _)| *fallback == name)
+            .map(|(_, path)| {
+                <Arc<str>>::from(format!(
+                    "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 16 16\" width=\"16\" height=\"16\"><path fill-rule=\"evenodd\" d=\"{}\"></path></svg>",
+                    path
+                ))
+            });
+        if !retry {
+            return fallback;
+        }
+        let first = self
+            .failed
+            .lock()
+            .await
+            .insert(Box::from(name), Instant::now())
+            .is_none();
+        if first {
+            match fallback {
+                Some(_) => log::warn!(
+                    "Octicon `{}` could not be fetched; using a built-in copy",
+                    name
+                ),
+                None => log::warn!(
+                    "Octicon `{}` could not be fetched and will be left blank",
+                    name
+                ),
+            }
+        }
+        fallback
+    }
+
+    async fn fetch(&self, name: &str) -> Option<String> {
         let res = self
             .client
+            .as_ref()?
             .get(format!(
                 "https://cdn.jsdelivr.net/gh/primer/octicons@14.2.2/icons/{}.svg",
                 name
             ))
-            .header("User-Agent", "markdown previewer")
             .send()
             .await
             .ok()?;
@@ -186,9 +874,7 @@ impl Octicons {
             return None;
         }
 
-        let svg = <Arc<str>>::from(res.text().await.ok()?);
-        cache.insert(Box::from(name), svg.clone());
-        Some(svg)
+        res.text().await.ok()
     }
 
     async fn populate(&self, html: String) -> String {
@@ -254,3 +940,82 @@ impl Octicons {
         result_rx.await.unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{debounce, rate_limited, Octicons, MAX_DEBOUNCE, MIN_DEBOUNCE, SECONDARY_RETRY};
+    use crate::recording::Exchange;
+
+    #[test]
+    fn debounce_spreads_quota_until_reset() {
+        let minutes = |n: u64| Duration::from_secs(n * 60);
+        assert_eq!(debounce(99, minutes(50)), Duration::from_secs(30));
+        assert_eq!(debounce(1, minutes(1)), Duration::from_secs(30));
+        assert_eq!(debounce(0, minutes(1)), minutes(1));
+        assert_eq!(debounce(999, minutes(1)), MIN_DEBOUNCE);
+        assert_eq!(debounce(10, Duration::ZERO), MIN_DEBOUNCE);
+        assert_eq!(debounce(1, minutes(60)), MAX_DEBOUNCE);
+    }
+
+    #[tokio::test]
+    async fn octicon_failures_are_remembered() {
+        // A proxy that refuses connections, so that every fetch fails straight away.
+        let client = reqwest::Client::builder()
+            .proxy(reqwest::Proxy::all("http://127.0.0.1:1").unwrap())
+            .build()
+            .unwrap();
+        let octicons = Octicons::new(Some(client));
+        let failed_at = || async { octicons.failed.lock().await.get("link-16").copied() };
+
+        assert!(octicons.get("link-16").await.unwrap().contains("<path"));
+        let first = failed_at().await.unwrap();
+        // The fallback is used without trying the CDN again.
+        assert!(octicons.get("link-16").await.is_some());
+        assert_eq!(failed_at().await, Some(first));
+        assert!(octicons.get("no-such-icon-16").await.is_none());
+    }
+
+    #[test]
+    fn rate_limits() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let check = |status: u16, headers: &[(&str, &str)]| {
+            let res = Exchange {
+                markdown: String::new(),
+                status,
+                headers: headers
+                    .iter()
+                    .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+                    .collect(),
+                body: String::new(),
+            };
+            let status = reqwest::StatusCode::from_u16(status).unwrap();
+            rate_limited(status, &res, now)
+                .unwrap()
+                .map(|limited| (limited.limit, limited.reset, limited.secondary))
+        };
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+        let quota = [
+            ("x-ratelimit-limit", "60"),
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "2000"),
+        ];
+        assert_eq!(check(403, &quota), Some((60, at(2000), false)));
+        assert_eq!(check(429, &quota), Some((60, at(2000), false)));
+
+        let secondary = [
+            ("x-ratelimit-limit", "5000"),
+            ("x-ratelimit-remaining", "4000"),
+            ("retry-after", "30"),
+        ];
+        assert_eq!(check(403, &secondary), Some((5000, at(1_030), true)));
+        assert_eq!(check(429, &secondary), Some((5000, at(1_030), true)));
+        assert_eq!(check(429, &[]), Some((0, now + SECONDARY_RETRY, true)));
+
+        // A 403 for any other reason is about the token.
+        assert_eq!(check(403, &[("x-ratelimit-remaining", "4000")]), None);
+        assert_eq!(check(200, &secondary), None);
+    }
+}
@@ -0,0 +1,268 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{ensure, Context as _};
+use async_stream::stream;
+use hyper::http;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use tokio::sync::watch;
+use tokio::{fs, task, time};
+
+use crate::compare;
+use crate::config::ProjectConfig;
+use crate::redact;
+use crate::renderer::Renderer;
+use crate::router::{self, Request, Response, Router};
+use crate::templater::{Liveness, Templater, Theme};
+use crate::Event;
+
+/// How often to check `git status` for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Serve a dashboard of every markdown file with uncommitted changes in the current repository.
+pub(crate) async fn run(
+    renderer: Renderer,
+    project: ProjectConfig,
+    theme: Theme,
+    bind: &[IpAddr],
+    port: u16,
+) -> anyhow::Result<()> {
+    let root = task::spawn_blocking(git_root).await.unwrap()?;
+
+    let (sender, status) = watch::channel(Status::read(&root, &project).await?);
+    tokio::spawn({
+        let root = root.clone();
+        async move {
+            loop {
+                time::sleep(POLL_INTERVAL).await;
+                let new_status = match Status::read(&root, &project).await {
+                    Ok(status) => status,
+                    Err(e) => {
+                        log::error!("{:?}", e);
+                        continue;
+                    }
+                };
+                if *sender.borrow() != new_status && sender.send(new_status).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let review = Arc::new(Review {
+        renderer,
+        theme,
+        root,
+        status,
+    });
+
+    let listeners = crate::bind(bind, port).await?;
+    let router =
+        Router::new(
+            |review: Arc<Review>, req: Request| async move { review.handle_request(req).await },
+        )
+        .events(|review: Arc<Review>| async move { review.event_stream() });
+    crate::serve(listeners, router.into_service(review), None).await
+}
+
+/// The root of the Git checkout the working directory is in.
+pub(crate) fn git_root() -> anyhow::Result<PathBuf> {
+    Ok(PathBuf::from(
+        git(Path::new("."), &["rev-parse", "--show-toplevel"])?.trim_end(),
+    ))
+}
+
+/// Run git in `dir`, failing if it does, and return what it prints.
+pub(crate) fn git(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .context("failed to run git")?;
+    ensure!(
+        output.status.success(),
+        "`git {}` failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// The modified markdown files in the repository, along with their modification times so that
+/// edits to already-modified files are noticed.
+#[derive(PartialEq)]
+struct Status {
+    files: Vec<(String, Option<SystemTime>)>,
+}
+
+impl Status {
+    async fn read(root: &Path, project: &ProjectConfig) -> anyhow::Result<Self> {
+        let output = task::spawn_blocking({
+            let root = root.to_owned();
+            move || {
+                git(
+                    &root,
+                    &["status", "--porcelain=v1", "-z", "--untracked-files=all"],
+                )
+            }
+        })
+        .await
+        .unwrap()?;
+
+        let mut files = Vec::new();
+        let mut entries = output.split('\0');
+        while let Some(entry) = entries.next() {
+            if entry.len() < 4 {
+                continue;
+            }
+            let (status, path) = entry.split_at(3);
+            // Renames and copies are followed by the original path, which we don't need.
+            if status.starts_with(['R', 'C']) {
+                entries.next();
+            }
+            if status.contains('D') || !is_markdown(path) || project.is_ignored(&root.join(path)) {
+                continue;
+            }
+            let modified = fs::metadata(root.join(path))
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            files.push((path.to_owned(), modified));
+        }
+        files.sort();
+
+        Ok(Self { files })
+    }
+}
+
+/// Whether a file name has one of the extensions markdown files are given.
+pub(crate) fn is_markdown(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    [".md", ".markdown", ".mdown", ".mkd"]
+        .iter()
+        .any(|extension| path.ends_with(extension))
+}
+
+struct Review {
+    renderer: Renderer,
+    theme: Theme,
+    root: PathBuf,
+    status: watch::Receiver<Status>,
+}
+
+impl Review {
+    async fn handle_request(&self, req: Request) -> Response {
+        let res = match req.uri().path() {
+            "/" => self.index().await.map(Some),
+            path => match path.strip_prefix("/file/") {
+                Some(file) => {
+                    self.file(&percent_decode_str(file).decode_utf8_lossy())
+                        .await
+                }
+                None => Ok(None),
+            },
+        };
+
+        match res {
+            Ok(Some(page)) => http::Response::builder()
+                .status(http::StatusCode::OK)
+                .header("Content-Type", "text/html")
+                .body(router::full(page))
+                .unwrap(),
+            Ok(None) => http::Response::builder()
+                .status(http::StatusCode::NOT_FOUND)
+                .header("Content-Type", "text/plain")
+                .body(router::full("Not Found"))
+                .unwrap(),
+            Err(e) => http::Response::builder()
+                .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+                .header("Content-Type", "text/plain")
+                .body(router::full(format!(
+                    "\
+                        Internal Server Error\n\
+                        =====================\n\
+                        \
+                        {}\
+                    ",
+                    redact::redact(&format!("{:?}", e)),
+                )))
+                .unwrap(),
+        }
+    }
+
+    async fn index(&self) -> anyhow::Result<String> {
+        let mut content = String::from("<h1>Changed documents</h1>\n");
+        let files: Vec<String> = self
+            .status
+            .borrow()
+            .files
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect();
+        if files.is_empty() {
+            content.push_str("<p>No markdown files have uncommitted changes.</p>\n");
+        } else {
+            content.push_str("<ul>\n");
+            for path in files {
+                content.push_str(&format!(
+                    "<li><a href=\"/file/{}\">{}</a></li>\n",
+                    utf8_percent_encode(&path, NON_ALPHANUMERIC),
+                    tera::escape_html(&path),
+                ));
+            }
+            content.push_str("</ul>\n");
+        }
+
+        Templater::new("Review".into(), self.theme)
+            .generate(&content, Liveness::Live, None)
+            .await
+    }
+
+    /// Render the changes to a single file, if it is one of the modified files.
+    async fn file(&self, path: &str) -> anyhow::Result<Option<String>> {
+        let modified = self.status.borrow().files.iter().any(|(p, _)| p == path);
+        if !modified {
+            return Ok(None);
+        }
+
+        let new = fs::read_to_string(self.root.join(path))
+            .await
+            .with_context(|| format!("failed to read `{}`", path))?;
+        // Files that are new in the working tree are compared against nothing.
+        let old = task::spawn_blocking({
+            let root = self.root.clone();
+            let spec = format!("HEAD:{}", path);
+            move || git(&root, &["show", &spec]).unwrap_or_default()
+        })
+        .await
+        .unwrap();
+
+        let mut content = String::from("<p><a href=\"/\">← All changed documents</a></p>\n");
+        content.push_str(&compare::render_diff(&self.renderer, &old, &new).await?);
+
+        let page = Templater::new(path.into(), self.theme)
+            .generate(&content, Liveness::Live, None)
+            .await?;
+        Ok(Some(page))
+    }
+
+    fn event_stream(&self) -> Response {
+        let mut status = self.status.clone();
+        let stream = router::stream(stream! {
+            let mut seq = 0;
+            while status.changed().await.is_ok() {
+                yield Event::page("reload").into_sse(seq);
+                seq += 1;
+            }
+        });
+
+        http::Response::builder()
+            .status(http::StatusCode::OK)
+            .header("Content-Type", "text/event-stream")
+            .body(stream)
+            .unwrap()
+    }
+}
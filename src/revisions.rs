@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::renderer;
+
+/// How many revisions are kept, across every document.
+const CAPACITY: usize = 50;
+
+/// A document as it was shown at some point.
+#[derive(Clone)]
+pub(crate) struct Revision {
+    /// The content hash of the document's markdown, by which the revision is found.
+    pub(crate) hash: String,
+    /// The index of the document.
+    pub(crate) document: usize,
+    /// The HTML that was shown, after ghmd's own changes to it.
+    pub(crate) html: Arc<str>,
+    pub(crate) shown_at: SystemTime,
+}
+
+/// The revisions of the documents shown most recently, so that each can be looked at again by
+/// its hash at `/rev/<hash>` after the document has moved on.
+pub(crate) struct Revisions {
+    /// Oldest first.
+    revisions: Mutex<VecDeque<Revision>>,
+}
+
+impl Revisions {
+    pub(crate) fn new() -> Self {
+        Self {
+            revisions: Mutex::new(VecDeque::with_capacity(CAPACITY)),
+        }
+    }
+
+    /// Remember what a document looked like, forgetting the oldest revision if there are too
+    /// many, and return the revision's hash.
+    pub(crate) fn insert(&self, document: usize, markdown: &str, html: &str) -> String {
+        let hash = renderer::content_hash(markdown);
+        let mut revisions = self.revisions.lock().unwrap();
+        revisions.retain(|revision| revision.document != document || revision.hash != hash);
+        if revisions.len() == CAPACITY {
+            revisions.pop_front();
+        }
+        revisions.push_back(Revision {
+            hash: hash.clone(),
+            document,
+            html: html.into(),
+            shown_at: SystemTime::now(),
+        });
+        hash
+    }
+
+    /// The most recent revision with this hash, of any document.
+    pub(crate) fn get(&self, hash: &str) -> Option<Revision> {
+        let revisions = self.revisions.lock().unwrap();
+        revisions
+            .iter()
+            .rev()
+            .find(|revision| revision.hash == hash)
+            .cloned()
+    }
+}
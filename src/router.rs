@@ -0,0 +1,466 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_core::Stream;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt as _, Full, LengthLimitError, Limited, StreamBody};
+use hyper::body::Frame;
+use hyper::http;
+use percent_encoding::percent_decode_str;
+use tokio::time;
+
+pub(crate) type Request = http::Request<UnsyncBoxBody<Bytes, hyper::Error>>;
+pub(crate) type Response = http::Response<Body>;
+
+/// The address of the client that made a request, stored in its extensions.
+#[derive(Clone, Copy)]
+pub(crate) struct RemoteAddr(pub(crate) SocketAddr);
+
+/// The address the server accepted a request's connection on, stored in its extensions.
+#[derive(Clone, Copy)]
+pub(crate) struct LocalAddr(pub(crate) SocketAddr);
+
+/// The body of a response, either sent all at once (see [`full`]) or streamed.
+pub(crate) type Body = UnsyncBoxBody<Bytes, Infallible>;
+
+type BoxFuture = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+/// Dispatches requests to handlers by their method and path.
+///
+/// Handlers are async functions taking the shared state `S` followed by any number of extractors
+/// (see [`FromRequest`]), so an endpoint only has to state what it needs from the request.
+pub(crate) struct Router<S> {
+    routes: Vec<Route<S>>,
+    events: Option<Box<dyn Erased<S>>>,
+    fallback: Box<dyn Erased<S>>,
+}
+
+struct Route<S> {
+    method: http::Method,
+    path: &'static str,
+    handler: Box<dyn Erased<S>>,
+}
+
+impl<S: Send + Sync + 'static> Router<S> {
+    /// A router that passes every request to `fallback` until routes are added.
+    pub(crate) fn new<T: 'static>(fallback: impl Handler<S, T>) -> Self {
+        Self {
+            routes: Vec::new(),
+            events: None,
+            fallback: Box::new(Erase(fallback, PhantomData)),
+        }
+    }
+
+    pub(crate) fn route<T: 'static>(
+        mut self,
+        method: http::Method,
+        path: &'static str,
+        handler: impl Handler<S, T>,
+    ) -> Self {
+        self.routes.push(Route {
+            method,
+            path,
+            handler: Box::new(Erase(handler, PhantomData)),
+        });
+        self
+    }
+
+    /// Handle requests for an event stream, which may be made to any path.
+    pub(crate) fn events<T: 'static>(mut self, handler: impl Handler<S, T>) -> Self {
+        self.events = Some(Box::new(Erase(handler, PhantomData)));
+        self
+    }
+
+    /// The path of every route, in the order they were added.
+    pub(crate) fn paths(&self) -> Vec<&'static str> {
+        let mut paths: Vec<&'static str> = Vec::new();
+        for route in &self.routes {
+            if !paths.contains(&route.path) {
+                paths.push(route.path);
+            }
+        }
+        paths
+    }
+
+    pub(crate) fn handle(&self, state: &Arc<S>, req: Request) -> BoxFuture {
+        let wants_events = req
+            .headers()
+            .get("accept")
+            .is_some_and(|val| val == "text/event-stream");
+        let handler = match &self.events {
+            Some(events) if wants_events => events,
+            _ => self
+                .routes
+                .iter()
+                .find(|route| route.method == req.method() && route.path == req.uri().path())
+                .map_or(&self.fallback, |route| &route.handler),
+        };
+        handler.call(state.clone(), req)
+    }
+
+    /// Turn the router into a service handling requests with the given state, so that it can be
+    /// wrapped in middleware.
+    pub(crate) fn into_service(self, state: Arc<S>) -> RouterService<S> {
+        RouterService {
+            router: Arc::new(self),
+            state,
+        }
+    }
+}
+
+pub(crate) struct RouterService<S> {
+    router: Arc<Router<S>>,
+    state: Arc<S>,
+}
+
+impl<S> Clone for RouterService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            router: self.router.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<S: Send + Sync + 'static> tower::Service<Request> for RouterService<S> {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let response = self.router.handle(&self.state, req);
+        Box::pin(async move { Ok(response.await) })
+    }
+}
+
+/// An async function that can handle requests.
+pub(crate) trait Handler<S, T>: Send + Sync + 'static {
+    fn call(&self, state: Arc<S>, req: Request) -> BoxFuture;
+}
+
+impl<S, F, Fut> Handler<S, ()> for F
+where
+    F: Fn(Arc<S>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    fn call(&self, state: Arc<S>, _req: Request) -> BoxFuture {
+        Box::pin(self(state))
+    }
+}
+
+macro_rules! impl_handler {
+    ($($extractor:ident),*) => {
+        impl<S, F, Fut, $($extractor,)*> Handler<S, ($($extractor,)*)> for F
+        where
+            F: Fn(Arc<S>, $($extractor,)*) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = Response> + Send + 'static,
+            $($extractor: FromRequest<S>,)*
+        {
+            #[allow(non_snake_case)]
+            fn call(&self, state: Arc<S>, mut req: Request) -> BoxFuture {
+                $(
+                    let $extractor = match $extractor::from_request(&state, &mut req) {
+                        Ok(value) => value,
+                        Err(Rejection { status, message }) => {
+                            return Box::pin(async move { text(status, message) });
+                        }
+                    };
+                )*
+                Box::pin(self(state, $($extractor,)*))
+            }
+        }
+    };
+}
+
+impl_handler!(A);
+impl_handler!(A, B);
+impl_handler!(A, B, C);
+impl_handler!(A, B, C, D);
+impl_handler!(A, B, C, D, E);
+
+/// A handler with its extractor types hidden, so that handlers can be stored together.
+trait Erased<S>: Send + Sync {
+    fn call(&self, state: Arc<S>, req: Request) -> BoxFuture;
+}
+
+struct Erase<H, T>(H, PhantomData<fn() -> T>);
+
+impl<S, T: 'static, H: Handler<S, T>> Erased<S> for Erase<H, T> {
+    fn call(&self, state: Arc<S>, req: Request) -> BoxFuture {
+        self.0.call(state, req)
+    }
+}
+
+/// Something taken from a request before it is handled. Extractors run in the order the handler
+/// lists them, and any of them can reject the request.
+pub(crate) trait FromRequest<S>: Sized {
+    fn from_request(state: &S, req: &mut Request) -> Result<Self, Rejection>;
+}
+
+/// Why an extractor refused a request, which is sent back as plain text.
+pub(crate) struct Rejection {
+    pub(crate) status: http::StatusCode,
+    pub(crate) message: &'static str,
+}
+
+/// The whole request. This takes the body, so it must come after any other extractors.
+impl<S> FromRequest<S> for Request {
+    fn from_request(_state: &S, req: &mut Request) -> Result<Self, Rejection> {
+        Ok(mem::take(req))
+    }
+}
+
+/// The request's query parameters, percent-decoded.
+pub(crate) struct Query(Vec<(String, String)>);
+
+impl Query {
+    /// The value of the first parameter with this name.
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| &**value)
+    }
+}
+
+impl<S> FromRequest<S> for Query {
+    fn from_request(_state: &S, req: &mut Request) -> Result<Self, Rejection> {
+        let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+        Ok(Self(
+            req.uri()
+                .query()
+                .unwrap_or("")
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(key, value)| (decode(key), decode(value)))
+                .collect(),
+        ))
+    }
+}
+
+/// Refuses requests made by pages from another origin, which a browser marks with an `Origin`
+/// header naming them. Requests without one, such as those from editor plugins and scripts, are
+/// let through.
+///
+/// The `Host` header must also name the server as `localhost` or by the address the request
+/// arrived at. Otherwise a site could point its own domain name at the server (DNS rebinding),
+/// making its requests look same-origin.
+pub(crate) struct SameOrigin;
+
+impl<S> FromRequest<S> for SameOrigin {
+    fn from_request(_state: &S, req: &mut Request) -> Result<Self, Rejection> {
+        let host = req
+            .headers()
+            .get(http::header::HOST)
+            .and_then(|host| host.to_str().ok());
+        let local = req.extensions().get::<LocalAddr>().map(|&LocalAddr(a)| a);
+        if !host.is_some_and(|host| names_server(host, local)) {
+            return Err(Rejection {
+                status: http::StatusCode::FORBIDDEN,
+                message: "the Host header must be localhost or the address of the server",
+            });
+        }
+        let origin = match req.headers().get(http::header::ORIGIN) {
+            Some(origin) => origin.to_str().ok(),
+            None => return Ok(Self),
+        };
+        let origin_host = origin.and_then(|origin| {
+            origin
+                .strip_prefix("http://")
+                .or_else(|| origin.strip_prefix("https://"))
+        });
+        match (origin_host, host) {
+            (Some(origin_host), Some(host)) if origin_host.eq_ignore_ascii_case(host) => Ok(Self),
+            _ => Err(Rejection {
+                status: http::StatusCode::FORBIDDEN,
+                message: "requests from other origins are not allowed",
+            }),
+        }
+    }
+}
+
+/// Whether a `Host` header names this server: `localhost`, or the IP address the request arrived
+/// at (any IP address if that isn't known), with any port.
+fn names_server(host: &str, local: Option<SocketAddr>) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((name, _)) => name,
+            None => return false,
+        },
+        None => host.split(':').next().unwrap(),
+    };
+    if name.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    match (name.parse::<IpAddr>(), local) {
+        (Ok(ip), Some(local)) => ip == local.ip(),
+        (Ok(_), None) => true,
+        (Err(_), _) => false,
+    }
+}
+
+/// The header that [`RequestedByPage`] looks for.
+const REQUESTED_BY: &str = "x-requested-by";
+
+/// Requires the [`REQUESTED_BY`] header that ghmd's own pages send, and that editor plugins must
+/// send as well. Other sites can't send a custom header without a CORS preflight, which is never
+/// allowed, so this keeps them from making a plain form post to a route that changes files.
+pub(crate) struct RequestedByPage;
+
+impl<S> FromRequest<S> for RequestedByPage {
+    fn from_request(_state: &S, req: &mut Request) -> Result<Self, Rejection> {
+        match req.headers().get(REQUESTED_BY) {
+            Some(value) if value == "ghmd" => Ok(Self),
+            _ => Err(Rejection {
+                status: http::StatusCode::FORBIDDEN,
+                message: "the request must come from the preview page",
+            }),
+        }
+    }
+}
+
+/// How long a client may take to send a request body, so that slow clients can't hold
+/// connections open indefinitely.
+const BODY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Read the whole body of a request, refusing bodies larger than `limit` bytes.
+pub(crate) async fn read_body(req: Request, limit: u64) -> Result<Bytes, Rejection> {
+    let too_large = || Rejection {
+        status: http::StatusCode::PAYLOAD_TOO_LARGE,
+        message: "request body is too large",
+    };
+    let length = req
+        .headers()
+        .get("content-length")
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if length.is_some_and(|length| length > limit) {
+        return Err(too_large());
+    }
+
+    let body = Limited::new(
+        req.into_body(),
+        usize::try_from(limit).unwrap_or(usize::MAX),
+    );
+    match time::timeout(BODY_TIMEOUT, body.collect()).await {
+        Ok(Ok(body)) => Ok(body.to_bytes()),
+        Ok(Err(e)) if e.is::<LengthLimitError>() => Err(too_large()),
+        Ok(Err(_)) => Err(Rejection {
+            status: http::StatusCode::BAD_REQUEST,
+            message: "failed to read request body",
+        }),
+        Err(_) => Err(Rejection {
+            status: http::StatusCode::REQUEST_TIMEOUT,
+            message: "request body took too long to send",
+        }),
+    }
+}
+
+/// A body containing all of `data`.
+pub(crate) fn full(data: impl Into<Bytes>) -> Body {
+    Full::new(data.into()).boxed_unsync()
+}
+
+/// A body sent a piece at a time as `stream` produces them, such as an event stream.
+pub(crate) fn stream<T: Into<Bytes>>(stream: impl Stream<Item = T> + Send + 'static) -> Body {
+    StreamBody::new(async_stream::stream! {
+        for await data in stream {
+            yield Ok(Frame::data(data.into()));
+        }
+    })
+    .boxed_unsync()
+}
+
+/// A plain text response with the given status.
+pub(crate) fn text(status: http::StatusCode, message: impl Into<String>) -> Response {
+    http::Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain")
+        .body(full(message.into()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{names_server, FromRequest as _, LocalAddr, Request, RequestedByPage, SameOrigin};
+
+    fn request(headers: &[(&'static str, &str)]) -> Request {
+        let mut req = Request::default();
+        for &(name, value) in headers {
+            req.headers_mut().insert(name, value.parse().unwrap());
+        }
+        req
+    }
+
+    #[test]
+    fn same_origin() {
+        let allowed = |headers: &[(&'static str, &str)]| {
+            SameOrigin::from_request(&(), &mut request(headers)).is_ok()
+        };
+        assert!(allowed(&[("host", "localhost:1729")]));
+        assert!(!allowed(&[]));
+        assert!(allowed(&[
+            ("host", "localhost:1729"),
+            ("origin", "http://localhost:1729")
+        ]));
+        assert!(allowed(&[
+            ("host", "LocalHost:1729"),
+            ("origin", "http://localhost:1729")
+        ]));
+        assert!(!allowed(&[
+            ("host", "localhost:1729"),
+            ("origin", "http://localhost:1730")
+        ]));
+        assert!(!allowed(&[
+            ("host", "localhost:1729"),
+            ("origin", "https://example.com")
+        ]));
+        assert!(!allowed(&[("host", "localhost:1729"), ("origin", "null")]));
+        assert!(!allowed(&[("origin", "http://localhost:1729")]));
+    }
+
+    #[test]
+    fn host() {
+        let local = "127.0.0.1:1729".parse().unwrap();
+        let allowed = |host: &str| {
+            let mut req = request(&[("host", host), ("origin", &format!("http://{}", host))]);
+            req.extensions_mut().insert(LocalAddr(local));
+            SameOrigin::from_request(&(), &mut req).is_ok()
+        };
+        assert!(allowed("localhost:1729"));
+        assert!(allowed("LOCALHOST"));
+        assert!(allowed("127.0.0.1:1729"));
+        // A domain name pointed at the server by another site.
+        assert!(!allowed("attacker.example:1729"));
+        assert!(!allowed("localhost.attacker.example:1729"));
+        // Addresses the server wasn't reached at.
+        assert!(!allowed("127.0.0.2:1729"));
+        assert!(!allowed("[::1]:1729"));
+
+        let v6 = |host: &str| names_server(host, Some("[::1]:1729".parse().unwrap()));
+        assert!(v6("[::1]:1729"));
+        assert!(v6("[::1]"));
+        assert!(!v6("[::1"));
+    }
+
+    #[test]
+    fn requested_by_page() {
+        let allowed = |headers: &[(&'static str, &str)]| {
+            RequestedByPage::from_request(&(), &mut request(headers)).is_ok()
+        };
+        assert!(allowed(&[("x-requested-by", "ghmd")]));
+        assert!(!allowed(&[]));
+        assert!(!allowed(&[("x-requested-by", "other")]));
+    }
+}
@@ -0,0 +1,114 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context as _};
+use clap::ArgEnum;
+use serde::Serialize;
+use tokio::fs;
+use tokio::io::AsyncWriteExt as _;
+
+use crate::badge::Repository;
+use crate::config::ProjectConfig;
+use crate::watcher::is_stdin;
+
+/// A kind of document that can be started from a template.
+#[derive(Clone, Copy, ArgEnum)]
+pub(crate) enum Kind {
+    Readme,
+    Contributing,
+}
+
+impl Kind {
+    /// The name the document is usually given.
+    fn file_name(self) -> &'static str {
+        match self {
+            Self::Readme => "README.md",
+            Self::Contributing => "CONTRIBUTING.md",
+        }
+    }
+
+    fn template(self) -> &'static str {
+        match self {
+            Self::Readme => include_str!("new_readme.md"),
+            Self::Contributing => include_str!("new_contributing.md"),
+        }
+    }
+}
+
+/// Write a starter document of the given kind, filled in with what can be found out about the
+/// repository in the working directory, and return where it was written. It is written to `path`,
+/// or the kind's usual file name in the working directory, which is only replaced with `force`.
+pub(crate) async fn run(
+    project: &ProjectConfig,
+    kind: Kind,
+    path: Option<PathBuf>,
+    force: bool,
+    remote: &str,
+) -> anyhow::Result<PathBuf> {
+    let path = path.unwrap_or_else(|| PathBuf::from(kind.file_name()));
+    ensure!(
+        !is_stdin(&path),
+        "the document must be written to a file to be previewed"
+    );
+    let repository = Repository::detect(project, remote).await?;
+    let markdown = generate(kind, &repository).await?;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    let mut file = match options.open(&path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => bail!(
+            "`{}` already exists; pass `--force` to replace it",
+            path.display()
+        ),
+        Err(e) => return Err(e).with_context(|| format!("failed to create `{}`", path.display())),
+    };
+    file.write_all(markdown.as_bytes())
+        .await
+        .with_context(|| format!("failed to write `{}`", path.display()))?;
+    log::info!("Wrote {}", path.display());
+    Ok(path)
+}
+
+async fn generate(kind: Kind, repository: &Repository) -> anyhow::Result<String> {
+    #[derive(Serialize)]
+    struct TemplateOpts<'a> {
+        name: String,
+        github: Option<&'a str>,
+        #[serde(rename = "crate")]
+        crate_name: Option<&'a str>,
+        license: Option<&'a str>,
+        badges: Vec<String>,
+    }
+
+    // The project is named after its repository, its crate or its directory, in that order.
+    let name = match (&repository.github, &repository.published_crate) {
+        (Some(github), _) => github.split('/').nth(1).unwrap().to_owned(),
+        (None, Some(crate_name)) => crate_name.clone(),
+        (None, None) => directory_name(&repository.root).await,
+    };
+    let opts = TemplateOpts {
+        name,
+        github: repository.github.as_deref(),
+        crate_name: repository.published_crate.as_deref(),
+        license: repository.license.as_deref(),
+        badges: repository.badges(),
+    };
+    let context = tera::Context::from_serialize(opts).unwrap();
+    tera::Tera::one_off(kind.template(), &context, false).context("failed to render template")
+}
+
+async fn directory_name(dir: &Path) -> String {
+    let dir = fs::canonicalize(dir)
+        .await
+        .unwrap_or_else(|_| dir.to_owned());
+    dir.file_name().map_or_else(
+        || "Project".to_owned(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
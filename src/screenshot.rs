@@ -0,0 +1,117 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::{ensure, Context as _};
+use tokio::{fs, process};
+
+use crate::config::ProjectConfig;
+use crate::hook::Hooks;
+use crate::renderer::Renderer;
+use crate::templater::Templater;
+use crate::Source;
+
+/// Browsers that can take screenshots from the command line, in the order they are looked for.
+const BROWSERS: &[&str] = &[
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "google-chrome-stable",
+    "chrome",
+    "microsoft-edge",
+];
+
+/// How to take a screenshot, as given on the command line.
+pub(crate) struct Options {
+    /// The PNG file to write.
+    pub(crate) output: PathBuf,
+    /// The browser to take it with, if not the first of [`BROWSERS`] that is installed.
+    pub(crate) browser: Option<PathBuf>,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+/// Render a document to a static page and have a headless Chromium-based browser take a
+/// screenshot of it.
+///
+/// The page is written beside the document for the duration, so that the browser finds its local
+/// images, and removed afterwards.
+pub(crate) async fn run(
+    source: &Source,
+    renderer: Renderer,
+    templater: Templater,
+    project: &ProjectConfig,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let browser = match &options.browser {
+        Some(browser) => browser.clone(),
+        None => find_browser().context(
+            "could not find Chromium or Chrome; install one or give its path with `--browser`",
+        )?,
+    };
+    let dir = match source.path() {
+        Some(path) => path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_owned(),
+        None => env::temp_dir(),
+    };
+    let dir = fs::canonicalize(&dir)
+        .await
+        .with_context(|| format!("failed to resolve `{}`", dir.display()))?;
+    let page = dir.join(format!(".ghmd-screenshot-{}.html", std::process::id()));
+    let output = env::current_dir()
+        .context("failed to get current directory")?
+        .join(&options.output);
+
+    let res = async {
+        crate::gen_output(
+            source,
+            renderer,
+            templater,
+            project,
+            &page,
+            &[],
+            &Hooks::default(),
+        )
+        .await?;
+        let url = reqwest::Url::from_file_path(&page).unwrap();
+        let result = process::Command::new(&browser)
+            .arg("--headless")
+            .arg("--disable-gpu")
+            .arg("--hide-scrollbars")
+            .arg(format!(
+                "--window-size={},{}",
+                options.width, options.height
+            ))
+            .arg(format!("--screenshot={}", output.display()))
+            .arg(url.as_str())
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .with_context(|| format!("failed to run `{}`", browser.display()))?;
+        ensure!(
+            result.status.success(),
+            "`{}` failed: {}",
+            browser.display(),
+            String::from_utf8_lossy(&result.stderr).trim()
+        );
+        Ok(())
+    }
+    .await;
+    let _ = fs::remove_file(&page).await;
+    res?;
+    log::info!("Saved screenshot to {}", options.output.display());
+    Ok(())
+}
+
+/// The first of [`BROWSERS`] that is on the `PATH`.
+fn find_browser() -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    BROWSERS.iter().find_map(|browser| {
+        env::split_paths(&path)
+            .map(|dir| dir.join(browser))
+            .find(|candidate| candidate.is_file())
+    })
+}
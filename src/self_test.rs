@@ -0,0 +1,136 @@
+use std::fmt::{self, Display, Formatter};
+
+use anyhow::{anyhow, ensure};
+
+use crate::queue::Priority;
+use crate::renderer::Renderer;
+use crate::templater::{Liveness, Templater};
+
+/// A tiny document exercising headings (which GitHub gives octicon anchors) and code.
+const SNIPPET: &str = "# ghmd self-test\n\n`code`\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stage {
+    Api,
+    Octicons,
+    Template,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Api => "GitHub API request",
+            Self::Octicons => "octicon population",
+            Self::Template => "page templating",
+        })
+    }
+}
+
+const STAGES: [Stage; 3] = [Stage::Api, Stage::Octicons, Stage::Template];
+
+/// How thorough the self-test should be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Depth {
+    /// Only check that the GitHub API accepts the token and renders markdown, and that the
+    /// template renders. This costs a single API request.
+    Light,
+    /// Run every stage of the pipeline.
+    Full,
+}
+
+pub(crate) struct Failure {
+    pub(crate) stage: Stage,
+    pub(crate) error: anyhow::Error,
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "self-test failed at stage: {}\n\n{:?}",
+            self.stage, self.error
+        )
+    }
+}
+
+/// Render a known snippet through the whole pipeline, calling `report` after each stage
+/// succeeds and returning the first stage that fails.
+pub(crate) async fn run(
+    renderer: &Renderer,
+    templater: &Templater,
+    depth: Depth,
+    mut report: impl FnMut(Stage),
+) -> Result<(), Failure> {
+    let fail = |stage| move |error| Failure { stage, error };
+
+    let html = async {
+        let html = renderer
+            .request(SNIPPET, Priority::Background)
+            .await?
+            .map_err(|rate_limited| anyhow!(rate_limited))?;
+        ensure!(
+            html.contains("<h1"),
+            "GitHub returned unexpected HTML:\n{}",
+            html
+        );
+        Ok(html)
+    }
+    .await
+    .map_err(fail(Stage::Api))?;
+    report(Stage::Api);
+
+    let html = if depth == Depth::Full {
+        let populated = renderer.populate_octicons(html).await;
+        ensure_svg(&populated).map_err(fail(Stage::Octicons))?;
+        if !renderer.octicons_reachable().await {
+            return Err(fail(Stage::Octicons)(anyhow!(
+                "only built-in octicons are available; is https://cdn.jsdelivr.net reachable?"
+            )));
+        }
+        report(Stage::Octicons);
+        populated
+    } else {
+        html
+    };
+
+    for liveness in [Liveness::Static, Liveness::Live] {
+        templater
+            .generate(&html, liveness, None)
+            .await
+            .map_err(fail(Stage::Template))?;
+    }
+    report(Stage::Template);
+
+    Ok(())
+}
+
+fn ensure_svg(html: &str) -> anyhow::Result<()> {
+    ensure!(
+        html.contains("<svg"),
+        "no octicons could be fetched; is https://cdn.jsdelivr.net reachable?"
+    );
+    Ok(())
+}
+
+/// Run the full self-test, printing the outcome of every stage to standard output.
+pub(crate) async fn run_verbose(renderer: &Renderer, templater: &Templater) -> anyhow::Result<()> {
+    let mut passed = Vec::new();
+    let res = run(renderer, templater, Depth::Full, |stage| {
+        println!("ok      {}", stage);
+        passed.push(stage);
+    })
+    .await;
+
+    let failure = match res {
+        Ok(()) => return Ok(()),
+        Err(failure) => failure,
+    };
+    println!("FAILED  {}", failure.stage);
+    for stage in STAGES.iter().filter(|stage| **stage != failure.stage) {
+        if !passed.contains(stage) {
+            println!("skipped {}", stage);
+        }
+    }
+    println!();
+    Err(anyhow!("{}", failure))
+}
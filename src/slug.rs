@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+/// Generates heading anchors the way GitHub does, including `-1`, `-2`, … suffixes for
+/// duplicate headings.
+///
+/// This follows `github-slugger`: the text is lowercased, everything other than letters,
+/// numbers, combining marks, `-`, `_` and spaces (so all other punctuation, symbols and emoji) is
+/// removed, and each space becomes a `-`. For example:
+///
+/// | Heading               | Anchor                |
+/// |-----------------------|-----------------------|
+/// | `Getting Started`     | `getting-started`     |
+/// | `What's new in 2.0?`  | `whats-new-in-20`     |
+/// | `🚀 Launch`           | `-launch`             |
+/// | `Café  au lait`       | `café--au-lait`       |
+/// | `snake_case-and-dash` | `snake_case-and-dash` |
+/// | `Setup` (second time) | `setup-1`             |
+#[derive(Default)]
+pub(crate) struct Slugger {
+    /// How many times each slug has been generated.
+    seen: HashMap<String, usize>,
+}
+
+impl Slugger {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generate the anchor for the next heading with the given text.
+    pub(crate) fn slug(&mut self, text: &str) -> String {
+        let base = slug(text);
+        let mut slug = base.clone();
+        // Like `github-slugger`, the count is kept for the heading's own slug, even when the
+        // clash is with a suffixed one.
+        while self.seen.contains_key(&slug) {
+            let count = self.seen.get_mut(&base).unwrap();
+            *count += 1;
+            slug = format!("{}-{}", base, count);
+        }
+        self.seen.insert(slug.clone(), 0);
+        slug
+    }
+}
+
+/// Generate the anchor for a single heading, without regard to duplicates.
+pub(crate) fn slug(text: &str) -> String {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() || is_combining_mark(c) => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether the character is a combining mark, which GitHub keeps so that decomposed accented
+/// letters survive.
+fn is_combining_mark(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE20}'..='\u{FE2F}'
+            // Indic and other scripts' vowel signs and viramas.
+            | '\u{0900}'..='\u{0903}'
+            | '\u{093A}'..='\u{094F}'
+            | '\u{0E31}'
+            | '\u{0E34}'..='\u{0E3A}'
+            | '\u{0E47}'..='\u{0E4E}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{slug, Slugger};
+
+    /// Headings and the anchors GitHub gives them.
+    const FIXTURES: &[(&str, &str)] = &[
+        ("Getting Started", "getting-started"),
+        ("What's new in 2.0?", "whats-new-in-20"),
+        ("C++ & Rust", "c--rust"),
+        ("foo.bar/baz", "foobarbaz"),
+        ("(Optional) Setup: step 1", "optional-setup-step-1"),
+        ("snake_case-and-dash", "snake_case-and-dash"),
+        ("Café  au lait", "café--au-lait"),
+        ("Ünïcödé Ωmega", "ünïcödé-ωmega"),
+        ("Привет, мир!", "привет-мир"),
+        ("日本語の見出し", "日本語の見出し"),
+        ("Cafe\u{301}", "cafe\u{301}"),
+        ("हिन्दी", "हिन्दी"),
+        ("🚀 Launch", "-launch"),
+        ("Launch 🚀", "launch-"),
+        ("👍", ""),
+        ("", ""),
+    ];
+
+    #[test]
+    fn fixtures() {
+        for (heading, anchor) in FIXTURES {
+            assert_eq!(slug(heading), *anchor, "{:?}", heading);
+        }
+    }
+
+    fn slugs(headings: &[&str]) -> Vec<String> {
+        let mut slugger = Slugger::new();
+        headings
+            .iter()
+            .map(|heading| slugger.slug(heading))
+            .collect()
+    }
+
+    #[test]
+    fn duplicates() {
+        assert_eq!(
+            slugs(&["Setup", "Setup", "setup"]),
+            ["setup", "setup-1", "setup-2"]
+        );
+        assert_eq!(slugs(&["a", "a-1", "a", "a"]), ["a", "a-1", "a-2", "a-3"]);
+        assert_eq!(slugs(&["a", "a", "a-1"]), ["a", "a-1", "a-1-1"]);
+    }
+
+    #[test]
+    fn empty_headings() {
+        assert_eq!(slugs(&["", "", "👍"]), ["", "-1", "-2"]);
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as _};
+use fn_error_context::context;
+use sha2::{Digest, Sha512};
+use tokio::fs;
+
+use crate::diff::{self, Line};
+use crate::dirs;
+use crate::queue::Priority;
+use crate::renderer::Renderer;
+
+/// The number of unchanged lines to show around each change.
+const CONTEXT: usize = 2;
+
+/// Render `input` and either store the result as a snapshot or compare it against the stored one.
+#[context("failed to snapshot `{}`", input.display())]
+pub(crate) async fn run(
+    renderer: &Renderer,
+    input: &Path,
+    check: bool,
+    dir: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let markdown = fs::read_to_string(input).await?;
+
+    let dir = match dir {
+        Some(dir) => dir,
+        None => dirs::cache_dir()?.join("snapshots"),
+    };
+    let hash = Sha512::digest(markdown.as_bytes());
+    let path = dir.join(format!("{}.html", &format!("{:x}", hash)[..32]));
+
+    let rendered = renderer.render(&markdown, Priority::Background).await??;
+
+    if !check {
+        fs::create_dir_all(&dir).await?;
+        fs::write(&path, &*rendered)
+            .await
+            .context("failed to write snapshot")?;
+        println!("Saved snapshot to {}", path.display());
+        return Ok(());
+    }
+
+    let snapshot = match fs::read_to_string(&path).await {
+        Ok(snapshot) => snapshot,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            bail!("there is no snapshot of the file's current contents; create one first")
+        }
+        Err(e) => return Err(e).context("failed to read snapshot"),
+    };
+
+    if snapshot == *rendered {
+        println!("Rendered output matches the snapshot");
+        return Ok(());
+    }
+
+    let lines = diff::lines(&snapshot, &rendered);
+    let changed: Vec<bool> = lines
+        .iter()
+        .map(|line| !matches!(line, Line::Same(_)))
+        .collect();
+    let mut skipped = false;
+    for (i, line) in lines.iter().enumerate() {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(lines.len());
+        if !changed[start..end].contains(&true) {
+            skipped = true;
+            continue;
+        }
+        if skipped {
+            println!("@@");
+            skipped = false;
+        }
+        match line {
+            Line::Same(line) => println!(" {}", line),
+            Line::Removed(line) => println!("-{}", line),
+            Line::Added(line) => println!("+{}", line),
+        }
+    }
+
+    bail!("rendered output differs from the snapshot")
+}
@@ -8,3 +8,119 @@ function correct_hash_scroll() {
 }
 addEventListener("hashchange", correct_hash_scroll);
 addEventListener("load", correct_hash_scroll);
+
+// A placeholder is only needed until its image arrives, and would show through transparent ones.
+function clear_placeholder(img) {
+	if (img.complete) {
+		delete img.dataset.ghmdPlaceholder;
+	}
+}
+addEventListener("load", e => {
+	if (e.target instanceof HTMLImageElement) {
+		clear_placeholder(e.target);
+	}
+}, true);
+document.querySelectorAll("img[data-ghmd-placeholder]").forEach(clear_placeholder);
+
+// The outline lists the headings of the document being shown, indented by level, and highlights
+// the one whose section is scrolled to.
+function build_outline() {
+	const outline = document.getElementById("outline");
+	if (outline === null) {
+		return;
+	}
+	const list = outline.querySelector("nav");
+	list.replaceChildren();
+	const headings = outline_headings();
+	const top_level = Math.min(...headings.map(heading => Number(heading.tagName[1])));
+	for (const heading of headings) {
+		const anchor = heading_anchor(heading);
+		const link = document.createElement("a");
+		link.href = anchor?.getAttribute("href") ?? "";
+		link.textContent = heading.textContent.trim();
+		link.style.paddingLeft = `${8 + 16 * (Number(heading.tagName[1]) - top_level)}px`;
+		link.addEventListener("click", e => {
+			if (anchor === null) {
+				e.preventDefault();
+				heading.scrollIntoView();
+			}
+		});
+		list.append(link);
+	}
+	outline.hidden = headings.length === 0 || outline.dataset.disabled !== undefined;
+	highlight_outline();
+}
+
+// GitHub puts the anchor either inside the heading or right after it.
+function heading_anchor(heading) {
+	const wrapper = heading.parentElement.closest(".markdown-heading");
+	return heading.querySelector("a.anchor") ?? wrapper?.querySelector(":scope > a.anchor") ?? null;
+}
+
+function outline_headings() {
+	const main = document.querySelector("main[data-document]:not([hidden])");
+	return main === null ? [] : [...main.querySelectorAll("h1, h2, h3, h4, h5, h6")];
+}
+
+function highlight_outline() {
+	const links = document.querySelectorAll("#outline nav a");
+	let current = -1;
+	outline_headings().forEach((heading, i) => {
+		// Leave room for the sticky header, and any fixed header that headings are scrolled below.
+		const margin = parseFloat(getComputedStyle(heading).scrollMarginTop) || 0;
+		if (heading.getBoundingClientRect().top <= 80 + margin) {
+			current = i;
+		}
+	});
+	links.forEach((link, i) => {
+		if (i === current) {
+			link.setAttribute("aria-current", "location");
+		} else {
+			link.removeAttribute("aria-current");
+		}
+	});
+}
+addEventListener("scroll", highlight_outline, { passive: true });
+
+const outline = document.getElementById("outline");
+if (outline !== null) {
+	outline.open = localStorage.getItem("ghmd-outline") !== "closed";
+	outline.addEventListener("toggle", () => {
+		localStorage.setItem("ghmd-outline", outline.open ? "open" : "closed");
+	});
+}
+build_outline();
+
+// A bar along the top of the page shows how far through the document the reader is, and the
+// position in each document read from a file is remembered, so that reopening it returns there.
+function scroll_fraction() {
+	const scrollable = document.documentElement.scrollHeight - innerHeight;
+	return scrollable <= 0 ? 0 : Math.min(scrollY / scrollable, 1);
+}
+
+function position_key() {
+	const path = document.querySelector("main[data-document]:not([hidden])")?.dataset.path;
+	return path === undefined ? null : `ghmd-position:${path}`;
+}
+
+let save_position = null;
+addEventListener("scroll", () => {
+	document.getElementById("progress").style.width = `${scroll_fraction() * 100}%`;
+	clearTimeout(save_position);
+	save_position = setTimeout(() => {
+		const key = position_key();
+		if (key !== null) {
+			localStorage.setItem(key, scroll_fraction());
+		}
+	}, 500);
+}, { passive: true });
+
+// Wait for images, which change the height of the page.
+addEventListener("load", () => {
+	const key = position_key();
+	const saved = key === null ? null : localStorage.getItem(key);
+	// Don't fight the browser restoring the position itself, or a link to a heading.
+	if (saved !== null && scrollY === 0 && location.hash === "") {
+		scrollTo(0, Number(saved) * (document.documentElement.scrollHeight - innerHeight));
+	}
+});
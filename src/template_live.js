@@ -1,12 +1,508 @@
 const events = new EventSource("/");
-events.addEventListener("update", e => {
-	document.getElementsByTagName("main")[0].innerHTML = e.data;
+
+// Every message is JSON carrying its sequence number on the stream and the document it concerns.
+// A gap in the sequence means a message was lost, so start afresh.
+let last_seq = -1;
+function on(kind, handler) {
+	events.addEventListener(kind, e => {
+		const data = JSON.parse(e.data);
+		if (data.seq !== last_seq + 1) {
+			location.reload();
+			return;
+		}
+		last_seq = data.seq;
+		// A document opened since the page loaded has no tab here, so load the page again for it.
+		if (data.document !== null && document_main(data.document) === null) {
+			location.reload();
+			return;
+		}
+		handler(data);
+	});
+}
+
+function document_main(id) {
+	return document.querySelector(`main[data-document="${id}"]`);
+}
+
+// The natural size of every image that has loaded, by its source.
+const image_sizes = new Map();
+addEventListener("load", e => {
+	if (e.target instanceof HTMLImageElement && e.target.naturalWidth !== 0) {
+		image_sizes.set(e.target.getAttribute("src"), [e.target.naturalWidth, e.target.naturalHeight]);
+	}
+}, true);
+
+// Replacing the HTML outright would load every image again, with the layout jumping as each one
+// arrives. Instead images whose source hasn't changed are moved into the new HTML as they are,
+// and new images of a size seen before are given that size up front.
+function patch(main, html) {
+	const old_images = new Map();
+	for (const img of main.querySelectorAll("img")) {
+		const src = img.getAttribute("src");
+		old_images.set(src, [...old_images.get(src) ?? [], img]);
+		// Images that loaded before this script ran were missed by the listener.
+		if (img.complete && img.naturalWidth !== 0) {
+			image_sizes.set(src, [img.naturalWidth, img.naturalHeight]);
+		}
+	}
+	const template = document.createElement("template");
+	template.innerHTML = html;
+	for (const img of template.content.querySelectorAll("img")) {
+		const src = img.getAttribute("src");
+		const old = old_images.get(src)?.shift();
+		if (old !== undefined) {
+			for (const { name } of [...old.attributes]) {
+				if (!img.hasAttribute(name)) {
+					old.removeAttribute(name);
+				}
+			}
+			for (const { name, value } of img.attributes) {
+				if (old.getAttribute(name) !== value) {
+					old.setAttribute(name, value);
+				}
+			}
+			img.replaceWith(old);
+		} else if (image_sizes.has(src) && !img.hasAttribute("width") && !img.hasAttribute("height")) {
+			const [width, height] = image_sizes.get(src);
+			img.width = width;
+			img.height = height;
+			img.dataset.ghmdSized = "";
+		}
+	}
+	main.replaceChildren(template.content);
+}
+
+// While the page is in the background, its title and icon show whether a document changed (•) or
+// failed to render (✗) since the page was last looked at.
+let base_title = document.title;
+let attention = null;
+function show_attention() {
+	document.title = attention === null ? base_title : `${attention} ${base_title}`;
+	document.getElementById("attention-icon")?.remove();
+	if (attention !== null) {
+		const color = attention === "✗" ? "#f85149" : "#58a6ff";
+		const icon = document.createElement("link");
+		icon.id = "attention-icon";
+		icon.rel = "icon";
+		icon.href = `data:image/svg+xml,${encodeURIComponent(
+			`<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 16 16"><circle cx="8" cy="8" r="6" fill="${color}"/></svg>`
+		)}`;
+		document.head.append(icon);
+	}
+}
+function attend(mark) {
+	if (document.visibilityState === "hidden" || !document.hasFocus()) {
+		attention = mark;
+		show_attention();
+	}
+}
+function clear_attention() {
+	if (attention !== null) {
+		attention = null;
+		show_attention();
+	}
+}
+addEventListener("focus", clear_attention);
+document.addEventListener("visibilitychange", () => {
+	if (document.visibilityState === "visible" && document.hasFocus()) {
+		clear_attention();
+	}
+});
+
+on("update", ({ document: id, html, revision }) => {
+	attend("•");
+	if (paused) {
+		pending.set(id, { html, revision });
+		pending_count += 1;
+		show_paused();
+	} else {
+		apply_update(id, html, revision);
+	}
+});
+function apply_update(id, html, revision) {
+	const main = document_main(id);
+	main.dataset.revision = revision;
+	const frame = main.querySelector("iframe.ghmd-frame");
+	if (frame === null) {
+		patch(main, html);
+	} else {
+		// A sandboxed document can't be changed from outside, so it is replaced entirely.
+		frame.srcdoc = `${main.parentElement.dataset.frameHead}${html}</body></html>`;
+	}
+	mark_prose(id);
+	if (id === selected_tab) {
+		build_outline();
+	}
+}
+on("reload", () => {
+	location.reload();
+});
+
+// Settings from frontmatter are applied as they change. Those of the whole page come from the
+// first document, and the rest only retitle the document's tab.
+on("config", ({ document: id, tab_title, page }) => {
+	const tab = document.querySelector(`[data-tab="${id}"]`);
+	if (tab !== null) {
+		tab.textContent = tab_title;
+	}
+	if (page === null) {
+		return;
+	}
+	const root = document.documentElement;
+	// Sandboxed documents have their own copy of the theme, which can't be changed from outside.
+	if (document.querySelector("iframe.ghmd-frame") !== null && root.dataset.darkTheme !== page.theme) {
+		location.reload();
+		return;
+	}
+	base_title = page.title;
+	show_attention();
+	document.querySelector(".Box-title").textContent = page.title;
+	root.dataset.colorMode = page.color_mode;
+	root.dataset.darkTheme = page.theme;
+
+	const { href, content } = page.syntax_stylesheet;
+	const syntax = document.getElementById("ghmd-syntax");
+	if (href !== null ? syntax.getAttribute("href") !== href : syntax.textContent !== content) {
+		const sheet = document.createElement(href !== null ? "link" : "style");
+		sheet.id = "ghmd-syntax";
+		if (href !== null) {
+			sheet.rel = "stylesheet";
+			sheet.href = href;
+		} else {
+			sheet.textContent = content;
+		}
+		syntax.replaceWith(sheet);
+	}
+
+	document.body.style.maxWidth = page.max_width === null ? "" : `${page.max_width}px`;
+	const outline = document.getElementById("outline");
+	if (outline !== null) {
+		outline.toggleAttribute("data-disabled", !page.outline);
+		build_outline();
+	}
+});
+
+// The save status of each document, of which the selected tab's is shown.
+const saved = new Map();
+function show_saved() {
+	document.getElementById("saved").textContent = saved.get(selected_tab) ?? "";
+}
+on("saved", ({ document: id, revision, modified }) => {
+	const time = modified === null ? "" : ` at ${new Date(modified * 1000).toLocaleTimeString()}`;
+	saved.set(id, `Revision ${revision} saved${time}`);
+	show_saved();
+});
+on("rate_limited", data => {
+	console.log(data);
+});
+on("auth_error", ({ status, message, request_id, help }) => {
+	attend("✗");
+	const id = request_id === null ? "" : ` [request ID ${request_id}]`;
+	console.error(`GitHub rejected the token (${status})${id}: ${message}\n${help}`);
+});
+on("render_error", ({ kind, message }) => {
+	attend("✗");
+	console.log(`${kind} error: ${message}`);
+});
+on("prose", ({ document: id, findings }) => {
+	prose_findings.set(id, findings);
+	mark_prose(id);
+});
+
+// Each document has its own URL, which is shown while its tab is selected. At `/` the selection
+// survives reloads instead.
+let selected_tab = 0;
+function select_tab(id) {
+	if (document_main(id) === null) {
+		return;
+	}
+	selected_tab = id;
+	sessionStorage.setItem("ghmd-tab", id);
+	for (const tab of document.querySelectorAll("[data-tab]")) {
+		tab.setAttribute("aria-selected", tab.dataset.tab === `${id}`);
+	}
+	for (const main of document.querySelectorAll("main[data-document]")) {
+		main.hidden = main.dataset.document !== `${id}`;
+	}
+	show_saved();
+	build_outline();
+	load_history();
+}
+for (const tab of document.querySelectorAll("[data-tab]")) {
+	tab.addEventListener("click", () => {
+		select_tab(Number(tab.dataset.tab));
+		if (tab.dataset.route !== undefined) {
+			history.replaceState(null, "", tab.dataset.route);
+		}
+	});
+}
+if (location.pathname === "/") {
+	select_tab(Number(sessionStorage.getItem("ghmd-tab") ?? 0));
+} else {
+	select_tab(Number(document.querySelector("[data-tab][aria-selected=true]")?.dataset.tab ?? 0));
+}
+
+const prose_findings = new Map();
+
+// Underline every occurrence of a word the prose linter flagged in a document, outside of code.
+function mark_prose(id) {
+	const main = document_main(id);
+	for (const span of main.querySelectorAll("span.ghmd-prose")) {
+		span.replaceWith(span.textContent);
+	}
+	main.normalize();
+
+	let total = 0;
+	for (const findings of prose_findings.values()) {
+		total += findings.length;
+	}
+	const toggle = document.getElementById("prose-toggle");
+	toggle.hidden = total === 0;
+	toggle.textContent = `${total} prose issue${total === 1 ? "" : "s"}`;
+
+	const messages = new Map();
+	for (const { word, message } of prose_findings.get(id) ?? []) {
+		if (word !== "") {
+			messages.set(word, messages.has(word) ? `${messages.get(word)}\n${message}` : message);
+		}
+	}
+	if (messages.size === 0) {
+		return;
+	}
+	const words = [...messages.keys()].map(word => word.replace(/[.*+?^${}()|[\]\\]/g, "\\$&"));
+	const pattern = new RegExp(`(?<![\\p{L}\\p{N}])(?:${words.join("|")})(?![\\p{L}\\p{N}])`, "gu");
+
+	const walker = document.createTreeWalker(main, NodeFilter.SHOW_TEXT, {
+		acceptNode: node => node.parentElement.closest("pre, code")
+			? NodeFilter.FILTER_REJECT
+			: NodeFilter.FILTER_ACCEPT,
+	});
+	const nodes = [];
+	while (walker.nextNode()) {
+		nodes.push(walker.currentNode);
+	}
+	for (const node of nodes) {
+		const text = node.textContent;
+		const fragment = document.createDocumentFragment();
+		let last = 0;
+		for (const match of text.matchAll(pattern)) {
+			fragment.append(text.slice(last, match.index));
+			const span = document.createElement("span");
+			span.className = "ghmd-prose";
+			span.title = messages.get(match[0]);
+			span.textContent = match[0];
+			fragment.append(span);
+			last = match.index + match[0].length;
+		}
+		if (last !== 0) {
+			fragment.append(text.slice(last));
+			node.replaceWith(fragment);
+		}
+	}
+}
+
+// Recent versions of the selected document from its journal, if versions are being recorded, each
+// of which can be viewed or written back to the file.
+function load_history() {
+	const id = selected_tab;
+	fetch(`/api/history?document=${id}`)
+		.then(res => res.ok ? res.json() : null)
+		.then(revisions => {
+			if (id !== selected_tab) {
+				return;
+			}
+			const details = document.getElementById("history");
+			details.hidden = revisions === null;
+			if (revisions === null) {
+				return;
+			}
+			const list = details.querySelector(".ghmd-history-list");
+			list.replaceChildren();
+			for (const [i, { at, time, words_added, words_removed }] of revisions.entries()) {
+				const when = new Date(time * 1000).toLocaleString();
+				const item = document.createElement("div");
+				item.className = "d-flex flex-items-center px-3 py-2 border-bottom f6";
+				const label = document.createElement("span");
+				label.className = "flex-auto";
+				label.textContent = `${when} (+${words_added} −${words_removed} words)`;
+				const view = document.createElement("a");
+				view.className = "ml-2";
+				view.href = `/history?document=${id}&at=${at}`;
+				view.target = "_blank";
+				view.textContent = "View";
+				item.append(label, view);
+				// The newest version is the one already in the file.
+				if (i !== 0) {
+					const restore = document.createElement("button");
+					restore.type = "button";
+					restore.className = "btn-link ml-2";
+					restore.textContent = "Restore";
+					restore.addEventListener("click", () => restore_version(id, at, when));
+					item.append(restore);
+				}
+				list.append(item);
+			}
+		});
+}
+function restore_version(id, at, when) {
+	const title = document.querySelector(`[data-tab="${id}"]`)?.textContent ?? base_title;
+	if (!confirm(`Replace the contents of ${title} with the version from ${when}?`)) {
+		return;
+	}
+	document.getElementById("history").open = false;
+	fetch(`/history/restore?document=${id}&at=${at}`, {
+		method: "POST",
+		headers: { "X-Requested-By": "ghmd" },
+	})
+		.then(async res => {
+			if (!res.ok) {
+				alert(`Could not restore the version: ${await res.text()}`);
+			}
+		});
+}
+document.getElementById("history").addEventListener("toggle", e => {
+	if (e.target.open) {
+		load_history();
+	}
+});
+
+// Updates can be paused, to read one part of a document while editing another. Only the latest
+// update to each document is kept, and applied on resuming.
+let paused = false;
+const pending = new Map();
+let pending_count = 0;
+function show_paused() {
+	const button = document.getElementById("pause");
+	button.textContent = paused ? "Resume" : "Pause";
+	button.title = `${paused ? "Resume" : "Pause"} live updates (P)`;
+	const badge = document.getElementById("pending");
+	badge.hidden = pending_count === 0;
+	badge.textContent = `${pending_count} update${pending_count === 1 ? "" : "s"} pending`;
+}
+function toggle_paused() {
+	paused = !paused;
+	if (!paused) {
+		for (const [id, { html, revision }] of pending) {
+			apply_update(id, html, revision);
+		}
+		pending.clear();
+		pending_count = 0;
+	}
+	show_paused();
+}
+document.getElementById("pause").hidden = false;
+document.getElementById("pause").addEventListener("click", toggle_paused);
+addEventListener("keydown", e => {
+	const typing = e.target instanceof Element
+		&& e.target.closest("input, textarea, select, [contenteditable]") !== null;
+	if (e.key === "p" && !typing && !e.ctrlKey && !e.metaKey && !e.altKey) {
+		toggle_paused();
+	}
+});
+
+// The document shown can be pinned, keeping a copy of it as it is now on the left while live
+// updates continue on the right, to compare an edit with what came before.
+function toggle_pinned() {
+	const button = document.getElementById("pin");
+	const body = document.querySelector("main[data-document]").parentElement;
+	const existing = document.getElementById("pinned");
+	if (existing !== null) {
+		existing.remove();
+		body.classList.remove("ghmd-split");
+		document.body.classList.remove("ghmd-split-page");
+		button.textContent = "Pin";
+		return;
+	}
+	const main = document_main(selected_tab);
+	const pinned = document.createElement("section");
+	pinned.id = "pinned";
+	pinned.className = "ghmd-pinned";
+	pinned.setAttribute("aria-label", "Pinned copy");
+	const label = document.createElement("div");
+	label.className = "color-text-secondary f6 mb-2";
+	const title = document.querySelector(`[data-tab="${selected_tab}"]`)?.textContent ?? base_title;
+	label.textContent = `${title} as of ${new Date().toLocaleTimeString()} `;
+	// The server keeps recent revisions, so the copy can also be opened on its own.
+	if (main.dataset.revision !== undefined) {
+		const link = document.createElement("a");
+		link.href = `/rev/${main.dataset.revision}`;
+		link.target = "_blank";
+		link.textContent = "Open in a new window";
+		label.append(link);
+	}
+	const copy = document.createElement("div");
+	copy.className = main.className;
+	copy.innerHTML = main.innerHTML;
+	// Links to headings should lead to the live copy.
+	for (const element of copy.querySelectorAll("[id]")) {
+		element.removeAttribute("id");
+	}
+	pinned.append(label, copy);
+	body.prepend(pinned);
+	body.classList.add("ghmd-split");
+	document.body.classList.add("ghmd-split-page");
+	button.textContent = "Unpin";
+}
+document.getElementById("pin").hidden = false;
+document.getElementById("pin").addEventListener("click", toggle_pinned);
+
+document.getElementById("prose-toggle").addEventListener("click", () => {
+	document.body.classList.toggle("ghmd-prose-hidden");
 });
-events.addEventListener("rate_limited", e => {
-	console.log(e.data);
+
+// Right-clicking a heading offers to copy where its section can be found, on GitHub or in the
+// source.
+function show_heading_menu(e, heading, main) {
+	const menu = document.getElementById("heading-menu");
+	menu.replaceChildren();
+	const add = (label, text) => {
+		const item = document.createElement("button");
+		item.type = "button";
+		item.className = "btn-link d-block width-full text-left px-3 py-1";
+		item.setAttribute("role", "menuitem");
+		item.textContent = label;
+		item.addEventListener("click", () => {
+			navigator.clipboard.writeText(text);
+			menu.hidden = true;
+		});
+		menu.append(item);
+	};
+
+	const href = heading_anchor(heading)?.getAttribute("href");
+	if (main.dataset.githubUrl !== undefined && href) {
+		add("Copy GitHub URL", `${main.dataset.githubUrl}${href}`);
+	}
+	const lines = heading.dataset.sourceLines;
+	if (lines !== undefined) {
+		const title = document.querySelector(`[data-tab="${main.dataset.document}"]`)?.textContent
+			?? base_title;
+		add("Copy source line range", `${title}:${lines}`);
+	}
+	if (menu.childElementCount === 0) {
+		return;
+	}
+	e.preventDefault();
+	menu.hidden = false;
+	menu.style.left = `${Math.min(e.clientX, innerWidth - menu.offsetWidth)}px`;
+	menu.style.top = `${Math.min(e.clientY, innerHeight - menu.offsetHeight)}px`;
+}
+addEventListener("contextmenu", e => {
+	const heading = e.target.closest("main[data-document] :is(h1, h2, h3, h4, h5, h6)");
+	document.getElementById("heading-menu").hidden = true;
+	if (heading !== null) {
+		show_heading_menu(e, heading, heading.closest("main"));
+	}
+});
+addEventListener("click", e => {
+	const menu = document.getElementById("heading-menu");
+	if (!menu.contains(e.target)) {
+		menu.hidden = true;
+	}
 });
-events.addEventListener("render_error", e => {
-	console.log(e.data);
+addEventListener("keydown", e => {
+	if (e.key === "Escape") {
+		document.getElementById("heading-menu").hidden = true;
+	}
 });
 
 let initial_connect = true;
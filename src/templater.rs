@@ -1,11 +1,90 @@
+use std::sync::Arc;
+
 use anyhow::Context as _;
 use clap::ArgEnum;
 use serde::Serialize;
+use sha2::{Digest, Sha256, Sha384};
 use tera::Tera;
 
+use crate::config::DocumentOptions;
+use crate::renderer::{self, Provenance};
+use crate::vendor::{self, Stylesheet, Vendored};
+
+/// A script or stylesheet shared by every page. Pages normally include their assets, but can
+/// instead link to them (see [`Templater::with_asset_routes`]) so that browsers cache them.
+pub(crate) struct Asset {
+    pub(crate) path: &'static str,
+    pub(crate) content_type: &'static str,
+    pub(crate) content: &'static str,
+}
+
+impl Asset {
+    /// A hash of the asset's content, which its URL includes so that browsers can cache it for as
+    /// long as they like.
+    pub(crate) fn version(&self) -> String {
+        renderer::content_hash(self.content)
+    }
+
+    fn url(&self) -> String {
+        format!("{}?v={}", self.path, self.version())
+    }
+}
+
+const STYLESHEET: Asset = Asset {
+    path: "/assets/ghmd.css",
+    content_type: "text/css; charset=utf-8",
+    content: include_str!("template.css"),
+};
+
+const SCRIPT: Asset = Asset {
+    path: "/assets/ghmd.js",
+    content_type: "text/javascript; charset=utf-8",
+    content: include_str!("template.js"),
+};
+
+const LIVE_SCRIPT: Asset = Asset {
+    path: "/assets/ghmd-live.js",
+    content_type: "text/javascript; charset=utf-8",
+    content: concat!(
+        include_str!("template_live.js"),
+        include_str!("template.js")
+    ),
+};
+
+/// Every asset, which the server must serve at its path if asset routes are used.
+pub(crate) static ASSETS: [Asset; 3] = [STYLESHEET, SCRIPT, LIVE_SCRIPT];
+
+/// A stylesheet or script, which is either linked to or included in the page.
+#[derive(Serialize)]
+struct Resource<'a> {
+    /// The ID of the element, for those the page's script changes.
+    id: Option<&'static str>,
+    href: Option<String>,
+    /// The hash the linked file must have, so that the browser refuses it if it was changed.
+    integrity: Option<String>,
+    content: Option<&'a str>,
+}
+
 pub(crate) struct Templater {
     title: Box<str>,
     theme: Theme,
+    /// The maximum width of the page in pixels, if not the default.
+    max_width: Option<u32>,
+    /// Extra CSS added to every page.
+    css: Option<Box<str>>,
+    /// Whether documents are shown in sandboxed iframes, for markdown that isn't trusted.
+    sandbox: bool,
+    /// How many columns a tab character in code takes up.
+    tab_size: u8,
+    /// How far below the top of the window headings are scrolled to, in pixels, to leave room for
+    /// a fixed header added by custom CSS.
+    scroll_margin: Option<u32>,
+    /// Whether pages show an outline of the document's headings.
+    outline: bool,
+    /// Whether pages link to their assets instead of including them.
+    asset_routes: bool,
+    /// Copies of the third-party stylesheets, if pages must not make requests to other sites.
+    vendored: Option<Arc<Vendored>>,
     template: Tera,
 }
 
@@ -14,43 +93,326 @@ impl Templater {
         let mut template = Tera::default();
         template.autoescape_on(Vec::new());
         template
-            .add_raw_template("html", include_str!("template.html"))
+            .add_raw_templates([
+                ("html", include_str!("template.html")),
+                ("frame", include_str!("template_frame.html")),
+            ])
             .unwrap();
 
         Self {
             title,
             theme,
+            max_width: None,
+            css: None,
+            sandbox: false,
+            // GitHub displays tabs as 8 columns by default.
+            tab_size: 8,
+            scroll_margin: None,
+            outline: true,
+            asset_routes: false,
+            vendored: None,
             template,
         }
     }
 
-    pub(crate) async fn generate(&self, html: &str, liveness: Liveness) -> anyhow::Result<String> {
+    /// A templater that adds the given stylesheet to the page.
+    pub(crate) fn with_css(self, css: Option<impl Into<Box<str>>>) -> Self {
+        Self {
+            css: css.map(Into::into),
+            ..self
+        }
+    }
+
+    /// A templater that shows each document in an iframe that may not run scripts or access the
+    /// rest of the page, so that markdown from untrusted sources can be previewed safely.
+    pub(crate) fn with_sandbox(self, sandbox: bool) -> Self {
+        Self { sandbox, ..self }
+    }
+
+    /// A templater that displays tabs in code as this many columns wide.
+    pub(crate) fn with_tab_size(self, tab_size: u8) -> Self {
+        Self { tab_size, ..self }
+    }
+
+    /// A templater whose pages scroll headings this many pixels below the top of the window when
+    /// following links to them.
+    pub(crate) fn with_scroll_margin(self, scroll_margin: Option<u32>) -> Self {
+        Self {
+            scroll_margin,
+            ..self
+        }
+    }
+
+    /// A templater whose pages load their scripts and stylesheets from the paths in [`ASSETS`],
+    /// which must be served, instead of including them. This makes each page smaller and lets
+    /// browsers cache the assets, but the pages no longer work on their own.
+    pub(crate) fn with_asset_routes(self, asset_routes: bool) -> Self {
+        Self {
+            asset_routes,
+            ..self
+        }
+    }
+
+    /// A templater whose pages make no requests to other sites: they include these copies of the
+    /// stylesheets that would come from a CDN, and forbid loading anything from elsewhere with a
+    /// Content Security Policy.
+    pub(crate) fn with_vendored(self, vendored: Option<Arc<Vendored>>) -> Self {
+        Self { vendored, ..self }
+    }
+
+    /// A templater identical to this one, but using a different theme.
+    pub(crate) fn with_theme(&self, theme: Theme) -> Self {
+        Self {
+            title: self.title.clone(),
+            theme,
+            max_width: self.max_width,
+            css: self.css.clone(),
+            sandbox: self.sandbox,
+            tab_size: self.tab_size,
+            scroll_margin: self.scroll_margin,
+            outline: self.outline,
+            asset_routes: self.asset_routes,
+            vendored: self.vendored.clone(),
+            template: self.template.clone(),
+        }
+    }
+
+    /// A templater identical to this one, but with a different title.
+    pub(crate) fn with_title(&self, title: Box<str>) -> Self {
+        Self {
+            title,
+            ..self.with_theme(self.theme)
+        }
+    }
+
+    /// A templater with the settings a document chose in its frontmatter applied on top of this
+    /// one's.
+    pub(crate) fn for_document(&self, options: &DocumentOptions) -> Self {
+        Self {
+            title: options
+                .title
+                .as_deref()
+                .map_or_else(|| self.title.clone(), Into::into),
+            max_width: options.max_width.or(self.max_width),
+            scroll_margin: options.scroll_margin.or(self.scroll_margin),
+            outline: options.toc.unwrap_or(self.outline),
+            ..self.with_theme(options.theme.unwrap_or(self.theme))
+        }
+    }
+
+    /// The settings of this templater's pages that a live page can change without being
+    /// generated again.
+    pub(crate) fn page_settings(&self) -> PageSettings<'_> {
+        PageSettings {
+            title: &self.title,
+            theme: self.theme.as_str(),
+            color_mode: self.theme.color_mode(),
+            syntax_stylesheet: self.third_party_stylesheet(self.theme.syntax_stylesheet()),
+            max_width: self.max_width,
+            outline: self.outline,
+        }
+    }
+
+    pub(crate) async fn generate(
+        &self,
+        html: &str,
+        liveness: Liveness,
+        provenance: Option<&Provenance>,
+    ) -> anyhow::Result<String> {
+        let tab = Tab {
+            title: &self.title,
+            path: None,
+            route: None,
+            selected: true,
+            github_url: None,
+            revision: None,
+            content: html,
+        };
+        self.generate_tabs(&[tab], liveness, provenance).await
+    }
+
+    /// Generate a page showing several documents, with a tab bar to switch between them if there
+    /// is more than one.
+    pub(crate) async fn generate_tabs(
+        &self,
+        tabs: &[Tab<'_>],
+        liveness: Liveness,
+        provenance: Option<&Provenance>,
+    ) -> anyhow::Result<String> {
+        #[derive(Serialize)]
+        struct FrameTemplateOpts<'a> {
+            theme: &'a str,
+            color_mode: &'a str,
+            tab_size: u8,
+            scroll_margin: Option<u32>,
+            css: Option<&'a str>,
+            stylesheets: &'a [Resource<'a>],
+        }
+        let stylesheets = self.third_party_stylesheets();
+        let frame_head = if self.sandbox {
+            let opts = FrameTemplateOpts {
+                theme: self.theme.as_str(),
+                color_mode: self.theme.color_mode(),
+                tab_size: self.tab_size,
+                scroll_margin: self.scroll_margin,
+                css: self.css.as_deref(),
+                stylesheets: &stylesheets,
+            };
+            let context = tera::Context::from_serialize(opts).unwrap();
+            Some(
+                self.template
+                    .render("frame", &context)
+                    .context("failed to render frame template")?,
+            )
+        } else {
+            None
+        };
+
         #[derive(Serialize)]
         struct HtmlTemplateOpts<'a> {
+            version: &'a str,
+            provenance: Option<&'a Provenance>,
             title: &'a str,
-            content: &'a str,
+            tabs: &'a [Tab<'a>],
             theme: &'a str,
-            javascript: &'a str,
+            color_mode: &'a str,
+            max_width: Option<u32>,
+            tab_size: u8,
+            scroll_margin: Option<u32>,
+            outline: bool,
+            css: Option<&'a str>,
+            /// The start of the document shown in each iframe, if sandboxed.
+            frame_head: Option<&'a str>,
+            stylesheets: &'a [Resource<'a>],
+            script: &'a Resource<'a>,
+            content_security_policy: Option<String>,
         }
+        let mut stylesheets = stylesheets;
+        stylesheets.push(self.resource(&STYLESHEET));
+        let script = self.resource(match liveness {
+            Liveness::Static => &SCRIPT,
+            Liveness::Live => &LIVE_SCRIPT,
+        });
         self.template
             .render(
                 "html",
                 &tera::Context::from_serialize(HtmlTemplateOpts {
+                    version: env!("CARGO_PKG_VERSION"),
+                    provenance,
                     title: &self.title,
-                    content: html,
+                    tabs,
                     theme: self.theme.as_str(),
-                    javascript: match liveness {
-                        Liveness::Static => include_str!("template.js"),
-                        Liveness::Live => concat!(
-                            include_str!("template_live.js"),
-                            include_str!("template.js")
-                        ),
-                    },
+                    color_mode: self.theme.color_mode(),
+                    max_width: self.max_width,
+                    tab_size: self.tab_size,
+                    scroll_margin: self.scroll_margin,
+                    outline: self.outline,
+                    css: self.css.as_deref(),
+                    frame_head: frame_head.as_deref(),
+                    stylesheets: &stylesheets,
+                    script: &script,
+                    content_security_policy: self
+                        .vendored
+                        .is_some()
+                        .then(|| content_security_policy(&script)),
                 })
                 .unwrap(),
             )
             .context("failed to render template")
     }
+
+    /// The stylesheets pages get from a CDN, or include copies of.
+    fn third_party_stylesheets(&self) -> Vec<Resource<'_>> {
+        vec![
+            self.third_party_stylesheet(vendor::PRIMER),
+            self.third_party_stylesheet(self.theme.syntax_stylesheet()),
+        ]
+    }
+
+    fn third_party_stylesheet(&self, sheet: Stylesheet) -> Resource<'_> {
+        // The syntax stylesheet depends on the theme, so it is swapped when that changes.
+        let id = (sheet.name != vendor::PRIMER.name).then_some("ghmd-syntax");
+        match &self.vendored {
+            Some(vendored) => Resource {
+                id,
+                href: None,
+                integrity: None,
+                content: Some(vendored.get(sheet.name)),
+            },
+            None => Resource {
+                id,
+                href: Some(sheet.url.to_owned()),
+                integrity: None,
+                content: None,
+            },
+        }
+    }
+
+    fn resource(&self, asset: &'static Asset) -> Resource<'static> {
+        if !self.asset_routes {
+            return Resource {
+                id: None,
+                href: None,
+                integrity: None,
+                content: Some(asset.content),
+            };
+        }
+        Resource {
+            id: None,
+            href: Some(asset.url()),
+            integrity: self.vendored.is_some().then(|| {
+                let hash = Sha384::digest(asset.content.as_bytes());
+                format!("sha384-{}", base64::encode(hash))
+            }),
+            content: None,
+        }
+    }
+}
+
+/// A policy allowing pages to load things only from the server they came from, and to run only
+/// ghmd's own script.
+fn content_security_policy(script: &Resource<'_>) -> String {
+    let script_src = match script.content {
+        Some(content) => format!(
+            "'sha256-{}'",
+            base64::encode(Sha256::digest(content.as_bytes()))
+        ),
+        None => "'self'".to_owned(),
+    };
+    format!(
+        "default-src 'none'; script-src {}; style-src 'self' 'unsafe-inline'; \
+            img-src 'self' data:; font-src 'self' data:; connect-src 'self'; \
+            base-uri 'self'; form-action 'self'",
+        script_src,
+    )
+}
+
+/// Settings of a page that a live page applies when they change; see
+/// [`Templater::page_settings`].
+#[derive(Serialize)]
+pub(crate) struct PageSettings<'a> {
+    title: &'a str,
+    theme: &'a str,
+    color_mode: &'a str,
+    syntax_stylesheet: Resource<'a>,
+    max_width: Option<u32>,
+    outline: bool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Tab<'a> {
+    pub(crate) title: &'a str,
+    /// The absolute path of the document, by which the page remembers how far it was read.
+    pub(crate) path: Option<&'a str>,
+    /// The URL path of the document's own page, which is shown while it is selected.
+    pub(crate) route: Option<&'a str>,
+    /// Whether the document is the one shown when the page loads.
+    pub(crate) selected: bool,
+    /// Where the document can be found on GitHub, for copying links to its sections.
+    pub(crate) github_url: Option<&'a str>,
+    /// The hash the document's revision can be seen at later, at `/rev/<hash>`.
+    pub(crate) revision: Option<&'a str>,
+    pub(crate) content: &'a str,
 }
 
 pub(crate) enum Liveness {
@@ -58,23 +420,36 @@ pub(crate) enum Liveness {
     Live,
 }
 
-#[derive(Clone, Copy, ArgEnum)]
+#[derive(Clone, Copy, Default, PartialEq, ArgEnum)]
 pub(crate) enum Theme {
+    #[default]
     Dark,
     Light,
+    #[clap(name = "dark_dimmed")]
+    DarkDimmed,
 }
 
 impl Theme {
-    fn as_str(self) -> &'static str {
+    pub(crate) fn as_str(self) -> &'static str {
         match self {
             Self::Dark => "dark",
             Self::Light => "light",
+            Self::DarkDimmed => "dark_dimmed",
+        }
+    }
+
+    fn color_mode(self) -> &'static str {
+        match self {
+            Self::Dark | Self::DarkDimmed => "dark",
+            Self::Light => "light",
         }
     }
-}
 
-impl Default for Theme {
-    fn default() -> Self {
-        Self::Dark
+    /// The `github-syntax-*` stylesheet to use; there is no dimmed variant.
+    fn syntax_stylesheet(self) -> Stylesheet {
+        match self {
+            Self::Dark | Self::DarkDimmed => vendor::SYNTAX_DARK,
+            Self::Light => vendor::SYNTAX_LIGHT,
+        }
     }
 }
@@ -0,0 +1,314 @@
+use std::io::{self, IsTerminal as _, Write as _};
+use std::num::NonZeroU8;
+
+use anyhow::Context as _;
+use scraper::{ElementRef, Html, Node};
+use tokio::signal;
+use tokio::sync::watch;
+
+use crate::queue::Priority;
+use crate::redact::redact;
+use crate::renderer::Renderer;
+use crate::watcher::DocumentState;
+
+/// Render the document as styled text in the terminal, re-rendering every time it changes, until
+/// Ctrl+C is pressed.
+pub(crate) async fn run(
+    renderer: &Renderer,
+    mut file: watch::Receiver<DocumentState>,
+    tab_size: NonZeroU8,
+) -> anyhow::Result<()> {
+    let interactive = io::stdout().is_terminal();
+
+    loop {
+        let res = file
+            .borrow_and_update()
+            .contents()
+            .map_err(|e| format!("{:?}", e));
+        let output = match res {
+            Ok(markdown) => match renderer.render(&markdown, Priority::Interactive).await {
+                Ok(Ok(rendered)) => to_ansi(&rendered, interactive, Some(tab_size)),
+                Ok(Err(e)) => format!("{}\n", e),
+                Err(e) => format!("{:?}\n", e),
+            },
+            Err(e) => format!("{}\n", e),
+        };
+
+        let mut stdout = io::stdout().lock();
+        if interactive {
+            // Clear the screen and scrollback, then move to the top left.
+            write!(stdout, "\x1b[2J\x1b[3J\x1b[H")?;
+        }
+        stdout
+            .write_all(redact(&output).as_bytes())
+            .context("failed to write to standard output")?;
+        stdout.flush()?;
+        drop(stdout);
+
+        tokio::select! {
+            res = file.changed() => res?,
+            res = signal::ctrl_c() => return Ok(res?),
+        }
+    }
+}
+
+/// Convert rendered HTML to text, styled with ANSI escape codes if `color` is set. Tabs in code
+/// are expanded to spaces if `tab_size` is given.
+pub(crate) fn to_ansi(html: &str, color: bool, tab_size: Option<NonZeroU8>) -> String {
+    let mut writer = Writer {
+        out: String::new(),
+        color,
+        tab_size,
+        prefixes: Vec::new(),
+        at_line_start: true,
+        preformatted: false,
+        column: 0,
+    };
+    for child in Html::parse_fragment(html).root_element().children() {
+        if let Some(element) = ElementRef::wrap(child) {
+            writer.element(element);
+        } else if let Node::Text(text) = child.value() {
+            writer.text(text);
+        }
+    }
+    let mut out = writer.out.trim_end().to_owned();
+    out.push('\n');
+    out
+}
+
+struct Writer {
+    out: String,
+    color: bool,
+    tab_size: Option<NonZeroU8>,
+    /// Text written at the start of every line, for block quotes and list items.
+    prefixes: Vec<String>,
+    at_line_start: bool,
+    preformatted: bool,
+    /// The column within the current line of preformatted text, for expanding tabs.
+    column: usize,
+}
+
+impl Writer {
+    fn element(&mut self, element: ElementRef<'_>) {
+        let value = element.value();
+        match value.name() {
+            "svg" | "script" | "style" | "template" => {}
+            "a" if value.classes().any(|class| class == "anchor") => {}
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.blank_line();
+                let level = value.name()[1..].parse().unwrap();
+                self.raw_text(&"#".repeat(level));
+                self.raw_text(" ");
+                let style = if level == 1 { "1;4;35" } else { "1;35" };
+                self.styled(style, "0", |this| this.children(element));
+                self.blank_line();
+            }
+            "p" => {
+                self.blank_line();
+                self.children(element);
+                self.blank_line();
+            }
+            "br" => self.newline(),
+            "hr" => {
+                self.blank_line();
+                self.styled("2", "22", |this| this.raw_text(&"─".repeat(40)));
+                self.blank_line();
+            }
+            "strong" | "b" | "th" => self.styled("1", "22", |this| this.children(element)),
+            "em" | "i" => self.styled("3", "23", |this| this.children(element)),
+            "del" | "s" => self.styled("9", "29", |this| this.children(element)),
+            "code" if !self.preformatted => self.styled("36", "39", |this| this.children(element)),
+            "pre" => {
+                self.blank_line();
+                self.prefixes.push("    ".to_owned());
+                self.preformatted = true;
+                self.styled("36", "39", |this| this.children(element));
+                self.preformatted = false;
+                self.prefixes.pop();
+                self.blank_line();
+            }
+            "a" => {
+                self.styled("4", "24", |this| this.children(element));
+                if let Some(href) = value.attr("href").filter(|href| !href.starts_with('#')) {
+                    let text: String = element.text().collect();
+                    if text.trim() != href {
+                        self.styled("2", "22", |this| this.raw_text(&format!(" ({})", href)));
+                    }
+                }
+            }
+            "img" => {
+                let alt = value.attr("alt").unwrap_or("");
+                self.styled("2", "22", |this| {
+                    this.raw_text(&format!("[image: {}]", alt))
+                });
+            }
+            "input" if value.attr("type") == Some("checkbox") => {
+                let checked = value.attr("checked").is_some();
+                self.raw_text(if checked { "[x] " } else { "[ ] " });
+            }
+            "blockquote" => {
+                self.blank_line();
+                self.prefixes.push("│ ".to_owned());
+                self.children(element);
+                self.prefixes.pop();
+                self.blank_line();
+            }
+            "ul" | "ol" => {
+                self.block_break();
+                let ordered = value.name() == "ol";
+                let start: usize = value
+                    .attr("start")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(1);
+                let items = element.children().filter_map(ElementRef::wrap);
+                for (number, item) in (start..).zip(items) {
+                    let marker = if ordered {
+                        format!("{}. ", number)
+                    } else {
+                        "• ".to_owned()
+                    };
+                    self.block_break();
+                    self.raw_text(&marker);
+                    self.prefixes.push(" ".repeat(marker.chars().count()));
+                    self.at_line_start = false;
+                    self.children(item);
+                    self.prefixes.pop();
+                }
+                self.block_break();
+                if self.prefixes.is_empty() {
+                    self.blank_line();
+                }
+            }
+            "tr" => {
+                self.block_break();
+                for (i, cell) in element.children().filter_map(ElementRef::wrap).enumerate() {
+                    if i != 0 {
+                        self.styled("2", "22", |this| this.raw_text(" │ "));
+                    }
+                    self.element(cell);
+                }
+                self.newline();
+            }
+            "table" => {
+                self.blank_line();
+                self.children(element);
+                self.blank_line();
+            }
+            "summary" => {
+                self.block_break();
+                self.raw_text("▸ ");
+                self.styled("1", "22", |this| this.children(element));
+                self.newline();
+            }
+            "div" | "details" | "li" | "dl" | "dd" | "dt" => {
+                self.block_break();
+                self.children(element);
+                self.block_break();
+            }
+            _ => self.children(element),
+        }
+    }
+
+    fn children(&mut self, element: ElementRef<'_>) {
+        for child in element.children() {
+            if let Some(element) = ElementRef::wrap(child) {
+                self.element(element);
+            } else if let Node::Text(text) = child.value() {
+                self.text(text);
+            }
+        }
+    }
+
+    /// Write text from the document, collapsing whitespace unless it is preformatted.
+    fn text(&mut self, text: &str) {
+        if self.preformatted {
+            let mut lines = text.split('\n');
+            self.code_text(lines.next().unwrap());
+            for line in lines {
+                self.newline();
+                self.code_text(line);
+            }
+            return;
+        }
+        let mut collapsed = String::with_capacity(text.len());
+        let mut last_space = self.at_line_start || self.out.ends_with(' ');
+        for c in text.chars() {
+            if c.is_whitespace() {
+                if !last_space {
+                    collapsed.push(' ');
+                }
+                last_space = true;
+            } else {
+                collapsed.push(c);
+                last_space = false;
+            }
+        }
+        self.raw_text(&collapsed);
+    }
+
+    /// Write part of a line of preformatted text, expanding its tabs.
+    fn code_text(&mut self, text: &str) {
+        let mut expanded = String::with_capacity(text.len());
+        for c in text.chars() {
+            match self.tab_size {
+                Some(tab_size) if c == '\t' => {
+                    let tab_size = usize::from(tab_size.get());
+                    let spaces = tab_size - self.column % tab_size;
+                    expanded.push_str(&" ".repeat(spaces));
+                    self.column += spaces;
+                }
+                _ => {
+                    expanded.push(c);
+                    self.column += 1;
+                }
+            }
+        }
+        self.raw_text(&expanded);
+    }
+
+    fn raw_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if self.at_line_start {
+            for prefix in &self.prefixes {
+                self.out.push_str(prefix);
+            }
+            self.at_line_start = false;
+        }
+        self.out.push_str(text);
+    }
+
+    fn styled(&mut self, start: &str, end: &str, f: impl FnOnce(&mut Self)) {
+        if self.color {
+            self.out.push_str(&format!("\x1b[{}m", start));
+        }
+        f(self);
+        if self.color {
+            self.out.push_str(&format!("\x1b[{}m", end));
+        }
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        self.at_line_start = true;
+        self.column = 0;
+    }
+
+    /// Make sure the next text starts on a new line.
+    fn block_break(&mut self) {
+        if !self.at_line_start {
+            let trimmed = self.out.trim_end_matches(' ').len();
+            self.out.truncate(trimmed);
+            self.newline();
+        }
+    }
+
+    /// Make sure there is an empty line before the next text.
+    fn blank_line(&mut self) {
+        self.block_break();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.newline();
+        }
+    }
+}
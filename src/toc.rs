@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use anyhow::{bail, Context as _};
+use fn_error_context::context;
+use tokio::fs;
+
+use crate::outline::{self, Heading};
+use crate::queue::Priority;
+use crate::renderer::Renderer;
+
+const START: &str = "<!-- toc -->";
+const END: &str = "<!-- /toc -->";
+
+/// Generate a table of contents for `input`, linking to each heading by the anchor GitHub will
+/// give it, and either print it or, with `write`, put it between the file's `<!-- toc -->` and
+/// `<!-- /toc -->` comments in place of whatever was there before.
+#[context("failed to generate a table of contents for `{}`", input.display())]
+pub(crate) async fn run(renderer: &Renderer, input: &Path, write: bool) -> anyhow::Result<()> {
+    let markdown = fs::read_to_string(input).await?;
+    let rendered = renderer.render(&markdown, Priority::Background).await??;
+    let toc = generate(&outline::headings(&rendered, &markdown));
+
+    if !write {
+        print!("{}", toc);
+        return Ok(());
+    }
+
+    let (start, end) = match markdown.find(START) {
+        Some(start) => match markdown[start..].find(END) {
+            Some(end) => (start + START.len(), start + end),
+            None => bail!("there is a `{}` comment with no `{}` after it", START, END),
+        },
+        None => bail!(
+            "there is nowhere to put the table of contents; add `{}` and `{}` comments where it \
+             should go",
+            START,
+            END,
+        ),
+    };
+    let updated = format!("{}\n\n{}\n{}", &markdown[..start], toc, &markdown[end..]);
+    if updated == markdown {
+        println!("The table of contents is up to date");
+        return Ok(());
+    }
+    fs::write(input, updated)
+        .await
+        .context("failed to write file")?;
+    println!("Updated the table of contents");
+    Ok(())
+}
+
+/// A nested markdown list of links to the headings. A lone `h1` at the start is taken to be the
+/// document's title and left out, as `--number-headings` does.
+fn generate(headings: &[Heading]) -> String {
+    let titled = headings.first().is_some_and(|heading| heading.level == 1)
+        && headings.iter().filter(|heading| heading.level == 1).count() == 1;
+    let headings = &headings[usize::from(titled)..];
+    let top = headings.iter().map(|heading| heading.level).min();
+
+    let mut toc = String::new();
+    for heading in headings {
+        let indent = usize::from(heading.level - top.unwrap());
+        toc.push_str(&"  ".repeat(indent));
+        toc.push_str("- [");
+        for c in heading.heading.chars() {
+            if matches!(c, '\\' | '[' | ']' | '`' | '*' | '_' | '<') {
+                toc.push('\\');
+            }
+            toc.push(c);
+        }
+        toc.push_str("](#");
+        toc.push_str(&heading.slug);
+        toc.push_str(")\n");
+    }
+    toc
+}
@@ -0,0 +1,85 @@
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::Context as _;
+use fn_error_context::context;
+use tokio::fs;
+use tokio::io::AsyncWriteExt as _;
+
+/// Uploads larger than this many bytes are rejected.
+pub(crate) const MAX_BYTES: u64 = 20 * 1024 * 1024;
+
+/// The file extension for an image MIME type, if it is one that GitHub displays.
+pub(crate) fn extension(content_type: &str) -> Option<&'static str> {
+    let essence = content_type.split(';').next().unwrap().trim();
+    Some(match &*essence.to_ascii_lowercase() {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        "image/avif" => "avif",
+        _ => return None,
+    })
+}
+
+/// Save an uploaded image in `dir`, never overwriting an existing file, and return the name it
+/// was saved under.
+///
+/// The name is based on `name` if given, and on the current time otherwise.
+#[context("failed to save uploaded image")]
+pub(crate) async fn save(
+    dir: &Path,
+    name: Option<&str>,
+    extension: &str,
+    data: &[u8],
+) -> anyhow::Result<String> {
+    let stem = match name.and_then(|name| Path::new(name).file_stem()) {
+        Some(stem) => stem
+            .to_string_lossy()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '-',
+            })
+            .collect(),
+        None => {
+            let time = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            format!("pasted-{}", time.as_secs())
+        }
+    };
+
+    fs::create_dir_all(dir).await?;
+    for suffix in 0.. {
+        let file_name = match suffix {
+            0 => format!("{}.{}", stem, extension),
+            _ => format!("{}-{}.{}", stem, suffix, extension),
+        };
+        let path = dir.join(&file_name);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await;
+        match file {
+            Ok(mut file) => {
+                // Tokio writes in the background, so flush before the image is linked to.
+                async {
+                    file.write_all(data).await?;
+                    file.flush().await
+                }
+                .await
+                .with_context(|| format!("failed to write `{}`", path.display()))?;
+                return Ok(file_name);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to create `{}`", path.display()))
+            }
+        }
+    }
+    unreachable!()
+}
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use fn_error_context::context;
+use tokio::fs;
+
+use crate::dirs;
+
+/// A stylesheet that pages load from a CDN, unless `--no-external` is given.
+pub(crate) struct Stylesheet {
+    /// The name of the local copy in the cache directory.
+    pub(crate) name: &'static str,
+    pub(crate) url: &'static str,
+}
+
+pub(crate) const PRIMER: Stylesheet = Stylesheet {
+    name: "primer.css",
+    url: "https://cdn.jsdelivr.net/npm/@primer/css@17.4.0/dist/primer.css",
+};
+
+pub(crate) const SYNTAX_DARK: Stylesheet = Stylesheet {
+    name: "github-syntax-dark.css",
+    url: "https://cdn.jsdelivr.net/npm/github-syntax-dark@0.5.0/lib/github-dark.css",
+};
+
+pub(crate) const SYNTAX_LIGHT: Stylesheet = Stylesheet {
+    name: "github-syntax-light.css",
+    url: "https://cdn.jsdelivr.net/npm/github-syntax-light@0.5.0/lib/github-light.css",
+};
+
+static STYLESHEETS: [Stylesheet; 3] = [PRIMER, SYNTAX_DARK, SYNTAX_LIGHT];
+
+/// Local copies of every stylesheet, so that pages can include them instead of loading them from
+/// the CDN.
+pub(crate) struct Vendored {
+    sheets: Vec<(&'static str, Box<str>)>,
+}
+
+impl Vendored {
+    /// The contents of the stylesheet with the given name.
+    pub(crate) fn get(&self, name: &str) -> &str {
+        self.sheets
+            .iter()
+            .find(|(sheet, _)| *sheet == name)
+            .map_or("", |(_, content)| content)
+    }
+}
+
+/// The directory the copies are kept in.
+fn dir() -> anyhow::Result<PathBuf> {
+    Ok(dirs::cache_dir()?.join("vendor"))
+}
+
+/// Read the local copy of every stylesheet, downloading any that are missing. On a machine that
+/// can't reach the CDN, the files can be copied into the cache directory by hand.
+#[context("failed to load local copies of stylesheets")]
+pub(crate) async fn load(client: &reqwest::Client) -> anyhow::Result<Vendored> {
+    let dir = dir()?;
+    let mut sheets = Vec::with_capacity(STYLESHEETS.len());
+    for sheet in &STYLESHEETS {
+        let path = dir.join(sheet.name);
+        let content = match fs::read_to_string(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let content = download(client, sheet).await.with_context(|| {
+                    format!(
+                        "`{}` is missing; if this machine can't reach the CDN, download {} \
+                            elsewhere and copy it there",
+                        path.display(),
+                        sheet.url,
+                    )
+                })?;
+                fs::create_dir_all(&dir).await?;
+                fs::write(&path, &content)
+                    .await
+                    .with_context(|| format!("failed to write `{}`", path.display()))?;
+                log::info!("Saved a copy of {} to `{}`", sheet.url, path.display());
+                content
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read `{}`", path.display()))
+            }
+        };
+        sheets.push((sheet.name, content.into()));
+    }
+    Ok(Vendored { sheets })
+}
+
+async fn download(client: &reqwest::Client, sheet: &Stylesheet) -> reqwest::Result<String> {
+    client
+        .get(sheet.url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await
+}
@@ -1,72 +1,570 @@
-use std::path::Path;
+use std::collections::hash_map::{self, HashMap};
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, Context as _};
 use fn_error_context::context;
-use notify::Watcher;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use notify::Watcher as _;
 use tokio::fs;
+use tokio::io::AsyncReadExt as _;
 use tokio::sync::watch;
 use tokio::sync::Notify;
+use tokio::time;
 
-#[context("failed to watch file `{}`", path.as_ref().display())]
-pub(crate) async fn watch_file(
-    path: impl AsRef<Path>,
-) -> anyhow::Result<watch::Receiver<anyhow::Result<Arc<str>>>> {
-    let path = <Arc<Path>>::from(fs::canonicalize(path.as_ref()).await?);
-
-    let initial_contents = <Arc<str>>::from(fs::read_to_string(&path).await?);
-
-    let modified = Arc::new(Notify::new());
-    let mut watcher = notify::recommended_watcher({
-        let path = path.clone();
-        let modified = modified.clone();
-        move |event: notify::Result<notify::Event>| {
-            let event = match event {
-                Ok(event) => event,
+use crate::paths;
+
+/// The number of times a suspicious read will be retried before its result is published anyway.
+const READ_RETRIES: u32 = 5;
+
+/// How long to wait before retrying a suspicious read.
+const READ_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// A single revision of the watched file.
+#[derive(Clone)]
+pub(crate) struct File {
+    pub(crate) contents: Arc<str>,
+    /// The modification time of the file, if the platform supports it.
+    pub(crate) modified: Option<SystemTime>,
+    /// The size of the file on disk as reported by its metadata at the time it was read. This may
+    /// differ from the length of `contents` if the file was being written to concurrently.
+    pub(crate) size: u64,
+    /// A counter that increases by one every time a new version of the file is published.
+    pub(crate) revision: u64,
+}
+
+impl File {
+    async fn read(path: &Path, revision: u64) -> anyhow::Result<Self> {
+        let metadata = fs::metadata(path).await?;
+        let contents = <Arc<str>>::from(fs::read_to_string(path).await?);
+        Ok(Self {
+            contents,
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+            revision,
+        })
+    }
+
+    /// Read the file, retrying if it looks like the read happened in the middle of a save.
+    async fn read_settled(path: &Path, revision: u64, previous_size: u64) -> anyhow::Result<Self> {
+        let mut retries = 0;
+        loop {
+            let file = Self::read(path, revision).await?;
+
+            if retries == READ_RETRIES {
+                return Ok(file);
+            }
+
+            let raced = match fs::metadata(path).await {
+                Ok(metadata) => {
+                    metadata.len() != file.size || metadata.modified().ok() != file.modified
+                }
+                Err(_) => true,
+            };
+            let truncated = file.contents.len() as u64 != file.size;
+            let shrank = file.size < previous_size / 2;
+
+            if !raced && !truncated && !shrank {
+                return Ok(file);
+            }
+
+            retries += 1;
+            time::sleep(READ_RETRY_DELAY).await;
+        }
+    }
+}
+
+/// The latest state of a watched document, shared by everything that watches it.
+#[derive(Clone)]
+pub(crate) enum DocumentState {
+    Content(File),
+    /// The file doesn't exist, such as while an editor is replacing it or after it was deleted.
+    Missing,
+    Error(ReadError),
+}
+
+impl DocumentState {
+    fn from_read(res: anyhow::Result<File>) -> Self {
+        match res {
+            Ok(file) => Self::Content(file),
+            Err(e) if is_not_found(&e) => Self::Missing,
+            Err(e) => Self::Error(ReadError::new(e)),
+        }
+    }
+
+    pub(crate) fn file(&self) -> Option<&File> {
+        match self {
+            Self::Content(file) => Some(file),
+            Self::Missing | Self::Error(_) => None,
+        }
+    }
+
+    /// The document's contents, or why they can't be had.
+    pub(crate) fn contents(&self) -> anyhow::Result<Arc<str>> {
+        match self {
+            Self::Content(file) => Ok(file.contents.clone()),
+            Self::Missing => Err(anyhow!("the file does not exist")),
+            Self::Error(e) => Err(e.clone().into()),
+        }
+    }
+}
+
+/// Why a document couldn't be read. Cloning it shares the original error, so its chain of
+/// causes can still be inspected and downcast.
+#[derive(Clone)]
+pub(crate) struct ReadError(Arc<anyhow::Error>);
+
+impl ReadError {
+    pub(crate) fn new(e: anyhow::Error) -> Self {
+        Self(Arc::new(e))
+    }
+}
+
+impl Debug for ReadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&*self.0, f)
+    }
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+}
+
+/// Whether a path is `-`, which stands for standard input.
+pub(crate) fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Read all of standard input.
+pub(crate) async fn read_stdin() -> anyhow::Result<String> {
+    let mut contents = String::new();
+    tokio::io::stdin()
+        .read_to_string(&mut contents)
+        .await
+        .context("failed to read standard input")?;
+    Ok(contents)
+}
+
+/// Publish everything read from standard input so far every time more arrives, so that output
+/// streamed from another program is shown as it is produced.
+fn stream_stdin() -> watch::Receiver<DocumentState> {
+    let (sender, receiver) = watch::channel(DocumentState::Content(File {
+        contents: Arc::from(""),
+        modified: None,
+        size: 0,
+        revision: 0,
+    }));
+    tokio::spawn(async move {
+        let mut stdin = tokio::io::stdin();
+        let mut bytes = Vec::new();
+        let mut revision = 0;
+        loop {
+            match stdin.read_buf(&mut bytes).await {
+                Ok(0) => break,
+                Ok(_) => {}
                 Err(e) => {
-                    log::error!("{:?}", anyhow!(e).context("failed to watch file"));
-                    return;
+                    let e = anyhow!(e).context("failed to read standard input");
+                    let _ = sender.send(DocumentState::Error(ReadError::new(e)));
+                    break;
+                }
+            }
+            // Don't publish a character split across two reads until all of it has arrived.
+            let contents = match std::str::from_utf8(&bytes) {
+                Ok(contents) => contents,
+                Err(e) if e.error_len().is_none() => {
+                    std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap()
+                }
+                Err(e) => {
+                    let e = anyhow!(e).context("standard input is not UTF-8");
+                    let _ = sender.send(DocumentState::Error(ReadError::new(e)));
+                    break;
                 }
             };
-            if !event.paths.iter().any(|p| *p == *path) {
+            revision += 1;
+            log::info!("Read revision {} ({} bytes)", revision, contents.len());
+            let file = File {
+                contents: Arc::from(contents),
+                modified: None,
+                size: contents.len() as u64,
+                revision,
+            };
+            if sender.send(DocumentState::Content(file)).is_err() {
                 return;
             }
-            if let notify::EventKind::Access(_) = event.kind {
-                return;
+        }
+        // Keep the channel open, since closing it would tell receivers to stop.
+        sender.closed().await;
+    });
+    receiver
+}
+
+/// A description of how changes to files are noticed on this platform.
+pub(crate) fn backend() -> String {
+    let name = std::any::type_name::<notify::RecommendedWatcher>();
+    let name = name.rsplit("::").next().unwrap_or(name);
+    match name {
+        "PollWatcher" => "polling".to_owned(),
+        _ => format!("native ({})", name),
+    }
+}
+
+/// A set of watched paths sharing a single underlying OS watcher.
+///
+/// Paths can be added at any time, each producing its own update channel. A path stops being
+/// watched once every receiver for its channel has been dropped.
+#[derive(Clone)]
+pub(crate) struct Watcher {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    /// Accessed from the notify event handler. This must never be locked while `dirs` is being
+    /// modified, since that may block on the event handler thread.
+    entries: Arc<Mutex<Vec<Entry>>>,
+    dirs: Mutex<Dirs>,
+}
+
+struct Entry {
+    matcher: Matcher,
+    modified: Arc<Notify>,
+}
+
+enum Matcher {
+    File {
+        /// Where the file was said to be, with its directory made canonical. This differs from
+        /// `path` if the file is a symlink.
+        location: Arc<Path>,
+        // The canonical path can change underneath us when editors save by renaming a temporary
+        // file over the target (or over the target of a symlink), so it is re-resolved after
+        // every rename.
+        path: Arc<Mutex<Arc<Path>>>,
+    },
+    Globs(GlobSet),
+}
+
+impl Matcher {
+    fn matches(&self, event: &notify::Event) -> bool {
+        match self {
+            Self::File { location, path } => {
+                let path = path.lock().unwrap().clone();
+                event.paths.iter().any(|p| *p == *path || *p == **location)
             }
-            modified.notify_one();
+            Self::Globs(globs) => event.paths.iter().any(|p| globs.is_match(p)),
         }
-    })?;
-    let dir = path.parent().context("file has no parent")?;
-    watcher.watch(dir, notify::RecursiveMode::Recursive)?;
+    }
+}
 
-    let (sender, receiver) = watch::channel(Ok(initial_contents.clone()));
+/// Reference-counted directory watches, so that several entries can share a directory.
+struct Dirs {
+    watcher: notify::RecommendedWatcher,
+    counts: HashMap<PathBuf, usize>,
+}
 
-    tokio::spawn(async move {
-        let mut previous_contents = Some(initial_contents);
-        loop {
-            modified.notified().await;
-
-            let res = fs::read_to_string(&path)
-                .await
-                .context("failed to read file");
-
-            let same = matches!(
-                (&res, &previous_contents),
-                (Ok(contents), Some(previous_contents)) if **contents == **previous_contents
-            );
-            if same {
-                continue;
+impl Dirs {
+    fn watch(&mut self, dir: &Path) -> notify::Result<()> {
+        match self.counts.entry(dir.to_owned()) {
+            hash_map::Entry::Occupied(mut entry) => *entry.get_mut() += 1,
+            hash_map::Entry::Vacant(entry) => {
+                self.watcher.watch(dir, notify::RecursiveMode::Recursive)?;
+                entry.insert(1);
             }
+        }
+        Ok(())
+    }
 
-            let res = res.map(<Arc<str>>::from);
-            previous_contents = res.as_ref().ok().cloned();
-            if sender.send(res).is_err() {
-                break;
+    fn unwatch(&mut self, dir: &Path) {
+        if let Some(count) = self.counts.get_mut(dir) {
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(dir);
+                let _ = self.watcher.unwatch(dir);
             }
         }
-        drop(watcher);
-    });
+    }
+}
+
+impl Watcher {
+    pub(crate) fn new() -> anyhow::Result<Self> {
+        let entries = Arc::new(Mutex::new(Vec::<Entry>::new()));
+        let watcher = notify::recommended_watcher({
+            let entries = entries.clone();
+            move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        log::error!("{:?}", anyhow!(e).context("failed to watch files"));
+                        return;
+                    }
+                };
+                if let notify::EventKind::Access(_) = event.kind {
+                    return;
+                }
+                for entry in &*entries.lock().unwrap() {
+                    if entry.matcher.matches(&event) {
+                        entry.modified.notify_one();
+                    }
+                }
+            }
+        })
+        .context("failed to create file watcher")?;
+
+        Ok(Self {
+            shared: Arc::new(Shared {
+                entries,
+                dirs: Mutex::new(Dirs {
+                    watcher,
+                    counts: HashMap::new(),
+                }),
+            }),
+        })
+    }
+
+    fn add_entry(&self, matcher: Matcher) -> Arc<Notify> {
+        let modified = Arc::new(Notify::new());
+        self.shared.entries.lock().unwrap().push(Entry {
+            matcher,
+            modified: modified.clone(),
+        });
+        modified
+    }
+
+    fn remove_entry(&self, modified: &Arc<Notify>) {
+        self.shared
+            .entries
+            .lock()
+            .unwrap()
+            .retain(|entry| !Arc::ptr_eq(&entry.modified, modified));
+    }
+
+    /// Watch a single file, publishing its contents every time they change. A path of `-` reads
+    /// from standard input instead.
+    #[context("failed to watch file `{}`", path.as_ref().display())]
+    pub(crate) async fn add_file(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<watch::Receiver<DocumentState>> {
+        if is_stdin(path.as_ref()) {
+            return Ok(stream_stdin());
+        }
+        let input = <Arc<Path>>::from(path.as_ref());
+        let path = <Arc<Path>>::from(paths::simplify(fs::canonicalize(&input).await?));
+        let parent = match input.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let location = paths::simplify(fs::canonicalize(parent).await?)
+            .join(input.file_name().context("file has no name")?);
+
+        let initial = File::read(&path, 0).await?;
+
+        let mut dir = <Arc<Path>>::from(path.parent().context("file has no parent")?);
+        self.shared.dirs.lock().unwrap().watch(&dir)?;
+
+        let current_path = Arc::new(Mutex::new(path));
+        let modified = self.add_entry(Matcher::File {
+            location: location.into(),
+            path: current_path.clone(),
+        });
+
+        let mut previous_contents = Some(initial.contents.clone());
+        let mut previous_size = initial.size;
+        let mut revision = initial.revision;
+        let (sender, receiver) = watch::channel(DocumentState::Content(initial));
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    () = modified.notified() => {}
+                    () = sender.closed() => break,
+                }
+
+                if let Ok(new_path) = fs::canonicalize(&input).await.map(paths::simplify) {
+                    let mut path = current_path.lock().unwrap();
+                    if *new_path != **path {
+                        log::info!(
+                            "`{}` now resolves to `{}`",
+                            input.display(),
+                            new_path.display()
+                        );
+                        *path = new_path.into();
+                    }
+                    if let Some(new_dir) = path.parent().filter(|new_dir| *new_dir != &*dir) {
+                        let new_dir = <Arc<Path>>::from(new_dir);
+                        drop(path);
+                        let mut dirs = this.shared.dirs.lock().unwrap();
+                        if let Err(e) = dirs.watch(&new_dir) {
+                            log::error!(
+                                "{:?}",
+                                anyhow!(e).context("failed to watch new directory")
+                            );
+                        } else {
+                            dirs.unwatch(&dir);
+                            dir = new_dir;
+                        }
+                    }
+                }
+
+                let path = current_path.lock().unwrap().clone();
+                let res = File::read_settled(&path, revision + 1, previous_size)
+                    .await
+                    .context("failed to read file");
+
+                let same = matches!(
+                    (&res, &previous_contents),
+                    (Ok(file), Some(previous_contents)) if *file.contents == **previous_contents
+                );
+                if same {
+                    continue;
+                }
+
+                revision += 1;
+                previous_contents = res.as_ref().ok().map(|file| file.contents.clone());
+                if let Ok(file) = &res {
+                    previous_size = file.size;
+                    log::info!("Read revision {} ({} bytes)", file.revision, file.size);
+                }
+                if sender.send(DocumentState::from_read(res)).is_err() {
+                    break;
+                }
+            }
+            this.remove_entry(&modified);
+            this.shared.dirs.lock().unwrap().unwatch(&dir);
+        });
+
+        Ok(receiver)
+    }
+
+    /// Watch every path matching any of the given glob patterns, notifying the returned receiver
+    /// whenever one of them changes.
+    ///
+    /// Patterns without any glob metacharacters match the path itself and, if it is a directory,
+    /// everything inside it.
+    #[context("failed to watch extra paths")]
+    pub(crate) fn add_globs(&self, patterns: &[String]) -> anyhow::Result<watch::Receiver<()>> {
+        let cwd = std::env::current_dir().context("failed to get current directory")?;
+
+        let mut globs = GlobSetBuilder::new();
+        let mut roots = Vec::new();
+        for pattern in patterns {
+            let pattern = cwd.join(pattern);
+            let pattern_str = pattern
+                .to_str()
+                .with_context(|| format!("path `{}` is not UTF-8", pattern.display()))?;
+
+            let root: PathBuf = pattern
+                .components()
+                .take_while(|component| {
+                    !component
+                        .as_os_str()
+                        .to_string_lossy()
+                        .contains(['*', '?', '[', '{'])
+                })
+                .collect();
+
+            let glob = |pattern: &str| {
+                GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .with_context(|| format!("invalid glob pattern `{}`", pattern))
+            };
+            globs.add(glob(pattern_str)?);
+            if root == pattern {
+                globs.add(glob(&format!("{}/**", pattern_str))?);
+            }
+
+            // The root may be a file that is replaced by renaming, so watch its parent instead.
+            let dir = match root.is_dir() {
+                true => root,
+                false => root.parent().map_or(root.clone(), Path::to_owned),
+            };
+            roots.push(dir);
+        }
+        let globs = globs.build()?;
+
+        {
+            let mut dirs = self.shared.dirs.lock().unwrap();
+            for (i, dir) in roots.iter().enumerate() {
+                if let Err(e) = dirs.watch(dir) {
+                    for dir in &roots[..i] {
+                        dirs.unwatch(dir);
+                    }
+                    return Err(anyhow!(e).context(format!("failed to watch `{}`", dir.display())));
+                }
+            }
+        }
+
+        let modified = self.add_entry(Matcher::Globs(globs));
+
+        let (sender, receiver) = watch::channel(());
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    () = modified.notified() => {}
+                    () = sender.closed() => break,
+                }
+                // Saves often produce several events in quick succession; coalesce them.
+                time::sleep(READ_RETRY_DELAY).await;
+                let _ = time::timeout(Duration::ZERO, modified.notified()).await;
+                if sender.send(()).is_err() {
+                    break;
+                }
+            }
+            this.remove_entry(&modified);
+            let mut dirs = this.shared.dirs.lock().unwrap();
+            for dir in &roots {
+                dirs.unwatch(dir);
+            }
+        });
+
+        Ok(receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use notify::event::{CreateKind, ModifyKind, RenameMode};
+    use notify::{Event, EventKind};
+
+    use super::Matcher;
 
-    Ok(receiver)
+    #[test]
+    fn renames_match_by_full_path() {
+        let matcher = Matcher::File {
+            location: Path::new("/docs/link.md").into(),
+            path: Arc::new(Mutex::new(Path::new("/docs/notes/README.md").into())),
+        };
+        let rename = |path: &str| {
+            Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::To))).add_path(path.into())
+        };
+        assert!(matcher.matches(&rename("/docs/notes/README.md")));
+        assert!(matcher.matches(&rename("/docs/link.md")));
+        assert!(!matcher.matches(&rename("/docs/other/README.md")));
+        assert!(!matcher.matches(&rename("/docs/notes/sub/link.md")));
+        let create = Event::new(EventKind::Create(CreateKind::File));
+        assert!(matcher.matches(&create.add_path("/docs/notes/README.md".into())));
+    }
 }
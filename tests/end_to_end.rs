@@ -0,0 +1,104 @@
+//! Runs ghmd against its mock of GitHub's API and checks what a browser would be sent.
+#![cfg(feature = "mock")]
+
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use tokio::time::{self, Instant};
+
+/// A running ghmd, killed when dropped.
+struct Ghmd {
+    child: Child,
+    url: String,
+}
+
+impl Ghmd {
+    fn start(file: &Path, cache: &Path) -> Self {
+        // Find a free port; ghmd binds it again straight after it is released here.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_ghmd"))
+            .args(["--mock-api", "--bind", "127.0.0.1", "--port"])
+            .arg(port.to_string())
+            .arg(file)
+            .env("XDG_CACHE_HOME", cache)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Self {
+            child,
+            url: format!("http://127.0.0.1:{}/", port),
+        }
+    }
+
+    /// Get the page, waiting for the server to start.
+    async fn page(&self) -> String {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        loop {
+            match reqwest::get(&self.url).await {
+                Ok(res) => {
+                    assert!(res.status().is_success(), "{}", res.status());
+                    return res.text().await.unwrap();
+                }
+                Err(e) if Instant::now() < deadline && e.is_connect() => {
+                    time::sleep(Duration::from_millis(100)).await;
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+}
+
+impl Drop for Ghmd {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn renders_and_updates() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("doc.md");
+    std::fs::write(&file, "# Hello\n\nSome text.\n").unwrap();
+    let ghmd = Ghmd::start(&file, &dir.path().join("cache"));
+
+    let page = ghmd.page().await;
+    assert!(page.contains("Hello</h1>"), "{}", page);
+    assert!(page.contains("<p>Some text.</p>"), "{}", page);
+
+    let mut events = reqwest::Client::new()
+        .get(&ghmd.url)
+        .header("Accept", "text/event-stream")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(events.headers()["content-type"], "text/event-stream");
+
+    std::fs::write(&file, "# Goodbye\n\nOther text.\n").unwrap();
+
+    let mut received = String::new();
+    let update = time::timeout(Duration::from_secs(30), async {
+        while let Some(chunk) = events.chunk().await.unwrap() {
+            received.push_str(&String::from_utf8_lossy(&chunk));
+            if let Some(start) = received.find("event: update") {
+                return received[start..].to_owned();
+            }
+        }
+        panic!("the event stream ended: {}", received);
+    })
+    .await
+    .expect("no update was sent");
+    let update = match update.find("\n\n") {
+        Some(end) => update[..end].to_owned(),
+        None => update,
+    };
+    assert!(update.contains("Goodbye</h1>"), "{}", update);
+    assert!(update.contains("<p>Other text.</p>"), "{}", update);
+}